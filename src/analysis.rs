@@ -0,0 +1,377 @@
+use super::{
+    args::Arg,
+    flags::Flags,
+    function::{Function, Item},
+    instruction::Instruction,
+    meta::ControlFlow,
+    register::{RegisterName, RegisterSet, RegisterSize},
+};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt::Display;
+
+/// A register read that no path from the function entry has written before.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UndefinedRead {
+    /// The index of the reading instruction in [`Function::items`].
+    pub item: usize,
+    pub register: RegisterName,
+}
+impl Display for UndefinedRead {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let register = self.register.quad();
+        write!(
+            f,
+            "item {} reads {register}, which is never written",
+            self.item
+        )
+    }
+}
+
+/// A flags consumer that does not see the flags its block most likely meant it to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlagsHazard {
+    /// The index of the reading instruction in [`Function::items`].
+    pub consumer: usize,
+    /// The index of the instruction that last modified the flags before the consumer.
+    pub clobber: usize,
+    pub flags: Flags,
+    pub kind: FlagsHazardKind,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FlagsHazardKind {
+    /// The clobbering instruction leaves the flags undefined.
+    Undefined,
+    /// The clobbering instruction overwrote the result of an earlier comparison.
+    Overwritten { comparison: usize },
+}
+impl Display for FlagsHazard {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Self {
+            consumer,
+            clobber,
+            flags,
+            ..
+        } = *self;
+        match self.kind {
+            FlagsHazardKind::Undefined => write!(
+                f,
+                "item {consumer} reads {flags}, which item {clobber} leaves undefined"
+            ),
+            FlagsHazardKind::Overwritten { comparison } => write!(
+                f,
+                "item {consumer} reads {flags}, which item {clobber} overwrote after the comparison at item {comparison}"
+            ),
+        }
+    }
+}
+
+/// A register tracked by [`Function::def_use`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ChainRegister {
+    Physical(RegisterName),
+    /// A virtual register, by number.
+    Virtual(u32),
+}
+impl Display for ChainRegister {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Physical(name) => write!(f, "{}", name.quad()),
+            Self::Virtual(number) => write!(f, "%v{number}"),
+        }
+    }
+}
+
+/// A value of a register within a block, and the instructions reading it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DefUse {
+    pub register: ChainRegister,
+    /// The index of the defining instruction in [`Function::items`], or `None` if the value
+    /// is live into the block.
+    pub def: Option<usize>,
+    /// The indices of the instructions reading the value before the block sets it again.
+    pub uses: Vec<usize>,
+}
+
+impl Function<'_> {
+    /// Computes the def-use chains of every block, in order of the blocks and of the first
+    /// appearance of each value within them.
+    /// Writes to 8 and 16-bit registers read the value they partially overwrite.
+    pub fn def_use(&self) -> Vec<DefUse> {
+        let mut chains = Vec::new();
+        for block in split_blocks(&self.items) {
+            // The chain of the current value of each register.
+            let mut current = BTreeMap::new();
+            for (i, instruction) in block.instructions(&self.items) {
+                let (uses, defs) = accesses(instruction);
+                for register in uses {
+                    let chain = *current.entry(register).or_insert_with(|| {
+                        chains.push(DefUse {
+                            register,
+                            def: None,
+                            uses: Vec::new(),
+                        });
+                        chains.len() - 1
+                    });
+                    chains[chain].uses.push(i);
+                }
+                for register in defs {
+                    chains.push(DefUse {
+                        register,
+                        def: Some(i),
+                        uses: Vec::new(),
+                    });
+                    current.insert(register, chains.len() - 1);
+                }
+            }
+        }
+        chains
+    }
+
+    /// Finds flag consumers that read flags which were left undefined, or which were
+    /// overwritten after a comparison within the same block.
+    pub fn flags_hazards(&self) -> Vec<FlagsHazard> {
+        let mut hazards = Vec::new();
+        for (consumer, item) in self.items.iter().enumerate() {
+            let Item::Instruction(instruction, _) = item else {
+                continue;
+            };
+            let flags = instruction.flags_effect().read;
+            if !flags.is_empty() {
+                hazards.extend(self.find_flags_hazard(consumer, flags));
+            }
+        }
+        hazards
+    }
+    fn find_flags_hazard(&self, consumer: usize, flags: Flags) -> Option<FlagsHazard> {
+        let mut preceding = self.items[..consumer]
+            .iter()
+            .enumerate()
+            .rev()
+            .map_while(|(i, item)| match item {
+                Item::Label(_) => None,
+                Item::Instruction(inst, _) if ends_block(inst) => None,
+                Item::Instruction(inst, _) => Some(Some((i, inst))),
+                Item::Comment(_) => Some(None),
+            })
+            .flatten();
+
+        let (clobber, clobbering) = preceding.find(|(_, inst)| {
+            let modified = inst.flags_effect().modified();
+            modified.intersects(flags)
+        })?;
+
+        let undefined = clobbering.flags_effect().undefined.intersection(flags);
+        if !undefined.is_empty() {
+            return Some(FlagsHazard {
+                consumer,
+                clobber,
+                flags: undefined,
+                kind: FlagsHazardKind::Undefined,
+            });
+        }
+        if clobbering.is_comparison() {
+            return None;
+        }
+
+        let comparison = preceding.find_map(|(i, inst)| {
+            let overwritten = inst.flags_effect().written.intersects(flags);
+            (inst.is_comparison() && overwritten).then_some(i)
+        })?;
+        Some(FlagsHazard {
+            consumer,
+            clobber,
+            flags,
+            kind: FlagsHazardKind::Overwritten { comparison },
+        })
+    }
+
+    /// Finds reads of registers that are neither arguments, callee-saved nor written on
+    /// any path leading to the read.
+    pub fn undefined_reads(&self) -> Vec<UndefinedRead> {
+        let blocks = split_blocks(&self.items);
+        let entry = self
+            .arguments
+            .union(RegisterSet::sysv_callee_saved())
+            .with(RegisterName::SP);
+
+        let mut defined_in = vec![RegisterSet::new(); blocks.len()];
+        for (i, block) in blocks.iter().enumerate() {
+            if i == 0 {
+                defined_in[i] = entry;
+            } else if block.predecessors.is_empty() {
+                // Only reachable in ways we cannot see, such as indirect jumps.
+                defined_in[i] = RegisterSet::all();
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, block) in blocks.iter().enumerate() {
+                let defined = block.predecessors.iter().fold(defined_in[i], |set, &p| {
+                    set.union(defined_in[p]).union(blocks[p].writes)
+                });
+                if defined != defined_in[i] {
+                    defined_in[i] = defined;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut undefined = Vec::new();
+        for (block, mut defined) in blocks.iter().zip(defined_in) {
+            for (item, instruction) in block.instructions(&self.items) {
+                for register in instruction.reads().difference(defined).iter() {
+                    undefined.push(UndefinedRead { item, register });
+                }
+                defined = defined.union(instruction.writes());
+            }
+        }
+        undefined
+    }
+}
+
+pub(crate) struct Block {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    writes: RegisterSet,
+    pub(crate) successors: Vec<usize>,
+    pub(crate) predecessors: Vec<usize>,
+}
+impl Block {
+    pub(crate) fn instructions<'i, 'a>(
+        &self,
+        items: &'i [Item<'a>],
+    ) -> impl Iterator<Item = (usize, &'i Instruction<'a>)> {
+        let start = self.start;
+        items[self.start..self.end]
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, item)| match item {
+                Item::Instruction(instruction, _) => Some((start + i, instruction)),
+                _ => None,
+            })
+    }
+}
+
+pub(crate) fn split_blocks(items: &[Item]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut labels = BTreeMap::new();
+    let mut start = 0;
+    for (i, item) in items.iter().enumerate() {
+        match item {
+            Item::Label(label) => {
+                if i != start {
+                    blocks.push((start, i));
+                    start = i;
+                }
+                labels.insert(label.label, blocks.len());
+            }
+            Item::Instruction(instruction, _) if ends_block(instruction) => {
+                blocks.push((start, i + 1));
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if start != items.len() || blocks.is_empty() {
+        blocks.push((start, items.len()));
+    }
+
+    let mut blocks: Vec<_> = blocks
+        .into_iter()
+        .map(|(start, end)| Block {
+            start,
+            end,
+            writes: RegisterSet::new(),
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        })
+        .collect();
+
+    for i in 0..blocks.len() {
+        let mut successors = Vec::new();
+        let last = blocks[i].instructions(items).last().map(|(_, inst)| *inst);
+        let falls_through = match last {
+            Some(Instruction::Jmp { target }) => {
+                successors.extend(jump_target(&labels, target));
+                false
+            }
+            Some(Instruction::CJmp { target, .. }) => {
+                successors.extend(jump_target(&labels, target));
+                true
+            }
+            Some(inst) if inst.info().control_flow == ControlFlow::Return => false,
+            _ => true,
+        };
+        if falls_through && i + 1 < blocks.len() {
+            successors.push(i + 1);
+        }
+
+        blocks[i].writes = blocks[i]
+            .instructions(items)
+            .fold(RegisterSet::new(), |set, (_, inst)| {
+                set.union(inst.writes())
+            });
+        for &successor in &successors {
+            blocks[successor].predecessors.push(i);
+        }
+        blocks[i].successors = successors;
+    }
+
+    blocks
+}
+/// The registers an instruction reads and those it writes, each without duplicates.
+fn accesses(instruction: &Instruction) -> (Vec<ChainRegister>, Vec<ChainRegister>) {
+    let mut uses: Vec<_> = instruction
+        .uses()
+        .into_iter()
+        .map(|r| ChainRegister::Physical(r.0))
+        .collect();
+    let mut defs = Vec::new();
+    for register in instruction.defs() {
+        if register.1 < RegisterSize::Double {
+            uses.push(ChainRegister::Physical(register.0));
+        }
+        defs.push(ChainRegister::Physical(register.0));
+    }
+
+    let zeroing = instruction.is_zeroing_idiom();
+    let operands = instruction
+        .operands()
+        .into_iter()
+        .zip(instruction.info().operands);
+    for (arg, operand) in operands {
+        let Arg::Virtual(register) = arg else {
+            continue;
+        };
+        let writes = operand.access.writes();
+        let partial = register.1 < RegisterSize::Double && writes;
+        if (operand.access.reads() && !zeroing) || partial {
+            uses.push(ChainRegister::Virtual(register.0));
+        }
+        if writes {
+            defs.push(ChainRegister::Virtual(register.0));
+        }
+    }
+
+    let unique = |registers: Vec<ChainRegister>| {
+        let mut unique = Vec::with_capacity(registers.len());
+        for register in registers {
+            if !unique.contains(&register) {
+                unique.push(register);
+            }
+        }
+        unique
+    };
+    (unique(uses), unique(defs))
+}
+fn ends_block(instruction: &Instruction) -> bool {
+    instruction.info().is_terminator()
+}
+fn jump_target(labels: &BTreeMap<&str, usize>, target: Arg) -> Option<usize> {
+    match target {
+        Arg::Label(label) => labels.get(label.label).copied(),
+        _ => None,
+    }
+}
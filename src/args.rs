@@ -1,46 +1,101 @@
 use super::{
-    label::Label,
-    register::{Register, RegisterSize},
+    error::X64WriterError,
+    label::{Label, LocalLabel, SymExpr},
+    register::{MaskRegister, Register, RegisterSize, VectorRegister, VirtualRegister},
 };
-use std::{fmt::Display, ops::AddAssign};
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Display;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Arg<'a> {
     Register(Register),
+    /// Only valid until [`Function::allocate_registers`](crate::function::Function::allocate_registers)
+    /// replaced it. Memory addresses always use physical registers.
+    Virtual(VirtualRegister),
+    Vector(VectorRegister),
+    Mask(MaskRegister),
     Label(Label<'a>),
+    SymExpr(SymExpr<'a>),
     Int(ConstInt),
     Memory(Memory<'a>),
 }
 impl<'a> Arg<'a> {
     pub fn size(&self) -> Option<ArgSize> {
-        let size = match self {
-            &Self::Register(r) => match r.1 {
-                RegisterSize::Byte => ArgSize::Byte,
-                RegisterSize::Word => ArgSize::Word,
-                RegisterSize::Double => ArgSize::Double,
-                RegisterSize::Quad => ArgSize::Quad,
-            },
-            &Self::Int(c) => match c {
+        let size = match *self {
+            Self::Register(Register(_, size)) | Self::Virtual(VirtualRegister(_, size)) => {
+                match size {
+                    RegisterSize::Byte => ArgSize::Byte,
+                    RegisterSize::Word => ArgSize::Word,
+                    RegisterSize::Double => ArgSize::Double,
+                    RegisterSize::Quad => ArgSize::Quad,
+                }
+            }
+            Self::Int(c) => match c {
                 ConstInt::I8(_) | ConstInt::U8(_) => ArgSize::Byte,
                 ConstInt::I32(_) => ArgSize::Double,
                 ConstInt::U32(_) => ArgSize::Double,
                 ConstInt::I64(_) | ConstInt::U64(_) => ArgSize::Quad,
             },
-            &Self::Label(_) => return None,
-            &Self::Memory(m) => return m.size,
+            Self::Vector(_) | Self::Mask(_) | Self::Label(_) | Self::SymExpr(_) => return None,
+            Self::Memory(m) => return m.size,
         };
         Some(size)
     }
     pub fn is_register(&self) -> bool {
+        matches!(self, Self::Register(_))
+    }
+    pub fn is_memory(&self) -> bool {
+        matches!(self, Self::Memory(_))
+    }
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Self::Virtual(_))
+    }
+    pub fn is_vector(&self) -> bool {
+        matches!(self, Self::Vector(_))
+    }
+    /// Replaces every register in the operand, including those in memory addresses.
+    pub fn map_registers(self, f: impl Fn(Register) -> Register) -> Self {
         match self {
-            Self::Register(_) => true,
-            _ => false,
+            Self::Register(r) => Self::Register(f(r)),
+            Self::Memory(m) => Self::Memory(m.map_registers(f)),
+            other => other,
         }
     }
-    pub fn is_memory(&self) -> bool {
+    /// Replaces every label in the operand, possibly with one of a different lifetime.
+    pub fn map_labels<'b>(self, f: impl Fn(Label<'a>) -> Label<'b>) -> Arg<'b> {
         match self {
-            Self::Memory(_) => true,
-            _ => false,
+            Self::Register(r) => Arg::Register(r),
+            Self::Virtual(v) => Arg::Virtual(v),
+            Self::Vector(v) => Arg::Vector(v),
+            Self::Mask(k) => Arg::Mask(k),
+            Self::Label(label) => Arg::Label(f(label)),
+            Self::SymExpr(expr) => Arg::SymExpr(SymExpr::new(f(expr.label), expr.offset)),
+            Self::Int(c) => Arg::Int(c),
+            Self::Memory(m) => Arg::Memory(m.map_labels(f)),
+        }
+    }
+    /// The symbol the operand refers to, including one used as a memory displacement.
+    pub fn symbol(&self) -> Option<Label<'a>> {
+        match *self {
+            Self::Label(label) => Some(label),
+            Self::SymExpr(expr) => Some(expr.label),
+            Self::Memory(m) => m.displacement_label,
+            Self::Register(_)
+            | Self::Virtual(_)
+            | Self::Vector(_)
+            | Self::Mask(_)
+            | Self::Int(_) => None,
+        }
+    }
+    /// Whether the operand can only be encoded in 64-bit mode.
+    pub fn requires_long_mode(&self) -> bool {
+        match *self {
+            Self::Register(r) => r.requires_long_mode(),
+            Self::Int(c) => matches!(c, ConstInt::I64(_) | ConstInt::U64(_)),
+            Self::Memory(m) => m.requires_long_mode(),
+            Self::Virtual(v) => v.1 == RegisterSize::Quad,
+            Self::Vector(v) => v.requires_long_mode(),
+            Self::Mask(_) | Self::Label(_) | Self::SymExpr(_) => false,
         }
     }
 }
@@ -49,11 +104,31 @@ impl From<Register> for Arg<'_> {
         Self::Register(value)
     }
 }
+impl From<VirtualRegister> for Arg<'_> {
+    fn from(value: VirtualRegister) -> Self {
+        Self::Virtual(value)
+    }
+}
+impl From<VectorRegister> for Arg<'_> {
+    fn from(value: VectorRegister) -> Self {
+        Self::Vector(value)
+    }
+}
+impl From<MaskRegister> for Arg<'_> {
+    fn from(value: MaskRegister) -> Self {
+        Self::Mask(value)
+    }
+}
 impl<'a> From<Label<'a>> for Arg<'a> {
     fn from(value: Label<'a>) -> Self {
         Self::Label(value)
     }
 }
+impl<'a> From<SymExpr<'a>> for Arg<'a> {
+    fn from(value: SymExpr<'a>) -> Self {
+        Self::SymExpr(value)
+    }
+}
 impl From<i8> for Arg<'_> {
     fn from(value: i8) -> Self {
         Self::Int(ConstInt::from(value))
@@ -99,18 +174,28 @@ impl<'a> From<&'a String> for Arg<'a> {
         Self::Label(Label::from(value))
     }
 }
+impl<'a> From<&'a LocalLabel> for Arg<'a> {
+    fn from(value: &'a LocalLabel) -> Self {
+        Self::Label(value.label())
+    }
+}
 impl<'a> From<Memory<'a>> for Arg<'a> {
     fn from(value: Memory<'a>) -> Self {
         Self::Memory(value)
     }
 }
 impl Display for Arg<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            &Arg::Int(c) => write!(f, "${c}"),
-            &Arg::Label(l) => write!(f, "{l}"),
-            &Arg::Register(r) => write!(f, "{r}"),
-            &Arg::Memory(mem) => write!(f, "{mem}"),
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Arg::Int(c) => write!(f, "${c}"),
+            Arg::Label(l) => write!(f, "{l}"),
+            Arg::SymExpr(e) => write!(f, "{e}"),
+            Arg::Register(r) => write!(f, "{r}"),
+            Arg::Virtual(v) => write!(f, "{v}"),
+            Arg::Vector(v) => write!(f, "{v}"),
+            Arg::Mask(k) => write!(f, "{k}"),
+            Arg::Memory(mem) if f.alternate() => write!(f, "{mem:#}"),
+            Arg::Memory(mem) => write!(f, "{mem}"),
         }
     }
 }
@@ -122,6 +207,16 @@ pub enum ArgSize {
     Double,
     Quad,
 }
+impl From<ArgSize> for RegisterSize {
+    fn from(value: ArgSize) -> Self {
+        match value {
+            ArgSize::Byte => RegisterSize::Byte,
+            ArgSize::Word => RegisterSize::Word,
+            ArgSize::Double => RegisterSize::Double,
+            ArgSize::Quad => RegisterSize::Quad,
+        }
+    }
+}
 impl ArgSize {
     pub fn suffix(self) -> &'static str {
         match self {
@@ -131,6 +226,55 @@ impl ArgSize {
             ArgSize::Quad => "q",
         }
     }
+    pub fn bytes(self) -> u8 {
+        match self {
+            ArgSize::Byte => 1,
+            ArgSize::Word => 2,
+            ArgSize::Double => 4,
+            ArgSize::Quad => 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Signedness {
+    Signed,
+    Unsigned,
+}
+
+/// The precision of a scalar float in the low lane of an xmm register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FloatSize {
+    Single,
+    Double,
+}
+impl FloatSize {
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::Single => 32,
+            Self::Double => 64,
+        }
+    }
+    /// An xmm register worth of floats of this size with only their sign bits set.
+    pub fn sign_mask(self) -> u128 {
+        match self {
+            Self::Single => 0x8000_0000_8000_0000_8000_0000_8000_0000,
+            Self::Double => 0x8000_0000_0000_0000_8000_0000_0000_0000,
+        }
+    }
+}
+
+/// How a float converts to a signed integer it is NaN or out of the range of.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FloatToInt<'a> {
+    /// NaN converts to zero and other values to the nearest integer in range, as Rust's
+    /// `as` does.
+    Saturating,
+    /// NaN and values out of range jump to the label, as WebAssembly's `trunc` traps.
+    Trapping(Label<'a>),
+    /// Converts a 32-bit integer to the low half of the 64-bit conversion, which wraps
+    /// around within ±2^63 and is zero beyond it and for NaN.
+    Wrapping,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -163,9 +307,47 @@ impl ConstInt {
             Self::U64(_) => false,
         }
     }
+    /// The sum of two constants of the same type, which has to fit that type.
+    pub fn checked_add(self, rhs: Self) -> Result<Self, X64WriterError> {
+        use ConstInt::*;
+        let sum = match (self, rhs) {
+            (I8(a), I8(b)) => a.checked_add(b).map(I8),
+            (U8(a), U8(b)) => a.checked_add(b).map(U8),
+            (I32(a), I32(b)) => a.checked_add(b).map(I32),
+            (U32(a), U32(b)) => a.checked_add(b).map(U32),
+            (I64(a), I64(b)) => a.checked_add(b).map(I64),
+            (U64(a), U64(b)) => a.checked_add(b).map(U64),
+            _ => {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "cannot add constants of different types, {self:?} and {rhs:?}"
+                )))
+            }
+        };
+        sum.ok_or(X64WriterError::DisplacementOverflow(
+            self.wide_value() + rhs.wide_value(),
+        ))
+    }
+    /// The value, exactly.
+    fn wide_value(self) -> i128 {
+        match self {
+            Self::U64(v) => v.into(),
+            c => c.value().into(),
+        }
+    }
+    /// The value as the bits of a 64-bit two's complement integer.
+    pub fn value(self) -> i64 {
+        match self {
+            Self::I8(v) => v.into(),
+            Self::U8(v) => v.into(),
+            Self::I32(v) => v.into(),
+            Self::U32(v) => v.into(),
+            Self::I64(v) => v,
+            Self::U64(v) => v as i64,
+        }
+    }
 }
 impl Display for ConstInt {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match *self {
             Self::I8(v) => write!(f, "{v}"),
             Self::U8(v) => write!(f, "{v}"),
@@ -206,22 +388,17 @@ impl From<u64> for ConstInt {
         Self::U64(value)
     }
 }
-impl AddAssign for ConstInt {
-    fn add_assign(&mut self, rhs: Self) {
-        use ConstInt::*;
-        match (self, rhs) {
-            (I32(a), I32(b)) => *a += b,
-            (U32(a), U32(b)) => *a += b,
-            (I64(a), I64(b)) => *a += b,
-            (U64(a), U64(b)) => *a += b,
-            _ => panic!(),
-        }
-    }
+
+fn rip_relative_register(register: Register) -> X64WriterError {
+    X64WriterError::InvalidOperand(format!(
+        "{register} cannot be added to a %rip relative address"
+    ))
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Memory<'a> {
     pub size: Option<ArgSize>,
+    pub segment: Option<Segment>,
     pub displacement_label: Option<Label<'a>>,
     pub displacement_constant: Option<ConstInt>,
     pub kind: MemoryKind,
@@ -230,6 +407,7 @@ impl<'a> Memory<'a> {
     pub fn sib() -> Self {
         Self {
             size: None,
+            segment: None,
             displacement_label: None,
             displacement_constant: None,
             kind: MemoryKind::Sib(SibMemory {
@@ -241,47 +419,164 @@ impl<'a> Memory<'a> {
     pub fn rip() -> Self {
         Self {
             size: None,
+            segment: None,
             displacement_label: None,
             displacement_constant: None,
             kind: MemoryKind::Rip,
         }
     }
 
-    pub fn base(mut self, base: Register) -> Self {
-        let MemoryKind::Sib(m) = &mut self.kind else { panic!() };
+    /// Sets the base register.
+    ///
+    /// # Panics
+    ///
+    /// For `%rip` relative addresses, see [`Self::try_base`].
+    #[track_caller]
+    pub fn base(self, base: Register) -> Self {
+        // A closure would hide the caller from #[track_caller].
+        match self.try_base(base) {
+            Ok(memory) => memory,
+            Err(error) => panic!("{error}"),
+        }
+    }
+    /// Sets the scaled index register.
+    ///
+    /// # Panics
+    ///
+    /// For `%rip` relative addresses, see [`Self::try_index`].
+    #[track_caller]
+    pub fn index(self, index: Register, scale: Scale) -> Self {
+        match self.try_index(index, scale) {
+            Ok(memory) => memory,
+            Err(error) => panic!("{error}"),
+        }
+    }
+    /// Adds `disp` to the displacement.
+    ///
+    /// # Panics
+    ///
+    /// If the displacements cannot be added, see [`Self::try_offset`].
+    #[track_caller]
+    pub fn offset(self, disp: impl Into<ConstInt>) -> Self {
+        match self.try_offset(disp) {
+            Ok(memory) => memory,
+            Err(error) => panic!("{error}"),
+        }
+    }
+    pub fn try_base(mut self, base: Register) -> Result<Self, X64WriterError> {
+        let MemoryKind::Sib(m) = &mut self.kind else {
+            return Err(rip_relative_register(base));
+        };
         m.base = Some(base);
-        self
+        Ok(self)
     }
-    pub fn index(mut self, index: Register, scale: Scale) -> Self {
-        let MemoryKind::Sib(m) = &mut self.kind else { panic!() };
+    pub fn try_index(mut self, index: Register, scale: Scale) -> Result<Self, X64WriterError> {
+        let MemoryKind::Sib(m) = &mut self.kind else {
+            return Err(rip_relative_register(index));
+        };
         m.index = Some((index, scale));
-        self
+        Ok(self)
     }
-    pub fn offset(mut self, disp: impl Into<ConstInt>) -> Self {
+    /// Adds `disp` to the displacement, which has to be of the same type if there is one.
+    pub fn try_offset(mut self, disp: impl Into<ConstInt>) -> Result<Self, X64WriterError> {
         let disp: ConstInt = disp.into();
-        if let Some(displacement) = &mut self.displacement_constant {
-            *displacement += disp;
-        } else {
-            self.displacement_constant = Some(disp);
+        self.displacement_constant = match self.displacement_constant {
+            Some(displacement) => Some(displacement.checked_add(disp)?),
+            None => Some(disp),
+        };
+        Ok(self)
+    }
+    /// Checks that the displacement fits the signed 32 bits of an address. Unsigned 32-bit
+    /// displacements are taken as their two's complement bits.
+    pub fn check_displacement(&self) -> Result<(), X64WriterError> {
+        let Some(displacement) = self.displacement_constant else {
+            return Ok(());
+        };
+        let value = displacement.wide_value();
+        let fits = match displacement {
+            ConstInt::I64(_) | ConstInt::U64(_) => i32::try_from(value).is_ok(),
+            _ => true,
+        };
+        match fits {
+            true => Ok(()),
+            false => Err(X64WriterError::DisplacementOverflow(value)),
         }
-
-        self
     }
-    pub fn label(mut self, label: impl Into<Label<'a>>) -> Self {
+    /// Adds `label` to the displacement.
+    ///
+    /// # Panics
+    ///
+    /// If the displacement already has a label, see [`Self::try_label`].
+    #[track_caller]
+    pub fn label(self, label: impl Into<Label<'a>>) -> Self {
+        match self.try_label(label) {
+            Ok(memory) => memory,
+            Err(error) => panic!("{error}"),
+        }
+    }
+    /// Adds `label` to the displacement, which can have only one.
+    pub fn try_label(mut self, label: impl Into<Label<'a>>) -> Result<Self, X64WriterError> {
         let label = label.into();
-        if self.displacement_label.is_some() {
-            panic!()
+        if let Some(existing) = self.displacement_label {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cannot add the label {label} to {self}, which already has {existing}"
+            )));
         }
         self.displacement_label = Some(label);
-        self
+        Ok(self)
     }
     pub fn size(mut self, size: ArgSize) -> Self {
         self.size = Some(size);
         self
     }
+    /// Addresses relative to the base of `segment`, such as per-CPU data through `%gs`.
+    pub fn segment(mut self, segment: Segment) -> Self {
+        self.segment = Some(segment);
+        self
+    }
+
+    pub fn map_registers(mut self, f: impl Fn(Register) -> Register) -> Self {
+        if let MemoryKind::Sib(sib) = &mut self.kind {
+            sib.base = sib.base.map(&f);
+            sib.index = sib.index.map(|(index, scale)| (f(index), scale));
+        }
+        self
+    }
+    /// Replaces the displacement label, possibly with one of a different lifetime.
+    pub fn map_labels<'b>(self, f: impl Fn(Label<'a>) -> Label<'b>) -> Memory<'b> {
+        Memory {
+            size: self.size,
+            segment: self.segment,
+            displacement_label: self.displacement_label.map(f),
+            displacement_constant: self.displacement_constant,
+            kind: self.kind,
+        }
+    }
+    /// The registers read to compute the address.
+    pub fn address_registers(&self) -> Vec<Register> {
+        let mut registers = Vec::new();
+        if let MemoryKind::Sib(sib) = self.kind {
+            registers.extend(sib.base);
+            registers.extend(sib.index.map(|(index, _)| index));
+        }
+        registers
+    }
+    /// Whether the access is rip-relative, 64 bits wide, or addressed through a register
+    /// that only exists in 64-bit mode.
+    pub fn requires_long_mode(&self) -> bool {
+        self.kind == MemoryKind::Rip
+            || self.size == Some(ArgSize::Quad)
+            || self
+                .address_registers()
+                .iter()
+                .any(|r| r.requires_long_mode())
+    }
 }
 impl Display for Memory<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(segment) = self.segment {
+            write!(f, "{segment}:")?;
+        }
         if let Some(label) = self.displacement_label {
             write!(f, "{label}")?;
         }
@@ -295,7 +590,25 @@ impl Display for Memory<'_> {
             }
         }
 
-        write!(f, "{}", self.kind)
+        if f.alternate() {
+            write!(f, "{:#}", self.kind)
+        } else {
+            write!(f, "{}", self.kind)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Fs,
+    Gs,
+}
+impl Display for Segment {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Fs => write!(f, "%fs"),
+            Self::Gs => write!(f, "%gs"),
+        }
     }
 }
 
@@ -305,9 +618,10 @@ pub enum MemoryKind {
     Sib(SibMemory),
 }
 impl Display for MemoryKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match *self {
             Self::Rip => write!(f, "(%rip)"),
+            Self::Sib(mem) if f.alternate() => write!(f, "{mem:#}"),
             Self::Sib(mem) => write!(f, "{mem}"),
         }
     }
@@ -318,8 +632,16 @@ pub struct SibMemory {
     base: Option<Register>,
     index: Option<(Register, Scale)>,
 }
+impl SibMemory {
+    pub fn base(&self) -> Option<Register> {
+        self.base
+    }
+    pub fn index(&self) -> Option<(Register, Scale)> {
+        self.index
+    }
+}
 impl Display for SibMemory {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let any = self.base.is_some() || self.index.is_some();
         if any {
             write!(f, "(")?;
@@ -330,9 +652,10 @@ impl Display for SibMemory {
         }
 
         if let Some((index, scale)) = self.index {
-            write!(f, ", {index}")?;
+            let separator = if f.alternate() { "," } else { ", " };
+            write!(f, "{separator}{index}")?;
             if scale != Scale::One {
-                write!(f, ", {scale}")?;
+                write!(f, "{separator}{scale}")?;
             }
         }
 
@@ -352,7 +675,7 @@ pub enum Scale {
     Eight,
 }
 impl Display for Scale {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::One => write!(f, "1"),
             Self::Two => write!(f, "2"),
@@ -0,0 +1,313 @@
+use super::{
+    data::DataValue,
+    io::{self, Write},
+    label::Label,
+    writer::AsmWriter,
+};
+use alloc::vec::Vec;
+
+pub const MULTIBOOT2_MAGIC: u32 = 0xe85250d6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Multiboot2Architecture {
+    /// 32-bit protected mode i386, also used for kernels that switch to long mode themselves.
+    I386 = 0,
+    Mips32 = 4,
+}
+
+/// A tag of the Multiboot2 header, requesting information or behavior from the boot loader.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Multiboot2Tag<'a> {
+    /// The boot information tag types the kernel wants to receive.
+    InformationRequest(Vec<u32>),
+    /// Load addresses for kernels that are not ELF images.
+    Address {
+        header: DataValue<'a>,
+        load: DataValue<'a>,
+        load_end: DataValue<'a>,
+        bss_end: DataValue<'a>,
+    },
+    EntryAddress(DataValue<'a>),
+    ConsoleFlags {
+        console_required: bool,
+        ega_text_supported: bool,
+    },
+    /// The preferred graphics mode, zero meaning no preference.
+    Framebuffer {
+        width: u32,
+        height: u32,
+        depth: u32,
+    },
+    /// Modules must be page aligned.
+    ModuleAlign,
+    /// The kernel may be started without exiting UEFI boot services.
+    EfiBootServices,
+    EntryAddressEfi32(DataValue<'a>),
+    EntryAddressEfi64(DataValue<'a>),
+    Relocatable {
+        min_address: u32,
+        max_address: u32,
+        alignment: u32,
+        preference: Multiboot2LoadPreference,
+    },
+}
+impl Multiboot2Tag<'_> {
+    fn tag_type(&self) -> u16 {
+        match self {
+            Self::InformationRequest(_) => 1,
+            Self::Address { .. } => 2,
+            Self::EntryAddress(_) => 3,
+            Self::ConsoleFlags { .. } => 4,
+            Self::Framebuffer { .. } => 5,
+            Self::ModuleAlign => 6,
+            Self::EfiBootServices => 7,
+            Self::EntryAddressEfi32(_) => 8,
+            Self::EntryAddressEfi64(_) => 9,
+            Self::Relocatable { .. } => 10,
+        }
+    }
+    /// The size of the tag in bytes, without padding.
+    fn size(&self) -> u32 {
+        match self {
+            Self::InformationRequest(types) => 8 + 4 * types.len() as u32,
+            Self::Address { .. } | Self::Relocatable { .. } => 24,
+            Self::Framebuffer { .. } => 20,
+            Self::EntryAddress(_)
+            | Self::ConsoleFlags { .. }
+            | Self::EntryAddressEfi32(_)
+            | Self::EntryAddressEfi64(_) => 12,
+            Self::ModuleAlign | Self::EfiBootServices => 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Multiboot2LoadPreference {
+    None = 0,
+    Lowest = 1,
+    Highest = 2,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Multiboot2Header<'a> {
+    pub architecture: Multiboot2Architecture,
+    tags: Vec<(Multiboot2Tag<'a>, bool)>,
+}
+impl<'a> Multiboot2Header<'a> {
+    pub fn new(architecture: Multiboot2Architecture) -> Self {
+        Self {
+            architecture,
+            tags: Vec::new(),
+        }
+    }
+    /// Adds a tag the boot loader must understand to load the kernel.
+    pub fn tag(mut self, tag: Multiboot2Tag<'a>) -> Self {
+        self.tags.push((tag, false));
+        self
+    }
+    /// Adds a tag the boot loader may ignore.
+    pub fn optional_tag(mut self, tag: Multiboot2Tag<'a>) -> Self {
+        self.tags.push((tag, true));
+        self
+    }
+
+    /// The size of the whole header including the end tag.
+    pub fn length(&self) -> u32 {
+        let tags: u32 = self.tags.iter().map(|(tag, _)| align8(tag.size())).sum();
+        16 + tags + 8
+    }
+    pub fn checksum(&self) -> u32 {
+        let sum = MULTIBOOT2_MAGIC
+            .wrapping_add(self.architecture as u32)
+            .wrapping_add(self.length());
+        sum.wrapping_neg()
+    }
+}
+
+/// The real mode kernel header of the Linux x86 boot protocol, located at offset `0x1f1`
+/// of the setup code. Fields filled in by the boot loader are emitted as zero.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LinuxSetupHeader<'a> {
+    /// The size of the setup code in 512-byte sectors, not counting the boot sector.
+    pub setup_sects: u8,
+    /// The size of the protected mode code in 16-byte paragraphs.
+    pub syssize: u32,
+    /// Where the two-byte jump at offset `0x200` leads.
+    pub entry: Label<'a>,
+    pub version: u16,
+    pub loadflags: u8,
+    pub code32_start: u32,
+    pub initrd_addr_max: u32,
+    pub kernel_alignment: u32,
+    pub relocatable_kernel: bool,
+    /// The minimum alignment as a power of two.
+    pub min_alignment: u8,
+    pub xloadflags: u16,
+    pub cmdline_size: u32,
+    pub payload_offset: u32,
+    pub payload_length: u32,
+    pub pref_address: u64,
+    pub init_size: u32,
+    pub handover_offset: u32,
+    pub kernel_info_offset: u32,
+}
+impl<'a> LinuxSetupHeader<'a> {
+    pub const OFFSET: u64 = 0x1f1;
+    pub const MAGIC: u32 = 0x53726448;
+    pub const LOADED_HIGH: u8 = 1 << 0;
+    pub const CAN_USE_HEAP: u8 = 1 << 7;
+    pub const XLF_KERNEL_64: u16 = 1 << 0;
+
+    /// A header for boot protocol 2.15 loading a bzImage at 1 MiB.
+    pub fn new(entry: impl Into<Label<'a>>) -> Self {
+        Self {
+            setup_sects: 0,
+            syssize: 0,
+            entry: entry.into(),
+            version: 0x020f,
+            loadflags: Self::LOADED_HIGH,
+            code32_start: 0x100000,
+            initrd_addr_max: 0x7fffffff,
+            kernel_alignment: 0x200000,
+            relocatable_kernel: false,
+            min_alignment: 21,
+            xloadflags: 0,
+            cmdline_size: 0x7ff,
+            payload_offset: 0,
+            payload_length: 0,
+            pref_address: 0x100000,
+            init_size: 0,
+            handover_offset: 0,
+            kernel_info_offset: 0,
+        }
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits `header` 8-byte aligned at `label`, followed by its end tag.
+    pub fn emit_multiboot2_header<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        header: &Multiboot2Header<'a>,
+    ) -> io::Result<()> {
+        self.emit_balign(8)?;
+        self.emit_label(label.into())?;
+        self.directive(format_args!(".long {MULTIBOOT2_MAGIC:#x}"))?;
+        self.emit_long(header.architecture as u32)?;
+        self.emit_long(header.length())?;
+        self.directive(format_args!(".long {:#x}", header.checksum()))?;
+
+        for (tag, optional) in &header.tags {
+            self.emit_word(tag.tag_type())?;
+            self.emit_word(u16::from(*optional))?;
+            self.emit_long(tag.size())?;
+            self.emit_multiboot2_tag_fields(tag)?;
+            self.emit_balign(8)?;
+        }
+
+        self.emit_word(0u16)?;
+        self.emit_word(0u16)?;
+        self.emit_long(8u32)
+    }
+    fn emit_multiboot2_tag_fields(&mut self, tag: &Multiboot2Tag) -> io::Result<()> {
+        match tag {
+            Multiboot2Tag::InformationRequest(types) => {
+                for &tag_type in types {
+                    self.emit_long(tag_type)?;
+                }
+            }
+            &Multiboot2Tag::Address {
+                header,
+                load,
+                load_end,
+                bss_end,
+            } => {
+                for address in [header, load, load_end, bss_end] {
+                    self.emit_long(address)?;
+                }
+            }
+            &Multiboot2Tag::EntryAddress(entry)
+            | &Multiboot2Tag::EntryAddressEfi32(entry)
+            | &Multiboot2Tag::EntryAddressEfi64(entry) => self.emit_long(entry)?,
+            &Multiboot2Tag::ConsoleFlags {
+                console_required,
+                ega_text_supported,
+            } => {
+                let flags = u32::from(console_required) | u32::from(ega_text_supported) << 1;
+                self.emit_long(flags)?;
+            }
+            &Multiboot2Tag::Framebuffer {
+                width,
+                height,
+                depth,
+            } => {
+                for value in [width, height, depth] {
+                    self.emit_long(value)?;
+                }
+            }
+            Multiboot2Tag::ModuleAlign | Multiboot2Tag::EfiBootServices => (),
+            &Multiboot2Tag::Relocatable {
+                min_address,
+                max_address,
+                alignment,
+                preference,
+            } => {
+                for value in [min_address, max_address, alignment, preference as u32] {
+                    self.emit_long(value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pads the current section, which must start at the beginning of the setup code,
+    /// up to offset `0x1f1` and emits `header` there, including the `0xaa55` boot flag.
+    pub fn emit_linux_setup_header(&mut self, header: &LinuxSetupHeader) -> io::Result<()> {
+        self.org(LinuxSetupHeader::OFFSET, 0)?;
+        self.emit_byte(header.setup_sects)?;
+        self.emit_word(0u16)?; // root_flags
+        self.emit_long(header.syssize)?;
+        self.emit_word(0u16)?; // ram_size
+        self.emit_word(0u16)?; // vid_mode
+        self.emit_word(0u16)?; // root_dev
+        self.directive(".word 0xaa55")?;
+        // A short jump over the rest of the header.
+        self.directive(".byte 0xeb")?;
+        self.directive(format_args!(".byte {} - (. + 1)", header.entry))?;
+        self.directive(format_args!(".long {:#x}", LinuxSetupHeader::MAGIC))?;
+        self.emit_word(header.version)?;
+        self.emit_long(0u32)?; // realmode_swtch
+        self.emit_word(0x1000u16)?; // start_sys_seg
+        self.emit_word(0u16)?; // kernel_version
+        self.emit_byte(0u8)?; // type_of_loader
+        self.emit_byte(header.loadflags)?;
+        self.emit_word(0x8000u16)?; // setup_move_size
+        self.emit_long(header.code32_start)?;
+        self.emit_long(0u32)?; // ramdisk_image
+        self.emit_long(0u32)?; // ramdisk_size
+        self.emit_long(0u32)?; // bootsect_kludge
+        self.emit_word(0u16)?; // heap_end_ptr
+        self.emit_byte(0u8)?; // ext_loader_ver
+        self.emit_byte(0u8)?; // ext_loader_type
+        self.emit_long(0u32)?; // cmd_line_ptr
+        self.emit_long(header.initrd_addr_max)?;
+        self.emit_long(header.kernel_alignment)?;
+        self.emit_byte(u8::from(header.relocatable_kernel))?;
+        self.emit_byte(header.min_alignment)?;
+        self.emit_word(header.xloadflags)?;
+        self.emit_long(header.cmdline_size)?;
+        self.emit_long(0u32)?; // hardware_subarch
+        self.emit_quad(0u64)?; // hardware_subarch_data
+        self.emit_long(header.payload_offset)?;
+        self.emit_long(header.payload_length)?;
+        self.emit_quad(0u64)?; // setup_data
+        self.emit_quad(header.pref_address)?;
+        self.emit_long(header.init_size)?;
+        self.emit_long(header.handover_offset)?;
+        self.emit_long(header.kernel_info_offset)
+    }
+}
+
+fn align8(size: u32) -> u32 {
+    (size + 7) & !7
+}
@@ -0,0 +1,2455 @@
+use super::{
+    args::{Arg, ArgSize, ConstInt, FloatSize, FloatToInt, Memory, Scale, Signedness},
+    convention::{CallingConvention, StackFrame},
+    error::X64WriterError,
+    features::{Feature, TargetFeatures},
+    instruction::Instruction,
+    label::Label,
+    msr::Msr,
+    register::{
+        al, ax, cl, eax, ecx, rax, rsp, Masking, Register,
+        RegisterName::{self, A, D},
+        RegisterSet, RegisterSize, VectorRegister,
+    },
+    writer::{
+        BinaryOp, BinaryOpDouble, BinaryOpSingle, Condition, NonaryOp, RepPrefix, SseOp, StringOp,
+        TernaryOp, UnaryOp, UnaryOpSingle, VectorOp,
+    },
+};
+use alloc::{format, vec::Vec};
+
+/// The instruction building API shared by the streaming [`AsmWriter`](crate::writer::AsmWriter)
+/// and the retained [`FunctionBuilder`](crate::function::FunctionBuilder).
+pub trait InstructionBuilder<'a> {
+    fn build_instruction(&mut self, instruction: Instruction<'a>) -> Result<(), X64WriterError>;
+    fn emit_label(&mut self, label: impl Into<Label<'a>>) -> Result<(), X64WriterError>;
+    fn comment(&mut self, comment: impl AsRef<str>) -> Result<(), X64WriterError>;
+    fn target_features(&self) -> TargetFeatures;
+
+    fn build_mov(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Mov {
+            dst: dst.into(),
+            src: src.into(),
+        })
+    }
+    fn build_cmov(
+        &mut self,
+        c: Condition,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::CMov {
+            condition: c,
+            dst: dst.into(),
+            src: src.into(),
+        })
+    }
+    /// Sets the byte `dst` to 1 if the condition holds and to 0 otherwise.
+    fn build_setcc(&mut self, c: Condition, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        let dst = dst.into();
+        if !matches!(dst, Arg::Register(_) | Arg::Virtual(_) | Arg::Memory(_))
+            || dst.size().is_some_and(|size| size != ArgSize::Byte)
+        {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "setcc writes a byte register or memory, not {dst}"
+            )));
+        }
+        self.build_instruction(Instruction::SetCC { condition: c, dst })
+    }
+    fn build_push(&mut self, src: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Push { src: src.into() })
+    }
+    fn build_pop(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Pop { dst: dst.into() })
+    }
+
+    fn build_binary_op(
+        &mut self,
+        op: impl Into<BinaryOp>,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Binary {
+            op: op.into(),
+            dst: dst.into(),
+            src: src.into(),
+        })
+    }
+
+    fn build_add(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Add, dst, src)
+    }
+    fn build_adc(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Adc, dst, src)
+    }
+    fn build_sub(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Sub, dst, src)
+    }
+    fn build_sbb(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Sbb, dst, src)
+    }
+    fn build_imul(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::IMul, dst, src)
+    }
+    fn build_and(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::And, dst, src)
+    }
+    fn build_or(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Or, dst, src)
+    }
+    fn build_xor(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Xor, dst, src)
+    }
+    fn build_lea(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Lea, dst, src)
+    }
+    fn build_cmp(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Cmp, dst, src)
+    }
+    fn build_test(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Test, dst, src)
+    }
+    /// Shifts `dst` by `count`, which is `%cl` or an immediate below 256 whatever the size
+    /// of `dst`.
+    fn build_shift(
+        &mut self,
+        op: BinaryOpSingle,
+        dst: impl Into<Arg<'a>>,
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        if !matches!(
+            op,
+            BinaryOpSingle::Shl | BinaryOpSingle::Shr | BinaryOpSingle::Sar
+        ) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{} is not a shift",
+                op.mnemonic()
+            )));
+        }
+        let count = count.into();
+        check_shift_count(count)?;
+        self.build_binary_op(op, dst, count)
+    }
+    fn build_shl(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_shift(BinaryOpSingle::Shl, dst, count)
+    }
+    fn build_shr(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_shift(BinaryOpSingle::Shr, dst, count)
+    }
+    fn build_sar(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_shift(BinaryOpSingle::Sar, dst, count)
+    }
+    fn build_xchg(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Xchg, dst, src)
+    }
+    /// Adds `src` to `dst` and leaves the old value of `dst` in `src`.
+    fn build_xadd(&mut self, dst: impl Into<Arg<'a>>, src: Register) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Xadd, dst, src)
+    }
+    /// Replaces `dst` by `src` if it equals the accumulator of the same size, else loads it
+    /// into the accumulator. The zero flag tells whether it was replaced.
+    fn build_cmpxchg(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: Register,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Cmpxchg, dst, src)
+    }
+    /// Compares `%rdx:%rax` with the 16 bytes at `dst`, replacing them by `%rcx:%rbx` if
+    /// equal and loading them into `%rdx:%rax` otherwise. Requires CX16.
+    fn build_cmpxchg16b(&mut self, dst: Memory<'a>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOp::Cmpxchg16b, dst)
+    }
+    /// Builds `instruction` with a `lock` prefix, which makes its read-modify-write of a
+    /// memory destination atomic. Fails for instructions that cannot take one.
+    fn build_locked(&mut self, instruction: Instruction<'a>) -> Result<(), X64WriterError> {
+        let locked = instruction.locked().ok_or_else(|| {
+            X64WriterError::InvalidOperand(format!("`{instruction}` cannot take a lock prefix"))
+        })?;
+        self.build_instruction(locked)
+    }
+    /// Moves between a register and memory, reversing the byte order. Requires MOVBE.
+    fn build_movbe(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Movbe, dst, src)
+    }
+    /// Zero-extends `src` into the wider register `dst`. There is no zero extension from
+    /// 32 bits, a 32-bit `mov` clears the upper half of the register instead.
+    fn build_movzx(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let src = src.into();
+        let src_size = src
+            .size()
+            .ok_or(X64WriterError::MissingSize { mnemonic: "movzx" })?;
+        if !matches!(src_size, ArgSize::Byte | ArgSize::Word) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "movzx only extends 8 and 16-bit values, not {src}"
+            )));
+        }
+        if RegisterSize::from(src_size) >= dst.1 {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "movzx needs a destination wider than {src}, not {dst}"
+            )));
+        }
+        self.build_binary_op(BinaryOpDouble::Movzx, dst, src)
+    }
+    /// Sign-extends `src` into the wider register `dst`.
+    fn build_movsx(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let src = src.into();
+        let src_size = src
+            .size()
+            .ok_or(X64WriterError::MissingSize { mnemonic: "movsx" })?;
+        if RegisterSize::from(src_size) >= dst.1 {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "movsx needs a destination wider than {src}, not {dst}"
+            )));
+        }
+        self.build_binary_op(BinaryOpDouble::Movsx, dst, src)
+    }
+
+    /// Moves a single precision float. Between registers, the upper lanes of `dst` are kept.
+    fn build_movss(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movss, dst, src)
+    }
+    /// Moves a double precision float. Between registers, the upper lane of `dst` is kept.
+    fn build_movsd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movsd, dst, src)
+    }
+    /// Moves 16 bytes, to or from memory aligned to 16 bytes.
+    fn build_movaps(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movaps, dst, src)
+    }
+    /// Moves 16 bytes, to or from memory of any alignment.
+    fn build_movups(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movups, dst, src)
+    }
+    fn build_movapd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movapd, dst, src)
+    }
+    fn build_movupd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movupd, dst, src)
+    }
+    /// Moves 32 bits between a general purpose and an `%xmm` register or memory, zeroing the upper lanes of an `%xmm` destination.
+    fn build_movd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movd, dst, src)
+    }
+    /// Moves 64 bits between registers or memory, zeroing the upper lane of an `%xmm` destination.
+    fn build_movq(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Movq, dst, src)
+    }
+    fn build_addss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Addss, dst, src)
+    }
+    fn build_addsd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Addsd, dst, src)
+    }
+    fn build_addps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Addps, dst, src)
+    }
+    fn build_addpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Addpd, dst, src)
+    }
+    fn build_subss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Subss, dst, src)
+    }
+    fn build_subsd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Subsd, dst, src)
+    }
+    fn build_subps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Subps, dst, src)
+    }
+    fn build_subpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Subpd, dst, src)
+    }
+    fn build_mulss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Mulss, dst, src)
+    }
+    fn build_mulsd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Mulsd, dst, src)
+    }
+    fn build_mulps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Mulps, dst, src)
+    }
+    fn build_mulpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Mulpd, dst, src)
+    }
+    fn build_divss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Divss, dst, src)
+    }
+    fn build_divsd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Divsd, dst, src)
+    }
+    fn build_divps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Divps, dst, src)
+    }
+    fn build_divpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Divpd, dst, src)
+    }
+    fn build_sqrtss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Sqrtss, dst, src)
+    }
+    fn build_sqrtsd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Sqrtsd, dst, src)
+    }
+    fn build_andps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Andps, dst, src)
+    }
+    fn build_andpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Andpd, dst, src)
+    }
+    /// Bitwise exclusive or, which clears a register when both operands are the same.
+    fn build_xorps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Xorps, dst, src)
+    }
+    fn build_xorpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Xorpd, dst, src)
+    }
+    /// Compares `lhs` with `rhs` and sets the flags like an unsigned comparison, with the parity flag marking unordered operands.
+    fn build_ucomiss(
+        &mut self,
+        lhs: VectorRegister,
+        rhs: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Ucomiss, lhs, rhs)
+    }
+    /// Like `build_ucomiss` for double precision floats.
+    fn build_ucomisd(
+        &mut self,
+        lhs: VectorRegister,
+        rhs: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Ucomisd, lhs, rhs)
+    }
+    /// Converts a signed integer, rounding according to MXCSR.
+    fn build_cvtsi2ss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let src = src.into();
+        let size = src.size().ok_or(X64WriterError::MissingSize {
+            mnemonic: "cvtsi2ss",
+        })?;
+        if !matches!(size, ArgSize::Double | ArgSize::Quad) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cvtsi2ss converts 32 or 64-bit integers, not {src}"
+            )));
+        }
+        self.build_binary_op(SseOp::Cvtsi2ss, dst, src)
+    }
+    fn build_cvtsi2sd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let src = src.into();
+        let size = src.size().ok_or(X64WriterError::MissingSize {
+            mnemonic: "cvtsi2sd",
+        })?;
+        if !matches!(size, ArgSize::Double | ArgSize::Quad) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cvtsi2sd converts 32 or 64-bit integers, not {src}"
+            )));
+        }
+        self.build_binary_op(SseOp::Cvtsi2sd, dst, src)
+    }
+    /// Converts to a signed integer, truncating towards zero.
+    fn build_cvttss2si(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        if !matches!(dst.1, RegisterSize::Double | RegisterSize::Quad) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cvttss2si converts to 32 or 64-bit integers, not {dst}"
+            )));
+        }
+        self.build_binary_op(SseOp::Cvttss2si, dst, src)
+    }
+    fn build_cvttsd2si(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        if !matches!(dst.1, RegisterSize::Double | RegisterSize::Quad) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cvttsd2si converts to 32 or 64-bit integers, not {dst}"
+            )));
+        }
+        self.build_binary_op(SseOp::Cvttsd2si, dst, src)
+    }
+    fn build_cvtss2sd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Cvtss2sd, dst, src)
+    }
+    fn build_cvtsd2ss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(SseOp::Cvtsd2ss, dst, src)
+    }
+    /// Truncates the float of `size` in `src` to the 32 or 64-bit signed integer `dst`,
+    /// handling NaN and values out of range as `conversion` says. Uses `scratch` to
+    /// examine the float, and jumps to `done`, a label of its own, past the handling once
+    /// the conversion is known to be in range.
+    fn emit_f2i(
+        &mut self,
+        dst: Register,
+        src: VectorRegister,
+        size: FloatSize,
+        conversion: FloatToInt<'a>,
+        scratch: RegisterName,
+        done: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        if !matches!(dst.1, RegisterSize::Double | RegisterSize::Quad) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cannot convert a float to the 8 or 16-bit {dst}"
+            )));
+        }
+        if scratch == dst.0 {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "the scratch register has to differ from {dst}"
+            )));
+        }
+        let convert = |builder: &mut Self, dst: Register| match size {
+            FloatSize::Single => builder.build_cvttss2si(dst, src),
+            FloatSize::Double => builder.build_cvttsd2si(dst, src),
+        };
+        // The bits of the float, in a register as wide as it.
+        let float_bits = |builder: &mut Self| -> Result<Register, X64WriterError> {
+            match size {
+                FloatSize::Single => {
+                    builder.build_movd(scratch.double(), src)?;
+                    Ok(scratch.double())
+                }
+                FloatSize::Double => {
+                    builder.build_movq(scratch.quad(), src)?;
+                    Ok(scratch.quad())
+                }
+            }
+        };
+        let done = done.into();
+        let (quad, narrow) = (dst.0.quad(), dst.0.double());
+        let scratch = scratch.with_size(dst.1);
+
+        match conversion {
+            FloatToInt::Saturating => {
+                // Out of range and NaN convert to the most negative integer, as does itself.
+                convert(self, dst)?;
+                self.build_cmp(dst, sized_immediate(dst.1, 1))?;
+                self.build_cjmp(Condition::NoOverflow, done)?;
+                // Flip the most negative to the most positive integer for a positive float.
+                let bits = float_bits(self)?;
+                self.build_sar(bits, ConstInt::U8(size.bits() as u8 - 1))?;
+                if size == FloatSize::Single && dst.1 == RegisterSize::Quad {
+                    self.build_movsx(scratch, bits)?;
+                }
+                self.build_not(scratch)?;
+                self.build_xor(dst, scratch)?;
+                // NaN is unordered even with itself, which sets the carry flag.
+                self.build_binary_op(ucomis(size), src, src)?;
+                self.build_mov(scratch, sized_immediate(dst.1, 0))?;
+                self.build_cmov(Condition::Below, dst, scratch)?;
+                self.emit_label(done)?;
+            }
+            FloatToInt::Trapping(trap) if dst.1 == RegisterSize::Double => {
+                // The 64-bit conversion is in range if it sign-extends from 32 bits.
+                let wide = scratch.0.quad();
+                convert(self, wide)?;
+                self.build_movsx(quad, wide.0.double())?;
+                self.build_cmp(quad, wide)?;
+                self.build_cjmp(Condition::NotEqual, trap)?;
+                self.build_mov(narrow, wide.0.double())?;
+            }
+            FloatToInt::Trapping(trap) => {
+                convert(self, dst)?;
+                self.build_cmp(dst, ConstInt::I64(1))?;
+                self.build_cjmp(Condition::NoOverflow, done)?;
+                // Only -2^63 itself converts to the most negative integer in range.
+                let bits = float_bits(self)?;
+                match size {
+                    FloatSize::Single => self.build_cmp(bits, ConstInt::U32(0xdf00_0000))?,
+                    FloatSize::Double => {
+                        // 0xc3e0_0000_0000_0000, byte swapped to fit an immediate.
+                        self.build_bswap(bits)?;
+                        self.build_cmp(bits, ConstInt::I64(0xe0c3))?;
+                    }
+                }
+                self.build_cjmp(Condition::NotEqual, trap)?;
+                self.emit_label(done)?;
+            }
+            FloatToInt::Wrapping if dst.1 == RegisterSize::Double => {
+                convert(self, quad)?;
+                // Clears the upper half, as a 32-bit result would.
+                self.build_mov(narrow, narrow)?;
+            }
+            FloatToInt::Wrapping => {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "wrapping conversions take a 32-bit destination, not {dst}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn build_vector_op(
+        &mut self,
+        op: VectorOp,
+        dst: impl Into<Arg<'a>>,
+        src1: Option<Arg<'a>>,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_masked_vector_op(op, dst, None, src1, src2, false)
+    }
+    /// Builds `op` with the destination masked by `masking` and, if `broadcast`, the
+    /// element at the memory operand `src2` repeated in every lane. Either needs AVX-512.
+    fn build_masked_vector_op(
+        &mut self,
+        op: VectorOp,
+        dst: impl Into<Arg<'a>>,
+        masking: Option<Masking>,
+        src1: Option<Arg<'a>>,
+        src2: impl Into<Arg<'a>>,
+        broadcast: bool,
+    ) -> Result<(), X64WriterError> {
+        let mnemonic = op.mnemonic();
+        if src1.is_none() != op.has_one_source() {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "wrong number of sources for {mnemonic}"
+            )));
+        }
+        let src2 = src2.into();
+        if broadcast && !src2.is_memory() {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{mnemonic} can only broadcast from memory"
+            )));
+        }
+        self.build_instruction(Instruction::Vector {
+            op,
+            dst: dst.into(),
+            src1,
+            src2,
+            masking,
+            broadcast,
+        })
+    }
+    fn build_vaddps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vaddps, dst, Some(src1.into()), src2)
+    }
+    fn build_vaddpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vaddpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vsubps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vsubps, dst, Some(src1.into()), src2)
+    }
+    fn build_vsubpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vsubpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vmulps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmulps, dst, Some(src1.into()), src2)
+    }
+    fn build_vmulpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmulpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vdivps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vdivps, dst, Some(src1.into()), src2)
+    }
+    fn build_vdivpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vdivpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vminps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vminps, dst, Some(src1.into()), src2)
+    }
+    fn build_vminpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vminpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vmaxps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmaxps, dst, Some(src1.into()), src2)
+    }
+    fn build_vmaxpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmaxpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vandps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vandps, dst, Some(src1.into()), src2)
+    }
+    fn build_vandpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vandpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vxorps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vxorps, dst, Some(src1.into()), src2)
+    }
+    fn build_vxorpd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vxorpd, dst, Some(src1.into()), src2)
+    }
+    fn build_vaddss(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vaddss, dst, Some(src1.into()), src2)
+    }
+    fn build_vaddsd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vaddsd, dst, Some(src1.into()), src2)
+    }
+    fn build_vsubss(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vsubss, dst, Some(src1.into()), src2)
+    }
+    fn build_vsubsd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vsubsd, dst, Some(src1.into()), src2)
+    }
+    fn build_vmulss(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmulss, dst, Some(src1.into()), src2)
+    }
+    fn build_vmulsd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmulsd, dst, Some(src1.into()), src2)
+    }
+    fn build_vdivss(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vdivss, dst, Some(src1.into()), src2)
+    }
+    fn build_vdivsd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vdivsd, dst, Some(src1.into()), src2)
+    }
+    /// Computes `dst + src1 * src2` per element, rounding only once.
+    fn build_vfmadd231ps(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vfmadd231ps, dst, Some(src1.into()), src2)
+    }
+    fn build_vfmadd231pd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vfmadd231pd, dst, Some(src1.into()), src2)
+    }
+    fn build_vfmadd231ss(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vfmadd231ss, dst, Some(src1.into()), src2)
+    }
+    fn build_vfmadd231sd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vfmadd231sd, dst, Some(src1.into()), src2)
+    }
+    fn build_vpaddd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpaddd, dst, Some(src1.into()), src2)
+    }
+    fn build_vpaddq(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpaddq, dst, Some(src1.into()), src2)
+    }
+    fn build_vpsubd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpsubd, dst, Some(src1.into()), src2)
+    }
+    fn build_vpsubq(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpsubq, dst, Some(src1.into()), src2)
+    }
+    fn build_vpmulld(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpmulld, dst, Some(src1.into()), src2)
+    }
+    fn build_vpand(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpand, dst, Some(src1.into()), src2)
+    }
+    /// Bitwise and of 32-bit elements, which AVX-512 can mask.
+    fn build_vpandd(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpandd, dst, Some(src1.into()), src2)
+    }
+    fn build_vpandq(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpandq, dst, Some(src1.into()), src2)
+    }
+    /// Bitwise exclusive or, which clears `dst` when both sources are the same.
+    fn build_vpxor(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpxor, dst, Some(src1.into()), src2)
+    }
+    /// Bitwise exclusive or of 32-bit elements, which AVX-512 can mask.
+    fn build_vpxord(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpxord, dst, Some(src1.into()), src2)
+    }
+    fn build_vpxorq(
+        &mut self,
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vpxorq, dst, Some(src1.into()), src2)
+    }
+    fn build_vsqrtps(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vsqrtps, dst, None, src)
+    }
+    fn build_vsqrtpd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vsqrtpd, dst, None, src)
+    }
+    /// Moves a whole vector, to or from memory aligned to its size.
+    fn build_vmovaps(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovaps, dst, None, src)
+    }
+    fn build_vmovapd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovapd, dst, None, src)
+    }
+    fn build_vmovups(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovups, dst, None, src)
+    }
+    fn build_vmovupd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovupd, dst, None, src)
+    }
+    /// Moves integers to or from memory of any alignment.
+    fn build_vmovdqu(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovdqu, dst, None, src)
+    }
+    /// Like `build_vmovdqu`, with masking per 32-bit element.
+    fn build_vmovdqu32(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovdqu32, dst, None, src)
+    }
+    fn build_vmovdqu64(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vmovdqu64, dst, None, src)
+    }
+    /// Repeats the lowest float of `src` in every lane of `dst`.
+    fn build_vbroadcastss(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vbroadcastss, dst, None, src)
+    }
+    fn build_vbroadcastsd(
+        &mut self,
+        dst: VectorRegister,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Vbroadcastsd, dst, None, src)
+    }
+    /// Moves a 16-bit mask between mask registers, 32-bit registers or memory.
+    fn build_kmovw(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Kmovw, dst, None, src)
+    }
+    /// Moves a 64-bit mask between mask registers, 64-bit registers or memory.
+    /// Requires AVX-512BW.
+    fn build_kmovq(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_vector_op(VectorOp::Kmovq, dst, None, src)
+    }
+
+    fn build_unary_op(
+        &mut self,
+        op: impl Into<UnaryOp>,
+        dst: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Unary {
+            op: op.into(),
+            dst: dst.into(),
+        })
+    }
+
+    fn build_inc(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Inc, dst)
+    }
+    fn build_dec(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Dec, dst)
+    }
+    fn build_neg(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Neg, dst)
+    }
+    fn build_not(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Not, dst)
+    }
+    fn build_mul(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Mul, dst)
+    }
+    fn build_unary_imul(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::IMul, dst)
+    }
+    fn build_div(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Div, dst)
+    }
+    fn build_idiv(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::IDiv, dst)
+    }
+    /// Counts the set bits of `src`. Requires POPCNT.
+    fn build_popcnt(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Popcnt, dst, src)
+    }
+    /// Counts the leading zero bits of `src`, giving its width if it is zero. Requires LZCNT.
+    fn build_lzcnt(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Lzcnt, dst, src)
+    }
+    /// Counts the trailing zero bits of `src`, giving its width if it is zero. Requires BMI1.
+    fn build_tzcnt(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Tzcnt, dst, src)
+    }
+    /// Finds the index of the lowest set bit of `src`. If `src` is zero, sets the zero flag
+    /// and leaves `dst` as it is.
+    fn build_bsf(&mut self, dst: Register, src: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Bsf, dst, src)
+    }
+    /// Finds the index of the highest set bit of `src`. If `src` is zero, sets the zero flag
+    /// and leaves `dst` as it is.
+    fn build_bsr(&mut self, dst: Register, src: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_binary_op(BinaryOpSingle::Bsr, dst, src)
+    }
+
+    /// Copies bit `offset` of `dst` into the carry flag, then leaves it as `op` says: `bt`
+    /// keeps it, `bts` sets it, `btr` clears it and `btc` flips it. Immediate offsets take a
+    /// byte whatever the size of `dst`.
+    fn build_bit_test(
+        &mut self,
+        op: BinaryOpSingle,
+        dst: impl Into<Arg<'a>>,
+        offset: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        use BinaryOpSingle::*;
+        if !matches!(op, Bt | Bts | Btr | Btc) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{} is not a bit test",
+                op.mnemonic()
+            )));
+        }
+        let offset = offset.into();
+        if matches!(offset, Arg::Int(c) if !(0..256).contains(&c.value())) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "immediate bit offsets have to fit a byte, not {offset}"
+            )));
+        }
+        self.build_binary_op(op, dst, offset)
+    }
+    fn build_bt(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        offset: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_bit_test(BinaryOpSingle::Bt, dst, offset)
+    }
+    fn build_bts(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        offset: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_bit_test(BinaryOpSingle::Bts, dst, offset)
+    }
+    fn build_btr(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        offset: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_bit_test(BinaryOpSingle::Btr, dst, offset)
+    }
+    fn build_btc(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        offset: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_bit_test(BinaryOpSingle::Btc, dst, offset)
+    }
+    /// Reverses the byte order of a 32 or 64-bit register.
+    fn build_bswap(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_unary_op(UnaryOpSingle::Bswap, dst)
+    }
+
+    fn build_ternary_op(
+        &mut self,
+        op: TernaryOp,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+        src2: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Ternary {
+            op,
+            dst: dst.into(),
+            src: src.into(),
+            src2: src2.into(),
+        })
+    }
+
+    /// Builds `dst = lhs op rhs` for a later
+    /// [`Function::legalize_two_address`](crate::function::Function::legalize_two_address).
+    fn build_three_address(
+        &mut self,
+        op: BinaryOpSingle,
+        dst: impl Into<Arg<'a>>,
+        lhs: impl Into<Arg<'a>>,
+        rhs: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        use BinaryOpSingle::*;
+        if !matches!(
+            op,
+            Add | Adc | Sub | Sbb | IMul | And | Or | Xor | Shl | Shr | Sar
+        ) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{} has no three-address form",
+                op.mnemonic()
+            )));
+        }
+        self.build_instruction(Instruction::ThreeAddress {
+            op,
+            dst: dst.into(),
+            lhs: lhs.into(),
+            rhs: rhs.into(),
+        })
+    }
+
+    /// Extracts the bit field of `src` described by `control`, whose low byte is the
+    /// offset and second byte the width. Requires BMI1.
+    fn build_bextr(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+        control: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_ternary_op(TernaryOp::Bextr, dst, src, control)
+    }
+    /// Shifts `dst` left by `count`, filling in the upper bits of `src`.
+    fn build_shld(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let count = count.into();
+        check_shift_count(count)?;
+        self.build_ternary_op(TernaryOp::Shld, dst, src, count)
+    }
+    /// Shifts `dst` right by `count`, filling in the lower bits of `src`.
+    fn build_shrd(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        src: impl Into<Arg<'a>>,
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let count = count.into();
+        check_shift_count(count)?;
+        self.build_ternary_op(TernaryOp::Shrd, dst, src, count)
+    }
+    /// Shifts `src` left by `count` into `dst` without touching the flags. The count is a
+    /// register of the same size, of which only the low 5 or 6 bits are used. Requires BMI2.
+    fn build_shlx(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+        count: Register,
+    ) -> Result<(), X64WriterError> {
+        self.build_ternary_op(TernaryOp::Shlx, dst, src, count)
+    }
+    /// Like [`build_shlx`](Self::build_shlx), shifting right and filling in zeros.
+    fn build_shrx(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+        count: Register,
+    ) -> Result<(), X64WriterError> {
+        self.build_ternary_op(TernaryOp::Shrx, dst, src, count)
+    }
+    /// Like [`build_shlx`](Self::build_shlx), shifting right and filling in the sign bit.
+    fn build_sarx(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+        count: Register,
+    ) -> Result<(), X64WriterError> {
+        self.build_ternary_op(TernaryOp::Sarx, dst, src, count)
+    }
+
+    fn build_call(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Call { target: dst.into() })
+    }
+    /// Calls `dst` and labels the return address, for the
+    /// [`Safepoint`](crate::stackmap::Safepoint) describing the call.
+    fn build_safepoint_call(
+        &mut self,
+        dst: impl Into<Arg<'a>>,
+        return_address: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        self.build_call(dst)?;
+        self.emit_label(return_address)?;
+        Ok(())
+    }
+    fn build_jmp(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Jmp { target: dst.into() })
+    }
+    fn build_cjmp(&mut self, c: Condition, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::CJmp {
+            condition: c,
+            target: dst.into(),
+        })
+    }
+
+    fn build_nonary_op(&mut self, op: NonaryOp) -> Result<(), X64WriterError> {
+        self.build_instruction(Instruction::Nonary { op })
+    }
+    fn build_ret(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Ret)
+    }
+    fn build_cpuid(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cpuid)
+    }
+    fn build_xgetbv(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Xgetbv)
+    }
+    fn build_rdmsr(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Rdmsr)
+    }
+    fn build_wrmsr(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Wrmsr)
+    }
+    fn build_swapgs(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Swapgs)
+    }
+    fn build_iretq(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Iretq)
+    }
+    fn build_cld(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cld)
+    }
+    fn build_sysretq(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Sysretq)
+    }
+    /// Sign-extends `%al` into `%ax`.
+    fn build_cbtw(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cbtw)
+    }
+    /// Sign-extends `%ax` into `%eax`.
+    fn build_cwtl(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cwtl)
+    }
+    /// Sign-extends `%eax` into `%rax`.
+    fn build_cltq(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cltq)
+    }
+    /// Sign-extends `%ax` into `%dx:%ax`.
+    fn build_cwtd(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cwtd)
+    }
+    /// Sign-extends `%eax` into `%edx:%eax`.
+    fn build_cltd(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cltd)
+    }
+    /// Sign-extends `%rax` into `%rdx:%rax`.
+    fn build_cqto(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Cqto)
+    }
+    /// Sign-extends the accumulator of `size` into the dividend of a signed division by an
+    /// operand of that size, `%al` into `%ax` or `%ax`, `%eax` or `%rax` into the `%dx`
+    /// register of the same size.
+    fn build_sign_extend_acc(&mut self, size: ArgSize) -> Result<(), X64WriterError> {
+        match size {
+            ArgSize::Byte => self.build_cbtw(),
+            ArgSize::Word => self.build_cwtd(),
+            ArgSize::Double => self.build_cltd(),
+            ArgSize::Quad => self.build_cqto(),
+        }
+    }
+    /// Orders all memory accesses before the fence before those after it.
+    fn build_mfence(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Mfence)
+    }
+    /// Keeps later instructions from starting before earlier ones have completed, which
+    /// also orders loads.
+    fn build_lfence(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Lfence)
+    }
+    /// Orders the stores before the fence before those after it.
+    fn build_sfence(&mut self) -> Result<(), X64WriterError> {
+        self.build_nonary_op(NonaryOp::Sfence)
+    }
+
+    /// Emits a string instruction on elements of `size`, repeated by `rep` if given.
+    /// Fails if the operation cannot take the prefix.
+    fn build_string_op(
+        &mut self,
+        op: StringOp,
+        size: ArgSize,
+        rep: Option<RepPrefix>,
+    ) -> Result<(), X64WriterError> {
+        if let Some(rep) = rep {
+            op.check_prefix(rep)?;
+        }
+        self.build_instruction(Instruction::String { op, size, rep })
+    }
+    /// Copies `%rcx` elements of `size` from `(%rsi)` to `(%rdi)`.
+    fn build_rep_movs(&mut self, size: ArgSize) -> Result<(), X64WriterError> {
+        self.build_string_op(StringOp::Movs, size, Some(RepPrefix::Rep))
+    }
+    /// Fills `%rcx` elements of `size` at `(%rdi)` with the accumulator of that size.
+    fn build_rep_stos(&mut self, size: ArgSize) -> Result<(), X64WriterError> {
+        self.build_string_op(StringOp::Stos, size, Some(RepPrefix::Rep))
+    }
+    /// Compares up to `%rcx` elements of `size` at `(%rsi)` and `(%rdi)`, stopping after the
+    /// first difference.
+    fn build_repe_cmps(&mut self, size: ArgSize) -> Result<(), X64WriterError> {
+        self.build_string_op(StringOp::Cmps, size, Some(RepPrefix::Repe))
+    }
+    /// Searches up to `%rcx` elements of `size` at `(%rdi)` for the accumulator of that size,
+    /// stopping after the first match.
+    fn build_repne_scas(&mut self, size: ArgSize) -> Result<(), X64WriterError> {
+        self.build_string_op(StringOp::Scas, size, Some(RepPrefix::Repne))
+    }
+
+    /// Zero or sign-extends `src` into `dst` with the shortest instruction for the sizes,
+    /// or moves it if the sizes match.
+    fn emit_extend(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+        signedness: Signedness,
+    ) -> Result<(), X64WriterError> {
+        let src = src.into();
+        let src_size = src
+            .size()
+            .ok_or(X64WriterError::MissingSize { mnemonic: "movzx" })?;
+        let src_size = RegisterSize::from(src_size);
+        if src_size > dst.1 {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cannot extend {src} into the narrower {dst}"
+            )));
+        }
+
+        if src_size == dst.1 {
+            return match src {
+                Arg::Register(src) if src == dst => Ok(()),
+                src => self.build_mov(dst, src),
+            };
+        }
+        match signedness {
+            // Writing a 32-bit register clears the upper half.
+            Signedness::Unsigned if src_size == RegisterSize::Double => {
+                self.build_mov(dst.0.double(), src)
+            }
+            Signedness::Unsigned if dst.1 == RegisterSize::Quad => {
+                self.build_movzx(dst.0.double(), src)
+            }
+            Signedness::Unsigned => self.build_movzx(dst, src),
+            Signedness::Signed if src == Arg::Register(al()) && dst == ax() => self.build_cbtw(),
+            Signedness::Signed if src == Arg::Register(ax()) && dst == eax() => self.build_cwtl(),
+            Signedness::Signed if src == Arg::Register(eax()) && dst == rax() => self.build_cltq(),
+            Signedness::Signed => self.build_movsx(dst, src),
+        }
+    }
+
+    /// Exchanges the values of `a` and `b`, at least one of which must be a register.
+    /// With a memory operand and a `scratch` register, three moves through `scratch`
+    /// avoid the implicit bus lock of `xchg` with memory.
+    fn build_swap(
+        &mut self,
+        a: impl Into<Arg<'a>>,
+        b: impl Into<Arg<'a>>,
+        scratch: Option<RegisterName>,
+    ) -> Result<(), X64WriterError> {
+        let (a, b) = (a.into(), b.into());
+        let (register, memory) = match (a, b) {
+            (Arg::Register(a), Arg::Register(b)) if a == b => return Ok(()),
+            (Arg::Register(_), Arg::Register(_)) => return self.build_xchg(a, b),
+            (Arg::Register(register), memory) | (memory, Arg::Register(register)) => {
+                (register, memory)
+            }
+            _ => {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "cannot swap {a} and {b} without a register"
+                )));
+            }
+        };
+
+        match scratch {
+            Some(scratch) => {
+                let scratch = scratch.with_size(register.1);
+                self.build_mov(scratch, memory)?;
+                self.build_mov(memory, register)?;
+                self.build_mov(register, scratch)
+            }
+            None => self.build_xchg(memory, register),
+        }
+    }
+
+    /// Loads the big-endian integer of `size` at `mem` into `dst`, with `movbe` if the target
+    /// has it and otherwise a `mov` and `bswap`. Without MOVBE, 16-bit values are swapped
+    /// in the 32-bit register and end up zero-extended.
+    fn emit_load_be(
+        &mut self,
+        dst: RegisterName,
+        mem: Memory<'a>,
+        size: ArgSize,
+    ) -> Result<(), X64WriterError> {
+        let mem = mem.size(size);
+        let register = dst.with_size(size.into());
+        match size {
+            ArgSize::Byte => self.build_mov(register, mem),
+            _ if self.target_features().contains(Feature::Movbe) => self.build_movbe(register, mem),
+            ArgSize::Word => {
+                let double = dst.double();
+                self.build_movzx(double, mem)?;
+                self.build_bswap(double)?;
+                self.build_shr(double, ConstInt::I32(16))
+            }
+            ArgSize::Double | ArgSize::Quad => {
+                self.build_mov(register, mem)?;
+                self.build_bswap(register)
+            }
+        }
+    }
+
+    /// Counts the set bits of the 32 or 64-bit register `src` into `dst`, with `popcnt` if
+    /// the target has it. Otherwise sums up bits in parallel, clobbering the first `scratch`
+    /// register and for 64 bits also the second, which holds the masks.
+    fn emit_popcount(
+        &mut self,
+        dst: Register,
+        src: Register,
+        scratch: (RegisterName, RegisterName),
+    ) -> Result<(), X64WriterError> {
+        check_same_size(dst, src)?;
+        if self.target_features().contains(Feature::Popcnt) {
+            return self.build_popcnt(dst, src);
+        }
+        let quad = match dst.1 {
+            RegisterSize::Double => false,
+            RegisterSize::Quad => true,
+            _ => {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "{dst} is not a 32 or 64-bit register"
+                )));
+            }
+        };
+        let (temp, masks) = (scratch.0.with_size(dst.1), scratch.1.quad());
+        if temp.0 == dst.0 || quad && (masks.0 == dst.0 || masks.0 == temp.0) {
+            return Err(X64WriterError::InvalidOperand(
+                "scratch registers overlap".into(),
+            ));
+        }
+        // 64-bit masks do not fit into immediates, so they go through a register.
+        let mask = |builder: &mut Self, repeated: u8| -> Result<Arg<'a>, X64WriterError> {
+            let value = u64::from_ne_bytes([repeated; 8]);
+            if !quad {
+                return Ok(ConstInt::I32(value as i32).into());
+            }
+            builder.build_mov(masks, ConstInt::U64(value))?;
+            Ok(masks.into())
+        };
+
+        move_unless_same(self, dst, src.into())?;
+        // Every 2-bit group holds the number of its set bits.
+        self.build_mov(temp, dst)?;
+        self.build_shr(temp, sized_immediate(dst.1, 1))?;
+        let m = mask(self, 0x55)?;
+        self.build_and(temp, m)?;
+        self.build_sub(dst, temp)?;
+        // Every 4-bit group.
+        self.build_mov(temp, dst)?;
+        self.build_shr(temp, sized_immediate(dst.1, 2))?;
+        let m = mask(self, 0x33)?;
+        self.build_and(temp, m)?;
+        self.build_and(dst, m)?;
+        self.build_add(dst, temp)?;
+        // Every byte.
+        self.build_mov(temp, dst)?;
+        self.build_shr(temp, sized_immediate(dst.1, 4))?;
+        self.build_add(dst, temp)?;
+        let m = mask(self, 0x0f)?;
+        self.build_and(dst, m)?;
+        // The top byte of the product is the sum of all bytes.
+        let m = mask(self, 0x01)?;
+        self.build_imul(dst, m)?;
+        let bits = if quad { 64 } else { 32 };
+        self.build_shr(dst, sized_immediate(dst.1, bits - 8))
+    }
+
+    /// Jumps to `trap` unless `size` bytes at `index` fit below the byte count `limit`, and
+    /// returns the operand accessing them at `base` plus `index`. Both registers are 64-bit,
+    /// and `index` is treated as unsigned and must leave room for the access size.
+    fn emit_bounds_checked_access(
+        &mut self,
+        base: Register,
+        index: Register,
+        limit: impl Into<Arg<'a>>,
+        trap: impl Into<Label<'a>>,
+        size: ArgSize,
+    ) -> Result<Memory<'a>, X64WriterError> {
+        if base.1 != RegisterSize::Quad || index.1 != RegisterSize::Quad {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "addresses need 64-bit registers, not {base} and {index}"
+            )));
+        }
+        // Compare the index of the last byte, with lea leaving the flags alone.
+        let last = i64::from(size.bytes() - 1);
+        if last != 0 {
+            self.build_lea(index, Memory::sib().base(index).offset(last))?;
+        }
+        self.build_cmp(index, limit)?;
+        if last != 0 {
+            self.build_lea(index, Memory::sib().base(index).offset(-last))?;
+        }
+        self.build_cjmp(Condition::AboveEqual, trap.into())?;
+
+        let access = Memory::sib().base(base).index(index, Scale::One);
+        Ok(access.size(size))
+    }
+
+    /// Copies the 64-bit `src` into `dst` of every `(dst, src)` pair as if all at once,
+    /// breaking cycles with `xchg`. No register may be the destination of two moves.
+    fn emit_parallel_move(
+        &mut self,
+        moves: &[(RegisterName, RegisterName)],
+    ) -> Result<(), X64WriterError> {
+        let copies: Vec<_> = moves
+            .iter()
+            .map(|&(dst, src)| (dst.quad().into(), src.quad().into()))
+            .collect();
+        self.emit_parallel_copy(&copies)
+    }
+    /// Copies `src` into `dst` of every `(dst, src)` pair as if all at once, breaking cycles
+    /// with `xchg`. Destinations are registers or virtual registers, each of which may
+    /// only be the destination of one copy.
+    fn emit_parallel_copy(&mut self, copies: &[(Arg<'a>, Arg<'a>)]) -> Result<(), X64WriterError> {
+        let mut pending: Vec<_> = copies
+            .iter()
+            .copied()
+            .filter(|&(dst, src)| !same_register(dst, src))
+            .collect();
+        for (i, &(dst, _)) in pending.iter().enumerate() {
+            if !dst.is_register() && !dst.is_virtual() {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "{dst} cannot be the destination of a parallel copy"
+                )));
+            }
+            if pending[i + 1..]
+                .iter()
+                .any(|&(other, _)| same_register(other, dst))
+            {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "{dst} is the destination of two copies"
+                )));
+            }
+        }
+
+        while !pending.is_empty() {
+            // A destination no other copy still reads from can be overwritten right away.
+            let free = pending
+                .iter()
+                .position(|&(dst, _)| pending.iter().all(|&(_, src)| !same_register(src, dst)));
+            if let Some(i) = free {
+                let (dst, src) = pending.remove(i);
+                self.build_mov(dst, src)?;
+                continue;
+            }
+
+            // Only cycles are left. Swapping puts one value in place, and the displaced one
+            // is now read from where it was swapped to.
+            let (dst, src) = pending.remove(0);
+            self.build_xchg(dst, src)?;
+            for (_, from) in &mut pending {
+                if same_register(*from, dst) {
+                    *from = with_register_of(*from, src);
+                }
+            }
+            pending.retain(|&(dst, src)| !same_register(dst, src));
+        }
+        Ok(())
+    }
+
+    /// Pushes the `saved` registers and reserves `locals` bytes plus the shadow space of
+    /// calls made with `convention`, keeping the stack aligned for them. Assumes the stack
+    /// was aligned the same way at the call to this function.
+    fn emit_prologue(
+        &mut self,
+        convention: &CallingConvention,
+        saved: RegisterSet,
+        locals: u32,
+    ) -> Result<StackFrame, X64WriterError> {
+        let frame = StackFrame::new(convention, saved, locals);
+        for name in &frame.saved {
+            self.build_push(name.quad())?;
+        }
+        if frame.adjustment != 0 {
+            self.build_sub(rsp(), i64::from(frame.adjustment))?;
+        }
+        Ok(frame)
+    }
+    /// Releases the stack of `frame`, restores the saved registers and returns.
+    fn emit_epilogue(&mut self, frame: &StackFrame) -> Result<(), X64WriterError> {
+        if frame.adjustment != 0 {
+            self.build_add(rsp(), i64::from(frame.adjustment))?;
+        }
+        for name in frame.saved.iter().rev() {
+            self.build_pop(name.quad())?;
+        }
+        self.build_ret()
+    }
+    /// Calls `target` with the values of the `arguments` registers moved into the argument
+    /// registers of `convention`. The stack must already be set up for the call.
+    fn emit_call_with(
+        &mut self,
+        convention: &CallingConvention,
+        target: impl Into<Arg<'a>>,
+        arguments: &[RegisterName],
+    ) -> Result<(), X64WriterError> {
+        if arguments.len() > convention.arguments.len() {
+            return Err(X64WriterError::InvalidOperand(
+                "only register arguments are supported".into(),
+            ));
+        }
+        let moves: Vec<_> = convention
+            .arguments
+            .iter()
+            .copied()
+            .zip(arguments.iter().copied())
+            .collect();
+        self.emit_parallel_move(&moves)?;
+        self.build_call(target)
+    }
+
+    /// Compares `arg` against zero, with `test` if it is a register, virtual or not, and
+    /// `cmp` otherwise.
+    fn emit_cmp_zero(&mut self, arg: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        let arg = arg.into();
+        if arg.is_register() || arg.is_virtual() {
+            return self.build_test(arg, arg);
+        }
+        let size = arg
+            .size()
+            .ok_or(X64WriterError::MissingSize { mnemonic: "cmp" })?;
+        let zero = match size {
+            ArgSize::Byte => ConstInt::U8(0),
+            ArgSize::Word => {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "cannot compare {arg} against zero without a 16-bit immediate"
+                )))
+            }
+            ArgSize::Double => ConstInt::I32(0),
+            ArgSize::Quad => ConstInt::I64(0),
+        };
+        self.build_cmp(arg, zero)
+    }
+
+    /// Sets `dst` to `if_true` if `condition` holds and to `if_false` otherwise, leaving
+    /// the flags alone. Uses `cmov` where its operand restrictions allow, loading an
+    /// immediate arm into `scratch` if one is given, and otherwise branches over a `mov`
+    /// to `skip`, a label of its own such as one from a
+    /// [`LabelAllocator`](crate::label::LabelAllocator). A byte `dst` both arms read
+    /// takes a `scratch`.
+    fn emit_select(
+        &mut self,
+        condition: Condition,
+        dst: Register,
+        if_true: impl Into<Arg<'a>>,
+        if_false: impl Into<Arg<'a>>,
+        scratch: Option<RegisterName>,
+        skip: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let (if_true, if_false, skip) = (if_true.into(), if_false.into(), skip.into());
+        let reads = |arg: Arg, register: Register| match arg {
+            Arg::Register(r) => r.0 == register.0,
+            Arg::Memory(m) => m.address_registers().iter().any(|r| r.0 == register.0),
+            _ => false,
+        };
+        let reads_dst = |arg: Arg| reads(arg, dst);
+
+        // Move one arm into dst first, then conditionally move the other over it.
+        let orders = [
+            (condition, if_false, if_true),
+            (condition.negate(), if_true, if_false),
+        ];
+        if dst.1 != RegisterSize::Byte {
+            for (condition, first, second) in orders {
+                if is_cmov_source(&second) && !reads_dst(second) {
+                    move_unless_same(self, dst, first)?;
+                    return self.build_cmov(condition, dst, second);
+                }
+            }
+            if let Some(scratch) = scratch.map(|s| s.with_size(dst.1)) {
+                for (condition, first, second) in orders {
+                    if !reads(first, scratch) {
+                        self.build_mov(scratch, second)?;
+                        move_unless_same(self, dst, first)?;
+                        return self.build_cmov(condition, dst, scratch);
+                    }
+                }
+            }
+        }
+
+        for (condition, first, second) in orders {
+            if !reads_dst(first) {
+                move_unless_same(self, dst, second)?;
+                self.build_cjmp(condition, skip)?;
+                self.build_mov(dst, first)?;
+                self.emit_label(skip)?;
+                return Ok(());
+            }
+        }
+        // Both arms depend on dst, so one of them has to wait in scratch.
+        let scratch = scratch.map(|s| s.with_size(dst.1));
+        for (condition, first, second) in orders {
+            if let Some(scratch) = scratch.filter(|&scratch| !reads(first, scratch)) {
+                self.build_mov(scratch, second)?;
+                self.build_mov(dst, first)?;
+                self.build_cjmp(condition.negate(), skip)?;
+                self.build_mov(dst, scratch)?;
+                self.emit_label(skip)?;
+                return Ok(());
+            }
+        }
+        Err(X64WriterError::InvalidOperand(format!(
+            "selecting {if_true} or {if_false} into {dst} takes a scratch register"
+        )))
+    }
+
+    /// Sets `dst` to the smaller of the integers `a` and `b`, only one of which may be an
+    /// immediate, branching to `skip` where [`Self::emit_select`] does.
+    fn emit_min(
+        &mut self,
+        dst: Register,
+        a: impl Into<Arg<'a>>,
+        b: impl Into<Arg<'a>>,
+        signedness: Signedness,
+        skip: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let less = match signedness {
+            Signedness::Signed => Condition::LessThan,
+            Signedness::Unsigned => Condition::Below,
+        };
+        emit_compare_select(self, less, dst, a.into(), b.into(), skip.into())
+    }
+    /// Sets `dst` to the larger of the integers `a` and `b`, only one of which may be an
+    /// immediate, branching to `skip` where [`Self::emit_select`] does.
+    fn emit_max(
+        &mut self,
+        dst: Register,
+        a: impl Into<Arg<'a>>,
+        b: impl Into<Arg<'a>>,
+        signedness: Signedness,
+        skip: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let greater = match signedness {
+            Signedness::Signed => Condition::GreaterThan,
+            Signedness::Unsigned => Condition::Above,
+        };
+        emit_compare_select(self, greater, dst, a.into(), b.into(), skip.into())
+    }
+
+    /// Sets `dst` to the absolute value of the 32 or 64-bit register `src`, which must be a
+    /// different register of the same size. The most negative integer stays as it is.
+    fn emit_abs(&mut self, dst: Register, src: Register) -> Result<(), X64WriterError> {
+        check_distinct_integers(dst, src)?;
+        self.build_mov(dst, src)?;
+        self.build_neg(dst)?;
+        // Negating the most negative integer overflows back to itself, which keeps the sign.
+        self.build_cmov(Condition::Negative, dst, src)
+    }
+    /// Sets `dst` to -1, 0 or 1 according to the sign of the 32 or 64-bit register `src`,
+    /// which must be a different register of the same size.
+    fn emit_sign(&mut self, dst: Register, src: Register) -> Result<(), X64WriterError> {
+        check_distinct_integers(dst, src)?;
+        let bits = match dst.1 {
+            RegisterSize::Quad => 64,
+            _ => 32,
+        };
+        self.build_mov(dst, src)?;
+        self.build_sar(dst, sized_immediate(dst.1, bits - 1))?;
+        // Only a positive src is above the sign mask as unsigned, carrying the 1 in.
+        self.build_cmp(dst, src)?;
+        self.build_adc(dst, sized_immediate(dst.1, 0))
+    }
+
+    /// Sets `dst` to the `width` bits of `src` starting at bit `offset`, zero-extended. Uses
+    /// `bextr` if the target has BMI1 and the registers differ, else shifts and a mask.
+    fn emit_extract_bits(
+        &mut self,
+        dst: Register,
+        src: Register,
+        offset: u8,
+        width: u8,
+    ) -> Result<(), X64WriterError> {
+        let bits = check_bit_field(dst, src, offset, width)?;
+        let (offset, width) = (i64::from(offset), i64::from(width));
+        if offset + width == bits {
+            move_unless_same(self, dst, src.into())?;
+            if offset == 0 {
+                return Ok(());
+            }
+            return self.build_shr(dst, sized_immediate(dst.1, offset));
+        }
+        if self.target_features().contains(Feature::Bmi1) && dst != src {
+            self.build_mov(dst, sized_immediate(dst.1, offset | width << 8))?;
+            return self.build_bextr(dst, src, dst);
+        }
+
+        move_unless_same(self, dst, src.into())?;
+        // A mask of 32 bits or more is not encodable as an immediate of a 64-bit and.
+        if width < 32 {
+            if offset != 0 {
+                self.build_shr(dst, sized_immediate(dst.1, offset))?;
+            }
+            self.build_and(dst, sized_immediate(dst.1, (1 << width) - 1))
+        } else {
+            self.build_shl(dst, sized_immediate(dst.1, bits - offset - width))?;
+            self.build_shr(dst, sized_immediate(dst.1, bits - width))
+        }
+    }
+    /// Replaces the `width` bits of `dst` starting at bit `offset` with the low bits of
+    /// `src`, which must be a different register. Rotates the field out of the way with
+    /// `shrd` and `shld`, so no scratch register or mask is needed.
+    fn emit_insert_bits(
+        &mut self,
+        dst: Register,
+        src: Register,
+        offset: u8,
+        width: u8,
+    ) -> Result<(), X64WriterError> {
+        check_distinct_integers(dst, src)?;
+        let bits = check_bit_field(dst, src, offset, width)?;
+        if i64::from(width) == bits {
+            return self.build_mov(dst, src);
+        }
+        if offset != 0 {
+            self.build_shrd(dst, dst, ConstInt::U8(offset))?;
+        }
+        self.build_shrd(dst, src, ConstInt::U8(width))?;
+        let back = (i64::from(offset) + i64::from(width)) % bits;
+        if back != 0 {
+            self.build_shld(dst, dst, ConstInt::U8(back as u8))?;
+        }
+        Ok(())
+    }
+
+    /// Multiplies the 32 or 64-bit register `src` by `k` into `dst` of the same size. Factors
+    /// of 3, 5 or 9 times a power of two use `lea` and `shl`, anything else `imul`.
+    fn emit_mul_const(
+        &mut self,
+        dst: Register,
+        src: Register,
+        k: i32,
+    ) -> Result<(), X64WriterError> {
+        check_same_size(dst, src)?;
+        if !matches!(dst.1, RegisterSize::Double | RegisterSize::Quad) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{dst} is not a 32 or 64-bit register"
+            )));
+        }
+
+        if k == 0 {
+            let zero = dst.0.double();
+            return self.build_xor(zero, zero);
+        }
+        let magnitude = k.unsigned_abs();
+        let shift = magnitude.trailing_zeros();
+        let scale = match magnitude >> shift {
+            1 => None,
+            3 => Some(Scale::Two),
+            5 => Some(Scale::Four),
+            9 => Some(Scale::Eight),
+            _ => {
+                if dst != src {
+                    self.build_mov(dst, src)?;
+                }
+                return self.build_imul(dst, sized_immediate(dst.1, k.into()));
+            }
+        };
+
+        match scale {
+            Some(scale) => {
+                let base = src.0.quad();
+                self.build_lea(dst, Memory::sib().base(base).index(base, scale))?;
+            }
+            None if dst != src => self.build_mov(dst, src)?,
+            None => (),
+        }
+        if shift != 0 {
+            self.build_shl(dst, sized_immediate(dst.1, shift.into()))?;
+        }
+        if k < 0 {
+            self.build_neg(dst)?;
+        }
+        Ok(())
+    }
+
+    /// Multiplies `dst` by `src` and returns the condition that holds if the product did not
+    /// fit. Signed multiplication uses `imul` and clobbers nothing else. Unsigned
+    /// multiplication uses `mul`, which takes no immediate and clobbers `%rdx`, and `%rax`
+    /// unless `dst` or `src` is in it.
+    fn emit_checked_mul(
+        &mut self,
+        dst: Register,
+        src: impl Into<Arg<'a>>,
+        signedness: Signedness,
+    ) -> Result<Condition, X64WriterError> {
+        let src = src.into();
+        if dst.1 == RegisterSize::Byte {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cannot check the 8-bit multiplication of {dst}"
+            )));
+        }
+        match signedness {
+            Signedness::Signed => self.build_imul(dst, src)?,
+            Signedness::Unsigned => {
+                if let Arg::Int(_) = src {
+                    return Err(X64WriterError::InvalidOperand(format!(
+                        "mul cannot take the immediate {src}"
+                    )));
+                }
+                let a = A.with_size(dst.1);
+                let addresses_a =
+                    |memory: Memory| memory.address_registers().iter().any(|r| r.0 == A);
+                match src {
+                    _ if dst == a => self.build_mul(src)?,
+                    Arg::Register(src) if src == a => {
+                        self.build_mul(dst)?;
+                        self.build_mov(dst, a)?;
+                    }
+                    // Loading dst into %rax first would change the address of src.
+                    Arg::Memory(memory) if addresses_a(memory) => {
+                        self.build_mov(a, src)?;
+                        self.build_mul(dst)?;
+                        self.build_mov(dst, a)?;
+                    }
+                    _ => {
+                        self.build_mov(a, dst)?;
+                        self.build_mul(src)?;
+                        self.build_mov(dst, a)?;
+                    }
+                }
+            }
+        }
+        // Both set the carry and overflow flags if the upper half of the product is needed.
+        Ok(Condition::Overflow)
+    }
+
+    /// Reads `msr` into the `(high, low)` halves of `dst`, clobbering `%ecx`, `%eax` and `%edx`.
+    fn read_msr(&mut self, dst: (Register, Register), msr: Msr) -> Result<(), X64WriterError> {
+        let (high, low) = (dst.0 .0, dst.1 .0);
+        self.build_mov(ecx(), msr.0)?;
+        self.build_rdmsr()?;
+        move_pair(self, (A, D), (low, high))
+    }
+    /// Writes the `(high, low)` halves of `src` to `msr`, clobbering `%ecx`, `%eax` and `%edx`.
+    fn write_msr(&mut self, msr: Msr, src: (Register, Register)) -> Result<(), X64WriterError> {
+        let (high, low) = (src.0 .0, src.1 .0);
+        move_pair(self, (low, high), (A, D))?;
+        self.build_mov(ecx(), msr.0)?;
+        self.build_wrmsr()
+    }
+}
+
+/// Whether both are the same register or virtual register, irrespective of size.
+fn same_register(a: Arg, b: Arg) -> bool {
+    match (a, b) {
+        (Arg::Register(a), Arg::Register(b)) => a.0 == b.0,
+        (Arg::Virtual(a), Arg::Virtual(b)) => a.0 == b.0,
+        _ => false,
+    }
+}
+/// `arg` moved to the register of `other`, keeping its size.
+fn with_register_of<'a>(arg: Arg<'a>, other: Arg<'a>) -> Arg<'a> {
+    match (arg, other) {
+        (Arg::Register(r), Arg::Register(o)) => Arg::Register(o.0.with_size(r.1)),
+        (Arg::Virtual(v), Arg::Virtual(o)) => Arg::Virtual(o.with_size(v.1)),
+        _ => other,
+    }
+}
+/// Checks that `dst` and `src` are registers of the same size.
+fn check_same_size(dst: Register, src: Register) -> Result<(), X64WriterError> {
+    let (expected, found) = (Arg::from(dst).size(), Arg::from(src).size());
+    match (expected, found) {
+        (Some(expected), Some(found)) if expected != found => {
+            Err(X64WriterError::SizeMismatch { expected, found })
+        }
+        _ => Ok(()),
+    }
+}
+/// Checks that `dst` and `src` are different 32 or 64-bit registers of the same size.
+fn check_distinct_integers(dst: Register, src: Register) -> Result<(), X64WriterError> {
+    check_same_size(dst, src)?;
+    if dst.0 == src.0 {
+        return Err(X64WriterError::InvalidOperand(format!(
+            "the destination {dst} has to differ from the source"
+        )));
+    }
+    if !matches!(dst.1, RegisterSize::Double | RegisterSize::Quad) {
+        return Err(X64WriterError::InvalidOperand(format!(
+            "{dst} is not a 32 or 64-bit register"
+        )));
+    }
+    Ok(())
+}
+/// Checks a bit field of a 32 or 64-bit register and returns the register width.
+fn check_bit_field(
+    dst: Register,
+    src: Register,
+    offset: u8,
+    width: u8,
+) -> Result<i64, X64WriterError> {
+    check_same_size(dst, src)?;
+    let bits = match dst.1 {
+        RegisterSize::Double => 32,
+        RegisterSize::Quad => 64,
+        _ => {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{dst} is not a 32 or 64-bit register"
+            )));
+        }
+    };
+    if width == 0 {
+        return Err(X64WriterError::InvalidOperand("bit field is empty".into()));
+    }
+    if i64::from(offset) + i64::from(width) > bits {
+        return Err(X64WriterError::InvalidOperand(format!(
+            "bit field of {width} bits at {offset} exceeds {dst}"
+        )));
+    }
+    Ok(bits)
+}
+/// Checks that `count` is `%cl`, a virtual byte register to allocate to it, or an
+/// immediate that fits the byte of a shift count.
+fn check_shift_count(count: Arg) -> Result<(), X64WriterError> {
+    let valid = match count {
+        Arg::Register(register) => register == cl(),
+        Arg::Virtual(register) => register.1 == RegisterSize::Byte,
+        Arg::Int(c) => (0..256).contains(&c.value()),
+        _ => false,
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(X64WriterError::InvalidOperand(format!(
+            "shift counts are %cl or 8-bit immediates, not {count}"
+        ))),
+    }
+}
+/// The comparison of floats of `size` that sets the flags like an unsigned one.
+fn ucomis(size: FloatSize) -> SseOp {
+    match size {
+        FloatSize::Single => SseOp::Ucomiss,
+        FloatSize::Double => SseOp::Ucomisd,
+    }
+}
+/// An immediate of the size shift counts and operands of 32 or 64-bit instructions take.
+pub(crate) fn sized_immediate(size: RegisterSize, value: i64) -> ConstInt {
+    match size {
+        RegisterSize::Quad => ConstInt::I64(value),
+        _ => ConstInt::I32(value as i32),
+    }
+}
+
+/// Selects `a` if `a` compares to `b` as `condition` says, else `b`.
+fn emit_compare_select<'a, B: InstructionBuilder<'a> + ?Sized>(
+    builder: &mut B,
+    condition: Condition,
+    dst: Register,
+    a: Arg<'a>,
+    b: Arg<'a>,
+    skip: Label<'a>,
+) -> Result<(), X64WriterError> {
+    // cmp cannot take an immediate as its first operand, so compare the other way around.
+    let (condition, lhs, rhs) = match a {
+        Arg::Int(_) => {
+            let swapped = swap_operands(condition).ok_or_else(|| {
+                X64WriterError::InvalidOperand(format!(
+                    "cannot swap the immediate {a} into the {} condition",
+                    condition.suffix()
+                ))
+            })?;
+            (swapped, b, a)
+        }
+        _ => (condition, a, b),
+    };
+    builder.build_cmp(lhs, rhs)?;
+    builder.emit_select(condition, dst, a, b, None, skip)
+}
+
+/// The condition that holds for `cmp a, b` exactly when `condition` holds for `cmp b, a`.
+fn swap_operands(condition: Condition) -> Option<Condition> {
+    use Condition::*;
+    Some(match condition {
+        GreaterThan => LessThan,
+        LessThan => GreaterThan,
+        GreaterEqual => LessEqual,
+        LessEqual => GreaterEqual,
+        Above => Below,
+        Below => Above,
+        AboveEqual => BelowEqual,
+        BelowEqual => AboveEqual,
+        Zero | NotZero | Equal | NotEqual => condition,
+        // The sign or overflow of a difference has no swapped condition.
+        Negative | NonNegative | Overflow | NoOverflow => return None,
+    })
+}
+
+/// Whether `cmov` can take `arg` as its source, which must be a register or memory.
+fn is_cmov_source(arg: &Arg) -> bool {
+    !matches!(arg, Arg::Int(_))
+}
+fn move_unless_same<'a, B: InstructionBuilder<'a> + ?Sized>(
+    builder: &mut B,
+    dst: Register,
+    src: Arg<'a>,
+) -> Result<(), X64WriterError> {
+    if src == Arg::Register(dst) {
+        return Ok(());
+    }
+    builder.build_mov(dst, src)
+}
+
+/// Moves the 32-bit values of `src` into `dst` as if in parallel, using `%ecx` as scratch
+/// if the registers are swapped.
+fn move_pair<'a, B: InstructionBuilder<'a> + ?Sized>(
+    builder: &mut B,
+    src: (RegisterName, RegisterName),
+    dst: (RegisterName, RegisterName),
+) -> Result<(), X64WriterError> {
+    if src.0 == dst.1 && src.1 == dst.0 && src.0 != src.1 {
+        builder.build_mov(ecx(), src.0.double())?;
+        builder.build_mov(dst.1.double(), src.1.double())?;
+        return builder.build_mov(dst.0.double(), ecx());
+    }
+
+    // Move out of the register that the other move overwrites first.
+    let moves = if src.1 == dst.0 {
+        [(dst.1, src.1), (dst.0, src.0)]
+    } else {
+        [(dst.0, src.0), (dst.1, src.1)]
+    };
+    for (to, from) in moves {
+        if to != from {
+            builder.build_mov(to.double(), from.double())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        function::FunctionBuilder,
+        register::{eax, ecx, rax, rcx},
+        writer::UnaryOpSingle,
+    };
+
+    /// Runs the register instructions `emit` builds with `%rcx` holding `src`, and returns
+    /// the value of `%rax`.
+    fn run(
+        src: u64,
+        emit: impl FnOnce(&mut FunctionBuilder<'static>) -> Result<(), X64WriterError>,
+    ) -> u64 {
+        let mut builder = FunctionBuilder::new("f");
+        emit(&mut builder).unwrap();
+        let function = builder.end_function().unwrap();
+
+        let mut registers = [0u64; 16];
+        registers[RegisterName::C as usize] = src;
+        let (mut carry, mut sign) = (false, false);
+        for instruction in function.instructions() {
+            let bits = match instruction.operands()[0] {
+                Arg::Register(Register(_, RegisterSize::Quad)) => 64,
+                _ => 32,
+            };
+            let mask = u64::MAX >> (64 - bits);
+            let value = |arg: Arg| match arg {
+                Arg::Register(r) => registers[r.0 as usize] & mask,
+                Arg::Int(ConstInt::I32(v)) => v as u64 & mask,
+                Arg::Int(ConstInt::I64(v)) => v as u64,
+                Arg::Int(ConstInt::U8(v)) => u64::from(v),
+                Arg::Int(ConstInt::I8(v)) => v as u64 & mask,
+                arg => panic!("unexpected operand {arg}"),
+            };
+            let is_negative = |v: u64| v >> (bits - 1) & 1 == 1;
+            let (dst, result) = match *instruction {
+                Instruction::Mov { dst, src } => (dst, value(src)),
+                Instruction::CMov {
+                    condition: Condition::Negative,
+                    dst,
+                    src,
+                } => (dst, if sign { value(src) } else { value(dst) }),
+                Instruction::Unary {
+                    op: UnaryOp::Single(UnaryOpSingle::Neg),
+                    dst,
+                } => {
+                    let result = value(dst).wrapping_neg() & mask;
+                    (carry, sign) = (value(dst) != 0, is_negative(result));
+                    (dst, result)
+                }
+                Instruction::Binary {
+                    op: BinaryOp::Single(op),
+                    dst,
+                    src,
+                } => {
+                    let (a, b) = (value(dst), value(src));
+                    let result = match op {
+                        BinaryOpSingle::Sar => {
+                            let shifted = (a << (64 - bits)) as i64 >> (64 - bits) >> b;
+                            shifted as u64 & mask
+                        }
+                        BinaryOpSingle::Cmp => {
+                            (carry, sign) = (a < b, is_negative(a.wrapping_sub(b) & mask));
+                            continue;
+                        }
+                        BinaryOpSingle::Adc => a.wrapping_add(b).wrapping_add(carry as u64) & mask,
+                        op => panic!("unexpected {op:?}"),
+                    };
+                    (dst, result)
+                }
+                ref instruction => panic!("unexpected {instruction:?}"),
+            };
+            let Arg::Register(dst) = dst else {
+                panic!("unexpected destination {dst}");
+            };
+            registers[dst.0 as usize] = result;
+        }
+        registers[RegisterName::A as usize]
+    }
+
+    #[test]
+    fn abs() {
+        let abs32 = |v: i32| run(v as u32 as u64, |b| b.emit_abs(eax(), ecx())) as u32 as i32;
+        let abs64 = |v: i64| run(v as u64, |b| b.emit_abs(rax(), rcx())) as i64;
+        for v in [0, 1, -1, 42, -42, i32::MAX, i32::MIN] {
+            assert_eq!(abs32(v), v.wrapping_abs(), "abs of {v}");
+        }
+        for v in [0, 1, -1, i64::from(i32::MIN), i64::MAX, i64::MIN] {
+            assert_eq!(abs64(v), v.wrapping_abs(), "abs of {v}");
+        }
+    }
+
+    #[test]
+    fn sign() {
+        let sign32 = |v: i32| run(v as u32 as u64, |b| b.emit_sign(eax(), ecx())) as u32 as i32;
+        let sign64 = |v: i64| run(v as u64, |b| b.emit_sign(rax(), rcx())) as i64;
+        for v in [0, 1, -1, 42, -42, i32::MAX, i32::MIN] {
+            assert_eq!(sign32(v), v.signum(), "sign of {v}");
+        }
+        for v in [0, 1, -1, i64::from(i32::MIN), i64::MAX, i64::MIN] {
+            assert_eq!(sign64(v), v.signum(), "sign of {v}");
+        }
+    }
+
+    #[test]
+    fn distinct_integers() {
+        let mut builder = FunctionBuilder::new("f");
+        assert!(builder.emit_abs(eax(), eax()).is_err());
+        assert!(builder.emit_sign(eax(), rcx()).is_err());
+        let (ax, cx) = (RegisterName::A.word(), RegisterName::C.word());
+        assert!(builder.emit_abs(ax, cx).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use super::{
+    analysis::split_blocks,
+    function::{Function, Item},
+    instruction::Instruction,
+    label::Label,
+};
+use alloc::{vec, vec::Vec};
+
+/// A basic block, as a range of [`Function::items`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    /// The blocks control may continue in, by index and without duplicates.
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+impl BasicBlock {
+    /// The label the block starts with, if any.
+    pub fn label<'a>(&self, items: &[Item<'a>]) -> Option<Label<'a>> {
+        match items[self.start..self.end].first() {
+            Some(&Item::Label(label)) => Some(label),
+            _ => None,
+        }
+    }
+    /// The instructions of the block with their indices in the items.
+    pub fn instructions<'i, 'a>(
+        &self,
+        items: &'i [Item<'a>],
+    ) -> impl Iterator<Item = (usize, &'i Instruction<'a>)> {
+        let start = self.start;
+        items[self.start..self.end]
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, item)| match item {
+                Item::Instruction(instruction, _) => Some((start + i, instruction)),
+                _ => None,
+            })
+    }
+}
+
+/// The control flow graph of a function, with its dominator tree rooted at the first block.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    /// The immediate dominator of each block, `None` for the entry and unreachable blocks.
+    idoms: Vec<Option<usize>>,
+    reachable: Vec<bool>,
+}
+impl Cfg {
+    /// The block containing the item at `item`.
+    pub fn block_of(&self, item: usize) -> usize {
+        self.blocks
+            .iter()
+            .position(|block| item < block.end)
+            .unwrap_or_else(|| panic!("item {item} is beyond the last block"))
+    }
+
+    /// Whether the entry block leads to `block`. Jumps to labels of other functions or
+    /// through registers are not followed, so blocks only entered that way are not.
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.reachable[block]
+    }
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        self.idoms[block]
+    }
+    /// Whether every path from the entry to `block` passes through `dominator`. Unreachable
+    /// blocks only dominate themselves and are dominated by nothing else.
+    pub fn dominates(&self, dominator: usize, block: usize) -> bool {
+        let mut current = Some(block);
+        while let Some(b) = current {
+            if b == dominator {
+                return true;
+            }
+            current = self.idoms[b];
+        }
+        false
+    }
+    /// The blocks immediately dominated by `block`, its children in the dominator tree.
+    pub fn dominated(&self, block: usize) -> Vec<usize> {
+        (0..self.blocks.len())
+            .filter(|&b| self.idoms[b] == Some(block))
+            .collect()
+    }
+
+    /// The reachable blocks in reverse postorder, starting with the entry.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        // Blocks with the index of the next successor to visit.
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            match self.blocks[block].successors.get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => order.push(block),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// The iterative algorithm of Cooper, Harvey and Kennedy.
+    fn compute_dominators(&mut self) {
+        let order = self.reverse_postorder();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (i, &block) in order.iter().enumerate() {
+            position[block] = i;
+            self.reachable[block] = true;
+        }
+
+        // The entry stands in as its own dominator while iterating.
+        let mut idoms = vec![None; self.blocks.len()];
+        if let Some(&entry) = order.first() {
+            idoms[entry] = Some(entry);
+        }
+        let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while position[a] > position[b] {
+                    a = idoms[a].unwrap();
+                }
+                while position[b] > position[a] {
+                    b = idoms[b].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let processed = self.blocks[block]
+                    .predecessors
+                    .iter()
+                    .copied()
+                    .filter(|&p| idoms[p].is_some());
+                let idom = processed.reduce(|a, b| intersect(&idoms, a, b));
+                if idom.is_some() && idoms[block] != idom {
+                    idoms[block] = idom;
+                    changed = true;
+                }
+            }
+        }
+
+        if let Some(&entry) = order.first() {
+            idoms[entry] = None;
+        }
+        self.idoms = idoms;
+    }
+}
+
+impl Function<'_> {
+    /// Splits the items into basic blocks at labels and terminators, links them by jumps
+    /// to labels of this function and fall through, and computes their dominators.
+    pub fn cfg(&self) -> Cfg {
+        let blocks: Vec<_> = split_blocks(&self.items)
+            .into_iter()
+            .map(|block| BasicBlock {
+                start: block.start,
+                end: block.end,
+                successors: unique(block.successors),
+                predecessors: unique(block.predecessors),
+            })
+            .collect();
+        let mut cfg = Cfg {
+            idoms: vec![None; blocks.len()],
+            reachable: vec![false; blocks.len()],
+            blocks,
+        };
+        cfg.compute_dominators();
+        cfg
+    }
+}
+
+fn unique(mut blocks: Vec<usize>) -> Vec<usize> {
+    let mut seen = Vec::with_capacity(blocks.len());
+    blocks.retain(|&b| {
+        let new = !seen.contains(&b);
+        seen.push(b);
+        new
+    });
+    blocks
+}
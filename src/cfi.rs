@@ -0,0 +1,466 @@
+use super::{
+    builder::InstructionBuilder,
+    convention::{CallingConvention, StackFrame},
+    data::{sleb128, uleb128},
+    eh::PointerEncoding,
+    error::X64WriterError,
+    io::{self, Write},
+    platform::Platform,
+    register::{rsp, Register, RegisterName, RegisterSet},
+    section::{Section, SectionType},
+    writer::AsmWriter,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xc0;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+/// The DWARF column holding the return address, after the 16 general purpose registers.
+const RETURN_ADDRESS: u8 = 16;
+const DATA_ALIGNMENT: i64 = -8;
+
+/// How the writer emits the call frame information unwinders use to walk the stack.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CfiMode {
+    /// `.cfi_*` directives, from which the assembler builds `.eh_frame`.
+    #[default]
+    Directives,
+    /// The `.eh_frame` contents computed by the writer, for assemblers without CFI
+    /// directives. Locations are label differences the assembler resolves.
+    EhFrame,
+}
+
+/// A call frame instruction, with register operands as DWARF numbers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum CfaOp {
+    DefCfa(u8, i64),
+    DefCfaRegister(u8),
+    DefCfaOffset(i64),
+    Offset(u8, i64),
+    Restore(u8),
+    RememberState,
+    RestoreState,
+}
+impl CfaOp {
+    fn encode(self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Self::DefCfa(register, offset) => {
+                bytes.push(DW_CFA_DEF_CFA);
+                bytes.extend(uleb128(register.into()));
+                bytes.extend(uleb128(offset as u64));
+            }
+            Self::DefCfaRegister(register) => {
+                bytes.push(DW_CFA_DEF_CFA_REGISTER);
+                bytes.extend(uleb128(register.into()));
+            }
+            Self::DefCfaOffset(offset) => {
+                bytes.push(DW_CFA_DEF_CFA_OFFSET);
+                bytes.extend(uleb128(offset as u64));
+            }
+            // Saved registers are at multiples of the data alignment from the CFA.
+            Self::Offset(register, offset) if offset <= 0 && register < 64 => {
+                bytes.push(DW_CFA_OFFSET | register);
+                bytes.extend(uleb128((offset / DATA_ALIGNMENT) as u64));
+            }
+            Self::Offset(register, offset) => {
+                bytes.push(DW_CFA_OFFSET_EXTENDED_SF);
+                bytes.extend(uleb128(register.into()));
+                bytes.extend(sleb128(offset / DATA_ALIGNMENT));
+            }
+            Self::Restore(register) if register < 64 => bytes.push(DW_CFA_RESTORE | register),
+            Self::Restore(register) => {
+                bytes.push(DW_CFA_RESTORE_EXTENDED);
+                bytes.extend(uleb128(register.into()));
+            }
+            Self::RememberState => bytes.push(DW_CFA_REMEMBER_STATE),
+            Self::RestoreState => bytes.push(DW_CFA_RESTORE_STATE),
+        }
+        bytes
+    }
+}
+
+/// The call frame information of the function being emitted in [`CfiMode::EhFrame`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct Frame {
+    start: String,
+    personality: Option<(PointerEncoding, String)>,
+    lsda: Option<(PointerEncoding, String)>,
+    /// The instructions, each with the label of the location it applies from.
+    instructions: Vec<(String, CfaOp)>,
+    /// The CFA offset, for `.cfi_adjust_cfa_offset`, and the offsets remembered with it.
+    cfa_offset: i64,
+    remembered: Vec<i64>,
+}
+
+/// What distinguishes the CIEs functions share: the personality and the LSDA encoding.
+type CieKey = (Option<(PointerEncoding, String)>, Option<PointerEncoding>);
+
+/// The call frame information state of an [`AsmWriter`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct CfiState {
+    pub(crate) mode: CfiMode,
+    frame: Option<Frame>,
+    /// The CIEs emitted so far, by their label.
+    cies: Vec<(CieKey, String)>,
+    labels: usize,
+}
+impl CfiState {
+    pub(crate) fn in_frame(&self) -> bool {
+        self.frame.is_some()
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Starts the call frame information of a function at the current location, with the
+    /// CFA 8 bytes above `%rsp` and the return address below it, as after a call. On
+    /// Windows, this starts the unwind information of the function begun on the writer.
+    pub fn emit_cfi_startproc(&mut self) -> io::Result<()> {
+        if self.platform() == Platform::Windows {
+            let Some(function) = self.open_function().clone() else {
+                let message = "unwind information is for a function begun on the writer";
+                return Err(X64WriterError::OutOfOrder(message.into()).into());
+            };
+            return self.directive(format_args!(".seh_proc {function}"));
+        }
+        if self.cfi_mode() == CfiMode::Directives {
+            return self.directive(".cfi_startproc");
+        }
+        if self.cfi().frame.is_some() {
+            let error = X64WriterError::OutOfOrder("the last frame was never ended".into());
+            return Err(error.into());
+        }
+        let start = self.cfi_label()?;
+        self.cfi().frame = Some(Frame {
+            start,
+            cfa_offset: 8,
+            ..Frame::default()
+        });
+        Ok(())
+    }
+    /// Ends the call frame information of the function at the current location.
+    pub fn emit_cfi_endproc(&mut self) -> io::Result<()> {
+        if self.platform() == Platform::Windows {
+            return self.directive(".seh_endproc");
+        }
+        if self.cfi_mode() == CfiMode::Directives {
+            return self.directive(".cfi_endproc");
+        }
+        let Some(frame) = self.cfi().frame.take() else {
+            return Err(no_frame().into());
+        };
+        let end = self.cfi_label()?;
+        self.emit_fde(&frame, &end)
+    }
+
+    /// Defines the CFA as `offset` bytes above the address in `register`.
+    pub fn emit_cfi_def_cfa(&mut self, register: Register, offset: i64) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        let register = register.0;
+        if self.cfi_mode() == CfiMode::Directives {
+            let name = register.quad();
+            return self.directive(format_args!(".cfi_def_cfa {name}, {offset}"));
+        }
+        self.frame()?.cfa_offset = offset;
+        self.record_cfa_op(CfaOp::DefCfa(register.dwarf_number(), offset))
+    }
+    /// Keeps the CFA register, with the CFA now `offset` bytes above it.
+    pub fn emit_cfi_def_cfa_offset(&mut self, offset: i64) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        if self.cfi_mode() == CfiMode::Directives {
+            return self.directive(format_args!(".cfi_def_cfa_offset {offset}"));
+        }
+        self.frame()?.cfa_offset = offset;
+        self.record_cfa_op(CfaOp::DefCfaOffset(offset))
+    }
+    /// Adds `delta` to the offset of the CFA, as after pushing or reserving stack space
+    /// while it is relative to `%rsp`.
+    pub fn emit_cfi_adjust_cfa_offset(&mut self, delta: i64) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        if self.cfi_mode() == CfiMode::Directives {
+            return self.directive(format_args!(".cfi_adjust_cfa_offset {delta}"));
+        }
+        let offset = self.frame()?.cfa_offset + delta;
+        self.emit_cfi_def_cfa_offset(offset)
+    }
+    /// Keeps the offset of the CFA, but now relative to `register`, as after setting up
+    /// a frame pointer.
+    pub fn emit_cfi_def_cfa_register(&mut self, register: Register) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        let register = register.0;
+        if self.cfi_mode() == CfiMode::Directives {
+            let name = register.quad();
+            return self.directive(format_args!(".cfi_def_cfa_register {name}"));
+        }
+        self.record_cfa_op(CfaOp::DefCfaRegister(register.dwarf_number()))
+    }
+    /// Records that the caller's value of `register` is saved `offset` bytes from the CFA,
+    /// a negative multiple of 8.
+    pub fn emit_cfi_offset(&mut self, register: Register, offset: i64) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        if offset % DATA_ALIGNMENT != 0 {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "saved registers have to be 8 byte aligned, not at {offset}"
+            ))
+            .into());
+        }
+        let register = register.0;
+        if self.cfi_mode() == CfiMode::Directives {
+            let name = register.quad();
+            return self.directive(format_args!(".cfi_offset {name}, {offset}"));
+        }
+        self.record_cfa_op(CfaOp::Offset(register.dwarf_number(), offset))
+    }
+    /// Records that `register` holds the caller's value again, as at the start of the function.
+    pub fn emit_cfi_restore(&mut self, register: Register) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        let register = register.0;
+        if self.cfi_mode() == CfiMode::Directives {
+            let name = register.quad();
+            return self.directive(format_args!(".cfi_restore {name}"));
+        }
+        self.record_cfa_op(CfaOp::Restore(register.dwarf_number()))
+    }
+    /// Saves the current rules, to be restored by [`Self::emit_cfi_restore_state`], such as
+    /// before an early return in the middle of a function.
+    pub fn emit_cfi_remember_state(&mut self) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        if self.cfi_mode() == CfiMode::Directives {
+            return self.directive(".cfi_remember_state");
+        }
+        let frame = self.frame()?;
+        frame.remembered.push(frame.cfa_offset);
+        self.record_cfa_op(CfaOp::RememberState)
+    }
+    pub fn emit_cfi_restore_state(&mut self) -> io::Result<()> {
+        self.check_dwarf_cfi()?;
+        if self.cfi_mode() == CfiMode::Directives {
+            return self.directive(".cfi_restore_state");
+        }
+        let frame = self.frame()?;
+        let Some(offset) = frame.remembered.pop() else {
+            let error = X64WriterError::OutOfOrder("no state was remembered".into());
+            return Err(error.into());
+        };
+        frame.cfa_offset = offset;
+        self.record_cfa_op(CfaOp::RestoreState)
+    }
+
+    /// Like [`InstructionBuilder::emit_prologue`], with the call frame information for
+    /// every push and the stack adjustment. Has to come first after
+    /// [`Self::emit_cfi_startproc`], where the CFA is still 8 bytes above `%rsp`.
+    pub fn emit_cfi_prologue(
+        &mut self,
+        convention: &CallingConvention,
+        saved: RegisterSet,
+        locals: u32,
+    ) -> Result<StackFrame, X64WriterError> {
+        let frame = StackFrame::new(convention, saved, locals);
+        if self.platform() == Platform::Windows {
+            for name in &frame.saved {
+                self.build_push(name.quad())?;
+                self.directive(format_args!(".seh_pushreg {}", name.quad()))?;
+            }
+            if frame.adjustment != 0 {
+                self.build_sub(rsp(), i64::from(frame.adjustment))?;
+                self.directive(format_args!(".seh_stackalloc {}", frame.adjustment))?;
+            }
+            self.directive(".seh_endprologue")?;
+            return Ok(frame);
+        }
+        let mut offset = 8;
+        for name in &frame.saved {
+            self.build_push(name.quad())?;
+            offset += 8;
+            self.emit_cfi_def_cfa_offset(offset)?;
+            self.emit_cfi_offset(name.quad(), -offset)?;
+        }
+        if frame.adjustment != 0 {
+            self.build_sub(rsp(), i64::from(frame.adjustment))?;
+            offset += i64::from(frame.adjustment);
+            self.emit_cfi_def_cfa_offset(offset)?;
+        }
+        Ok(frame)
+    }
+    /// Like [`InstructionBuilder::emit_epilogue`], with the call frame information for
+    /// every pop. The rules of the frame are restored after the `ret`, so an epilogue may
+    /// be followed by more code of the function.
+    pub fn emit_cfi_epilogue(&mut self, frame: &StackFrame) -> Result<(), X64WriterError> {
+        // Windows unwinders recognize epilogues by their instructions.
+        if self.platform() == Platform::Windows {
+            return self.emit_epilogue(frame);
+        }
+        self.emit_cfi_remember_state()?;
+        let mut offset = 8 * (frame.saved.len() as i64 + 1);
+        if frame.adjustment != 0 {
+            self.build_add(rsp(), i64::from(frame.adjustment))?;
+            self.emit_cfi_def_cfa_offset(offset)?;
+        }
+        for name in frame.saved.iter().rev() {
+            self.build_pop(name.quad())?;
+            offset -= 8;
+            self.emit_cfi_def_cfa_offset(offset)?;
+            self.emit_cfi_restore(name.quad())?;
+        }
+        self.build_ret()?;
+        self.emit_cfi_restore_state()?;
+        Ok(())
+    }
+
+    /// Windows unwind information only describes the prologue, as written by
+    /// [`Self::emit_cfi_prologue`], so the other call frame instructions have no equivalent.
+    fn check_dwarf_cfi(&self) -> Result<(), X64WriterError> {
+        match self.platform() {
+            Platform::Windows => Err(X64WriterError::Unsupported(
+                "Windows unwind information only describes prologues, see emit_cfi_prologue".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Records the personality routine of the current frame, given in directive form.
+    pub(crate) fn record_personality(
+        &mut self,
+        encoding: PointerEncoding,
+        target: String,
+    ) -> Result<(), X64WriterError> {
+        self.frame()?.personality = Some((encoding, target));
+        Ok(())
+    }
+    /// Records the LSDA of the current frame, given in directive form.
+    pub(crate) fn record_lsda(
+        &mut self,
+        encoding: PointerEncoding,
+        target: String,
+    ) -> Result<(), X64WriterError> {
+        self.frame()?.lsda = Some((encoding, target));
+        Ok(())
+    }
+
+    fn frame(&mut self) -> Result<&mut Frame, X64WriterError> {
+        self.cfi().frame.as_mut().ok_or_else(no_frame)
+    }
+    /// Emits a new label at the current location.
+    fn cfi_label(&mut self) -> io::Result<String> {
+        let label = self.next_cfi_label();
+        self.emit_label(label.as_str())?;
+        Ok(label)
+    }
+    fn next_cfi_label(&mut self) -> String {
+        let state = self.cfi();
+        state.labels += 1;
+        format!(".Lcfi{}", state.labels - 1)
+    }
+    fn record_cfa_op(&mut self, op: CfaOp) -> io::Result<()> {
+        // Checked before the label, so nothing is written outside of a frame.
+        self.frame()?;
+        let label = self.cfi_label()?;
+        self.frame()?.instructions.push((label, op));
+        Ok(())
+    }
+
+    /// Emits the FDE of `frame`, which ends at the label `end`, into `.eh_frame`, after
+    /// its CIE unless an earlier function shares it.
+    fn emit_fde(&mut self, frame: &Frame, end: &str) -> io::Result<()> {
+        let key = (
+            frame.personality.clone(),
+            frame.lsda.as_ref().map(|lsda| lsda.0),
+        );
+        self.push_section(Section::named(".eh_frame", "a", SectionType::Progbits)?)?;
+        let existing = self.cfi().cies.iter().find(|(cie, _)| *cie == key);
+        let cie = match existing {
+            Some((_, label)) => label.clone(),
+            None => {
+                let label = self.emit_cie(&key)?;
+                self.cfi().cies.push((key, label.clone()));
+                label
+            }
+        };
+
+        let (pointer, fde_end) = (self.next_cfi_label(), self.next_cfi_label());
+        self.directive(format_args!(".long {fde_end}-{pointer}"))?;
+        self.emit_label(pointer.as_str())?;
+        self.directive(format_args!(".long {pointer}-{cie}"))?;
+        // The start is PC relative, like every pointer the CIE's `R` augmentation covers.
+        let start = &frame.start;
+        self.directive(format_args!(".long {start}-."))?;
+        self.directive(format_args!(".long {end}-{start}"))?;
+        match &frame.lsda {
+            Some((encoding, lsda)) => {
+                self.emit_uleb128(encoding.format.size().unwrap() as u64)?;
+                self.emit_pointer_to(*encoding, lsda)?;
+            }
+            None => self.emit_uleb128(0)?,
+        }
+
+        // Each instruction has its own label, at a distance the assembler computes.
+        let mut location = start;
+        for (label, op) in &frame.instructions {
+            self.emit_byte(DW_CFA_ADVANCE_LOC4)?;
+            self.directive(format_args!(".long {label}-{location}"))?;
+            self.emit_bytes(&op.encode())?;
+            location = label;
+        }
+        self.emit_balign(8)?;
+        self.emit_label(fde_end.as_str())?;
+        self.pop_section()
+    }
+    /// Emits a CIE with the personality and LSDA encoding of `key` and returns its label.
+    fn emit_cie(&mut self, key: &CieKey) -> io::Result<String> {
+        let (personality, lsda) = key;
+        let (cie, end) = (self.next_cfi_label(), self.next_cfi_label());
+        self.emit_balign(8)?;
+        self.emit_label(cie.as_str())?;
+        self.directive(format_args!(".long {end}-{cie}-4"))?;
+        self.emit_long(0u32)?; // CIE id
+        self.emit_byte(1u8)?; // version
+
+        let mut augmentation = "z".to_string();
+        let mut augmentation_size = 1;
+        if let Some((encoding, _)) = personality {
+            augmentation.push('P');
+            augmentation_size += 1 + encoding.format.size().unwrap();
+        }
+        if lsda.is_some() {
+            augmentation.push('L');
+            augmentation_size += 1;
+        }
+        augmentation.push('R');
+        self.emit_asciz(augmentation)?;
+        self.emit_uleb128(1)?; // code alignment
+        self.emit_sleb128(DATA_ALIGNMENT)?;
+        self.emit_uleb128(RETURN_ADDRESS.into())?;
+
+        self.emit_uleb128(augmentation_size as u64)?;
+        if let Some((encoding, target)) = personality {
+            self.directive(format_args!(".byte {encoding}"))?;
+            self.emit_pointer_to(*encoding, target)?;
+        }
+        if let Some(encoding) = lsda {
+            self.directive(format_args!(".byte {encoding}"))?;
+        }
+        self.directive(format_args!(".byte {}", PointerEncoding::PC_RELATIVE))?;
+
+        // The CFA is 8 bytes above `%rsp`, and the return address is right below it.
+        let sp = RegisterName::SP.dwarf_number();
+        self.emit_bytes(&CfaOp::DefCfa(sp, 8).encode())?;
+        self.emit_bytes(&CfaOp::Offset(RETURN_ADDRESS, -8).encode())?;
+        self.emit_balign(8)?;
+        self.emit_label(end.as_str())?;
+        Ok(cie)
+    }
+}
+
+fn no_frame() -> X64WriterError {
+    X64WriterError::OutOfOrder("no frame was started".into())
+}
@@ -0,0 +1,89 @@
+use super::{
+    args::Memory,
+    register::{rsp, RegisterName, RegisterSet},
+};
+use alloc::{vec, vec::Vec};
+
+/// How integer arguments and results are passed between functions, and what a call may
+/// change besides them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CallingConvention {
+    /// The registers carrying the arguments, in order.
+    pub arguments: Vec<RegisterName>,
+    /// The registers carrying the results, in order.
+    pub returns: Vec<RegisterName>,
+    /// The registers a function has to preserve, excluding `%rsp`.
+    pub callee_saved: RegisterSet,
+    /// The alignment of `%rsp` at a call instruction, in bytes.
+    pub stack_alignment: u32,
+    /// The bytes a caller reserves right above the return address for the callee to spill
+    /// its register arguments to.
+    pub shadow_space: u32,
+}
+impl CallingConvention {
+    /// The System V AMD64 convention used by Linux and most Unix-like systems.
+    pub fn sysv() -> Self {
+        use RegisterName::*;
+        Self {
+            arguments: vec![DI, SI, D, C, R8, R9],
+            returns: vec![A, D],
+            callee_saved: RegisterSet::sysv_callee_saved(),
+            stack_alignment: 16,
+            shadow_space: 0,
+        }
+    }
+    /// The Microsoft x64 convention used by Windows.
+    pub fn win64() -> Self {
+        use RegisterName::*;
+        Self {
+            arguments: vec![C, D, R8, R9],
+            returns: vec![A],
+            callee_saved: RegisterSet::from_slice(&[B, BP, DI, SI, R12, R13, R14, R15]),
+            stack_alignment: 16,
+            shadow_space: 32,
+        }
+    }
+
+    /// The registers a call may overwrite.
+    pub fn caller_saved(&self) -> RegisterSet {
+        RegisterSet::all()
+            .difference(self.callee_saved)
+            .without(RegisterName::SP)
+    }
+}
+
+/// The stack layout set up by [`emit_prologue`](crate::builder::InstructionBuilder::emit_prologue),
+/// to be torn down again by [`emit_epilogue`](crate::builder::InstructionBuilder::emit_epilogue).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StackFrame {
+    /// The registers pushed, in order.
+    pub(crate) saved: Vec<RegisterName>,
+    /// The bytes subtracted from `%rsp` after pushing.
+    pub(crate) adjustment: u32,
+    pub(crate) shadow_space: u32,
+}
+impl StackFrame {
+    /// The frame saving `saved` and reserving `locals` bytes plus the shadow space, with
+    /// `%rsp` aligned for calls.
+    pub(crate) fn new(convention: &CallingConvention, saved: RegisterSet, locals: u32) -> Self {
+        let saved: Vec<_> = saved.iter().collect();
+        // The return address and the pushes already moved the stack pointer.
+        let alignment = convention.stack_alignment.max(8);
+        let pushed = 8 * (saved.len() as u32 + 1);
+        let needed = locals + convention.shadow_space;
+        let adjustment = (pushed + needed).next_multiple_of(alignment) - pushed;
+        Self {
+            saved,
+            adjustment,
+            shadow_space: convention.shadow_space,
+        }
+    }
+
+    pub fn saved(&self) -> &[RegisterName] {
+        &self.saved
+    }
+    /// The start of the local variables, above the shadow space of outgoing calls.
+    pub fn locals(&self) -> Memory<'static> {
+        rsp().memory().offset(i64::from(self.shadow_space))
+    }
+}
@@ -0,0 +1,307 @@
+use super::{
+    args::ConstInt,
+    io::{self, Write},
+    label::{Label, LocalLabel, SymExpr},
+    writer::AsmWriter,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
+
+/// A value stored by a data directive, either a constant or an address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DataValue<'a> {
+    Int(ConstInt),
+    Symbol(SymExpr<'a>),
+}
+impl From<u8> for DataValue<'_> {
+    fn from(value: u8) -> Self {
+        Self::Int(value.into())
+    }
+}
+impl From<i8> for DataValue<'_> {
+    fn from(value: i8) -> Self {
+        Self::Int(value.into())
+    }
+}
+impl From<u16> for DataValue<'_> {
+    fn from(value: u16) -> Self {
+        Self::Int(u32::from(value).into())
+    }
+}
+impl From<i16> for DataValue<'_> {
+    fn from(value: i16) -> Self {
+        Self::Int(i32::from(value).into())
+    }
+}
+impl From<u32> for DataValue<'_> {
+    fn from(value: u32) -> Self {
+        Self::Int(value.into())
+    }
+}
+impl From<i32> for DataValue<'_> {
+    fn from(value: i32) -> Self {
+        Self::Int(value.into())
+    }
+}
+impl From<u64> for DataValue<'_> {
+    fn from(value: u64) -> Self {
+        Self::Int(value.into())
+    }
+}
+impl From<i64> for DataValue<'_> {
+    fn from(value: i64) -> Self {
+        Self::Int(value.into())
+    }
+}
+impl From<ConstInt> for DataValue<'_> {
+    fn from(value: ConstInt) -> Self {
+        Self::Int(value)
+    }
+}
+impl<'a> From<Label<'a>> for DataValue<'a> {
+    fn from(value: Label<'a>) -> Self {
+        Self::Symbol(value.into())
+    }
+}
+impl<'a> From<SymExpr<'a>> for DataValue<'a> {
+    fn from(value: SymExpr<'a>) -> Self {
+        Self::Symbol(value)
+    }
+}
+impl<'a> From<&'a str> for DataValue<'a> {
+    fn from(value: &'a str) -> Self {
+        Self::Symbol(Label::from(value).into())
+    }
+}
+impl<'a> From<&'a LocalLabel> for DataValue<'a> {
+    fn from(value: &'a LocalLabel) -> Self {
+        Self::Symbol(value.label().into())
+    }
+}
+impl Display for DataValue<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Int(c) => write!(f, "{c}"),
+            Self::Symbol(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DataSize {
+    Byte,
+    Word,
+    Long,
+    Quad,
+}
+impl DataSize {
+    pub fn directive(self) -> &'static str {
+        match self {
+            Self::Byte => ".byte",
+            Self::Word => ".word",
+            Self::Long => ".long",
+            Self::Quad => ".quad",
+        }
+    }
+    pub fn in_bytes(self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Word => 2,
+            Self::Long => 4,
+            Self::Quad => 8,
+        }
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    pub fn emit_byte<'a>(&mut self, value: impl Into<DataValue<'a>>) -> io::Result<()> {
+        let value = self.data_text(value.into());
+        self.directive(format_args!(".byte {value}"))
+    }
+    pub fn emit_word<'a>(&mut self, value: impl Into<DataValue<'a>>) -> io::Result<()> {
+        let value = self.data_text(value.into());
+        self.directive(format_args!(".word {value}"))
+    }
+    pub fn emit_long<'a>(&mut self, value: impl Into<DataValue<'a>>) -> io::Result<()> {
+        let value = self.data_text(value.into());
+        self.directive(format_args!(".long {value}"))
+    }
+    pub fn emit_quad<'a>(&mut self, value: impl Into<DataValue<'a>>) -> io::Result<()> {
+        let value = self.data_text(value.into());
+        self.directive(format_args!(".quad {value}"))
+    }
+    /// The text of `value`, with the name its symbol has on the platform. The symbol counts
+    /// as referenced.
+    fn data_text(&mut self, value: DataValue) -> String {
+        match value {
+            DataValue::Int(c) => c.to_string(),
+            DataValue::Symbol(expr) => {
+                self.reference_symbol(expr.label.label);
+                let label = Label {
+                    label: &self.symbol_name(expr.label.label),
+                    ..expr.label
+                };
+                SymExpr::new(label, expr.offset).to_string()
+            }
+        }
+    }
+    pub fn emit_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let values: Vec<_> = bytes.iter().map(|&b| DataValue::from(b)).collect();
+        self.emit_data(DataSize::Byte, &values)
+    }
+    /// Emits `bytes` as string literals if they are mostly printable text, and as `.byte`
+    /// lists otherwise.
+    pub fn emit_data_slice(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let text = |byte: &&u8| byte.is_ascii_graphic() || matches!(byte, b' ' | b'\n' | b'\t');
+        if 4 * bytes.iter().filter(text).count() >= 3 * bytes.len() {
+            self.emit_ascii(bytes)
+        } else {
+            self.emit_bytes(bytes)
+        }
+    }
+    /// Emits `values` as consecutive directives, starting a new one whenever a line would
+    /// exceed the data line width. A list spanning several lines gets the offset of each
+    /// line from its start as comment, unless the output is minified.
+    pub fn emit_data(&mut self, size: DataSize, values: &[DataValue]) -> io::Result<()> {
+        let directive = size.directive();
+        // The leading tab counts as 8 columns.
+        let prefix = 8 + directive.len() + 1;
+        let width = self.data_line_width();
+        let minified = self.is_minified();
+        let separator = if minified { "," } else { ", " };
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        let mut start = 0;
+        for (i, value) in values.iter().enumerate() {
+            let value = self.data_text(*value);
+            if !line.is_empty() && prefix + line.len() + separator.len() + value.len() > width {
+                lines.push((start, core::mem::take(&mut line)));
+                start = i;
+            }
+            if !line.is_empty() {
+                line.push_str(separator);
+            }
+            line.push_str(&value);
+        }
+        if !line.is_empty() {
+            lines.push((start, line));
+        }
+
+        let wrapped = lines.len() > 1;
+        for (start, line) in lines {
+            let offset = start * size.in_bytes();
+            let comment = wrapped.then(|| format!("+{offset:#x}"));
+            self.commented_directive(format_args!("{directive} {line}"), comment)?;
+        }
+        Ok(())
+    }
+    /// Emits `bytes` as escaped string literals, split over several `.ascii` directives if
+    /// a line would exceed the data line width.
+    pub fn emit_ascii(&mut self, bytes: impl AsRef<[u8]>) -> io::Result<()> {
+        self.emit_string(bytes.as_ref(), false)
+    }
+    /// Like `emit_ascii`, but followed by a terminating zero byte.
+    pub fn emit_asciz(&mut self, bytes: impl AsRef<[u8]>) -> io::Result<()> {
+        self.emit_string(bytes.as_ref(), true)
+    }
+    fn emit_string(&mut self, bytes: &[u8], terminated: bool) -> io::Result<()> {
+        // The leading tab counts as 8 columns, and the literal needs quotes.
+        let prefix = 8 + ".ascii".len() + 3;
+        let width = self.data_line_width();
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for &byte in bytes {
+            let escaped = escape(byte);
+            if !line.is_empty() && prefix + line.len() + escaped.len() > width {
+                lines.push(core::mem::take(&mut line));
+            }
+            line.push_str(&escaped);
+        }
+        if !line.is_empty() || (terminated && lines.is_empty()) {
+            lines.push(line);
+        }
+
+        let count = lines.len();
+        for (i, line) in lines.into_iter().enumerate() {
+            let directive = if terminated && i + 1 == count {
+                ".asciz"
+            } else {
+                ".ascii"
+            };
+            self.directive(format_args!("{directive} \"{line}\""))?;
+        }
+        Ok(())
+    }
+    /// Emits `value` in the unsigned LEB128 encoding, as bytes unless the writer uses LEB128
+    /// directives.
+    pub fn emit_uleb128(&mut self, value: u64) -> io::Result<()> {
+        if self.leb128_directives() {
+            return self.directive(format_args!(".uleb128 {value}"));
+        }
+        self.emit_bytes(&uleb128(value))
+    }
+    /// Emits `value` in the signed LEB128 encoding, as bytes unless the writer uses LEB128
+    /// directives.
+    pub fn emit_sleb128(&mut self, value: i64) -> io::Result<()> {
+        if self.leb128_directives() {
+            return self.directive(format_args!(".sleb128 {value}"));
+        }
+        self.emit_bytes(&sleb128(value))
+    }
+    /// Fills `bytes` bytes with zeroes.
+    pub fn emit_zero(&mut self, bytes: u64) -> io::Result<()> {
+        self.directive(format_args!(".zero {bytes}"))
+    }
+    /// Pads the current section with zeroes up to a multiple of `alignment` bytes.
+    pub fn emit_balign(&mut self, alignment: u64) -> io::Result<()> {
+        self.directive(format_args!(".balign {alignment}, 0"))
+    }
+}
+
+/// `byte` as it has to be written inside a string literal.
+pub(crate) fn escape(byte: u8) -> String {
+    match byte {
+        b'"' => "\\\"".to_string(),
+        b'\\' => "\\\\".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b' '..=b'~' => char::from(byte).to_string(),
+        _ => format!("\\{byte:03o}"),
+    }
+}
+
+/// `value` in the unsigned LEB128 encoding of DWARF, seven bits per byte starting with the
+/// lowest, each but the last with the high bit set.
+pub fn uleb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = value as u8 & 0x7f;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+/// `value` in the signed LEB128 encoding of DWARF, which ends once the remaining bits are
+/// all copies of the sign bit of the last byte.
+pub fn sleb128(mut value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = value as u8 & 0x7f;
+        value >>= 7;
+        let sign = byte & 0x40 != 0;
+        if (value == 0 && !sign) || (value == -1 && sign) {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,105 @@
+use super::{
+    data::escape,
+    function::Tags,
+    io::{self, Write},
+    writer::AsmWriter,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// A position in a source file, which `.loc` maps the code following it to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    /// The column, 0 if unknown.
+    pub column: u32,
+}
+impl SourceLocation {
+    pub fn new(file: impl Into<String>, line: u32, column: u32) -> Self {
+        Self {
+            file: file.into(),
+            line,
+            column,
+        }
+    }
+    /// The tag mapping an instruction of a [`Function`](crate::function::Function) to the
+    /// location, for which the writer emits a `.loc`.
+    pub fn tag(&self) -> String {
+        format!("loc {}:{}:{}", self.file, self.line, self.column)
+    }
+    /// The location in a tag made by [`Self::tag`].
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let mut parts = tag.strip_prefix("loc ")?.rsplitn(3, ':');
+        let column = parts.next()?.parse().ok()?;
+        let line = parts.next()?.parse().ok()?;
+        Some(Self::new(parts.next()?, line, column))
+    }
+}
+
+/// The line table of an [`AsmWriter`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct DebugLineState {
+    /// The files declared with `.file`, numbered from 1.
+    files: Vec<String>,
+    /// The location of the instructions written next.
+    pub(crate) location: Option<SourceLocation>,
+    /// The location of the last `.loc`.
+    emitted: Option<SourceLocation>,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// The number of `path` in the line table, declaring it with `.file` the first time.
+    pub fn debug_file(&mut self, path: &str) -> io::Result<u32> {
+        let files = &mut self.debug_line().files;
+        if let Some(i) = files.iter().position(|file| file == path) {
+            return Ok(i as u32 + 1);
+        }
+        files.push(path.to_string());
+        let number = files.len();
+        let path: String = path.bytes().map(escape).collect();
+        self.directive(format_args!(".file {number} \"{path}\""))?;
+        Ok(number as u32)
+    }
+    /// Maps the code from the current location on to `location`, for debuggers.
+    pub fn emit_loc(&mut self, location: &SourceLocation) -> io::Result<()> {
+        let file = self.debug_file(&location.file)?;
+        let SourceLocation { line, column, .. } = location;
+        self.directive(format_args!(".loc {file} {line} {column}"))?;
+        self.debug_line().emitted = Some(location.clone());
+        Ok(())
+    }
+    /// Maps the instructions written from now on to `location`, with a `.loc` before the
+    /// first one. Instructions without a location stay mapped to the last one.
+    pub fn set_source_location(&mut self, location: Option<SourceLocation>) {
+        self.debug_line().location = location;
+    }
+    /// Runs `f` with the instructions it writes mapped to `location`, then returns to the
+    /// location before.
+    pub fn with_source_location<R>(
+        &mut self,
+        location: SourceLocation,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let previous = self.debug_line().location.replace(location);
+        let result = f(self);
+        self.debug_line().location = previous;
+        result
+    }
+    /// Emits a `.loc` for an instruction with `tags` if its location, from the tags or else
+    /// the writer, differs from the last one.
+    pub(crate) fn emit_instruction_loc(&mut self, tags: &Tags) -> io::Result<()> {
+        let state = self.debug_line();
+        let location = tags.iter().find_map(SourceLocation::from_tag);
+        let Some(location) = location.or_else(|| state.location.clone()) else {
+            return Ok(());
+        };
+        if state.emitted.as_ref() == Some(&location) {
+            return Ok(());
+        }
+        self.emit_loc(&location)
+    }
+}
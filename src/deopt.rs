@@ -0,0 +1,90 @@
+use super::{
+    io::{self, Write},
+    label::Label,
+    register::RegisterName,
+    section::{Section, SectionType},
+    stackmap::Location,
+    writer::AsmWriter,
+};
+use alloc::vec::Vec;
+
+/// The section of the deoptimization table. Its name is a C identifier, so the linker
+/// defines `__start_deopt` and `__stop_deopt` around the table of the whole program.
+pub const DEOPT_SECTION: &str = "deopt";
+
+/// The state of the interpreter to resume in when compiled code is left at a patchpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeoptEntry<'a> {
+    /// The label of the patchpoint, as returned by [`AsmWriter::reserve_patchpoint`].
+    pub patchpoint: Label<'a>,
+    pub bytecode_offset: u32,
+    /// Where the value of each interpreter slot is, by slot number.
+    pub slots: Vec<(u32, Location)>,
+}
+impl<'a> DeoptEntry<'a> {
+    pub fn new(patchpoint: impl Into<Label<'a>>, bytecode_offset: u32) -> Self {
+        Self {
+            patchpoint: patchpoint.into(),
+            bytecode_offset,
+            slots: Vec::new(),
+        }
+    }
+    pub fn slot(mut self, slot: u32, location: Location) -> Self {
+        self.slots.push((slot, location));
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DeoptTable<'a> {
+    pub entries: Vec<DeoptEntry<'a>>,
+}
+impl<'a> DeoptTable<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_entry(&mut self, entry: DeoptEntry<'a>) {
+        self.entries.push(entry);
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits the table to [`DEOPT_SECTION`]. Patchpoint labels are never renamed, see
+    /// [`AsmWriter::set_rename_local_labels`]. Each entry is the 8-byte patchpoint address,
+    /// the 4-byte bytecode offset and the 4-byte number of slots, followed by 24 bytes per
+    /// slot: the 4-byte slot number, then the location kind, size and DWARF register as in
+    /// LLVM stack maps, padding, and the 8-byte offset or constant.
+    pub fn emit_deopt_table(&mut self, table: &DeoptTable) -> io::Result<()> {
+        self.push_section(Section::named(DEOPT_SECTION, "a", SectionType::Progbits)?)?;
+        self.emit_balign(8)?;
+        for entry in &table.entries {
+            self.emit_quad(entry.patchpoint)?;
+            self.emit_long(entry.bytecode_offset)?;
+            self.emit_long(entry.slots.len() as u32)?;
+            for &(slot, location) in &entry.slots {
+                self.emit_long(slot)?;
+                // Constants are stored in full rather than in a constant pool.
+                let kind = match location {
+                    Location::Constant(_) => 4,
+                    location => location.kind(),
+                };
+                self.emit_byte(kind)?;
+                self.emit_byte(0u8)?;
+                self.emit_word(location.size())?;
+                let register = location.register().map_or(0, RegisterName::dwarf_number);
+                self.emit_word(u16::from(register))?;
+                self.emit_word(0u16)?;
+                self.emit_long(0u32)?;
+                let value = match location {
+                    Location::Register(_) => 0,
+                    Location::Indirect { offset, .. } | Location::Direct { offset, .. } => {
+                        offset.into()
+                    }
+                    Location::Constant(value) => value,
+                };
+                self.emit_quad(value)?;
+            }
+        }
+        self.pop_section()
+    }
+}
@@ -0,0 +1,280 @@
+use super::{
+    data::DataValue,
+    error::X64WriterError,
+    io::{self, Write},
+    label::Label,
+    writer::AsmWriter,
+};
+use alloc::format;
+use core::num::NonZeroU32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SegmentKind {
+    Code { readable: bool, conforming: bool },
+    Data { writable: bool, expand_down: bool },
+}
+
+/// The default operand size of a segment, encoded in its `L` and `D/B` bits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SegmentSize {
+    Bits16,
+    Bits32,
+    /// A 64-bit code segment. Data segments use [`Self::Bits32`] in long mode.
+    Long,
+}
+
+/// A code or data segment descriptor of the GDT or an LDT.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SegmentDescriptor {
+    pub base: u32,
+    /// The 20-bit segment limit, in bytes or pages depending on `page_granular`.
+    pub limit: u32,
+    pub kind: SegmentKind,
+    pub size: SegmentSize,
+    /// The descriptor privilege level, 0 to 3.
+    pub privilege: u8,
+    pub present: bool,
+    pub page_granular: bool,
+}
+impl SegmentDescriptor {
+    /// A present segment covering the whole 4 GiB address space.
+    pub fn flat(kind: SegmentKind, size: SegmentSize, privilege: u8) -> Self {
+        Self {
+            base: 0,
+            limit: 0xfffff,
+            kind,
+            size,
+            privilege,
+            present: true,
+            page_granular: true,
+        }
+    }
+    pub fn code64(privilege: u8) -> Self {
+        let kind = SegmentKind::Code {
+            readable: true,
+            conforming: false,
+        };
+        Self::flat(kind, SegmentSize::Long, privilege)
+    }
+    pub fn code32(privilege: u8) -> Self {
+        let kind = SegmentKind::Code {
+            readable: true,
+            conforming: false,
+        };
+        Self::flat(kind, SegmentSize::Bits32, privilege)
+    }
+    pub fn data(privilege: u8) -> Self {
+        let kind = SegmentKind::Data {
+            writable: true,
+            expand_down: false,
+        };
+        Self::flat(kind, SegmentSize::Bits32, privilege)
+    }
+
+    pub fn encode(&self) -> u64 {
+        let (executable, bit1, bit2) = match self.kind {
+            SegmentKind::Code {
+                readable,
+                conforming,
+            } => (true, readable, conforming),
+            SegmentKind::Data {
+                writable,
+                expand_down,
+            } => (false, writable, expand_down),
+        };
+        let access = u64::from(bit1) << 1
+            | u64::from(bit2) << 2
+            | u64::from(executable) << 3
+            | 1 << 4
+            | u64::from(self.privilege & 3) << 5
+            | u64::from(self.present) << 7;
+        let flags = u64::from(self.size == SegmentSize::Long) << 1
+            | u64::from(self.size == SegmentSize::Bits32) << 2
+            | u64::from(self.page_granular) << 3;
+
+        encode_base_limit(self.base.into(), self.limit) | access << 40 | flags << 52
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SystemSegmentKind {
+    Ldt = 0x2,
+    AvailableTss = 0x9,
+    BusyTss = 0xb,
+}
+
+/// A 16-byte long mode descriptor for a TSS or LDT.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SystemSegmentDescriptor {
+    pub base: u64,
+    pub limit: u32,
+    pub kind: SystemSegmentKind,
+    pub privilege: u8,
+    pub present: bool,
+}
+impl SystemSegmentDescriptor {
+    /// An available TSS of `size` bytes at `base`.
+    pub fn tss(base: u64, size: NonZeroU32) -> Self {
+        Self {
+            base,
+            limit: size.get() - 1,
+            kind: SystemSegmentKind::AvailableTss,
+            privilege: 0,
+            present: true,
+        }
+    }
+
+    pub fn encode(&self) -> u128 {
+        let access =
+            self.kind as u64 | u64::from(self.privilege & 3) << 5 | u64::from(self.present) << 7;
+        let low = encode_base_limit(self.base, self.limit) | access << 40;
+        u128::from(low) | u128::from(self.base >> 32) << 64
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GdtEntry {
+    Null,
+    Segment(SegmentDescriptor),
+    System(SystemSegmentDescriptor),
+}
+impl GdtEntry {
+    /// The size of the entry in bytes, which system descriptors occupy two slots of.
+    pub fn size(&self) -> u16 {
+        match self {
+            Self::Null | Self::Segment(_) => 8,
+            Self::System(_) => 16,
+        }
+    }
+}
+impl From<SegmentDescriptor> for GdtEntry {
+    fn from(value: SegmentDescriptor) -> Self {
+        Self::Segment(value)
+    }
+}
+impl From<SystemSegmentDescriptor> for GdtEntry {
+    fn from(value: SystemSegmentDescriptor) -> Self {
+        Self::System(value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GateKind {
+    /// Clears the interrupt flag on entry.
+    Interrupt = 0xe,
+    Trap = 0xf,
+}
+
+/// A 16-byte long mode IDT entry.
+///
+/// The handler address is split across the entry, so it has to be known when the
+/// table is assembled. Tables pointing at relocatable handlers are filled at run time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GateDescriptor {
+    pub offset: u64,
+    pub selector: u16,
+    /// The interrupt stack table slot to switch to, or 0 to stay on the current stack.
+    pub ist: u8,
+    pub kind: GateKind,
+    pub privilege: u8,
+    pub present: bool,
+}
+impl GateDescriptor {
+    pub fn interrupt(offset: u64, selector: u16) -> Self {
+        Self {
+            offset,
+            selector,
+            ist: 0,
+            kind: GateKind::Interrupt,
+            privilege: 0,
+            present: true,
+        }
+    }
+    pub fn trap(offset: u64, selector: u16) -> Self {
+        Self {
+            kind: GateKind::Trap,
+            ..Self::interrupt(offset, selector)
+        }
+    }
+
+    pub fn encode(&self) -> u128 {
+        let access =
+            self.kind as u64 | u64::from(self.privilege & 3) << 5 | u64::from(self.present) << 7;
+        let low = self.offset & 0xffff
+            | u64::from(self.selector) << 16
+            | u64::from(self.ist & 7) << 32
+            | access << 40
+            | (self.offset >> 16 & 0xffff) << 48;
+        u128::from(low) | u128::from(self.offset >> 32) << 64
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits the entries of a global descriptor table, 8-byte aligned at `label`.
+    pub fn emit_gdt<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        entries: &[GdtEntry],
+    ) -> io::Result<()> {
+        self.emit_balign(8)?;
+        self.emit_label(label.into())?;
+        for entry in entries {
+            match entry {
+                GdtEntry::Null => self.emit_descriptor(0)?,
+                GdtEntry::Segment(segment) => self.emit_descriptor(segment.encode())?,
+                GdtEntry::System(system) => self.emit_wide_descriptor(system.encode())?,
+            }
+        }
+        Ok(())
+    }
+    /// Emits an interrupt descriptor table at `label`, with `None` entries left not present.
+    pub fn emit_idt<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        gates: &[Option<GateDescriptor>],
+    ) -> io::Result<()> {
+        self.emit_balign(16)?;
+        self.emit_label(label.into())?;
+        for gate in gates {
+            let encoded = gate.map_or(0, |gate| gate.encode());
+            self.emit_wide_descriptor(encoded)?;
+        }
+        Ok(())
+    }
+    /// Emits the limit and base operand of `lgdt` and `lidt` for a table of `size` bytes,
+    /// which has to be between 1 and 64 KiB.
+    pub fn emit_descriptor_table_pointer<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        table: impl Into<DataValue<'a>>,
+        size: u32,
+    ) -> Result<(), X64WriterError> {
+        let limit = size
+            .checked_sub(1)
+            .and_then(|limit| u16::try_from(limit).ok())
+            .ok_or_else(|| {
+                X64WriterError::InvalidOperand(format!(
+                    "a descriptor table of {size} bytes is not between 1 and 65536 bytes"
+                ))
+            })?;
+        self.emit_balign(2)?;
+        self.emit_label(label.into())?;
+        self.emit_word(limit)?;
+        Ok(self.emit_quad(table)?)
+    }
+    fn emit_descriptor(&mut self, encoded: u64) -> io::Result<()> {
+        self.directive(format_args!(".quad {encoded:#018x}"))
+    }
+    fn emit_wide_descriptor(&mut self, encoded: u128) -> io::Result<()> {
+        self.emit_descriptor(encoded as u64)?;
+        self.emit_descriptor((encoded >> 64) as u64)
+    }
+}
+
+fn encode_base_limit(base: u64, limit: u32) -> u64 {
+    let limit = u64::from(limit);
+    limit & 0xffff
+        | (base & 0xff_ffff) << 16
+        | (limit >> 16 & 0xf) << 48
+        | (base >> 24 & 0xff) << 56
+}
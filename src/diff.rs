@@ -0,0 +1,217 @@
+use super::{
+    function::{Function, Item},
+    module::Module,
+};
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Display;
+
+/// The functions that differ between two modules, in the order of the new module followed
+/// by the removed ones.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ModuleDiff<'a> {
+    pub functions: Vec<FunctionDiff<'a>>,
+}
+impl ModuleDiff<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionDiff<'a> {
+    /// The symbol name, which for target clones is that of the clone.
+    pub name: String,
+    pub status: FunctionStatus,
+    /// The labels and instructions of the old and new body, merged in order.
+    pub changes: Vec<ItemChange<'a>>,
+}
+impl FunctionDiff<'_> {
+    pub fn added(&self) -> usize {
+        let added = |c: &&ItemChange| matches!(c, ItemChange::Added(_));
+        self.changes.iter().filter(added).count()
+    }
+    pub fn removed(&self) -> usize {
+        let removed = |c: &&ItemChange| matches!(c, ItemChange::Removed(_));
+        self.changes.iter().filter(removed).count()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FunctionStatus {
+    Added,
+    Removed,
+    Changed,
+}
+impl Display for FunctionStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Added => write!(f, "added"),
+            Self::Removed => write!(f, "removed"),
+            Self::Changed => write!(f, "changed"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ItemChange<'a> {
+    Unchanged(Item<'a>),
+    Added(Item<'a>),
+    Removed(Item<'a>),
+}
+
+/// How many unchanged items are shown around a change when rendering.
+const CONTEXT: usize = 3;
+
+impl Display for ModuleDiff<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for function in &self.functions {
+            write!(f, "{function}")?;
+        }
+        Ok(())
+    }
+}
+impl Display for FunctionDiff<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{} ({}, +{} -{})",
+            self.name,
+            self.status,
+            self.added(),
+            self.removed()
+        )?;
+
+        let changed: Vec<_> = self
+            .changes
+            .iter()
+            .map(|c| !matches!(c, ItemChange::Unchanged(_)))
+            .collect();
+        let mut elided = false;
+        for (i, change) in self.changes.iter().enumerate() {
+            let start = i.saturating_sub(CONTEXT);
+            let end = (i + CONTEXT + 1).min(changed.len());
+            if !changed[start..end].contains(&true) {
+                if !elided {
+                    writeln!(f, "  ...")?;
+                    elided = true;
+                }
+                continue;
+            }
+            elided = false;
+
+            let (marker, item) = match change {
+                ItemChange::Unchanged(item) => (' ', item),
+                ItemChange::Added(item) => ('+', item),
+                ItemChange::Removed(item) => ('-', item),
+            };
+            match item {
+                Item::Label(label) => writeln!(f, "{marker} {label}:")?,
+                Item::Instruction(instruction, _) => writeln!(f, "{marker}     {instruction}")?,
+                Item::Comment(comment) => writeln!(f, "{marker}     # {comment}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Module<'a> {
+    /// Compares the labels and instructions of every function with the one of the same
+    /// name in `other`, treating `self` as the old version. Comments and tags are ignored.
+    pub fn diff(&self, other: &Module<'a>) -> ModuleDiff<'a> {
+        let old = named_functions(self);
+        let new = named_functions(other);
+
+        let mut functions = Vec::new();
+        for &(ref name, function) in &new {
+            let previous = old.iter().find(|(n, _)| n == name);
+            let (status, changes) = match previous {
+                Some((_, previous)) => (FunctionStatus::Changed, diff_items(previous, function)),
+                None => (FunctionStatus::Added, diff_items(&[], function)),
+            };
+            if changes
+                .iter()
+                .any(|c| !matches!(c, ItemChange::Unchanged(_)))
+            {
+                functions.push(FunctionDiff {
+                    name: name.clone(),
+                    status,
+                    changes,
+                });
+            }
+        }
+        for (name, function) in &old {
+            if !new.iter().any(|(n, _)| n == name) {
+                functions.push(FunctionDiff {
+                    name: name.clone(),
+                    status: FunctionStatus::Removed,
+                    changes: diff_items(function, &[]),
+                });
+            }
+        }
+        ModuleDiff { functions }
+    }
+}
+
+fn named_functions<'m, 'a>(module: &'m Module<'a>) -> Vec<(String, &'m [Item<'a>])> {
+    let mut functions: Vec<_> = module
+        .functions()
+        .iter()
+        .map(|f| (f.name.label.to_string(), f.items.as_slice()))
+        .collect();
+    for clones in module.target_clones() {
+        let bodies = clones.clones.iter().chain([&clones.default]);
+        let names = clones.clone_names();
+        let bodies = bodies.map(|f: &Function<'a>| f.items.as_slice());
+        functions.extend(names.into_iter().zip(bodies));
+    }
+    functions
+}
+
+/// A longest common subsequence diff of the labels and instructions.
+fn diff_items<'a>(old: &[Item<'a>], new: &[Item<'a>]) -> Vec<ItemChange<'a>> {
+    let old: Vec<_> = old.iter().filter(|i| !is_comment(i)).collect();
+    let new: Vec<_> = new.iter().filter(|i| !is_comment(i)).collect();
+
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if same(old[i], new[j]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(old[i], new[j]) {
+            changes.push(ItemChange::Unchanged(new[j].clone()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(ItemChange::Removed(old[i].clone()));
+            i += 1;
+        } else {
+            changes.push(ItemChange::Added(new[j].clone()));
+            j += 1;
+        }
+    }
+    changes
+}
+fn is_comment(item: &Item) -> bool {
+    matches!(item, Item::Comment(_))
+}
+fn same(a: &Item, b: &Item) -> bool {
+    match (a, b) {
+        (Item::Label(a), Item::Label(b)) => a == b,
+        (Item::Instruction(a, _), Item::Instruction(b, _)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,219 @@
+use super::{
+    builder::InstructionBuilder,
+    features::{Feature, TargetFeatures},
+    io::{self, Write},
+    label::Label,
+    register::{
+        eax, ebx, ecx, edx, rax, rbx, rcx, rdi, rdx, rsi, rx, rxd, Register, RegisterName,
+        RegisterSize,
+    },
+    section::Section,
+    writer::{AsmWriter, Condition},
+};
+use alloc::format;
+
+/// One implementation of a dispatched function, usable when all `features` are present.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DispatchVariant<'a> {
+    pub features: TargetFeatures,
+    pub target: Label<'a>,
+}
+impl<'a> DispatchVariant<'a> {
+    pub fn new(features: TargetFeatures, target: impl Into<Label<'a>>) -> Self {
+        Self {
+            features,
+            target: target.into(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DispatchMethod {
+    /// Emit `name` as a `@gnu_indirect_function` whose resolver is run by the dynamic loader.
+    IFunc,
+    /// Emit `name` as a trampoline jumping through a pointer that is resolved on first call.
+    FunctionPointer,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits a dispatcher for `name` that selects the first variant whose features are
+    /// supported by the executing CPU, or `fallback` if there is none.
+    pub fn emit_cpu_dispatch<'a>(
+        &mut self,
+        name: impl Into<Label<'a>>,
+        variants: &[DispatchVariant<'a>],
+        fallback: impl Into<Label<'a>>,
+        method: DispatchMethod,
+    ) -> io::Result<()> {
+        let name = name.into().label;
+        let fallback = fallback.into();
+
+        let resolver = format!("{name}.resolver");
+        match method {
+            DispatchMethod::IFunc => {
+                self.directive(format_args!(".type {name}, @gnu_indirect_function"))?;
+                self.directive(format_args!(".set {name}, {resolver}"))?;
+                self.emit_resolver(name, &resolver, variants, fallback)
+            }
+            DispatchMethod::FunctionPointer => {
+                let init = format!("{name}.init");
+                let pointer = format!("{name}.ptr");
+
+                self.emit_label(name)?;
+                self.build_jmp(Label::from(&pointer).rip())?;
+
+                // Saves the argument registers, resolves and caches the target,
+                // then continues into it as if it had been called directly.
+                let saved = [rdi(), rsi(), rdx(), rcx(), rx(8), rx(9), rax()];
+                self.emit_label(&init)?;
+                for reg in saved {
+                    self.build_push(reg)?;
+                }
+                self.build_call(&resolver)?;
+                self.build_mov(Label::from(&pointer).rip(), rax())?;
+                self.build_mov(rx(11), rax())?;
+                for reg in saved.into_iter().rev() {
+                    self.build_pop(reg)?;
+                }
+                self.build_jmp(rx(11))?;
+
+                self.emit_resolver(name, &resolver, variants, fallback)?;
+
+                self.begin_section(Section::Data)?;
+                self.directive(".p2align 3")?;
+                self.emit_label(&pointer)?;
+                self.directive(format_args!(".quad {init}"))?;
+                self.begin_text()
+            }
+        }
+    }
+
+    fn emit_resolver(
+        &mut self,
+        name: &str,
+        resolver: &str,
+        variants: &[DispatchVariant],
+        fallback: Label,
+    ) -> io::Result<()> {
+        let no_leaf_7 = format!(".L{name}.no_leaf_7");
+        let no_ext_leaf = format!(".L{name}.no_ext_leaf");
+        let no_xsave = format!(".L{name}.no_xsave");
+
+        self.emit_label(resolver)?;
+        self.build_push(rbx())?;
+
+        self.build_xor(eax(), eax())?;
+        self.build_cpuid()?;
+        self.build_mov(rxd(11), eax())?;
+
+        self.build_mov(eax(), 1u32)?;
+        self.build_cpuid()?;
+        self.build_mov(LEAF_1_ECX, ecx())?;
+        self.build_mov(LEAF_1_EDX, edx())?;
+
+        self.build_xor(LEAF_7_EBX, LEAF_7_EBX)?;
+        self.build_cmp(rxd(11), 7u32)?;
+        self.build_cjmp(Condition::Below, &no_leaf_7)?;
+        self.build_mov(eax(), 7u32)?;
+        self.build_xor(ecx(), ecx())?;
+        self.build_cpuid()?;
+        self.build_mov(LEAF_7_EBX, ebx())?;
+        self.emit_label(&no_leaf_7)?;
+
+        self.build_mov(eax(), 0x8000_0000u32)?;
+        self.build_cpuid()?;
+        self.build_xor(EXT_LEAF_1_ECX, EXT_LEAF_1_ECX)?;
+        self.build_cmp(eax(), 0x8000_0001u32)?;
+        self.build_cjmp(Condition::Below, &no_ext_leaf)?;
+        self.build_mov(eax(), 0x8000_0001u32)?;
+        self.build_cpuid()?;
+        self.build_mov(EXT_LEAF_1_ECX, ecx())?;
+        self.emit_label(&no_ext_leaf)?;
+
+        self.build_xor(XCR0, XCR0)?;
+        self.build_test(LEAF_1_ECX, 1u32 << OSXSAVE_BIT)?;
+        self.build_cjmp(Condition::Zero, &no_xsave)?;
+        self.build_xor(ecx(), ecx())?;
+        self.build_xgetbv()?;
+        self.build_mov(XCR0, eax())?;
+        self.emit_label(&no_xsave)?;
+
+        self.build_pop(rbx())?;
+
+        for (i, variant) in variants.iter().enumerate() {
+            let next = format!(".L{name}.variant_{}", i + 1);
+            self.emit_feature_checks(variant.features, &next)?;
+            self.build_lea(rax(), variant.target.rip())?;
+            self.build_ret()?;
+            self.emit_label(&next)?;
+        }
+
+        self.build_lea(rax(), fallback.rip())?;
+        self.build_ret()?;
+        Ok(())
+    }
+    fn emit_feature_checks(&mut self, features: TargetFeatures, fail: &str) -> io::Result<()> {
+        for feature in features.iter() {
+            let (reg, bit) = cpuid_location(feature);
+            self.build_test(reg, 1u32 << bit)?;
+            self.build_cjmp(Condition::Zero, fail)?;
+        }
+
+        let os_state = os_state_mask(features);
+        if os_state != 0 {
+            self.build_mov(eax(), XCR0)?;
+            self.build_and(eax(), os_state)?;
+            self.build_cmp(eax(), os_state)?;
+            self.build_cjmp(Condition::NotEqual, fail)?;
+        }
+
+        Ok(())
+    }
+}
+
+const LEAF_1_ECX: Register = Register(RegisterName::R8, RegisterSize::Double);
+const LEAF_1_EDX: Register = Register(RegisterName::R9, RegisterSize::Double);
+const LEAF_7_EBX: Register = Register(RegisterName::R10, RegisterSize::Double);
+const EXT_LEAF_1_ECX: Register = Register(RegisterName::R11, RegisterSize::Double);
+const XCR0: Register = Register(RegisterName::SI, RegisterSize::Double);
+const OSXSAVE_BIT: u32 = 27;
+
+fn cpuid_location(feature: Feature) -> (Register, u32) {
+    use Feature::*;
+    match feature {
+        Sse => (LEAF_1_EDX, 25),
+        Sse2 => (LEAF_1_EDX, 26),
+        Sse3 => (LEAF_1_ECX, 0),
+        Ssse3 => (LEAF_1_ECX, 9),
+        Fma => (LEAF_1_ECX, 12),
+        Cx16 => (LEAF_1_ECX, 13),
+        Sse41 => (LEAF_1_ECX, 19),
+        Sse42 => (LEAF_1_ECX, 20),
+        Movbe => (LEAF_1_ECX, 22),
+        Popcnt => (LEAF_1_ECX, 23),
+        Avx => (LEAF_1_ECX, 28),
+        F16c => (LEAF_1_ECX, 29),
+        Bmi1 => (LEAF_7_EBX, 3),
+        Avx2 => (LEAF_7_EBX, 5),
+        Bmi2 => (LEAF_7_EBX, 8),
+        Avx512F => (LEAF_7_EBX, 16),
+        Avx512Dq => (LEAF_7_EBX, 17),
+        Avx512Bw => (LEAF_7_EBX, 30),
+        Avx512Vl => (LEAF_7_EBX, 31),
+        Lzcnt => (EXT_LEAF_1_ECX, 5),
+    }
+}
+
+/// The XCR0 bits the operating system has to set for the register state the features use.
+fn os_state_mask(features: TargetFeatures) -> u32 {
+    use Feature::*;
+    let mut mask = 0;
+    for feature in features.iter() {
+        match feature {
+            Avx | Avx2 | Fma | F16c => mask |= 0x06,
+            Avx512F | Avx512Bw | Avx512Dq | Avx512Vl => mask |= 0xe6,
+            _ => (),
+        }
+    }
+    mask
+}
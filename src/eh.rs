@@ -0,0 +1,490 @@
+use super::{
+    cfi::CfiMode,
+    data::{sleb128, DataSize},
+    io::{self, Write},
+    label::Label,
+    section::{Section, SectionType},
+    symbol::SymbolVisibility,
+    writer::AsmWriter,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
+
+/// The value a `DW_EH_PE_*` byte has when the pointer it describes is left out.
+pub const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_INDIRECT: u8 = 0x80;
+
+/// How a pointer in unwind or exception handling data is stored, a `DW_EH_PE_*` byte.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PointerEncoding {
+    pub format: PointerFormat,
+    pub application: PointerApplication,
+    /// Whether the stored pointer points to a pointer to the symbol instead of the symbol.
+    /// The writer then refers to `DW.ref.<symbol>`, defined by
+    /// [`AsmWriter::emit_indirect_pointer`].
+    pub indirect: bool,
+}
+impl PointerEncoding {
+    /// Absolute 8-byte addresses, for code that is not position independent.
+    pub const ABSOLUTE: Self = Self::new(PointerFormat::Absolute, PointerApplication::Absolute);
+    /// Signed 4-byte offsets from the pointer, as used for LSDA pointers.
+    pub const PC_RELATIVE: Self = Self::new(PointerFormat::Sdata4, PointerApplication::PcRelative);
+    /// Like [`Self::PC_RELATIVE`] but indirect, as position independent code uses for
+    /// personality routines and type info, which may be defined in a shared library.
+    pub const INDIRECT_PC_RELATIVE: Self = Self::PC_RELATIVE.indirect();
+
+    pub const fn new(format: PointerFormat, application: PointerApplication) -> Self {
+        Self {
+            format,
+            application,
+            indirect: false,
+        }
+    }
+    pub const fn indirect(self) -> Self {
+        Self {
+            indirect: true,
+            ..self
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        let indirect = if self.indirect { DW_EH_PE_INDIRECT } else { 0 };
+        self.format.code() | self.application.code() | indirect
+    }
+    /// Decodes a `DW_EH_PE_*` byte, `None` for [`DW_EH_PE_OMIT`] and unknown encodings.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        let format = PointerFormat::ALL
+            .into_iter()
+            .find(|format| format.code() == byte & 0x0f)?;
+        let application = PointerApplication::ALL
+            .into_iter()
+            .find(|application| application.code() == byte & 0x70)?;
+        Some(Self {
+            format,
+            application,
+            indirect: byte & DW_EH_PE_INDIRECT != 0,
+        })
+    }
+
+    /// The directive storing a pointer in this encoding, or `None` if the assembler
+    /// cannot compute it. Assemblers only handle fixed sizes, absolute or PC relative.
+    fn data_size(self) -> Option<DataSize> {
+        match self.application {
+            PointerApplication::Absolute | PointerApplication::PcRelative => {
+                self.format.data_size()
+            }
+            _ => None,
+        }
+    }
+    /// The symbol the stored pointer refers to for `symbol`.
+    fn target(self, symbol: Label) -> String {
+        if self.indirect {
+            indirect_pointer_name(symbol)
+        } else {
+            symbol.label.to_string()
+        }
+    }
+}
+impl Display for PointerEncoding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#04x}", self.to_byte())
+    }
+}
+
+/// The size and signedness of a stored pointer, the low nibble of a `DW_EH_PE_*` byte.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PointerFormat {
+    /// A pointer sized value, 8 bytes on x86-64.
+    Absolute,
+    Uleb128,
+    Udata2,
+    Udata4,
+    Udata8,
+    Sleb128,
+    Sdata2,
+    Sdata4,
+    Sdata8,
+}
+impl PointerFormat {
+    pub const ALL: [Self; 9] = [
+        Self::Absolute,
+        Self::Uleb128,
+        Self::Udata2,
+        Self::Udata4,
+        Self::Udata8,
+        Self::Sleb128,
+        Self::Sdata2,
+        Self::Sdata4,
+        Self::Sdata8,
+    ];
+
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Absolute => 0x00,
+            Self::Uleb128 => 0x01,
+            Self::Udata2 => 0x02,
+            Self::Udata4 => 0x03,
+            Self::Udata8 => 0x04,
+            Self::Sleb128 => 0x09,
+            Self::Sdata2 => 0x0a,
+            Self::Sdata4 => 0x0b,
+            Self::Sdata8 => 0x0c,
+        }
+    }
+    /// The size of a stored pointer in bytes, `None` for the variable length formats.
+    pub fn size(self) -> Option<usize> {
+        self.data_size().map(DataSize::in_bytes)
+    }
+    fn data_size(self) -> Option<DataSize> {
+        match self {
+            Self::Udata2 | Self::Sdata2 => Some(DataSize::Word),
+            Self::Udata4 | Self::Sdata4 => Some(DataSize::Long),
+            Self::Absolute | Self::Udata8 | Self::Sdata8 => Some(DataSize::Quad),
+            Self::Uleb128 | Self::Sleb128 => None,
+        }
+    }
+}
+
+/// What a stored pointer is relative to, bits 4 to 6 of a `DW_EH_PE_*` byte.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PointerApplication {
+    Absolute,
+    /// Relative to the address of the pointer itself.
+    PcRelative,
+    TextRelative,
+    DataRelative,
+    /// Relative to the start of the function.
+    FunctionRelative,
+    Aligned,
+}
+impl PointerApplication {
+    pub const ALL: [Self; 6] = [
+        Self::Absolute,
+        Self::PcRelative,
+        Self::TextRelative,
+        Self::DataRelative,
+        Self::FunctionRelative,
+        Self::Aligned,
+    ];
+
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Absolute => 0x00,
+            Self::PcRelative => 0x10,
+            Self::TextRelative => 0x20,
+            Self::DataRelative => 0x30,
+            Self::FunctionRelative => 0x40,
+            Self::Aligned => 0x50,
+        }
+    }
+}
+
+/// The name of the pointer to `symbol` that indirect encodings refer to.
+pub fn indirect_pointer_name(symbol: Label) -> String {
+    format!("DW.ref.{symbol}")
+}
+
+/// A range of instructions that may throw and how its exceptions are handled.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CallSite<'a> {
+    /// The label at the first instruction of the range.
+    pub start: Label<'a>,
+    /// The label just past the last instruction of the range.
+    pub end: Label<'a>,
+    /// Where execution continues if an exception is thrown in the range, `None` to let
+    /// every exception pass.
+    pub landing_pad: Option<Label<'a>>,
+    /// The type info symbols of the exceptions the landing pad catches, `None` catching
+    /// all. The selector passed to the landing pad is the index of the matching type in
+    /// [`ExceptionTable::types`], starting at 1.
+    pub catches: Vec<Option<Label<'a>>>,
+    /// Whether the landing pad is also entered for exceptions it does not catch, with a
+    /// selector of 0, to clean up before they are resumed.
+    pub cleanup: bool,
+}
+impl<'a> CallSite<'a> {
+    pub fn new(start: Label<'a>, end: Label<'a>, landing_pad: Option<Label<'a>>) -> Self {
+        Self {
+            start,
+            end,
+            landing_pad,
+            catches: Vec::new(),
+            cleanup: false,
+        }
+    }
+    pub fn catch(mut self, type_info: Option<Label<'a>>) -> Self {
+        self.catches.push(type_info);
+        self
+    }
+    pub fn cleanup(mut self) -> Self {
+        self.cleanup = true;
+        self
+    }
+}
+
+/// The language specific data area of a function, as a `.gcc_except_table` for the C++
+/// personality routine `__gxx_personality_v0` and others following its format.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ExceptionTable<'a> {
+    /// The label of the table, passed to [`AsmWriter::emit_cfi_lsda`].
+    pub label: Label<'a>,
+    /// The label at the start of the function, where its `.cfi_startproc` is.
+    pub function: Label<'a>,
+    /// The ranges that may throw, in address order. An exception thrown outside of them
+    /// terminates the program.
+    pub call_sites: Vec<CallSite<'a>>,
+    /// How the type info pointers are stored.
+    pub type_encoding: PointerEncoding,
+}
+impl<'a> ExceptionTable<'a> {
+    /// An empty table storing type info pointers indirectly, as GCC does.
+    pub fn new(label: impl Into<Label<'a>>, function: impl Into<Label<'a>>) -> Self {
+        Self {
+            label: label.into(),
+            function: function.into(),
+            call_sites: Vec::new(),
+            type_encoding: PointerEncoding::INDIRECT_PC_RELATIVE,
+        }
+    }
+    pub fn add_call_site(&mut self, call_site: CallSite<'a>) {
+        self.call_sites.push(call_site);
+    }
+
+    /// Every label the table refers to, other than type info symbols.
+    pub fn labels(&self) -> impl Iterator<Item = Label<'a>> + '_ {
+        let sites = self
+            .call_sites
+            .iter()
+            .flat_map(|site| [site.start, site.end].into_iter().chain(site.landing_pad));
+        [self.label, self.function].into_iter().chain(sites)
+    }
+    /// The type info symbols in the order of their indices, starting at 1.
+    pub fn types(&self) -> Vec<Option<Label<'a>>> {
+        let mut types = Vec::new();
+        for &type_info in self.call_sites.iter().flat_map(|site| &site.catches) {
+            if !types.contains(&type_info) {
+                types.push(type_info);
+            }
+        }
+        types
+    }
+    /// The type info symbols stored through indirect pointers, which the caller has to
+    /// define once per object file with [`AsmWriter::emit_indirect_pointer`].
+    pub fn indirect_symbols(&self) -> Vec<Label<'a>> {
+        if !self.type_encoding.indirect {
+            return Vec::new();
+        }
+        self.types().into_iter().flatten().collect()
+    }
+
+    /// The action table, and the action of each call site as offset into it plus 1, or 0
+    /// if the site has no landing pad or one that only cleans up. Sites with the same
+    /// actions share them.
+    fn actions(&self, types: &[Option<Label<'a>>]) -> (Vec<u8>, Vec<u64>) {
+        let mut table = Vec::new();
+        let mut chains: Vec<(Vec<i64>, u64)> = Vec::new();
+        let mut actions = Vec::new();
+        for site in &self.call_sites {
+            assert!(
+                site.landing_pad.is_some() || (site.catches.is_empty() && !site.cleanup),
+                "call site at {} catches exceptions without a landing pad",
+                site.start
+            );
+            // Each filter is the index of a type, or 0 to clean up.
+            let mut filters: Vec<_> = site
+                .catches
+                .iter()
+                .map(|type_info| types.iter().position(|t| t == type_info).unwrap() as i64 + 1)
+                .collect();
+            if filters.is_empty() {
+                actions.push(0);
+                continue;
+            }
+            if site.cleanup {
+                filters.push(0);
+            }
+
+            if let Some(&(_, action)) = chains.iter().find(|(chain, _)| *chain == filters) {
+                actions.push(action);
+                continue;
+            }
+            let action = table.len() as u64 + 1;
+            for (i, &filter) in filters.iter().enumerate() {
+                table.extend(sleb128(filter));
+                // The next record follows right after the one byte displacement, which
+                // is relative to its own position.
+                let next = if i + 1 < filters.len() { 1 } else { 0 };
+                table.extend(sleb128(next));
+            }
+            chains.push((filters, action));
+            actions.push(action);
+        }
+        (table, actions)
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Names the personality routine the unwinder calls for the current function, which
+    /// has to be between `.cfi_startproc` and `.cfi_endproc`.
+    pub fn emit_cfi_personality<'a>(
+        &mut self,
+        encoding: PointerEncoding,
+        personality: impl Into<Label<'a>>,
+    ) -> io::Result<()> {
+        assert_assemblable(encoding);
+        let personality = personality.into();
+        self.reference_symbol(personality.label);
+        let target = encoding.target(personality);
+        if self.cfi_mode() == CfiMode::EhFrame {
+            self.record_personality(encoding, target)?;
+            return Ok(());
+        }
+        self.directive(format_args!(".cfi_personality {encoding}, {target}"))
+    }
+    /// Names the language specific data area the personality routine is passed for the
+    /// current function, usually an [`ExceptionTable`]. Local labels are renamed like
+    /// those of functions.
+    pub fn emit_cfi_lsda<'a>(
+        &mut self,
+        encoding: PointerEncoding,
+        lsda: impl Into<Label<'a>>,
+    ) -> io::Result<()> {
+        assert_assemblable(encoding);
+        let lsda = lsda.into();
+        let renamed = self.rename_local_labels([lsda]);
+        let name = renamed.as_ref().and_then(|names| names.get(lsda.label));
+        let target = encoding.target(name.map_or(lsda, |name| Label::new(name)));
+        if self.cfi_mode() == CfiMode::EhFrame {
+            self.record_lsda(encoding, target)?;
+            return Ok(());
+        }
+        self.directive(format_args!(".cfi_lsda {encoding}, {target}"))
+    }
+
+    /// Defines `DW.ref.<symbol>`, the pointer to `symbol` that indirect encodings refer to.
+    /// It is a hidden COMDAT, so every object file referring to it may define it once.
+    pub fn emit_indirect_pointer<'a>(&mut self, symbol: impl Into<Label<'a>>) -> io::Result<()> {
+        let symbol = symbol.into();
+        let name = indirect_pointer_name(symbol);
+        let section = format!(".data.rel.local.{name}");
+        self.push_section(Section::named(section, "aw", SectionType::Progbits)?.comdat(&name)?)?;
+        self.emit_balign(8)?;
+        self.declare_visibility(name.as_str(), SymbolVisibility::Hidden)?;
+        self.declare_weak(name.as_str())?;
+        self.directive(format_args!(".type {name}, @object"))?;
+        self.directive(format_args!(".size {name}, 8"))?;
+        self.emit_label(name.as_str())?;
+        self.emit_quad(symbol)?;
+        self.pop_section()
+    }
+
+    /// Emits `table` into `.gcc_except_table`, then returns to the current section.
+    /// Local labels are renamed like those of functions.
+    pub fn emit_exception_table(&mut self, table: &ExceptionTable) -> io::Result<()> {
+        let names = self.rename_local_labels(table.labels());
+        let name = |label: Label| -> String {
+            let renamed = names.as_ref().and_then(|names| names.get(label.label));
+            renamed.map_or(label.label, String::as_str).to_string()
+        };
+        let own = name(table.label);
+        // Labels of the table itself, local even if the table label is not.
+        let inner = |suffix: &str| match own.starts_with(".L") {
+            true => format!("{own}_{suffix}"),
+            false => format!(".L{own}_{suffix}"),
+        };
+        let function = name(table.function);
+        let types = table.types();
+        let (actions, site_actions) = table.actions(&types);
+
+        self.push_section(Section::named(
+            ".gcc_except_table",
+            "a",
+            SectionType::Progbits,
+        )?)?;
+        self.emit_balign(4)?;
+        self.emit_label(own.as_str())?;
+        // Landing pads are relative to the start of the function.
+        self.directive(format_args!(".byte {DW_EH_PE_OMIT:#04x}"))?;
+        if types.is_empty() {
+            self.directive(format_args!(".byte {DW_EH_PE_OMIT:#04x}"))?;
+        } else {
+            assert_assemblable(table.type_encoding);
+            let (base, reference) = (inner("types"), inner("types_offset"));
+            self.directive(format_args!(".byte {}", table.type_encoding))?;
+            self.directive(format_args!(".uleb128 {base}-{reference}"))?;
+            self.emit_label(reference.as_str())?;
+        }
+
+        let (sites, sites_end) = (inner("call_sites"), inner("call_sites_end"));
+        self.directive(format_args!(".byte {:#04x}", PointerFormat::Uleb128.code()))?;
+        self.directive(format_args!(".uleb128 {sites_end}-{sites}"))?;
+        self.emit_label(sites.as_str())?;
+        for (site, action) in table.call_sites.iter().zip(site_actions) {
+            let (start, end) = (name(site.start), name(site.end));
+            self.directive(format_args!(".uleb128 {start}-{function}"))?;
+            self.directive(format_args!(".uleb128 {end}-{start}"))?;
+            match site.landing_pad {
+                Some(pad) => {
+                    let pad = name(pad);
+                    self.directive(format_args!(".uleb128 {pad}-{function}"))?;
+                }
+                None => self.emit_byte(0u8)?,
+            }
+            self.emit_uleb128(action)?;
+        }
+        self.emit_label(sites_end.as_str())?;
+
+        if !actions.is_empty() {
+            self.emit_bytes(&actions)?;
+        }
+        if !types.is_empty() {
+            // The type info pointers are indexed backwards from the end of the table.
+            self.emit_balign(4)?;
+            for &type_info in types.iter().rev() {
+                self.emit_encoded_pointer(table.type_encoding, type_info)?;
+            }
+            self.emit_label(inner("types").as_str())?;
+        }
+        self.pop_section()
+    }
+    /// Stores a pointer to `symbol`, or a null pointer, as `encoding` describes.
+    fn emit_encoded_pointer(
+        &mut self,
+        encoding: PointerEncoding,
+        symbol: Option<Label>,
+    ) -> io::Result<()> {
+        match symbol {
+            Some(symbol) => self.emit_pointer_to(encoding, &encoding.target(symbol)),
+            None => {
+                let directive = encoding.data_size().unwrap().directive();
+                self.directive(format_args!("{directive} 0"))
+            }
+        }
+    }
+    /// Stores a pointer to `target` as `encoding` describes, where `target` already
+    /// names the indirect pointer if the encoding is indirect.
+    pub(crate) fn emit_pointer_to(
+        &mut self,
+        encoding: PointerEncoding,
+        target: &str,
+    ) -> io::Result<()> {
+        let directive = encoding.data_size().unwrap().directive();
+        match encoding.application {
+            PointerApplication::PcRelative => {
+                self.directive(format_args!("{directive} {target}-."))
+            }
+            _ => self.directive(format_args!("{directive} {target}")),
+        }
+    }
+}
+
+fn assert_assemblable(encoding: PointerEncoding) {
+    assert!(
+        encoding.data_size().is_some(),
+        "pointer encoding {encoding} cannot be emitted by the assembler"
+    );
+}
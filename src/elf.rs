@@ -0,0 +1,331 @@
+use super::{
+    encode::{MachineCode, RelocationKind},
+    io::{self, Write},
+    label::Label,
+};
+use alloc::{vec, vec::Vec};
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const TEXT: u16 = 1;
+
+impl MachineCode<'_> {
+    /// Writes a relocatable x86-64 ELF object with the code in `.text`, like `as` would
+    /// assemble the text output. Labels starting with `.L` stay out of the symbol table.
+    pub fn write_elf(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut strtab = StringTable::default();
+        let mut symtab = Vec::new();
+        symtab.extend(symbol_entry(0, 0, 0, 0, 0, 0));
+        symtab.extend(symbol_entry(0, STB_LOCAL, STT_SECTION, TEXT, 0, 0));
+        let mut indices: Vec<(Label, u32)> = Vec::new();
+        let mut count = 2;
+
+        // Locals have to come before globals.
+        let mut first_global = 0;
+        for global in [false, true] {
+            if global {
+                first_global = count;
+            }
+            let binding = if global { STB_GLOBAL } else { STB_LOCAL };
+            let symbols = self.symbols.iter().filter(|s| s.global == global);
+            for symbol in symbols.filter(|s| s.global || !s.label.label.starts_with(".L")) {
+                let kind = symbol.function_size.map_or(STT_NOTYPE, |_| STT_FUNC);
+                let size = symbol.function_size.unwrap_or(0) as u64;
+                let name = strtab.add(symbol.label.label);
+                let offset = symbol.offset as u64;
+                symtab.extend(symbol_entry(name, binding, kind, TEXT, offset, size));
+                indices.push((symbol.label, count));
+                count += 1;
+            }
+        }
+
+        let mut rela = Vec::new();
+        for relocation in &self.relocations {
+            let (index, addend) = match self.symbol(relocation.symbol) {
+                // Local symbols are referenced through the section, as `as` does.
+                Some(s) if !s.global => (1, relocation.addend + s.offset as i64),
+                _ => {
+                    let known = indices
+                        .iter()
+                        .find(|(label, _)| *label == relocation.symbol);
+                    let index = match known {
+                        Some(&(_, index)) => index,
+                        None => {
+                            let name = strtab.add(relocation.symbol.label);
+                            symtab.extend(symbol_entry(name, STB_GLOBAL, STT_NOTYPE, 0, 0, 0));
+                            indices.push((relocation.symbol, count));
+                            count += 1;
+                            count - 1
+                        }
+                    };
+                    (index, relocation.addend)
+                }
+            };
+            let kind: u64 = match relocation.kind {
+                RelocationKind::Pc32 => 2,
+                RelocationKind::Plt32 => 4,
+                RelocationKind::Absolute32S => 11,
+                RelocationKind::GotPcRel => 9,
+                RelocationKind::TlsGd => 19,
+                RelocationKind::TlsLd => 20,
+                RelocationKind::DtpOff32 => 21,
+                RelocationKind::GotTpOff => 22,
+                RelocationKind::TpOff32 => 23,
+            };
+            rela.extend((relocation.offset as u64).to_le_bytes());
+            rela.extend(((index as u64) << 32 | kind).to_le_bytes());
+            rela.extend(addend.to_le_bytes());
+        }
+
+        let mut shstrtab = StringTable::default();
+        let names = [
+            ".text",
+            ".rela.text",
+            ".symtab",
+            ".strtab",
+            ".shstrtab",
+            ".note.GNU-stack",
+        ]
+        .map(|name| shstrtab.add(name));
+
+        let mut file = vec![0; 64];
+        let mut place = |data: &[u8], alignment: usize| {
+            file.resize(file.len().next_multiple_of(alignment), 0);
+            let offset = file.len() as u64;
+            file.extend(data);
+            offset
+        };
+        let text = place(&self.code, 16);
+        let rela_offset = place(&rela, 8);
+        let symtab_offset = place(&symtab, 8);
+        let strtab_offset = place(&strtab.0, 1);
+        let shstrtab_offset = place(&shstrtab.0, 1);
+        let sections = place(&[], 8);
+
+        let headers = [
+            SectionHeader::default(),
+            SectionHeader {
+                name: names[0],
+                kind: SHT_PROGBITS,
+                flags: SHF_ALLOC | SHF_EXECINSTR,
+                offset: text,
+                size: self.code.len(),
+                alignment: 16,
+                ..SectionHeader::default()
+            },
+            SectionHeader {
+                name: names[1],
+                kind: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset: rela_offset,
+                size: rela.len(),
+                link: 3,
+                info: 1,
+                alignment: 8,
+                entry_size: 24,
+            },
+            SectionHeader {
+                name: names[2],
+                kind: SHT_SYMTAB,
+                offset: symtab_offset,
+                size: symtab.len(),
+                link: 4,
+                info: first_global,
+                alignment: 8,
+                entry_size: 24,
+                ..SectionHeader::default()
+            },
+            SectionHeader {
+                name: names[3],
+                kind: SHT_STRTAB,
+                offset: strtab_offset,
+                size: strtab.0.len(),
+                alignment: 1,
+                ..SectionHeader::default()
+            },
+            SectionHeader {
+                name: names[4],
+                kind: SHT_STRTAB,
+                offset: shstrtab_offset,
+                size: shstrtab.0.len(),
+                alignment: 1,
+                ..SectionHeader::default()
+            },
+            // Marks the stack as not executable.
+            SectionHeader {
+                name: names[5],
+                kind: SHT_PROGBITS,
+                offset: sections,
+                alignment: 1,
+                ..SectionHeader::default()
+            },
+        ];
+        for header in &headers {
+            header.write(&mut file);
+        }
+
+        let mut ident = [0; 16];
+        ident[..4].copy_from_slice(b"\x7fELF");
+        // 64-bit, little-endian, version 1, System V ABI.
+        ident[4..7].copy_from_slice(&[2, 1, 1]);
+        let mut header = Vec::with_capacity(64);
+        header.extend(ident);
+        header.extend(1u16.to_le_bytes()); // ET_REL
+        header.extend(0x3eu16.to_le_bytes()); // EM_X86_64
+        header.extend(1u32.to_le_bytes());
+        header.extend(0u64.to_le_bytes()); // entry
+        header.extend(0u64.to_le_bytes()); // program headers
+        header.extend(sections.to_le_bytes());
+        header.extend(0u32.to_le_bytes()); // flags
+        header.extend(64u16.to_le_bytes());
+        header.extend([0; 4]); // program header size and count
+        header.extend(64u16.to_le_bytes());
+        header.extend((headers.len() as u16).to_le_bytes());
+        header.extend(5u16.to_le_bytes()); // .shstrtab
+        file[..64].copy_from_slice(&header);
+
+        out.write_all(&file)
+    }
+}
+
+#[derive(Default)]
+struct StringTable(Vec<u8>);
+impl StringTable {
+    fn add(&mut self, name: &str) -> u32 {
+        if self.0.is_empty() {
+            self.0.push(0);
+        }
+        let offset = self.0.len() as u32;
+        self.0.extend(name.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+fn symbol_entry(name: u32, binding: u8, kind: u8, section: u16, value: u64, size: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(24);
+    bytes.extend(name.to_le_bytes());
+    bytes.push(binding << 4 | kind);
+    bytes.push(0);
+    bytes.extend(section.to_le_bytes());
+    bytes.extend(value.to_le_bytes());
+    bytes.extend(size.to_le_bytes());
+    bytes
+}
+
+#[derive(Default)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: usize,
+    link: u32,
+    info: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+impl SectionHeader {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.name.to_le_bytes());
+        bytes.extend(self.kind.to_le_bytes());
+        bytes.extend(self.flags.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes()); // address
+        bytes.extend(self.offset.to_le_bytes());
+        bytes.extend((self.size as u64).to_le_bytes());
+        bytes.extend(self.link.to_le_bytes());
+        bytes.extend(self.info.to_le_bytes());
+        bytes.extend(self.alignment.to_le_bytes());
+        bytes.extend(self.entry_size.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::InstructionBuilder, function::FunctionBuilder, register::rax};
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+    fn u64_at(bytes: &[u8], offset: usize) -> usize {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+    }
+    fn name_at(table: &[u8], offset: u32) -> &str {
+        let name = &table[offset as usize..];
+        let end = name.iter().position(|&b| b == 0).unwrap();
+        core::str::from_utf8(&name[..end]).unwrap()
+    }
+
+    #[test]
+    fn object_layout() {
+        let mut builder = FunctionBuilder::new("f");
+        builder.set_global(true);
+        builder.build_call(Label::new("g")).unwrap();
+        builder.build_mov(rax(), 0i64).unwrap();
+        builder.build_ret().unwrap();
+        let code = builder.end_function().unwrap().assemble().unwrap();
+        let mut file = Vec::new();
+        code.write_elf(&mut file).unwrap();
+
+        assert_eq!(file[..8], *b"\x7fELF\x02\x01\x01\x00");
+        assert_eq!(u16_at(&file, 16), 1);
+        assert_eq!(u16_at(&file, 18), 0x3e);
+        let (headers, count) = (u64_at(&file, 40), usize::from(u16_at(&file, 60)));
+        assert_eq!(headers + count * 64, file.len());
+        let header = |index: usize| &file[headers + index * 64..][..64];
+        let contents = |index: usize| {
+            let (offset, size) = (u64_at(header(index), 24), u64_at(header(index), 32));
+            &file[offset..offset + size]
+        };
+
+        let shstrtab = contents(usize::from(u16_at(&file, 62)));
+        let names: Vec<_> = (0..count)
+            .map(|index| name_at(shstrtab, u32_at(header(index), 0)))
+            .collect();
+        let expected = [
+            "",
+            ".text",
+            ".rela.text",
+            ".symtab",
+            ".strtab",
+            ".shstrtab",
+            ".note.GNU-stack",
+        ];
+        assert_eq!(names, expected);
+        assert_eq!(contents(1), code.code);
+
+        // The symbol table has the null symbol, the section, then the globals f and g.
+        let (symtab, strtab) = (contents(3), contents(4));
+        assert_eq!(u32_at(header(3), 44), 2);
+        let symbol = |index: usize| &symtab[index * 24..][..24];
+        assert_eq!(symtab.len(), 4 * 24);
+        assert_eq!(name_at(strtab, u32_at(symbol(2), 0)), "f");
+        assert_eq!(symbol(2)[4], 0x12);
+        assert_eq!(u64_at(symbol(2), 16), code.code.len());
+        assert_eq!(name_at(strtab, u32_at(symbol(3), 0)), "g");
+        assert_eq!(u16_at(symbol(3), 6), 0);
+
+        // A single R_X86_64_PLT32 to g, the displacement of the call.
+        let rela = contents(2);
+        assert_eq!(rela.len(), 24);
+        assert_eq!(u64_at(rela, 0), 1);
+        assert_eq!(u64_at(rela, 8), 3 << 32 | 4);
+        assert_eq!(u64_at(rela, 16) as i64, -4);
+    }
+}
@@ -0,0 +1,1930 @@
+use super::{
+    args::{Arg, ArgSize, ConstInt, Memory, MemoryKind, Scale, Segment},
+    function::{Function, Item},
+    instruction::Instruction,
+    island::ConstantValue,
+    label::{Label, RelocationModifier},
+    module::Module,
+    register::{
+        MaskRegister, Masking, Register, RegisterName, RegisterSize, VectorRegister, VectorSize,
+    },
+    writer::{
+        BinaryOp, BinaryOpDouble, BinaryOpSingle, Condition, NonaryOp, RepPrefix, SseOp, StringOp,
+        TernaryOp, UnaryOp, UnaryOpSingle, VectorOp,
+    },
+};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{error::Error, fmt::Display};
+
+/// How the value patched in by a [`Relocation`] is computed, after the x86-64 ELF
+/// relocation types.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RelocationKind {
+    /// `R_X86_64_PC32`: the address relative to the patched field.
+    Pc32,
+    /// `R_X86_64_PLT32`: like [`Self::Pc32`], but may go through the PLT. Used for calls
+    /// and jumps.
+    Plt32,
+    /// `R_X86_64_32S`: the absolute address, sign-extended from 32 bits.
+    Absolute32S,
+    /// `R_X86_64_GOTPCREL`: the GOT entry of the symbol relative to the patched field.
+    GotPcRel,
+    /// `R_X86_64_TLSGD`: the general dynamic TLS GOT entries, relative to the field.
+    TlsGd,
+    /// `R_X86_64_TLSLD`: the local dynamic TLS GOT entries, relative to the field.
+    TlsLd,
+    /// `R_X86_64_DTPOFF32`: the offset within the module's TLS block.
+    DtpOff32,
+    /// `R_X86_64_GOTTPOFF`: the GOT entry of the thread pointer offset, relative to the
+    /// field.
+    GotTpOff,
+    /// `R_X86_64_TPOFF32`: the offset from the thread pointer.
+    TpOff32,
+}
+impl RelocationKind {
+    pub fn is_pc_relative(self) -> bool {
+        matches!(
+            self,
+            Self::Pc32 | Self::Plt32 | Self::GotPcRel | Self::TlsGd | Self::TlsLd | Self::GotTpOff
+        )
+    }
+    /// The relocation of a reference to `label` in a field that would otherwise take
+    /// `kind`, which is pc-relative for rip-relative addresses and branches.
+    fn of(label: Label, kind: RelocationKind) -> Result<Self, String> {
+        use RelocationModifier::*;
+        let pc_relative = kind.is_pc_relative();
+        Ok(match label.modifier {
+            None => kind,
+            Some(Plt) if pc_relative => Self::Plt32,
+            Some(GotPcRel) if pc_relative => Self::GotPcRel,
+            Some(TlsGd) if pc_relative => Self::TlsGd,
+            Some(TlsLd) if pc_relative => Self::TlsLd,
+            Some(GotTpOff) if pc_relative => Self::GotTpOff,
+            Some(DtpOff) if !pc_relative => Self::DtpOff32,
+            Some(TpOff) if !pc_relative => Self::TpOff32,
+            Some(_) => return Err(format!("cannot encode a reference to {label}")),
+        })
+    }
+}
+
+/// A 32-bit field to be patched with the address of `symbol` plus `addend`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Relocation<'a> {
+    /// The offset of the field in the code.
+    pub offset: usize,
+    pub symbol: Label<'a>,
+    pub addend: i64,
+    pub kind: RelocationKind,
+}
+
+/// A label defined in [`MachineCode`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Symbol<'a> {
+    pub label: Label<'a>,
+    pub offset: usize,
+    pub global: bool,
+    /// The size of the body and constants if this is the name of a function.
+    pub function_size: Option<usize>,
+}
+
+/// Encoded functions with the labels they define and the references left for the linker.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MachineCode<'a> {
+    pub code: Vec<u8>,
+    pub symbols: Vec<Symbol<'a>>,
+    /// References to global or undefined symbols, and absolute addresses.
+    pub relocations: Vec<Relocation<'a>>,
+}
+impl<'a> MachineCode<'a> {
+    pub fn symbol(&self, label: Label) -> Option<&Symbol<'a>> {
+        self.symbols.iter().find(|s| s.label == label)
+    }
+
+    fn append(&mut self, other: MachineCode<'a>) {
+        let base = self.code.len();
+        self.code.extend(other.code);
+        self.symbols
+            .extend(other.symbols.into_iter().map(|symbol| Symbol {
+                offset: symbol.offset + base,
+                ..symbol
+            }));
+        self.relocations
+            .extend(other.relocations.into_iter().map(|relocation| Relocation {
+                offset: relocation.offset + base,
+                ..relocation
+            }));
+    }
+    /// Patches pc-relative references to local symbols, which no linker can redirect.
+    fn resolve_local(&mut self) -> Result<(), EncodeError> {
+        let mut offsets = BTreeMap::new();
+        for symbol in &self.symbols {
+            if offsets.insert(symbol.label, symbol).is_some() {
+                return Err(EncodeError {
+                    message: format!("{} is defined more than once", symbol.label),
+                });
+            }
+        }
+
+        let code = &mut self.code;
+        self.relocations.retain(|relocation| {
+            let local = offsets.get(&relocation.symbol).filter(|s| !s.global);
+            let Some(symbol) = local.filter(|_| relocation.kind.is_pc_relative()) else {
+                return true;
+            };
+            let value = symbol.offset as i64 + relocation.addend - relocation.offset as i64;
+            let field = &mut code[relocation.offset..relocation.offset + 4];
+            field.copy_from_slice(&(value as i32).to_le_bytes());
+            false
+        });
+        Ok(())
+    }
+}
+
+/// Returned for instructions, operands and label layouts that have no encoding.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EncodeError {
+    pub message: String,
+}
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl Error for EncodeError {}
+
+impl<'a> Instruction<'a> {
+    /// Appends the machine code to `code` and returns the relocations it needs, with
+    /// offsets into `code`. Jumps and calls to labels always take the 32-bit form and are
+    /// left to relocations.
+    pub fn encode(&self, code: &mut Vec<u8>) -> Result<Vec<Relocation<'a>>, EncodeError> {
+        let start = code.len();
+        let mut encoder = Encoder {
+            code,
+            relocations: Vec::new(),
+        };
+        match encoder.instruction(self) {
+            Ok(()) => {
+                // The processor adds pc-relative displacements to the end of the instruction.
+                let end = encoder.code.len();
+                let mut relocations = encoder.relocations;
+                for relocation in &mut relocations {
+                    if relocation.kind.is_pc_relative() {
+                        relocation.addend -= (end - relocation.offset) as i64;
+                    }
+                }
+                Ok(relocations)
+            }
+            Err(message) => {
+                code.truncate(start);
+                // Instructions without a textual form can only be shown as they are built.
+                let message = match self.check_operands() {
+                    Ok(()) => format!("cannot encode `{self}`: {message}"),
+                    Err(_) => format!("cannot encode {self:?}: {message}"),
+                };
+                Err(EncodeError { message })
+            }
+        }
+    }
+}
+
+impl<'a> Function<'a> {
+    /// Encodes the body and constants like the assembler would lay them out, with jumps
+    /// to labels of the function in their shortest form.
+    pub fn assemble(&self) -> Result<MachineCode<'a>, EncodeError> {
+        let mut machine_code = self.layout(0)?;
+        machine_code.resolve_local()?;
+        Ok(machine_code)
+    }
+
+    /// Lays the function out to start at `base`, which alignment is relative to.
+    fn layout(&self, base: usize) -> Result<MachineCode<'a>, EncodeError> {
+        let defined: Vec<_> = self
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Label(label) => Some(*label),
+                _ => None,
+            })
+            .collect();
+
+        let mut fragments = Vec::new();
+        for item in &self.items {
+            if let Item::Label(label) = *item {
+                if let Some(alignment) = self.alignment_of(label) {
+                    fragments.push(Fragment::Align {
+                        alignment: alignment.in_bytes(),
+                        max_skip: alignment.max_skip as usize,
+                    });
+                }
+            }
+            let fragment = match *item {
+                Item::Label(label) => Fragment::Label(label),
+                Item::Instruction(Instruction::Jmp { target }, _)
+                    if jumps_within(&defined, target) =>
+                {
+                    Fragment::jump(None, target)
+                }
+                Item::Instruction(Instruction::CJmp { condition, target }, _)
+                    if jumps_within(&defined, target) =>
+                {
+                    Fragment::jump(Some(condition), target)
+                }
+                Item::Instruction(instruction, _) => {
+                    let mut code = Vec::new();
+                    let relocations = instruction.encode(&mut code)?;
+                    Fragment::Code(code, relocations)
+                }
+                Item::Comment(_) => continue,
+            };
+            fragments.push(fragment);
+        }
+
+        // Largest first and shared by equal values, like the constant island of the text
+        // output.
+        let mut constants = self.constants.clone();
+        constants.sort_by_key(|constant| core::cmp::Reverse(constant.value.size()));
+        let mut alignment = 0;
+        let mut emitted = Vec::new();
+        for constant in &constants {
+            if emitted.contains(&constant.value) {
+                continue;
+            }
+            emitted.push(constant.value);
+            let size = constant.value.size();
+            if size != alignment {
+                fragments.push(Fragment::Align {
+                    alignment: size,
+                    max_skip: size,
+                });
+                alignment = size;
+            }
+            for alias in constants.iter().filter(|c| c.value == constant.value) {
+                fragments.push(Fragment::Label(alias.label));
+            }
+            let bytes = match constant.value {
+                ConstantValue::Long(v) => v.to_le_bytes().to_vec(),
+                ConstantValue::Quad(v) => v.to_le_bytes().to_vec(),
+                ConstantValue::Octa(v) => v.to_le_bytes().to_vec(),
+            };
+            fragments.push(Fragment::Code(bytes, Vec::new()));
+        }
+
+        // Jumps start out short and grow until every one of them reaches its target.
+        let labels = loop {
+            let labels = place(&fragments, base);
+            let mut grown = false;
+            let mut offset = base;
+            for fragment in &mut fragments {
+                if let Fragment::Jump { target, short, .. } = fragment {
+                    let displacement = labels[target] as i64 - (offset as i64 + 2);
+                    if *short && i8::try_from(displacement).is_err() {
+                        *short = false;
+                        grown = true;
+                    }
+                }
+                offset += fragment.size(offset);
+            }
+            if !grown {
+                break labels;
+            }
+        };
+
+        let mut machine_code = MachineCode::default();
+        machine_code.symbols.push(Symbol {
+            label: self.name,
+            offset: 0,
+            global: self.global,
+            function_size: None,
+        });
+        for fragment in fragments {
+            let offset = machine_code.code.len();
+            let code = &mut machine_code.code;
+            match fragment {
+                Fragment::Label(label) => machine_code.symbols.push(Symbol {
+                    label,
+                    offset,
+                    global: false,
+                    function_size: None,
+                }),
+                Fragment::Code(bytes, relocations) => {
+                    code.extend(bytes);
+                    let relocations = relocations.into_iter().map(|relocation| Relocation {
+                        offset: relocation.offset + offset,
+                        ..relocation
+                    });
+                    machine_code.relocations.extend(relocations);
+                }
+                Fragment::Jump {
+                    condition,
+                    target,
+                    short,
+                } => {
+                    let opcode = match (condition, short) {
+                        (None, true) => vec![0xeb],
+                        (None, false) => vec![0xe9],
+                        (Some(c), true) => vec![0x70 + condition_code(c)],
+                        (Some(c), false) => vec![0x0f, 0x80 + condition_code(c)],
+                    };
+                    code.extend(opcode);
+                    let end = base + code.len() + if short { 1 } else { 4 };
+                    let displacement = labels[&target] as i64 - end as i64;
+                    match short {
+                        true => code.push(displacement as i8 as u8),
+                        false => code.extend((displacement as i32).to_le_bytes()),
+                    }
+                }
+                Fragment::Align { .. } => code.extend(nops(fragment.size(base + offset))),
+            }
+        }
+        machine_code.symbols[0].function_size = Some(machine_code.code.len());
+        Ok(machine_code)
+    }
+}
+
+impl<'a> Module<'a> {
+    /// Encodes every function one after another. Target clones need a dispatcher only
+    /// the text output provides, so they are rejected.
+    pub fn assemble(&self) -> Result<MachineCode<'a>, EncodeError> {
+        if !self.target_clones().is_empty() {
+            return Err(EncodeError {
+                message: "target clones can only be written as text".to_string(),
+            });
+        }
+        let mut machine_code = MachineCode::default();
+        for function in self.functions() {
+            let base = machine_code.code.len();
+            machine_code.append(function.layout(base)?);
+        }
+        machine_code.resolve_local()?;
+        Ok(machine_code)
+    }
+}
+
+/// A piece of a function whose size may depend on where it ends up.
+enum Fragment<'a> {
+    Label(Label<'a>),
+    Code(Vec<u8>, Vec<Relocation<'a>>),
+    /// A jump to a label of the same function, short as long as it reaches.
+    Jump {
+        condition: Option<Condition>,
+        target: Label<'a>,
+        short: bool,
+    },
+    /// Padding up to a multiple of the alignment in bytes, left out if it would take more
+    /// than `max_skip` bytes.
+    Align {
+        alignment: usize,
+        max_skip: usize,
+    },
+}
+impl<'a> Fragment<'a> {
+    fn jump(condition: Option<Condition>, target: Arg<'a>) -> Self {
+        let Arg::Label(target) = target else {
+            unreachable!()
+        };
+        Self::Jump {
+            condition,
+            target,
+            short: true,
+        }
+    }
+    fn size(&self, offset: usize) -> usize {
+        match self {
+            Self::Label(_) => 0,
+            Self::Code(bytes, _) => bytes.len(),
+            Self::Jump { short: true, .. } => 2,
+            Self::Jump {
+                condition: None, ..
+            } => 5,
+            Self::Jump { .. } => 6,
+            Self::Align {
+                alignment,
+                max_skip,
+            } => match offset.next_multiple_of(*alignment) - offset {
+                padding if padding <= *max_skip => padding,
+                _ => 0,
+            },
+        }
+    }
+}
+
+fn jumps_within(defined: &[Label], target: Arg) -> bool {
+    matches!(target, Arg::Label(label) if defined.contains(&label))
+}
+/// The offset of every label when the fragments start at `base`.
+fn place<'a>(fragments: &[Fragment<'a>], base: usize) -> BTreeMap<Label<'a>, usize> {
+    let mut labels = BTreeMap::new();
+    let mut offset = base;
+    for fragment in fragments {
+        if let Fragment::Label(label) = fragment {
+            labels.insert(*label, offset);
+        }
+        offset += fragment.size(offset);
+    }
+    labels
+}
+
+/// Padding of `size` bytes in as few instructions as possible, the same `as` uses.
+pub(crate) fn nops(mut size: usize) -> Vec<u8> {
+    const NOPS: [&[u8]; 12] = [
+        &[],
+        &[0x90],
+        &[0x66, 0x90],
+        &[0x0f, 0x1f, 0x00],
+        &[0x0f, 0x1f, 0x40, 0x00],
+        &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+        &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+        &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+        &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+        &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+        &[0x66, 0x2e, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+        &[
+            0x66, 0x66, 0x2e, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+    ];
+    let mut bytes = Vec::new();
+    while size >= NOPS.len() {
+        bytes.extend(NOPS[NOPS.len() - 1]);
+        size -= NOPS.len() - 1;
+    }
+    bytes.extend(NOPS[size]);
+    bytes
+}
+
+/// The register field of ModRM, which is either a register or an opcode extension.
+#[derive(Copy, Clone)]
+enum Field {
+    Register(Register),
+    Vector(VectorRegister),
+    Mask(MaskRegister),
+    Digit(u8),
+}
+impl Field {
+    fn number(self) -> u8 {
+        match self {
+            Self::Register(register) => number(register.0),
+            Self::Vector(register) => register.0,
+            Self::Mask(register) => register.0,
+            Self::Digit(digit) => digit,
+        }
+    }
+}
+
+/// The fields of a VEX or EVEX prefix that do not come from the operands.
+#[derive(Copy, Clone)]
+struct VexPrefix {
+    /// The implied mandatory prefix: none, `66`, `f3` or `f2`.
+    pp: u8,
+    /// The opcode map: `0f` or `0f38`.
+    map: u8,
+    w: bool,
+    /// The vector length: 128, 256 or 512 bits.
+    l: u8,
+}
+
+/// What an EVEX prefix adds to VEX.
+#[derive(Copy, Clone)]
+struct Evex {
+    masking: Option<Masking>,
+    broadcast: bool,
+    /// The unit of 8-bit displacements in bytes.
+    scale: i64,
+}
+
+#[derive(Copy, Clone)]
+enum Immediate {
+    Byte(i64),
+    Word(i64),
+    Double(i64),
+    Quad(i64),
+}
+
+struct Encoder<'a, 'c> {
+    code: &'c mut Vec<u8>,
+    relocations: Vec<Relocation<'a>>,
+}
+impl<'a> Encoder<'a, '_> {
+    fn instruction(&mut self, instruction: &Instruction<'a>) -> Result<(), String> {
+        let operands = instruction.operands();
+        if let Some(register) = operands.iter().find(|arg| arg.is_virtual()) {
+            return Err(format!("virtual register {register} was never allocated"));
+        }
+        // Forms the assembler rejects have no encoding either.
+        instruction
+            .validate_operands()
+            .map_err(|error| error.to_string())?;
+        let general = !matches!(
+            instruction,
+            Instruction::Binary {
+                op: BinaryOp::Sse(_),
+                ..
+            } | Instruction::Vector { .. }
+        );
+        let special = |arg: &&Arg| matches!(arg, Arg::Vector(_) | Arg::Mask(_));
+        if let Some(arg) = operands.iter().find(special).filter(|_| general) {
+            return Err(format!("{arg} is not a general-purpose operand"));
+        }
+
+        match *instruction {
+            Instruction::Mov { dst, src } => self.mov(dst, src),
+            Instruction::CMov {
+                condition,
+                dst,
+                src,
+            } => {
+                let size = operation_size(dst, src)?;
+                if size == ArgSize::Byte {
+                    return Err("cmov has no 8-bit form".to_string());
+                }
+                let opcode = [0x0f, 0x40 + condition_code(condition)];
+                self.modrm(Some(size), None, &opcode, register(dst)?, src, None)
+            }
+            Instruction::SetCC { condition, dst } => {
+                if dst.size().is_some_and(|size| size != ArgSize::Byte) {
+                    return Err("setcc writes a byte".to_string());
+                }
+                if !dst.is_register() && !dst.is_memory() {
+                    return Err(format!("{dst} is neither a register nor memory"));
+                }
+                let opcode = [0x0f, 0x90 + condition_code(condition)];
+                self.modrm(
+                    Some(ArgSize::Byte),
+                    None,
+                    &opcode,
+                    Field::Digit(0),
+                    dst,
+                    None,
+                )
+            }
+            Instruction::Push { src } => match src {
+                Arg::Register(r) => self.plus_register(stack_size(r.1)?, &[0x50], r, None),
+                Arg::Int(c) => {
+                    stack_size(src.size().map_or(RegisterSize::Quad, Into::into))?;
+                    let value = sign_extended(c, ArgSize::Quad)?;
+                    match i8::try_from(value) {
+                        Ok(_) => self.plain(&[0x6a], Some(Immediate::Byte(value))),
+                        Err(_) => self.plain(&[0x68], Some(Immediate::Double(value))),
+                    }
+                }
+                _ => {
+                    let size = stack_size(src.size().map_or(RegisterSize::Quad, Into::into))?;
+                    self.modrm(size, None, &[0xff], Field::Digit(6), src, None)
+                }
+            },
+            Instruction::Pop { dst } => match dst {
+                Arg::Register(r) => self.plus_register(stack_size(r.1)?, &[0x58], r, None),
+                _ => {
+                    let size = stack_size(dst.size().map_or(RegisterSize::Quad, Into::into))?;
+                    self.modrm(size, None, &[0x8f], Field::Digit(0), dst, None)
+                }
+            },
+            Instruction::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src,
+            } => self.binary(op, dst, src),
+            Instruction::Binary {
+                op: BinaryOp::Double(op),
+                dst,
+                src,
+            } => {
+                let from = src.size().ok_or("the source size is unknown")?;
+                let opcode: &[u8] = match (op, from) {
+                    (BinaryOpDouble::Movzx, ArgSize::Byte) => &[0x0f, 0xb6],
+                    (BinaryOpDouble::Movzx, ArgSize::Word) => &[0x0f, 0xb7],
+                    (BinaryOpDouble::Movsx, ArgSize::Byte) => &[0x0f, 0xbe],
+                    (BinaryOpDouble::Movsx, ArgSize::Word) => &[0x0f, 0xbf],
+                    (BinaryOpDouble::Movsx, ArgSize::Double) => &[0x63],
+                    _ => return Err("there is no such extension".to_string()),
+                };
+                let size = dst.size().ok_or("the destination size is unknown")?;
+                self.modrm(Some(size), None, opcode, register(dst)?, src, None)
+            }
+            Instruction::Binary {
+                op: BinaryOp::Sse(op),
+                dst,
+                src,
+            } => self.sse(op, dst, src),
+            Instruction::Unary {
+                op: UnaryOp::Single(op),
+                dst,
+            } => {
+                let size = dst.size().ok_or("the operand size is unknown")?;
+                if op == UnaryOpSingle::Bswap {
+                    let Arg::Register(r) = dst else {
+                        return Err("bswap needs a register".to_string());
+                    };
+                    if !matches!(size, ArgSize::Double | ArgSize::Quad) {
+                        return Err("bswap takes 32 or 64-bit operands".to_string());
+                    }
+                    return self.plus_register(Some(size), &[0x0f, 0xc8], r, None);
+                }
+                let (opcode, digit) = match op {
+                    UnaryOpSingle::Inc => (0xfe, 0),
+                    UnaryOpSingle::Dec => (0xfe, 1),
+                    UnaryOpSingle::Not => (0xf6, 2),
+                    UnaryOpSingle::Neg => (0xf6, 3),
+                    UnaryOpSingle::Mul => (0xf6, 4),
+                    UnaryOpSingle::IMul => (0xf6, 5),
+                    UnaryOpSingle::Div => (0xf6, 6),
+                    UnaryOpSingle::IDiv => (0xf6, 7),
+                    UnaryOpSingle::Bswap => unreachable!(),
+                };
+                let opcode = sized(size, opcode);
+                self.modrm(Some(size), None, &[opcode], Field::Digit(digit), dst, None)
+            }
+            Instruction::Unary {
+                op: UnaryOp::Cmpxchg16b,
+                dst,
+            } => {
+                if !is_memory(dst) {
+                    return Err("cmpxchg16b needs a memory operand".to_string());
+                }
+                let size = Some(ArgSize::Quad);
+                self.modrm(size, None, &[0x0f, 0xc7], Field::Digit(1), dst, None)
+            }
+            Instruction::Ternary { op, dst, src, src2 } => {
+                let size = operation_size(dst, src)?;
+                // The BMI instructions share an opcode and differ in the implied prefix.
+                let pp = match op {
+                    TernaryOp::Bextr => Some(0),
+                    TernaryOp::Shlx => Some(1),
+                    TernaryOp::Sarx => Some(2),
+                    TernaryOp::Shrx => Some(3),
+                    _ => None,
+                };
+                if let Some(pp) = pp {
+                    if !matches!(size, ArgSize::Double | ArgSize::Quad) {
+                        return Err(format!("{} takes 32 or 64-bit operands", op.mnemonic()));
+                    }
+                    let prefix = VexPrefix {
+                        pp,
+                        map: 2,
+                        w: size == ArgSize::Quad,
+                        l: 0,
+                    };
+                    return self.vex(prefix, 0xf7, register(dst)?, src, Some(register(src2)?));
+                }
+                let opcode = match op {
+                    TernaryOp::Shld => 0xa4,
+                    TernaryOp::Shrd => 0xac,
+                    _ => unreachable!(),
+                };
+                let (opcode, count) = shift_count(opcode, src2)?;
+                self.modrm(
+                    Some(size),
+                    None,
+                    &[0x0f, opcode],
+                    register(src)?,
+                    dst,
+                    count,
+                )
+            }
+            Instruction::ThreeAddress { .. } => {
+                Err("three-address instructions have to be legalized first".to_string())
+            }
+            Instruction::Call { target } => self.branch(target, &[0xe8], 2),
+            Instruction::Jmp { target } => self.branch(target, &[0xe9], 4),
+            Instruction::CJmp { condition, target } => match target {
+                Arg::Label(_) | Arg::SymExpr(_) => {
+                    self.branch(target, &[0x0f, 0x80 + condition_code(condition)], 0)
+                }
+                _ => Err("conditional jumps need a label".to_string()),
+            },
+            Instruction::Nonary { op } => {
+                let bytes: &[u8] = match op {
+                    NonaryOp::Ret => &[0xc3],
+                    NonaryOp::Cpuid => &[0x0f, 0xa2],
+                    NonaryOp::Xgetbv => &[0x0f, 0x01, 0xd0],
+                    NonaryOp::Rdmsr => &[0x0f, 0x32],
+                    NonaryOp::Wrmsr => &[0x0f, 0x30],
+                    NonaryOp::Swapgs => &[0x0f, 0x01, 0xf8],
+                    NonaryOp::Iretq => &[0x48, 0xcf],
+                    NonaryOp::Cld => &[0xfc],
+                    NonaryOp::Sysretq => &[0x48, 0x0f, 0x07],
+                    NonaryOp::Cltq => &[0x48, 0x98],
+                    NonaryOp::Cbtw => &[0x66, 0x98],
+                    NonaryOp::Cwtl => &[0x98],
+                    NonaryOp::Cwtd => &[0x66, 0x99],
+                    NonaryOp::Cltd => &[0x99],
+                    NonaryOp::Cqto => &[0x48, 0x99],
+                    NonaryOp::Mfence => &[0x0f, 0xae, 0xf0],
+                    NonaryOp::Lfence => &[0x0f, 0xae, 0xe8],
+                    NonaryOp::Sfence => &[0x0f, 0xae, 0xf8],
+                };
+                self.plain(bytes, None)
+            }
+            Instruction::String { op, size, rep } => {
+                if size == ArgSize::Word {
+                    self.code.push(0x66);
+                }
+                match rep {
+                    Some(RepPrefix::Rep | RepPrefix::Repe) => self.code.push(0xf3),
+                    Some(RepPrefix::Repne) => self.code.push(0xf2),
+                    None => (),
+                }
+                if size == ArgSize::Quad {
+                    self.code.push(0x48);
+                }
+                let opcode = match op {
+                    StringOp::Movs => 0xa4,
+                    StringOp::Cmps => 0xa6,
+                    StringOp::Stos => 0xaa,
+                    StringOp::Lods => 0xac,
+                    StringOp::Scas => 0xae,
+                };
+                self.plain(&[sized(size, opcode)], None)
+            }
+            Instruction::Vector { .. } => self.vector(instruction),
+            Instruction::Locked { dst, .. } => {
+                if !is_memory(dst) {
+                    return Err("lock needs a memory destination".to_string());
+                }
+                let start = self.code.len();
+                self.instruction(&instruction.unlocked())?;
+                // The assembler puts lock after the segment, address and operand size
+                // prefixes, but before REX.
+                let prefixes = self.code[start..]
+                    .iter()
+                    .take_while(|byte| matches!(byte, 0x64..=0x67))
+                    .count();
+                self.code.insert(start + prefixes, 0xf0);
+                for relocation in &mut self.relocations {
+                    if relocation.offset >= start {
+                        relocation.offset += 1;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn mov(&mut self, dst: Arg<'a>, src: Arg<'a>) -> Result<(), String> {
+        let size = operation_size(dst, src)?;
+        match (dst, src) {
+            (Arg::Register(r), Arg::Int(c)) => {
+                let value = c.value();
+                if size == ArgSize::Quad && i32::try_from(value).is_ok() {
+                    let imm = Some(Immediate::Double(value));
+                    self.modrm(Some(size), None, &[0xc7], Field::Digit(0), dst, imm)
+                } else {
+                    let imm = match size {
+                        ArgSize::Byte => Immediate::Byte(value),
+                        ArgSize::Word => Immediate::Word(value),
+                        ArgSize::Double => Immediate::Double(value),
+                        ArgSize::Quad => Immediate::Quad(value),
+                    };
+                    let opcode = if size == ArgSize::Byte { 0xb0 } else { 0xb8 };
+                    self.plus_register(Some(size), &[opcode], r, Some(imm))
+                }
+            }
+            (_, Arg::Int(c)) => {
+                let imm = immediate(c, size)?;
+                let opcode = sized(size, 0xc6);
+                self.modrm(Some(size), None, &[opcode], Field::Digit(0), dst, Some(imm))
+            }
+            (_, Arg::Register(r)) => {
+                let opcode = sized(size, 0x88);
+                self.modrm(Some(size), None, &[opcode], Field::Register(r), dst, None)
+            }
+            (Arg::Register(r), _) => {
+                let opcode = sized(size, 0x8a);
+                self.modrm(Some(size), None, &[opcode], Field::Register(r), src, None)
+            }
+            _ => Err("one operand has to be a register or immediate".to_string()),
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOpSingle, dst: Arg<'a>, src: Arg<'a>) -> Result<(), String> {
+        use BinaryOpSingle::*;
+        let size = operation_size(dst, src)?;
+        let alu = match op {
+            Add => Some(0),
+            Or => Some(1),
+            Adc => Some(2),
+            Sbb => Some(3),
+            And => Some(4),
+            Sub => Some(5),
+            Xor => Some(6),
+            Cmp => Some(7),
+            _ => None,
+        };
+        if let Some(digit) = alu {
+            return match (dst, src) {
+                (_, Arg::Int(c)) => {
+                    let imm = immediate(c, size)?;
+                    let value = sign_extended(c, size)?;
+                    if size == ArgSize::Byte && is_accumulator(dst) {
+                        self.sized_plain(size, &[digit * 8 + 4], imm)
+                    } else if size == ArgSize::Byte {
+                        self.modrm(
+                            Some(size),
+                            None,
+                            &[0x80],
+                            Field::Digit(digit),
+                            dst,
+                            Some(imm),
+                        )
+                    } else if i8::try_from(value).is_ok() {
+                        let imm = Some(Immediate::Byte(value));
+                        self.modrm(Some(size), None, &[0x83], Field::Digit(digit), dst, imm)
+                    } else if is_accumulator(dst) {
+                        self.sized_plain(size, &[digit * 8 + 5], imm)
+                    } else {
+                        self.modrm(
+                            Some(size),
+                            None,
+                            &[0x81],
+                            Field::Digit(digit),
+                            dst,
+                            Some(imm),
+                        )
+                    }
+                }
+                (_, Arg::Register(r)) => {
+                    let opcode = sized(size, digit * 8);
+                    self.modrm(Some(size), None, &[opcode], Field::Register(r), dst, None)
+                }
+                (Arg::Register(r), _) => {
+                    let opcode = sized(size, digit * 8 + 2);
+                    self.modrm(Some(size), None, &[opcode], Field::Register(r), src, None)
+                }
+                _ => Err("one operand has to be a register or immediate".to_string()),
+            };
+        }
+
+        if size == ArgSize::Byte && matches!(op, IMul | Lea | Movbe | Popcnt) {
+            return Err(format!("{} has no 8-bit form", op.mnemonic()));
+        }
+        match op {
+            IMul => match src {
+                Arg::Int(c) => {
+                    let value = sign_extended(c, size)?;
+                    let (opcode, imm) = match i8::try_from(value) {
+                        Ok(_) => (0x6b, Immediate::Byte(value)),
+                        Err(_) => (0x69, immediate(c, size)?),
+                    };
+                    self.modrm(Some(size), None, &[opcode], register(dst)?, dst, Some(imm))
+                }
+                _ => self.modrm(Some(size), None, &[0x0f, 0xaf], register(dst)?, src, None),
+            },
+            Lea => {
+                if !is_memory(src) {
+                    return Err("lea needs a memory operand".to_string());
+                }
+                self.modrm(Some(size), None, &[0x8d], register(dst)?, src, None)
+            }
+            Test => match (dst, src) {
+                (_, Arg::Int(c)) => {
+                    let imm = immediate(c, size)?;
+                    match is_accumulator(dst) {
+                        true => self.sized_plain(size, &[sized(size, 0xa8)], imm),
+                        false => {
+                            let opcode = sized(size, 0xf6);
+                            self.modrm(Some(size), None, &[opcode], Field::Digit(0), dst, Some(imm))
+                        }
+                    }
+                }
+                (_, Arg::Register(r)) | (Arg::Register(r), _) => {
+                    let rm = if src == Arg::Register(r) { dst } else { src };
+                    let opcode = sized(size, 0x84);
+                    self.modrm(Some(size), None, &[opcode], Field::Register(r), rm, None)
+                }
+                _ => Err("one operand has to be a register or immediate".to_string()),
+            },
+            Shl | Shr | Sar => {
+                let digit = match op {
+                    Shl => 4,
+                    Shr => 5,
+                    _ => 7,
+                };
+                let (opcode, imm) = match src {
+                    Arg::Int(c) if c.value() == 1 => (0xd0, None),
+                    Arg::Int(c) => (0xc0, Some(byte_immediate(c)?)),
+                    Arg::Register(Register(RegisterName::C, RegisterSize::Byte)) => (0xd2, None),
+                    _ => return Err("shift counts are immediates or %cl".to_string()),
+                };
+                let opcode = sized(size, opcode);
+                self.modrm(Some(size), None, &[opcode], Field::Digit(digit), dst, imm)
+            }
+            Xchg => match (dst, src) {
+                (Arg::Register(d), Arg::Register(s)) if size != ArgSize::Byte => {
+                    // The short form of `%eax` with itself is `nop`, which does not clear
+                    // the upper half like `xchgl` does.
+                    let short = match (d.0, s.0) {
+                        (RegisterName::A, RegisterName::A) if size == ArgSize::Quad => {
+                            return self.plain(&[0x90], None);
+                        }
+                        (RegisterName::A, RegisterName::A) if size == ArgSize::Double => None,
+                        (RegisterName::A, _) => Some(s),
+                        (_, RegisterName::A) => Some(d),
+                        _ => None,
+                    };
+                    match short {
+                        Some(r) => self.plus_register(Some(size), &[0x90], r, None),
+                        None => {
+                            self.modrm(Some(size), None, &[0x87], Field::Register(s), dst, None)
+                        }
+                    }
+                }
+                (_, Arg::Register(r)) | (Arg::Register(r), _) => {
+                    let rm = if src == Arg::Register(r) { dst } else { src };
+                    let opcode = sized(size, 0x86);
+                    self.modrm(Some(size), None, &[opcode], Field::Register(r), rm, None)
+                }
+                _ => Err("one operand has to be a register".to_string()),
+            },
+            Movbe => match (dst, src) {
+                (Arg::Register(r), _) if is_memory(src) => self.modrm(
+                    Some(size),
+                    None,
+                    &[0x0f, 0x38, 0xf0],
+                    Field::Register(r),
+                    src,
+                    None,
+                ),
+                (_, Arg::Register(r)) if is_memory(dst) => self.modrm(
+                    Some(size),
+                    None,
+                    &[0x0f, 0x38, 0xf1],
+                    Field::Register(r),
+                    dst,
+                    None,
+                ),
+                _ => Err("movbe needs a register and a memory operand".to_string()),
+            },
+            Popcnt => self.modrm(
+                Some(size),
+                Some(0xf3),
+                &[0x0f, 0xb8],
+                register(dst)?,
+                src,
+                None,
+            ),
+            Lzcnt | Tzcnt | Bsf | Bsr => {
+                if size == ArgSize::Byte {
+                    return Err(format!("{} has no 8-bit form", op.mnemonic()));
+                }
+                let (prefix, opcode) = match op {
+                    Lzcnt => (Some(0xf3), 0xbd),
+                    Tzcnt => (Some(0xf3), 0xbc),
+                    Bsf => (None, 0xbc),
+                    _ => (None, 0xbd),
+                };
+                self.modrm(
+                    Some(size),
+                    prefix,
+                    &[0x0f, opcode],
+                    register(dst)?,
+                    src,
+                    None,
+                )
+            }
+            Xadd | Cmpxchg => {
+                let Arg::Register(r) = src else {
+                    return Err(format!("{} needs a register source", op.mnemonic()));
+                };
+                let opcode = sized(size, if op == Xadd { 0xc0 } else { 0xb0 });
+                self.modrm(
+                    Some(size),
+                    None,
+                    &[0x0f, opcode],
+                    Field::Register(r),
+                    dst,
+                    None,
+                )
+            }
+            Bt | Bts | Btr | Btc => {
+                if size == ArgSize::Byte {
+                    return Err(format!("{} has no 8-bit form", op.mnemonic()));
+                }
+                let digit = match op {
+                    Bt => 4,
+                    Bts => 5,
+                    Btr => 6,
+                    _ => 7,
+                };
+                match src {
+                    Arg::Int(c) => {
+                        let imm = Some(byte_immediate(c)?);
+                        self.modrm(
+                            Some(size),
+                            None,
+                            &[0x0f, 0xba],
+                            Field::Digit(digit),
+                            dst,
+                            imm,
+                        )
+                    }
+                    Arg::Register(r) => {
+                        let opcode = 0xa3 + (digit - 4) * 8;
+                        self.modrm(
+                            Some(size),
+                            None,
+                            &[0x0f, opcode],
+                            Field::Register(r),
+                            dst,
+                            None,
+                        )
+                    }
+                    _ => Err("bit offsets are immediates or registers".to_string()),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn sse(&mut self, op: SseOp, dst: Arg<'a>, src: Arg<'a>) -> Result<(), String> {
+        use SseOp::*;
+        let integer = |arg: Arg| match arg.size() {
+            Some(ArgSize::Double) => Ok(None),
+            Some(ArgSize::Quad) => Ok(Some(ArgSize::Quad)),
+            _ => Err("conversions take 32 or 64-bit integers".to_string()),
+        };
+        for arg in [dst, src] {
+            if let Arg::Vector(register) = arg {
+                if register.1 != VectorSize::Xmm || register.0 >= 16 {
+                    return Err(format!("{} cannot encode {register}", op.mnemonic()));
+                }
+            }
+        }
+        match op {
+            Movd | Movq => {
+                // The integer operand decides REX.W, so `movd` of a 64-bit register is `movq`.
+                let integer = if dst.is_vector() { src } else { dst };
+                let w = match integer {
+                    Arg::Register(r) if r.1 == RegisterSize::Quad => Some(ArgSize::Quad),
+                    Arg::Register(r) if r.1 == RegisterSize::Double && op == Movd => None,
+                    Arg::Register(r) => {
+                        return Err(format!("{} cannot move {r}", op.mnemonic()));
+                    }
+                    _ => (op == Movq).then_some(ArgSize::Quad),
+                };
+                return match (dst, src) {
+                    (Arg::Vector(d), Arg::Vector(_)) if op == Movq => {
+                        self.modrm(None, Some(0xf3), &[0x0f, 0x7e], Field::Vector(d), src, None)
+                    }
+                    (Arg::Vector(d), _) if op == Movq && is_memory(src) => {
+                        self.modrm(None, Some(0xf3), &[0x0f, 0x7e], Field::Vector(d), src, None)
+                    }
+                    (_, Arg::Vector(s)) if op == Movq && is_memory(dst) => {
+                        self.modrm(None, Some(0x66), &[0x0f, 0xd6], Field::Vector(s), dst, None)
+                    }
+                    (Arg::Vector(d), Arg::Register(_)) | (Arg::Vector(d), Arg::Memory(_)) => {
+                        self.modrm(w, Some(0x66), &[0x0f, 0x6e], Field::Vector(d), src, None)
+                    }
+                    (Arg::Register(_) | Arg::Memory(_), Arg::Vector(s)) => {
+                        self.modrm(w, Some(0x66), &[0x0f, 0x7e], Field::Vector(s), dst, None)
+                    }
+                    _ => Err(format!("{} moves between %xmm and integers", op.mnemonic())),
+                };
+            }
+            Cvtsi2ss | Cvtsi2sd => {
+                let prefix = if op == Cvtsi2ss { 0xf3 } else { 0xf2 };
+                let size = integer(src)?;
+                return self.modrm(size, Some(prefix), &[0x0f, 0x2a], xmm(dst)?, src, None);
+            }
+            Cvttss2si | Cvttsd2si => {
+                let prefix = if op == Cvttss2si { 0xf3 } else { 0xf2 };
+                let size = integer(dst)?;
+                let reg = register(dst)?;
+                return self.modrm(size, Some(prefix), &[0x0f, 0x2c], reg, src, None);
+            }
+            _ => (),
+        }
+
+        // The mandatory prefix and the opcode loading into the register operand.
+        let (prefix, opcode) = match op {
+            Movss => (Some(0xf3), 0x10),
+            Movsd => (Some(0xf2), 0x10),
+            Movaps => (None, 0x28),
+            Movups => (None, 0x10),
+            Movapd => (Some(0x66), 0x28),
+            Movupd => (Some(0x66), 0x10),
+            Addss | Addsd | Addps | Addpd => (packing(op), 0x58),
+            Mulss | Mulsd | Mulps | Mulpd => (packing(op), 0x59),
+            Subss | Subsd | Subps | Subpd => (packing(op), 0x5c),
+            Divss | Divsd | Divps | Divpd => (packing(op), 0x5e),
+            Sqrtss | Sqrtsd => (packing(op), 0x51),
+            Andps | Andpd => (packing(op), 0x54),
+            Xorps | Xorpd => (packing(op), 0x57),
+            Ucomiss | Ucomisd => (packing(op), 0x2e),
+            Cvtss2sd => (Some(0xf3), 0x5a),
+            Cvtsd2ss => (Some(0xf2), 0x5a),
+            Movd | Movq | Cvtsi2ss | Cvtsi2sd | Cvttss2si | Cvttsd2si => unreachable!(),
+        };
+        let moves = matches!(op, Movss | Movsd | Movaps | Movups | Movapd | Movupd);
+        match (dst, src) {
+            (_, Arg::Vector(s)) if moves && is_memory(dst) => self.modrm(
+                None,
+                prefix,
+                &[0x0f, opcode + 1],
+                Field::Vector(s),
+                dst,
+                None,
+            ),
+            (Arg::Vector(d), Arg::Vector(_)) => {
+                self.modrm(None, prefix, &[0x0f, opcode], Field::Vector(d), src, None)
+            }
+            (Arg::Vector(d), _) if is_memory(src) => {
+                self.modrm(None, prefix, &[0x0f, opcode], Field::Vector(d), src, None)
+            }
+            _ => Err(format!("{} needs an %xmm destination", op.mnemonic())),
+        }
+    }
+
+    fn vector(&mut self, instruction: &Instruction<'a>) -> Result<(), String> {
+        use VectorOp::*;
+        let Instruction::Vector {
+            op,
+            dst,
+            src1,
+            src2,
+            masking,
+            broadcast,
+        } = *instruction
+        else {
+            unreachable!()
+        };
+        // validate_operands rejected the forms without an encoding.
+        if matches!(op, Kmovw | Kmovq) {
+            return self.kmov(op, dst, src2);
+        }
+        let evex = instruction.requires_evex();
+        let size = instruction
+            .vector_size()
+            .ok_or_else(|| format!("{} needs a vector register", op.mnemonic()))?;
+        let broadcasts = matches!(op, Vbroadcastss | Vbroadcastsd);
+        let moves = op.is_move();
+
+        let (pp, map, opcode) = vector_opcode(op);
+        let store = if opcode == 0x6f { 0x7f } else { opcode + 1 };
+        let (opcode, reg, rm) = match (dst, src2) {
+            _ if moves && is_memory(dst) => (store, vector(src2)?, dst),
+            // Like as, move between registers with the store form if that saves the
+            // third VEX byte.
+            (Arg::Vector(d), Arg::Vector(s)) if moves && !evex && s.0 >= 8 && d.0 < 8 => {
+                (store, Field::Vector(s), dst)
+            }
+            _ => (opcode, vector(dst)?, src2),
+        };
+        if !rm.is_vector() && !is_memory(rm) {
+            return Err(format!("{rm} is neither a vector register nor memory"));
+        }
+        let vvvv = src1.map(vector).transpose()?;
+
+        let double = op.element_size() == Some(8);
+        let prefix = VexPrefix {
+            pp,
+            map,
+            // VEX ignores W apart from fused multiply-adds, and as leaves it clear.
+            w: double && (evex || op.accumulates()),
+            l: match size {
+                _ if op.is_scalar() => 0,
+                VectorSize::Xmm => 0,
+                VectorSize::Ymm => 1,
+                VectorSize::Zmm => 2,
+            },
+        };
+        if !evex {
+            return self.vex(prefix, opcode, reg, rm, vvvv);
+        }
+        // Compressed displacements count in elements where only one is loaded, and in
+        // whole vectors otherwise.
+        let element = op.element_size().map_or(1, i64::from);
+        let scale = if broadcast || broadcasts || op.is_scalar() {
+            element
+        } else {
+            size.in_bytes().into()
+        };
+        let evex = Evex {
+            masking,
+            broadcast,
+            scale,
+        };
+        self.evex(prefix, evex, opcode, reg, rm, vvvv)
+    }
+    /// Moves between mask registers, or to and from general purpose registers of the mask
+    /// size or memory.
+    fn kmov(&mut self, op: VectorOp, dst: Arg<'a>, src: Arg<'a>) -> Result<(), String> {
+        let quad = op == VectorOp::Kmovq;
+        let size = if quad {
+            RegisterSize::Quad
+        } else {
+            RegisterSize::Double
+        };
+        let prefix = |pp| VexPrefix {
+            pp,
+            map: 1,
+            w: quad,
+            l: 0,
+        };
+        let general = prefix(if quad { 3 } else { 0 });
+        match (dst, src) {
+            (Arg::Mask(d), Arg::Mask(_)) => self.vex(prefix(0), 0x90, Field::Mask(d), src, None),
+            (Arg::Mask(d), _) if is_memory(src) => {
+                self.vex(prefix(0), 0x90, Field::Mask(d), src, None)
+            }
+            (_, Arg::Mask(s)) if is_memory(dst) => {
+                self.vex(prefix(0), 0x91, Field::Mask(s), dst, None)
+            }
+            (Arg::Mask(d), Arg::Register(r)) if r.1 == size => {
+                self.vex(general, 0x92, Field::Mask(d), src, None)
+            }
+            (Arg::Register(r), Arg::Mask(_)) if r.1 == size => {
+                self.vex(general, 0x93, Field::Register(r), src, None)
+            }
+            _ => Err(format!(
+                "{} moves between mask registers, memory and {}-bit registers",
+                op.mnemonic(),
+                size.in_bytes() * 8
+            )),
+        }
+    }
+
+    /// A call or jump, directly to a symbol or indirectly through `digit` of `0xff`.
+    fn branch(&mut self, target: Arg<'a>, opcode: &[u8], digit: u8) -> Result<(), String> {
+        let (symbol, addend) = match target {
+            Arg::Label(label) => (label, 0),
+            Arg::SymExpr(expr) => (expr.label, expr.offset),
+            // Only AMD honours the operand size prefix of an indirect branch in long mode.
+            _ if matches!(target.size(), None | Some(ArgSize::Quad)) => {
+                return self.modrm(None, None, &[0xff], Field::Digit(digit), target, None);
+            }
+            _ => return Err("indirect branches take 64-bit operands".into()),
+        };
+        let kind = RelocationKind::of(symbol, RelocationKind::Plt32)?;
+        self.code.extend(opcode);
+        self.relocations.push(Relocation {
+            offset: self.code.len(),
+            symbol: symbol.symbol(),
+            addend,
+            kind,
+        });
+        self.code.extend([0; 4]);
+        Ok(())
+    }
+
+    /// An instruction without operands beyond an immediate.
+    fn plain(&mut self, opcode: &[u8], imm: Option<Immediate>) -> Result<(), String> {
+        self.code.extend(opcode);
+        self.immediate(imm);
+        Ok(())
+    }
+    /// An instruction on the accumulator, whose only explicit operand is an immediate.
+    fn sized_plain(&mut self, size: ArgSize, opcode: &[u8], imm: Immediate) -> Result<(), String> {
+        match size {
+            ArgSize::Word => self.code.push(0x66),
+            ArgSize::Quad => self.code.push(0x48),
+            _ => (),
+        }
+        self.plain(opcode, Some(imm))
+    }
+    /// An instruction with the register in the low bits of the last opcode byte.
+    fn plus_register(
+        &mut self,
+        size: Option<ArgSize>,
+        opcode: &[u8],
+        register: Register,
+        imm: Option<Immediate>,
+    ) -> Result<(), String> {
+        if size == Some(ArgSize::Word) {
+            self.code.push(0x66);
+        }
+        let rex = Rex {
+            w: size == Some(ArgSize::Quad),
+            r: false,
+            x: false,
+            b: number(register.0) >= 8,
+            force: needs_rex(register),
+        };
+        rex.write(self.code);
+        let (last, rest) = opcode.split_last().unwrap();
+        self.code.extend(rest);
+        self.code.push(last + (number(register.0) & 7));
+        self.immediate(imm);
+        Ok(())
+    }
+
+    /// An instruction with a ModRM byte, whose `rm` operand is a register or memory.
+    /// Without a `size`, the operand size is the 64-bit default of stack and branch
+    /// instructions.
+    fn modrm(
+        &mut self,
+        size: Option<ArgSize>,
+        mandatory: Option<u8>,
+        opcode: &[u8],
+        reg: Field,
+        rm: Arg<'a>,
+        imm: Option<Immediate>,
+    ) -> Result<(), String> {
+        let memory = memory(rm);
+        self.address_prefixes(memory)?;
+        if size == Some(ArgSize::Word) {
+            self.code.push(0x66);
+        }
+        self.code.extend(mandatory);
+
+        let (x, b) = rm_extensions(rm, memory)?;
+        let byte_register = |field| matches!(field, Field::Register(r) if needs_rex(r));
+        let rex = Rex {
+            w: size == Some(ArgSize::Quad),
+            r: reg.number() >= 8,
+            x,
+            b,
+            force: byte_register(reg) || matches!(rm, Arg::Register(r) if needs_rex(r)),
+        };
+        rex.write(self.code);
+        self.code.extend(opcode);
+        self.operand(reg.number(), rm, memory)?;
+        self.immediate(imm);
+        Ok(())
+    }
+    /// A VEX-encoded instruction, whose `vvvv` field holds the first source if any.
+    /// Uses the two byte form where possible.
+    fn vex(
+        &mut self,
+        prefix: VexPrefix,
+        opcode: u8,
+        reg: Field,
+        rm: Arg<'a>,
+        vvvv: Option<Field>,
+    ) -> Result<(), String> {
+        let memory = memory(rm);
+        self.address_prefixes(memory)?;
+        let (x, b) = rm_extensions(rm, memory)?;
+        let inverted = |bit: bool| u8::from(!bit);
+        let r = reg.number() & 8 != 0;
+        let vvvv = vvvv.map_or(0, Field::number);
+        let last = u8::from(prefix.w) << 7 | (!vvvv & 0xf) << 3 | prefix.l << 2 | prefix.pp;
+        if prefix.map == 1 && !prefix.w && !x && !b {
+            self.code.push(0xc5);
+            self.code.push(inverted(r) << 7 | (last & 0x7f));
+        } else {
+            self.code.push(0xc4);
+            self.code
+                .push(inverted(r) << 7 | inverted(x) << 6 | inverted(b) << 5 | prefix.map);
+            self.code.push(last);
+        }
+        self.code.push(opcode);
+        self.operand(reg.number(), rm, memory)
+    }
+    /// An EVEX-encoded instruction, whose `vvvv` field holds the first source if any.
+    fn evex(
+        &mut self,
+        prefix: VexPrefix,
+        evex: Evex,
+        opcode: u8,
+        reg: Field,
+        rm: Arg<'a>,
+        vvvv: Option<Field>,
+    ) -> Result<(), String> {
+        let memory = memory(rm);
+        self.address_prefixes(memory)?;
+        let (x, b) = rm_extensions(rm, memory)?;
+        let inverted = |bit: bool| u8::from(!bit);
+        let reg = reg.number();
+        let vvvv = vvvv.map_or(0, Field::number);
+        let (z, aaa) = evex
+            .masking
+            .map_or((false, 0), |masking| (masking.zeroing, masking.mask.0));
+        self.code.push(0x62);
+        self.code.push(
+            inverted(reg & 8 != 0) << 7
+                | inverted(x) << 6
+                | inverted(b) << 5
+                | inverted(reg & 16 != 0) << 4
+                | prefix.map,
+        );
+        self.code
+            .push(u8::from(prefix.w) << 7 | (!vvvv & 0xf) << 3 | 1 << 2 | prefix.pp);
+        self.code.push(
+            u8::from(z) << 7
+                | prefix.l << 5
+                | u8::from(evex.broadcast) << 4
+                | inverted(vvvv & 16 != 0) << 3
+                | aaa,
+        );
+        self.code.push(opcode);
+        self.scaled_operand(reg, rm, memory, evex.scale)
+    }
+
+    fn address_prefixes(&mut self, memory: Option<Memory>) -> Result<(), String> {
+        let Some(memory) = memory else {
+            return Ok(());
+        };
+        match memory.segment {
+            Some(Segment::Fs) => self.code.push(0x64),
+            Some(Segment::Gs) => self.code.push(0x65),
+            None => (),
+        }
+        let sizes: Vec<_> = memory.address_registers().iter().map(|r| r.1).collect();
+        match sizes.first() {
+            Some(&size) if sizes.iter().any(|&s| s != size) => {
+                Err("address registers have different sizes".to_string())
+            }
+            Some(RegisterSize::Double) => {
+                self.code.push(0x67);
+                Ok(())
+            }
+            Some(RegisterSize::Quad) | None => Ok(()),
+            Some(_) => Err("addresses need 32 or 64-bit registers".to_string()),
+        }
+    }
+
+    /// The ModRM byte and anything the `rm` operand adds to it.
+    fn operand(&mut self, reg: u8, rm: Arg<'a>, memory: Option<Memory<'a>>) -> Result<(), String> {
+        self.scaled_operand(reg, rm, memory, 1)
+    }
+    /// Like [`Self::operand`], with 8-bit displacements counting in units of `scale` bytes
+    /// as EVEX compresses them.
+    fn scaled_operand(
+        &mut self,
+        reg: u8,
+        rm: Arg<'a>,
+        memory: Option<Memory<'a>>,
+        scale: i64,
+    ) -> Result<(), String> {
+        let modrm = |mode: u8, rm: u8| mode << 6 | (reg & 7) << 3 | (rm & 7);
+        let Some(memory) = memory else {
+            let rm = match rm {
+                Arg::Register(r) => number(r.0),
+                Arg::Vector(r) => r.0,
+                Arg::Mask(r) => r.0,
+                _ => unreachable!(),
+            };
+            self.code.push(modrm(0b11, rm));
+            return Ok(());
+        };
+
+        let displacement = memory.displacement_constant.map_or(0, displacement_value);
+        let label = memory.displacement_label;
+        let sib = match memory.kind {
+            MemoryKind::Rip => {
+                self.code.push(modrm(0b00, 0b101));
+                return self.displacement(label, displacement, RelocationKind::Pc32);
+            }
+            MemoryKind::Sib(sib) => sib,
+        };
+        let index = match sib.index() {
+            Some((index, _)) if index.0 == RegisterName::SP => {
+                return Err("%rsp cannot be an index".to_string());
+            }
+            Some((index, scale)) => Some((number(index.0), scale)),
+            None => None,
+        };
+        let sib_byte = |base: u8| {
+            let (index, scale) = index.unwrap_or((0b100, Scale::One));
+            let scale = match scale {
+                Scale::One => 0,
+                Scale::Two => 1,
+                Scale::Four => 2,
+                Scale::Eight => 3,
+            };
+            scale << 6 | (index & 7) << 3 | (base & 7)
+        };
+
+        let Some(base) = sib.base() else {
+            self.code.push(modrm(0b00, 0b100));
+            self.code.push(sib_byte(0b101));
+            return self.displacement(label, displacement, RelocationKind::Absolute32S);
+        };
+        let base = number(base.0);
+        let short = (displacement % scale == 0)
+            .then(|| i8::try_from(displacement / scale).ok())
+            .flatten();
+        let mode = if label.is_some() || short.is_none() {
+            0b10
+        } else if displacement == 0 && base & 7 != 0b101 {
+            0b00
+        } else {
+            0b01
+        };
+        if index.is_some() || base & 7 == 0b100 {
+            self.code.push(modrm(mode, 0b100));
+            self.code.push(sib_byte(base));
+        } else {
+            self.code.push(modrm(mode, base));
+        }
+        match mode {
+            0b01 => self.code.push(short.unwrap() as u8),
+            0b10 => self.displacement(label, displacement, RelocationKind::Absolute32S)?,
+            _ => (),
+        }
+        Ok(())
+    }
+    fn displacement(
+        &mut self,
+        label: Option<Label<'a>>,
+        value: i64,
+        kind: RelocationKind,
+    ) -> Result<(), String> {
+        let value32 = i32::try_from(value).map_err(|_| "displacement out of range")?;
+        match label {
+            Some(symbol) => {
+                self.relocations.push(Relocation {
+                    offset: self.code.len(),
+                    symbol: symbol.symbol(),
+                    addend: value,
+                    kind: RelocationKind::of(symbol, kind)?,
+                });
+                self.code.extend([0; 4]);
+            }
+            None => self.code.extend(value32.to_le_bytes()),
+        }
+        Ok(())
+    }
+    fn immediate(&mut self, imm: Option<Immediate>) {
+        match imm {
+            Some(Immediate::Byte(v)) => self.code.push(v as u8),
+            Some(Immediate::Word(v)) => self.code.extend((v as u16).to_le_bytes()),
+            Some(Immediate::Double(v)) => self.code.extend((v as u32).to_le_bytes()),
+            Some(Immediate::Quad(v)) => self.code.extend(v.to_le_bytes()),
+            None => (),
+        }
+    }
+}
+
+struct Rex {
+    w: bool,
+    r: bool,
+    x: bool,
+    b: bool,
+    /// Needed to address `%spl`, `%bpl`, `%sil` and `%dil` instead of the high bytes.
+    force: bool,
+}
+impl Rex {
+    fn write(&self, code: &mut Vec<u8>) {
+        let bits = u8::from(self.w) << 3
+            | u8::from(self.r) << 2
+            | u8::from(self.x) << 1
+            | u8::from(self.b);
+        if bits != 0 || self.force {
+            code.push(0x40 | bits);
+        }
+    }
+}
+
+/// The number of the register in ModRM, SIB and REX.
+fn number(name: RegisterName) -> u8 {
+    use RegisterName::*;
+    match name {
+        A => 0,
+        C => 1,
+        D => 2,
+        B => 3,
+        SP => 4,
+        BP => 5,
+        SI => 6,
+        DI => 7,
+        R8 => 8,
+        R9 => 9,
+        R10 => 10,
+        R11 => 11,
+        R12 => 12,
+        R13 => 13,
+        R14 => 14,
+        R15 => 15,
+    }
+}
+fn needs_rex(register: Register) -> bool {
+    register.1 == RegisterSize::Byte && (4..8).contains(&number(register.0))
+}
+/// The REX.X and REX.B bits of `rm`, where EVEX takes X as bit 4 of a vector register.
+fn rm_extensions(rm: Arg, memory: Option<Memory>) -> Result<(bool, bool), String> {
+    match (rm, memory) {
+        (Arg::Register(r), _) => Ok((false, number(r.0) >= 8)),
+        (Arg::Vector(r), _) => Ok((r.0 & 16 != 0, r.0 & 8 != 0)),
+        (Arg::Mask(_), _) => Ok((false, false)),
+        (_, Some(memory)) => Ok(extensions(memory)),
+        _ => Err(format!("{rm} is neither a register nor memory")),
+    }
+}
+/// Whether the index and base of `memory` need the REX.X and REX.B bits.
+fn extensions(memory: Memory) -> (bool, bool) {
+    match memory.kind {
+        MemoryKind::Rip => (false, false),
+        MemoryKind::Sib(sib) => (
+            sib.index().is_some_and(|(index, _)| number(index.0) >= 8),
+            sib.base().is_some_and(|base| number(base.0) >= 8),
+        ),
+    }
+}
+fn condition_code(condition: Condition) -> u8 {
+    use Condition::*;
+    match condition {
+        Overflow => 0x0,
+        NoOverflow => 0x1,
+        Below => 0x2,
+        AboveEqual => 0x3,
+        Zero | Equal => 0x4,
+        NotZero | NotEqual => 0x5,
+        BelowEqual => 0x6,
+        Above => 0x7,
+        Negative => 0x8,
+        NonNegative => 0x9,
+        LessThan => 0xc,
+        GreaterEqual => 0xd,
+        LessEqual => 0xe,
+        GreaterThan => 0xf,
+    }
+}
+
+/// The operand as memory, with a bare symbol being its absolute address.
+fn memory(arg: Arg) -> Option<Memory> {
+    match arg {
+        Arg::Memory(memory) => Some(memory),
+        Arg::Label(label) => Some(Memory::sib().label(label)),
+        Arg::SymExpr(expr) => Some(Memory::sib().label(expr.label).offset(expr.offset)),
+        _ => None,
+    }
+}
+fn is_memory(arg: Arg) -> bool {
+    memory(arg).is_some()
+}
+fn is_accumulator(arg: Arg) -> bool {
+    matches!(arg, Arg::Register(Register(RegisterName::A, _)))
+}
+fn register(arg: Arg) -> Result<Field, String> {
+    match arg {
+        Arg::Register(register) => Ok(Field::Register(register)),
+        _ => Err(format!("{arg} has to be a register")),
+    }
+}
+fn vector(arg: Arg) -> Result<Field, String> {
+    match arg {
+        Arg::Vector(register) => Ok(Field::Vector(register)),
+        _ => Err(format!("{arg} has to be a vector register")),
+    }
+}
+fn xmm(arg: Arg) -> Result<Field, String> {
+    match arg {
+        Arg::Vector(register) => Ok(Field::Vector(register)),
+        _ => Err(format!("{arg} has to be an %xmm register")),
+    }
+}
+/// The implied prefix as VEX encodes it, the opcode map and the opcode of a vector
+/// operation. Moves load into the register operand with this opcode.
+fn vector_opcode(op: VectorOp) -> (u8, u8, u8) {
+    use VectorOp::*;
+    match op {
+        Vaddps => (0, 1, 0x58),
+        Vaddpd => (1, 1, 0x58),
+        Vsubps => (0, 1, 0x5c),
+        Vsubpd => (1, 1, 0x5c),
+        Vmulps => (0, 1, 0x59),
+        Vmulpd => (1, 1, 0x59),
+        Vdivps => (0, 1, 0x5e),
+        Vdivpd => (1, 1, 0x5e),
+        Vminps => (0, 1, 0x5d),
+        Vminpd => (1, 1, 0x5d),
+        Vmaxps => (0, 1, 0x5f),
+        Vmaxpd => (1, 1, 0x5f),
+        Vandps => (0, 1, 0x54),
+        Vandpd => (1, 1, 0x54),
+        Vxorps => (0, 1, 0x57),
+        Vxorpd => (1, 1, 0x57),
+        Vaddss => (2, 1, 0x58),
+        Vaddsd => (3, 1, 0x58),
+        Vsubss => (2, 1, 0x5c),
+        Vsubsd => (3, 1, 0x5c),
+        Vmulss => (2, 1, 0x59),
+        Vmulsd => (3, 1, 0x59),
+        Vdivss => (2, 1, 0x5e),
+        Vdivsd => (3, 1, 0x5e),
+        Vfmadd231ps | Vfmadd231pd => (1, 2, 0xb8),
+        Vfmadd231ss | Vfmadd231sd => (1, 2, 0xb9),
+        Vpaddd => (1, 1, 0xfe),
+        Vpaddq => (1, 1, 0xd4),
+        Vpsubd => (1, 1, 0xfa),
+        Vpsubq => (1, 1, 0xfb),
+        Vpmulld => (1, 2, 0x40),
+        Vpand | Vpandd | Vpandq => (1, 1, 0xdb),
+        Vpxor | Vpxord | Vpxorq => (1, 1, 0xef),
+        Vsqrtps => (0, 1, 0x51),
+        Vsqrtpd => (1, 1, 0x51),
+        Vmovaps => (0, 1, 0x28),
+        Vmovapd => (1, 1, 0x28),
+        Vmovups => (0, 1, 0x10),
+        Vmovupd => (1, 1, 0x10),
+        Vmovdqu | Vmovdqu32 | Vmovdqu64 => (2, 1, 0x6f),
+        Vbroadcastss => (1, 2, 0x18),
+        Vbroadcastsd => (1, 2, 0x19),
+        Kmovw | Kmovq => unreachable!(),
+    }
+}
+/// The mandatory prefix selecting the scalar or packed, single or double precision form.
+fn packing(op: SseOp) -> Option<u8> {
+    use SseOp::*;
+    match op {
+        Addss | Subss | Mulss | Divss | Sqrtss => Some(0xf3),
+        Addsd | Subsd | Mulsd | Divsd | Sqrtsd => Some(0xf2),
+        Addpd | Subpd | Mulpd | Divpd | Andpd | Xorpd | Ucomisd => Some(0x66),
+        _ => None,
+    }
+}
+fn operation_size(dst: Arg, src: Arg) -> Result<ArgSize, String> {
+    dst.size()
+        .or(src.size())
+        .ok_or_else(|| "the operand size is unknown".to_string())
+}
+/// The operand size prefix of a push or pop, which default to 64 bits.
+fn stack_size(size: RegisterSize) -> Result<Option<ArgSize>, String> {
+    match size {
+        RegisterSize::Quad => Ok(None),
+        RegisterSize::Word => Ok(Some(ArgSize::Word)),
+        _ => Err("the stack takes 16 or 64-bit operands".to_string()),
+    }
+}
+/// The opcode for `size`, given the one for bytes that the other sizes follow.
+fn sized(size: ArgSize, byte_opcode: u8) -> u8 {
+    match size {
+        ArgSize::Byte => byte_opcode,
+        _ => byte_opcode + 1,
+    }
+}
+fn shift_count(opcode: u8, count: Arg) -> Result<(u8, Option<Immediate>), String> {
+    match count {
+        Arg::Int(c) => Ok((opcode, Some(byte_immediate(c)?))),
+        Arg::Register(Register(RegisterName::C, RegisterSize::Byte)) => Ok((opcode + 1, None)),
+        _ => Err("shift counts are immediates or %cl".to_string()),
+    }
+}
+
+fn displacement_value(c: ConstInt) -> i64 {
+    match c {
+        ConstInt::U32(v) => (v as i32).into(),
+        c => c.value(),
+    }
+}
+/// The immediate as the processor sees it after sign-extending it to `size`.
+fn sign_extended(c: ConstInt, size: ArgSize) -> Result<i64, String> {
+    let value = c.value();
+    match size {
+        ArgSize::Byte => Ok((value as i8).into()),
+        ArgSize::Word => Ok((value as i16).into()),
+        ArgSize::Double => Ok((value as i32).into()),
+        ArgSize::Quad => match i32::try_from(value) {
+            Ok(_) => Ok(value),
+            Err(_) => Err(format!(
+                "{c} does not fit in a sign-extended 32-bit immediate"
+            )),
+        },
+    }
+}
+/// An 8-bit immediate, which the assembler takes signed or unsigned.
+fn byte_immediate(c: ConstInt) -> Result<Immediate, String> {
+    let value = c.value();
+    match value {
+        -128..=255 => Ok(Immediate::Byte(value)),
+        _ => Err(format!("{c} does not fit in an 8-bit immediate")),
+    }
+}
+/// The full-size immediate for an operation of `size`, which is 32 bits for `Quad`.
+fn immediate(c: ConstInt, size: ArgSize) -> Result<Immediate, String> {
+    let value = sign_extended(c, size)?;
+    Ok(match size {
+        ArgSize::Byte => Immediate::Byte(value),
+        ArgSize::Word => Immediate::Word(value),
+        ArgSize::Double | ArgSize::Quad => Immediate::Double(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::InstructionBuilder,
+        function::FunctionBuilder,
+        register::{k, rax, rbp, rcx, rsp, rx, xmm, ymm, zmm},
+    };
+
+    fn encode(instruction: Instruction) -> (Vec<u8>, Vec<Relocation>) {
+        let mut code = Vec::new();
+        let relocations = instruction.encode(&mut code).unwrap();
+        (code, relocations)
+    }
+    fn load(src: Memory) -> Vec<u8> {
+        let dst = rax().into();
+        encode(Instruction::Mov {
+            dst,
+            src: src.into(),
+        })
+        .0
+    }
+    fn vector<'a>(
+        dst: VectorRegister,
+        src1: VectorRegister,
+        src2: impl Into<Arg<'a>>,
+        masking: Option<Masking>,
+        broadcast: bool,
+    ) -> Vec<u8> {
+        encode(Instruction::Vector {
+            op: VectorOp::Vaddps,
+            dst: dst.into(),
+            src1: Some(src1.into()),
+            src2: src2.into(),
+            masking,
+            broadcast,
+        })
+        .0
+    }
+
+    #[test]
+    fn addressing() {
+        // %rsp and %r12 as base need a SIB byte, %rbp and %r13 a displacement.
+        assert_eq!(load(Memory::sib().base(rsp())), [0x48, 0x8b, 0x04, 0x24]);
+        assert_eq!(load(Memory::sib().base(rx(12))), [0x49, 0x8b, 0x04, 0x24]);
+        assert_eq!(load(Memory::sib().base(rbp())), [0x48, 0x8b, 0x45, 0x00]);
+        assert_eq!(load(Memory::sib().base(rx(13))), [0x49, 0x8b, 0x45, 0x00]);
+        let scaled = Memory::sib()
+            .base(rsp())
+            .index(rcx(), Scale::Four)
+            .offset(8i32);
+        assert_eq!(load(scaled), [0x48, 0x8b, 0x44, 0x8c, 0x08]);
+        let unbased = Memory::sib().index(rcx(), Scale::Eight);
+        assert_eq!(load(unbased), [0x48, 0x8b, 0x04, 0xcd, 0, 0, 0, 0]);
+
+        let extended = Memory::sib().base(rx(10)).index(rx(11), Scale::Two);
+        let (code, _) = encode(Instruction::Mov {
+            dst: extended.into(),
+            src: rx(9).into(),
+        });
+        assert_eq!(code, [0x4f, 0x89, 0x0c, 0x5a]);
+    }
+
+    #[test]
+    fn relocations() {
+        let (code, relocations) = encode(Instruction::Mov {
+            dst: rax().into(),
+            src: Label::new("sym").rip().into(),
+        });
+        assert_eq!(code, [0x48, 0x8b, 0x05, 0, 0, 0, 0]);
+        let expected = Relocation {
+            offset: 3,
+            symbol: Label::new("sym"),
+            addend: -4,
+            kind: RelocationKind::Pc32,
+        };
+        assert_eq!(relocations, [expected]);
+
+        let (code, relocations) = encode(Instruction::Call {
+            target: Label::new("foo").into(),
+        });
+        assert_eq!(code, [0xe8, 0, 0, 0, 0]);
+        let expected = Relocation {
+            offset: 1,
+            symbol: Label::new("foo"),
+            addend: -4,
+            kind: RelocationKind::Plt32,
+        };
+        assert_eq!(relocations, [expected]);
+    }
+
+    #[test]
+    fn vex_and_evex() {
+        assert_eq!(
+            vector(ymm(0), ymm(1), ymm(2), None, false),
+            [0xc5, 0xf4, 0x58, 0xc2]
+        );
+        // A register beyond %xmm7 in r/m needs the three byte VEX prefix.
+        assert_eq!(
+            vector(ymm(8), ymm(1), ymm(10), None, false),
+            [0xc4, 0x41, 0x74, 0x58, 0xc2]
+        );
+
+        let memory = Memory::sib().base(rax());
+        let broadcast = vector(zmm(0), zmm(1), memory, Some(k(1).merge()), true);
+        assert_eq!(broadcast, [0x62, 0xf1, 0x74, 0x59, 0x58, 0x00]);
+        // The displacement is compressed to whole vectors.
+        let compressed = vector(zmm(0), zmm(1), memory.offset(64i32), None, false);
+        assert_eq!(compressed, [0x62, 0xf1, 0x74, 0x48, 0x58, 0x40, 0x01]);
+        let high = vector(xmm(16), xmm(17), xmm(18), Some(k(2).zero()), false);
+        assert_eq!(high, [0x62, 0xa1, 0x74, 0x82, 0x58, 0xc2]);
+    }
+
+    #[test]
+    fn jump_relaxation() {
+        let mut builder = FunctionBuilder::new("f");
+        builder.emit_label(".Lnear").unwrap();
+        builder.build_jmp(Label::new(".Lnear")).unwrap();
+        builder.build_jmp(Label::new(".Lfar")).unwrap();
+        for _ in 0..30 {
+            builder.build_mov(rax(), 1i64).unwrap();
+        }
+        builder.emit_label(".Lfar").unwrap();
+        builder.build_ret().unwrap();
+        let code = builder.end_function().unwrap().assemble().unwrap().code;
+
+        // Backwards within reach stays short, and the 30 moves of 7 bytes are too far.
+        assert_eq!(code[..2], [0xeb, 0xfe]);
+        assert_eq!(code[2..7], [0xe9, 210, 0, 0, 0]);
+        assert_eq!(code.len(), 7 + 210 + 1);
+    }
+}
@@ -0,0 +1,181 @@
+use super::{
+    args::ArgSize, features::Feature, io, meta::Mnemonic, validate::OperandKind, writer::CodeMode,
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{convert::Infallible, error::Error, fmt::Display};
+
+/// Returned by the instruction builders for operands that cannot be written, and for
+/// failures of the underlying output.
+#[derive(Debug)]
+pub enum X64WriterError {
+    /// Two operands that have to agree in size do not.
+    SizeMismatch {
+        expected: ArgSize,
+        found: ArgSize,
+    },
+    /// No operand of the instruction has a size, so neither has the instruction.
+    MissingSize {
+        mnemonic: &'static str,
+    },
+    /// An operand of a kind the instruction or memory address does not take.
+    InvalidOperand(String),
+    /// A displacement that does not fit the signed 32 bits of a memory address.
+    DisplacementOverflow(i128),
+    /// The instruction has no form taking these kinds of operands, given in AT&T order.
+    IllegalOperands {
+        mnemonic: Mnemonic,
+        operands: Vec<OperandKind>,
+    },
+    /// An immediate that does not fit the `bits` wide field of the instruction.
+    ImmediateOutOfRange {
+        mnemonic: Mnemonic,
+        value: i128,
+        bits: u8,
+    },
+    /// An instruction of an extension that is not enabled for the target, in strict mode.
+    MissingFeature {
+        mnemonic: Mnemonic,
+        feature: Feature,
+    },
+    /// An instruction that needs 64-bit mode while the writer is in `mode`, in strict mode.
+    WrongMode {
+        mnemonic: Mnemonic,
+        mode: CodeMode,
+    },
+    /// A call that does not fit what the writer was told before, like popping a section
+    /// that was never pushed.
+    OutOfOrder(String),
+    /// A tag given to a function before it has an instruction to attach it to.
+    NothingToTag(String),
+    /// A directive the platform of the writer has no equivalent of.
+    Unsupported(String),
+    /// A line that cannot be wrapped to the maximum line length of the writer.
+    LineTooLong {
+        columns: usize,
+        max: usize,
+    },
+    /// An error in an instruction of a function, with where in the function it is.
+    InFunction {
+        function: String,
+        /// The index of the instruction among the instructions of the function.
+        index: usize,
+        /// The nearest label before the instruction, and the instructions in between.
+        label: Option<(String, usize)>,
+        /// The `Debug` representation of the operands, in AT&T order.
+        operands: String,
+        error: Box<X64WriterError>,
+    },
+    Io(io::Error),
+}
+impl Display for X64WriterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SizeMismatch { expected, found } => write!(
+                f,
+                "operand sizes do not match: expected {} bytes, found {}",
+                expected.bytes(),
+                found.bytes()
+            ),
+            Self::MissingSize { mnemonic } => {
+                write!(f, "the operand size of {mnemonic} is unknown")
+            }
+            Self::InvalidOperand(message) => write!(f, "{message}"),
+            Self::DisplacementOverflow(displacement) => {
+                write!(f, "displacement {displacement} does not fit in 32 bits")
+            }
+            Self::IllegalOperands { mnemonic, operands } => {
+                write!(f, "{mnemonic} has no form taking")?;
+                for (i, operand) in operands.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { "," };
+                    write!(f, "{separator} {operand}")?;
+                }
+                match operands.len() {
+                    1 => write!(f, " operand"),
+                    _ => write!(f, " operands"),
+                }
+            }
+            Self::ImmediateOutOfRange {
+                mnemonic,
+                value,
+                bits,
+            } => write!(
+                f,
+                "{value} does not fit the {bits}-bit immediate of {mnemonic}"
+            ),
+            Self::MissingFeature { mnemonic, feature } => write!(
+                f,
+                "{mnemonic} requires {feature}, which is not enabled for the target"
+            ),
+            Self::WrongMode { mnemonic, mode } => write!(
+                f,
+                "{mnemonic} requires 64-bit mode, but the writer is in {mode} mode"
+            ),
+            Self::OutOfOrder(message) => write!(f, "{message}"),
+            Self::NothingToTag(tag) => write!(f, "no instruction to tag with {tag:?}"),
+            Self::Unsupported(message) => write!(f, "{message}"),
+            Self::LineTooLong { columns, max } => write!(
+                f,
+                "a line of {columns} columns exceeds the maximum line length of {max}"
+            ),
+            Self::InFunction {
+                function,
+                index,
+                label,
+                operands,
+                error,
+            } => {
+                write!(f, "in {function}, instruction {index}")?;
+                if let Some((label, distance)) = label {
+                    write!(f, " ({distance} after {label})")?;
+                }
+                write!(f, ": {error}; operands: {operands}")
+            }
+            Self::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+impl X64WriterError {
+    fn io_kind(&self) -> io::ErrorKind {
+        match self {
+            Self::Io(error) => error.kind(),
+            Self::InFunction { error, .. } => error.io_kind(),
+            Self::MissingFeature { .. } | Self::WrongMode { .. } | Self::Unsupported(_) => {
+                io::ErrorKind::Unsupported
+            }
+            _ => io::ErrorKind::InvalidInput,
+        }
+    }
+}
+impl Error for X64WriterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::InFunction { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for X64WriterError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+/// Lets conversions that cannot fail stand in for fallible ones, like [`SectionFlags`]
+/// for the letters of [`Section::named`].
+///
+/// [`SectionFlags`]: crate::section::SectionFlags
+/// [`Section::named`]: crate::section::Section::named
+impl From<Infallible> for X64WriterError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+/// Lets functions returning [`io::Result`] propagate builder errors with `?`.
+impl From<X64WriterError> for io::Error {
+    fn from(error: X64WriterError) -> Self {
+        match error {
+            X64WriterError::Io(error) => error,
+            error => io::Error::new(error.io_kind(), error),
+        }
+    }
+}
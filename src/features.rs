@@ -0,0 +1,183 @@
+use core::fmt::Display;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Feature {
+    Sse,
+    Sse2,
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    Popcnt,
+    Lzcnt,
+    Bmi1,
+    Bmi2,
+    Movbe,
+    Cx16,
+    Avx,
+    Avx2,
+    Fma,
+    F16c,
+    Avx512F,
+    Avx512Bw,
+    Avx512Dq,
+    Avx512Vl,
+}
+impl Feature {
+    pub const ALL: [Feature; 20] = [
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::Sse3,
+        Feature::Ssse3,
+        Feature::Sse41,
+        Feature::Sse42,
+        Feature::Popcnt,
+        Feature::Lzcnt,
+        Feature::Bmi1,
+        Feature::Bmi2,
+        Feature::Movbe,
+        Feature::Cx16,
+        Feature::Avx,
+        Feature::Avx2,
+        Feature::Fma,
+        Feature::F16c,
+        Feature::Avx512F,
+        Feature::Avx512Bw,
+        Feature::Avx512Dq,
+        Feature::Avx512Vl,
+    ];
+
+    pub fn name(self) -> &'static str {
+        use Feature::*;
+        match self {
+            Sse => "sse",
+            Sse2 => "sse2",
+            Sse3 => "sse3",
+            Ssse3 => "ssse3",
+            Sse41 => "sse4.1",
+            Sse42 => "sse4.2",
+            Popcnt => "popcnt",
+            Lzcnt => "lzcnt",
+            Bmi1 => "bmi",
+            Bmi2 => "bmi2",
+            Movbe => "movbe",
+            Cx16 => "cx16",
+            Avx => "avx",
+            Avx2 => "avx2",
+            Fma => "fma",
+            F16c => "f16c",
+            Avx512F => "avx512f",
+            Avx512Bw => "avx512bw",
+            Avx512Dq => "avx512dq",
+            Avx512Vl => "avx512vl",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+impl Display for Feature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A set of instruction set extensions the emitted code may assume.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TargetFeatures(u32);
+impl TargetFeatures {
+    pub fn none() -> Self {
+        Self(0)
+    }
+    pub fn all() -> Self {
+        Self::from_slice(&Feature::ALL)
+    }
+    pub fn from_slice(features: &[Feature]) -> Self {
+        features
+            .iter()
+            .fold(Self::none(), |set, &feature| set.with(feature))
+    }
+
+    /// The baseline every x86-64 processor supports.
+    pub fn x86_64() -> Self {
+        Self::from_slice(&[Feature::Sse, Feature::Sse2])
+    }
+    pub fn x86_64_v2() -> Self {
+        Self::x86_64().union(Self::from_slice(&[
+            Feature::Sse3,
+            Feature::Ssse3,
+            Feature::Sse41,
+            Feature::Sse42,
+            Feature::Popcnt,
+            Feature::Cx16,
+        ]))
+    }
+    pub fn x86_64_v3() -> Self {
+        Self::x86_64_v2().union(Self::from_slice(&[
+            Feature::Avx,
+            Feature::Avx2,
+            Feature::Bmi1,
+            Feature::Bmi2,
+            Feature::Fma,
+            Feature::F16c,
+            Feature::Lzcnt,
+            Feature::Movbe,
+        ]))
+    }
+    pub fn x86_64_v4() -> Self {
+        Self::x86_64_v3().union(Self::from_slice(&[
+            Feature::Avx512F,
+            Feature::Avx512Bw,
+            Feature::Avx512Dq,
+            Feature::Avx512Vl,
+        ]))
+    }
+
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+    pub fn is_superset(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub fn with(self, feature: Feature) -> Self {
+        Self(self.0 | feature.bit())
+    }
+    pub fn without(self, feature: Feature) -> Self {
+        Self(self.0 & !feature.bit())
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+    pub fn insert(&mut self, feature: Feature) {
+        *self = self.with(feature);
+    }
+    pub fn remove(&mut self, feature: Feature) {
+        *self = self.without(feature);
+    }
+    pub fn iter(self) -> impl Iterator<Item = Feature> {
+        Feature::ALL.into_iter().filter(move |&f| self.contains(f))
+    }
+}
+impl From<Feature> for TargetFeatures {
+    fn from(value: Feature) -> Self {
+        Self::none().with(value)
+    }
+}
+impl Display for TargetFeatures {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, feature) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{feature}")?;
+        }
+        Ok(())
+    }
+}
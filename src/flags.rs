@@ -0,0 +1,101 @@
+use core::fmt::Display;
+
+/// A set of the arithmetic status flags.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flags(u8);
+impl Flags {
+    pub const CARRY: Flags = Flags(1 << 0);
+    pub const PARITY: Flags = Flags(1 << 1);
+    pub const AUXILIARY: Flags = Flags(1 << 2);
+    pub const ZERO: Flags = Flags(1 << 3);
+    pub const SIGN: Flags = Flags(1 << 4);
+    pub const OVERFLOW: Flags = Flags(1 << 5);
+
+    pub fn none() -> Self {
+        Self(0)
+    }
+    pub fn all() -> Self {
+        Self(0x3f)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+impl Display for Flags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let names = [
+            (Self::CARRY, "CF"),
+            (Self::PARITY, "PF"),
+            (Self::AUXILIARY, "AF"),
+            (Self::ZERO, "ZF"),
+            (Self::SIGN, "SF"),
+            (Self::OVERFLOW, "OF"),
+        ];
+
+        let mut first = true;
+        for (flag, name) in names {
+            if self.contains(flag) {
+                if !first {
+                    write!(f, "|")?;
+                }
+                write!(f, "{name}")?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How an instruction affects the status flags.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FlagsEffect {
+    pub read: Flags,
+    /// Flags set to a defined value depending on the result.
+    pub written: Flags,
+    /// Flags left in an undefined state.
+    pub undefined: Flags,
+}
+impl FlagsEffect {
+    pub fn none() -> Self {
+        Self::default()
+    }
+    pub fn reads(read: Flags) -> Self {
+        Self {
+            read,
+            ..Self::default()
+        }
+    }
+    pub fn writes(written: Flags) -> Self {
+        Self {
+            written,
+            ..Self::default()
+        }
+    }
+    pub fn clobbers(undefined: Flags) -> Self {
+        Self {
+            undefined,
+            ..Self::default()
+        }
+    }
+
+    /// All flags whose previous value is lost.
+    pub fn modified(self) -> Flags {
+        self.written.union(self.undefined)
+    }
+}
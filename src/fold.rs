@@ -0,0 +1,263 @@
+use super::{
+    args::{Arg, Memory, MemoryKind, Scale},
+    flags::Flags,
+    function::{Function, Item},
+    instruction::Instruction,
+    label::Label,
+    register::{Register, RegisterName, RegisterSize},
+    writer::{BinaryOp, BinaryOpSingle, NonaryOp},
+};
+use alloc::vec::Vec;
+
+impl<'a> Function<'a> {
+    /// Removes `lea`s and additions of constants to 64-bit registers whose result is used
+    /// exactly once before being overwritten in the same block, as part of an address, by
+    /// folding the computation into that address. Additions are only removed if nothing
+    /// reads the flags they set.
+    /// The tags of removed instructions are added to the instruction using the address.
+    pub fn fold_addresses(&mut self) {
+        let mut i = 0;
+        while i < self.items.len() {
+            let Item::Instruction(instruction, _) = &self.items[i] else {
+                i += 1;
+                continue;
+            };
+            let flags = instruction.flags_effect().modified();
+            let folding = address_computation(instruction)
+                .filter(|_| flags.is_empty() || self.flags_dead_after(i, flags))
+                .and_then(|(dst, address)| self.find_folding(i, dst, address));
+
+            if let Some((user, folded)) = folding {
+                let Item::Instruction(_, tags) = self.items.remove(i) else {
+                    unreachable!()
+                };
+                let Item::Instruction(instruction, user_tags) = &mut self.items[user - 1] else {
+                    unreachable!()
+                };
+                *instruction = folded;
+                user_tags.extend(tags);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Finds the single reader of `dst` after the instruction at `index`, returning it
+    /// with `address` folded in, if `dst` is overwritten afterwards and no register of
+    /// `address` is written in between.
+    fn find_folding(
+        &self,
+        index: usize,
+        dst: Register,
+        address: Memory<'a>,
+    ) -> Option<(usize, Instruction<'a>)> {
+        let inputs = address.address_registers();
+        let mut user = None;
+        for (i, item) in self.items.iter().enumerate().skip(index + 1) {
+            let instruction = match item {
+                Item::Label(_) => return None,
+                Item::Comment(_) => continue,
+                Item::Instruction(instruction, _) => instruction,
+            };
+
+            if instruction.reads().contains(dst.0) {
+                if user.is_some() {
+                    return None;
+                }
+                user = Some((i, fold(instruction, dst.0, address)?));
+            }
+            if instruction.writes().contains(dst.0) {
+                let full = instruction.defs().iter().any(|r| {
+                    r.0 == dst.0 && matches!(r.1, RegisterSize::Double | RegisterSize::Quad)
+                });
+                return if full { user } else { None };
+            }
+            let writes = instruction.writes();
+            if user.is_none() && inputs.iter().any(|r| writes.contains(r.0)) {
+                return None;
+            }
+            // Whether `dst` is read after leaving the block is unknown.
+            if instruction.info().is_terminator() {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Whether all of `flags` are overwritten before being read after the instruction at
+    /// `index`, or the function returns first.
+    fn flags_dead_after(&self, index: usize, mut flags: Flags) -> bool {
+        for item in &self.items[index + 1..] {
+            let instruction = match item {
+                Item::Label(_) => return false,
+                Item::Comment(_) => continue,
+                Item::Instruction(instruction, _) => instruction,
+            };
+            let effect = instruction.flags_effect();
+            if effect.read.intersects(flags) {
+                return false;
+            }
+            flags = flags.difference(effect.modified());
+            let returns = matches!(instruction, Instruction::Nonary { op: NonaryOp::Ret });
+            if flags.is_empty() || returns {
+                return true;
+            }
+            if instruction.info().is_terminator() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+/// The 64-bit register an instruction sets to an address, and that address.
+fn address_computation<'a>(instruction: &Instruction<'a>) -> Option<(Register, Memory<'a>)> {
+    let (dst, address) = match *instruction {
+        Instruction::Binary {
+            op: BinaryOp::Single(BinaryOpSingle::Lea),
+            dst: Arg::Register(dst),
+            src: Arg::Memory(memory),
+        } => (dst, memory),
+        Instruction::Binary {
+            op: BinaryOp::Single(BinaryOpSingle::Add),
+            dst: Arg::Register(dst),
+            src: Arg::Int(value),
+        } => {
+            let value = i32::try_from(value.value()).ok()?;
+            (dst, Memory::sib().base(dst).offset(value))
+        }
+        _ => return None,
+    };
+    let quad = |r: &Register| r.1 == RegisterSize::Quad;
+    let plain = address.segment.is_none();
+    (quad(&dst) && plain && address.address_registers().iter().all(quad)).then_some((dst, address))
+}
+
+/// `instruction` with `address` in place of `dst` in every address, if it reads `dst`
+/// nowhere else.
+fn fold<'a>(
+    instruction: &Instruction<'a>,
+    dst: RegisterName,
+    address: Memory<'a>,
+) -> Option<Instruction<'a>> {
+    let implicit = instruction.implicit_uses().iter().any(|r| r.0 == dst);
+    if implicit {
+        return None;
+    }
+    let operands = instruction
+        .operands()
+        .into_iter()
+        .map(|operand| match operand {
+            Arg::Register(r) if r.0 == dst => None,
+            Arg::Memory(memory) if memory.address_registers().iter().any(|r| r.0 == dst) => {
+                substitute(memory, dst, address).map(Arg::Memory)
+            }
+            operand => Some(operand),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(instruction.with_operands(&operands))
+}
+
+/// The sum of the registers, scaled, plus a displacement.
+struct Address<'a> {
+    terms: Vec<(RegisterName, i64)>,
+    displacement: i64,
+    label: Option<Label<'a>>,
+    rip: bool,
+}
+impl<'a> Address<'a> {
+    fn new(memory: Memory<'a>) -> Self {
+        let mut terms = Vec::new();
+        if let MemoryKind::Sib(sib) = memory.kind {
+            terms.extend(sib.base().map(|base| (base.0, 1)));
+            terms.extend(sib.index().map(|(index, scale)| (index.0, factor(scale))));
+        }
+        Self {
+            terms,
+            displacement: memory.displacement_constant.map_or(0, |c| c.value()),
+            label: memory.displacement_label,
+            rip: memory.kind == MemoryKind::Rip,
+        }
+    }
+    fn add(&mut self, other: Self, scale: i64) -> Option<()> {
+        if scale != 1 && (other.label.is_some() || other.rip) {
+            return None;
+        }
+        for (name, factor) in other.terms {
+            match self.terms.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, existing)) => *existing += factor * scale,
+                None => self.terms.push((name, factor * scale)),
+            }
+        }
+        self.displacement = self.displacement.checked_add(other.displacement * scale)?;
+        self.label = match (self.label, other.label) {
+            (Some(_), Some(_)) => return None,
+            (label, other) => label.or(other),
+        };
+        if other.rip && !self.terms.is_empty() {
+            return None;
+        }
+        self.rip |= other.rip;
+        Some(())
+    }
+}
+
+/// `memory` with `address` in place of the register `dst`, if the result can be encoded.
+fn substitute<'a>(
+    memory: Memory<'a>,
+    dst: RegisterName,
+    address: Memory<'a>,
+) -> Option<Memory<'a>> {
+    let mut sum = Address::new(memory);
+    let position = sum.terms.iter().position(|(name, _)| *name == dst)?;
+    let (_, factor) = sum.terms.remove(position);
+    sum.add(Address::new(address), factor)?;
+
+    let displacement = i32::try_from(sum.displacement).ok()?;
+    let mut folded = match sum.rip {
+        true => Memory::rip(),
+        false => {
+            // Unscaled terms come first to be the base, which `%rsp` has to be.
+            sum.terms
+                .sort_by_key(|&(name, factor)| (factor != 1, name != RegisterName::SP));
+            let mut folded = Memory::sib();
+            match sum.terms[..] {
+                [] => (),
+                [(base, 1)] => folded = folded.base(base.quad()),
+                [(index, factor)] => folded = folded.index(index.quad(), scale(factor)?),
+                [(base, 1), (index, factor)] if index != RegisterName::SP => {
+                    folded = folded.base(base.quad()).index(index.quad(), scale(factor)?);
+                }
+                _ => return None,
+            }
+            folded
+        }
+    };
+    if let Some(label) = sum.label {
+        folded = folded.label(label);
+    }
+    if displacement != 0 {
+        folded = folded.offset(displacement);
+    }
+    folded.size = memory.size;
+    folded.segment = memory.segment;
+    Some(folded)
+}
+
+fn factor(scale: Scale) -> i64 {
+    match scale {
+        Scale::One => 1,
+        Scale::Two => 2,
+        Scale::Four => 4,
+        Scale::Eight => 8,
+    }
+}
+fn scale(factor: i64) -> Option<Scale> {
+    match factor {
+        1 => Some(Scale::One),
+        2 => Some(Scale::Two),
+        4 => Some(Scale::Four),
+        8 => Some(Scale::Eight),
+        _ => None,
+    }
+}
@@ -0,0 +1,662 @@
+use super::{
+    args::{Arg, FloatSize, Memory},
+    builder::InstructionBuilder,
+    convention::CallingConvention,
+    debug::SourceLocation,
+    error::X64WriterError,
+    features::TargetFeatures,
+    instruction::Instruction,
+    io::{self, Write},
+    island::{Constant, ConstantValue},
+    label::Label,
+    meta::ControlFlow,
+    platform::Platform,
+    register::{Register, RegisterSet, RegisterSize, VectorRegister, VirtualRegister},
+    symbol::SymbolBinding,
+    trace::TraceHook,
+    writer::AsmWriter,
+};
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{error::Error, fmt::Display};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Item<'a> {
+    Label(Label<'a>),
+    Instruction(Instruction<'a>, Tags),
+    Comment(String),
+}
+
+/// Opaque metadata attached to an instruction, such as decisions of an external scheduler.
+/// Passes that move or rewrite an instruction keep its tags.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Tags(Vec<String>);
+impl Tags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, tag: impl Into<String>) {
+        self.0.push(tag.into());
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+    pub fn extend(&mut self, other: Tags) {
+        self.0.extend(other.0);
+    }
+}
+
+/// Padding before a label, such as a loop header, so that it starts at an aligned address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Alignment {
+    /// The alignment as a power of two.
+    pub power: u8,
+    /// The most bytes of padding worth inserting, beyond which the label stays unaligned.
+    pub max_skip: u32,
+}
+impl Alignment {
+    /// Aligns to `2^power` bytes however much padding that takes.
+    pub fn new(power: u8) -> Self {
+        Self::with_max_skip(power, (1 << power) - 1)
+    }
+    pub fn with_max_skip(power: u8, max_skip: u32) -> Self {
+        assert!(power < 16, "cannot align code to 2^{power} bytes");
+        Self { power, max_skip }
+    }
+    pub fn in_bytes(self) -> usize {
+        1 << self.power
+    }
+}
+impl Default for Alignment {
+    /// The 16 bytes GCC aligns loop headers to.
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Function<'a> {
+    pub name: Label<'a>,
+    pub global: bool,
+    /// The registers holding a value on entry, beyond the callee-saved ones.
+    pub arguments: RegisterSet,
+    /// The instruction set extensions the body may assume.
+    pub features: TargetFeatures,
+    pub items: Vec<Item<'a>>,
+    /// Constants emitted right after the body, referenced rip-relative.
+    pub constants: Vec<Constant<'a>>,
+    /// Alignment hints for labels of the body.
+    pub alignments: Vec<(Label<'a>, Alignment)>,
+}
+impl<'a> Function<'a> {
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction<'a>> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Instruction(instruction, _) => Some(instruction),
+            _ => None,
+        })
+    }
+    /// Every label defined or referenced, including the name and constant labels.
+    pub fn labels(&self) -> Vec<Label<'a>> {
+        let mut labels = vec![self.name];
+        for item in &self.items {
+            match item {
+                Item::Label(label) => labels.push(*label),
+                Item::Instruction(instruction, _) => {
+                    let operands = instruction.operands();
+                    labels.extend(operands.iter().filter_map(Arg::symbol));
+                }
+                Item::Comment(_) => (),
+            }
+        }
+        labels.extend(self.constants.iter().map(|c| c.label));
+        labels
+    }
+    /// Adds to `error` where `instruction`, the item at `item` or one appended after the
+    /// items, is in the function `name`.
+    pub(crate) fn error_context(
+        &self,
+        name: &str,
+        item: usize,
+        instruction: &Instruction,
+        error: X64WriterError,
+    ) -> X64WriterError {
+        if let X64WriterError::InFunction { .. } = error {
+            return error;
+        }
+        let mut index = 0;
+        let mut label = None;
+        for item in &self.items[..item] {
+            match item {
+                Item::Label(l) => label = Some((l.label.to_string(), 0)),
+                Item::Instruction(..) => {
+                    index += 1;
+                    if let Some((_, distance)) = &mut label {
+                        *distance += 1;
+                    }
+                }
+                Item::Comment(_) => (),
+            }
+        }
+        let mut operands = instruction.operands();
+        operands.reverse();
+        X64WriterError::InFunction {
+            function: name.to_string(),
+            index,
+            label,
+            operands: format!("{operands:?}"),
+            error: Box::new(error),
+        }
+    }
+    pub fn alignment_of(&self, label: Label) -> Option<Alignment> {
+        let hint = self.alignments.iter().find(|(l, _)| *l == label);
+        hint.map(|&(_, alignment)| alignment)
+    }
+    /// Pads before `label` as `alignment` asks, replacing an earlier hint for it.
+    pub fn align_label(&mut self, label: impl Into<Label<'a>>, alignment: Alignment) {
+        let label = label.into();
+        self.alignments.retain(|(l, _)| *l != label);
+        self.alignments.push((label, alignment));
+    }
+    /// Aligns every label that a later jump goes back to, unless it already has a hint.
+    pub fn align_loop_headers(&mut self, alignment: Alignment) {
+        let mut headers = Vec::new();
+        for (i, item) in self.items.iter().enumerate() {
+            let Item::Instruction(
+                Instruction::Jmp {
+                    target: Arg::Label(target),
+                }
+                | Instruction::CJmp {
+                    target: Arg::Label(target),
+                    ..
+                },
+                _,
+            ) = *item
+            else {
+                continue;
+            };
+            let backwards = self.items[..i].contains(&Item::Label(target));
+            if backwards && self.alignment_of(target).is_none() && !headers.contains(&target) {
+                headers.push(target);
+            }
+        }
+        for label in headers {
+            self.alignments.push((label, alignment));
+        }
+    }
+    /// Replaces every label defined or referenced, possibly with one of a different lifetime.
+    pub fn map_labels<'b>(&self, f: impl Fn(Label<'a>) -> Label<'b>) -> Function<'b> {
+        let items = self
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Label(label) => Item::Label(f(*label)),
+                Item::Instruction(instruction, tags) => {
+                    Item::Instruction(instruction.map_labels(&f), tags.clone())
+                }
+                Item::Comment(comment) => Item::Comment(comment.clone()),
+            })
+            .collect();
+        let constants = self
+            .constants
+            .iter()
+            .map(|c| Constant {
+                label: f(c.label),
+                value: c.value,
+            })
+            .collect();
+        let alignments = self
+            .alignments
+            .iter()
+            .map(|&(label, alignment)| (f(label), alignment))
+            .collect();
+        Function {
+            name: f(self.name),
+            global: self.global,
+            arguments: self.arguments,
+            features: self.features,
+            items,
+            constants,
+            alignments,
+        }
+    }
+}
+
+pub struct FunctionBuilder<'a> {
+    function: Function<'a>,
+    callee_saved: RegisterSet,
+    saved: RegisterSet,
+    written: RegisterSet,
+    virtuals: u32,
+    entry_hook: Option<TraceHook<'a>>,
+    exit_hook: Option<TraceHook<'a>>,
+    location: Option<SourceLocation>,
+}
+impl<'a> FunctionBuilder<'a> {
+    pub fn new(name: impl Into<Label<'a>>) -> Self {
+        Self {
+            function: Function {
+                name: name.into(),
+                global: false,
+                arguments: RegisterSet::new(),
+                features: TargetFeatures::x86_64(),
+                items: Vec::new(),
+                constants: Vec::new(),
+                alignments: Vec::new(),
+            },
+            callee_saved: RegisterSet::sysv_callee_saved(),
+            saved: RegisterSet::new(),
+            written: RegisterSet::new(),
+            virtuals: 0,
+            entry_hook: None,
+            exit_hook: None,
+            location: None,
+        }
+    }
+
+    pub fn set_global(&mut self, global: bool) {
+        self.function.global = global;
+    }
+    pub fn set_target_features(&mut self, features: TargetFeatures) {
+        self.function.features = features;
+    }
+    pub fn declare_argument(&mut self, register: Register) {
+        self.function.arguments.insert(register.0);
+    }
+
+    /// Returns a virtual register not used in the body yet, to be replaced by
+    /// [`Function::allocate_registers`].
+    pub fn new_virtual(&mut self, size: RegisterSize) -> VirtualRegister {
+        self.virtuals += 1;
+        VirtualRegister(self.virtuals - 1, size)
+    }
+
+    /// Sets the registers the body must not clobber without saving them first.
+    pub fn set_callee_saved(&mut self, callee_saved: RegisterSet) {
+        self.callee_saved = callee_saved;
+    }
+    /// Checks the body against the callee-saved registers of `convention`.
+    pub fn set_calling_convention(&mut self, convention: &CallingConvention) {
+        self.callee_saved = convention.callee_saved;
+    }
+    /// Declares that `register` is preserved by means the builder cannot see.
+    /// Pushing a register before it is written counts as saving it.
+    pub fn declare_saved(&mut self, register: Register) {
+        self.saved.insert(register.0);
+    }
+    /// The callee-saved registers written by the body so far without being saved.
+    pub fn clobbered_callee_saved(&self) -> RegisterSet {
+        self.written
+            .intersection(self.callee_saved)
+            .difference(self.saved)
+    }
+
+    /// Calls `hook` before anything else in the function, tagged `trace`.
+    pub fn set_entry_hook(&mut self, hook: TraceHook<'a>) {
+        self.entry_hook = Some(hook);
+    }
+    /// Calls `hook` right before each return, tagged `trace`.
+    pub fn set_exit_hook(&mut self, hook: TraceHook<'a>) {
+        self.exit_hook = Some(hook);
+    }
+
+    /// Pads before `label` as `alignment` asks, see [`Function::align_label`].
+    pub fn align_label(&mut self, label: impl Into<Label<'a>>, alignment: Alignment) {
+        self.function.align_label(label, alignment);
+    }
+
+    /// Tags the instructions built from now on with `location`, see [`SourceLocation::tag`].
+    pub fn set_source_location(&mut self, location: Option<SourceLocation>) {
+        self.location = location;
+    }
+    /// Runs `f` with the instructions it builds tagged with `location`, then returns to the
+    /// location before.
+    pub fn with_source_location<R>(
+        &mut self,
+        location: SourceLocation,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let previous = self.location.replace(location);
+        let result = f(self);
+        self.location = previous;
+        result
+    }
+    /// Attaches `tag` to the most recently built instruction, failing if there is none.
+    pub fn tag(&mut self, tag: impl Into<String>) -> Result<(), X64WriterError> {
+        let last = self
+            .function
+            .items
+            .iter_mut()
+            .rev()
+            .find_map(|item| match item {
+                Item::Instruction(_, tags) => Some(tags),
+                _ => None,
+            });
+        let tag = tag.into();
+        match last {
+            Some(tags) => {
+                tags.push(tag);
+                Ok(())
+            }
+            None => Err(X64WriterError::NothingToTag(tag)),
+        }
+    }
+
+    /// Queues `value` for emission after the function under `label` and returns a
+    /// rip-relative operand referring to it. A value queued before under another label
+    /// shares its storage, which both labels are defined at.
+    pub fn constant(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        value: impl Into<ConstantValue>,
+    ) -> Memory<'a> {
+        let (label, value) = (label.into(), value.into());
+        let constant = Constant { label, value };
+        if !self.function.constants.contains(&constant) {
+            self.function.constants.push(constant);
+        }
+
+        let memory = label.rip();
+        match value.arg_size() {
+            Some(size) => memory.size(size),
+            None => memory,
+        }
+    }
+
+    /// Negates the float of `size` in `dst` by flipping its sign bit, and those of the
+    /// other lanes, with the [sign mask](FloatSize::sign_mask) queued under `mask`.
+    pub fn emit_fneg(
+        &mut self,
+        dst: VectorRegister,
+        size: FloatSize,
+        mask: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let mask = self.constant(mask, size.sign_mask());
+        match size {
+            FloatSize::Single => self.build_xorps(dst, mask),
+            FloatSize::Double => self.build_xorpd(dst, mask),
+        }
+    }
+    /// Sets the float of `size` in `dst` to its absolute value by clearing its sign bit,
+    /// and those of the other lanes, with the complement of the sign mask queued under
+    /// `mask`.
+    pub fn emit_fabs(
+        &mut self,
+        dst: VectorRegister,
+        size: FloatSize,
+        mask: impl Into<Label<'a>>,
+    ) -> Result<(), X64WriterError> {
+        let mask = self.constant(mask, !size.sign_mask());
+        match size {
+            FloatSize::Single => self.build_andps(dst, mask),
+            FloatSize::Double => self.build_andpd(dst, mask),
+        }
+    }
+
+    pub fn end_function(self) -> Result<Function<'a>, CalleeSavedError> {
+        let clobbered = self.clobbered_callee_saved();
+        if !clobbered.is_empty() {
+            return Err(CalleeSavedError {
+                function: self.function.name.label.to_string(),
+                clobbered,
+            });
+        }
+
+        let mut function = self.function;
+        // The hooks are built from registers and labels that are always valid operands.
+        let name = function.name;
+        let features = function.features;
+        let call = |hook: TraceHook<'a>| {
+            let mut items = hook.call(name, self.callee_saved, features).unwrap();
+            for item in &mut items {
+                if let Item::Instruction(_, tags) = item {
+                    tags.push("trace");
+                }
+            }
+            items
+        };
+        if let Some(hook) = self.exit_hook {
+            let exit = call(hook);
+            let mut items = Vec::with_capacity(function.items.len());
+            for item in function.items {
+                if let Item::Instruction(instruction, _) = &item {
+                    if instruction.info().control_flow == ControlFlow::Return {
+                        items.extend(exit.iter().cloned());
+                    }
+                }
+                items.push(item);
+            }
+            function.items = items;
+        }
+        if let Some(hook) = self.entry_hook {
+            function.items.splice(0..0, call(hook));
+        }
+
+        Ok(function)
+    }
+}
+impl<'a> InstructionBuilder<'a> for FunctionBuilder<'a> {
+    fn build_instruction(&mut self, instruction: Instruction<'a>) -> Result<(), X64WriterError> {
+        instruction.check_operands().map_err(|error| {
+            let function = &self.function;
+            let (name, item) = (function.name.label, function.items.len());
+            function.error_context(name, item, &instruction, error)
+        })?;
+        if let Instruction::Push {
+            src: Arg::Register(register),
+        } = instruction
+        {
+            if !self.written.contains(register.0) {
+                self.saved.insert(register.0);
+            }
+        }
+        self.written = self.written.union(instruction.writes());
+        let mut tags = Tags::new();
+        if let Some(location) = &self.location {
+            tags.push(location.tag());
+        }
+        self.function
+            .items
+            .push(Item::Instruction(instruction, tags));
+        Ok(())
+    }
+    fn emit_label(&mut self, label: impl Into<Label<'a>>) -> Result<(), X64WriterError> {
+        self.function.items.push(Item::Label(label.into()));
+        Ok(())
+    }
+    fn comment(&mut self, comment: impl AsRef<str>) -> Result<(), X64WriterError> {
+        let comment = comment.as_ref().to_string();
+        self.function.items.push(Item::Comment(comment));
+        Ok(())
+    }
+    fn target_features(&self) -> TargetFeatures {
+        self.function.features
+    }
+}
+
+/// Returned when a function writes callee-saved registers it never saved.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CalleeSavedError {
+    pub function: String,
+    pub clobbered: RegisterSet,
+}
+impl Display for CalleeSavedError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} clobbers callee-saved registers without saving them:",
+            self.function
+        )?;
+        for name in self.clobbered.iter() {
+            write!(f, " {}", name.quad())?;
+        }
+        Ok(())
+    }
+}
+impl Error for CalleeSavedError {}
+
+/// Collects built instructions as items.
+pub(crate) struct Items<'a> {
+    pub(crate) items: Vec<Item<'a>>,
+    features: TargetFeatures,
+}
+impl Items<'_> {
+    pub(crate) fn new(features: TargetFeatures) -> Self {
+        Self {
+            items: Vec::new(),
+            features,
+        }
+    }
+}
+impl<'a> InstructionBuilder<'a> for Items<'a> {
+    fn build_instruction(&mut self, instruction: Instruction<'a>) -> Result<(), X64WriterError> {
+        self.items.push(Item::Instruction(instruction, Tags::new()));
+        Ok(())
+    }
+    fn emit_label(&mut self, label: impl Into<Label<'a>>) -> Result<(), X64WriterError> {
+        self.items.push(Item::Label(label.into()));
+        Ok(())
+    }
+    fn comment(&mut self, comment: impl AsRef<str>) -> Result<(), X64WriterError> {
+        let comment = comment.as_ref().to_string();
+        self.items.push(Item::Comment(comment));
+        Ok(())
+    }
+    fn target_features(&self) -> TargetFeatures {
+        self.features
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    pub fn emit_function(&mut self, function: &Function) -> io::Result<()> {
+        self.emit_function_as(function, function.name.label, function.global)
+    }
+    pub(crate) fn emit_function_as(
+        &mut self,
+        function: &Function,
+        name: &str,
+        global: bool,
+    ) -> io::Result<()> {
+        let features = self.target_features();
+        self.set_target_features(features.union(function.features));
+        let result = match self.rename_local_labels(function.labels()) {
+            Some(names) => {
+                let renamed = function.map_labels(|label| match names.get(label.label) {
+                    Some(name) => Label {
+                        label: name,
+                        modifier: label.modifier,
+                    },
+                    None => label,
+                });
+                self.emit_function_body(&renamed, name, global)
+            }
+            None => self.emit_function_body(function, name, global),
+        };
+        self.set_target_features(features);
+        result
+    }
+    fn emit_function_body(
+        &mut self,
+        function: &Function,
+        name: &str,
+        global: bool,
+    ) -> io::Result<()> {
+        let binding = match global {
+            true => SymbolBinding::Global,
+            false => SymbolBinding::Local,
+        };
+        self.begin_function(name, binding)?;
+
+        for (i, item) in function.items.iter().enumerate() {
+            match item {
+                &Item::Label(label) => {
+                    if let Some(alignment) = function.alignment_of(label) {
+                        let Alignment { power, max_skip } = alignment;
+                        self.directive(format_args!(".p2align {power},,{max_skip}"))?;
+                    }
+                    self.emit_label(label)?
+                }
+                Item::Instruction(instruction, tags) => {
+                    self.check_instruction(instruction)
+                        .map_err(|error| function.error_context(name, i, instruction, error))?;
+                    self.write_tagged_instruction(instruction, tags)?;
+                }
+                Item::Comment(comment) => self.comment(comment)?,
+            }
+        }
+        if !function.constants.is_empty() {
+            self.emit_constant_island(&function.constants)?;
+        }
+        self.end_function()
+    }
+
+    /// Starts a function by declaring and defining the symbol `name`, to be followed by its
+    /// body and [`Self::end_function`]. With [`Self::set_function_symbols`], the symbol is
+    /// also typed as a function and given its size on ELF, and defined with `.def` on COFF.
+    /// Fails while another function is open, or outside an executable section.
+    pub fn begin_function(&mut self, name: &str, binding: SymbolBinding) -> io::Result<()> {
+        if let Some(open) = &*self.open_function() {
+            let message = format!("cannot begin {name} before ending {open}");
+            return Err(X64WriterError::OutOfOrder(message).into());
+        }
+        let section = self.current_section();
+        if !section.is_executable() {
+            let message = format!(
+                "cannot begin {name} in {}, which is not executable",
+                section.name()
+            );
+            return Err(X64WriterError::OutOfOrder(message).into());
+        }
+        *self.open_function() = Some(name.to_string());
+        self.declare_binding(name, binding)?;
+        if self.function_symbols() {
+            match self.platform() {
+                Platform::Linux => self.directive(format_args!(".type {name}, @function"))?,
+                Platform::MacOs => (),
+                Platform::Windows => {
+                    // Storage class 2 is external and 3 static, type 32 a function.
+                    let class = match binding {
+                        SymbolBinding::Local => 3,
+                        SymbolBinding::Global | SymbolBinding::Weak => 2,
+                    };
+                    self.directive(format_args!(".def {name}"))?;
+                    self.directive(format_args!(".scl {class}"))?;
+                    self.directive(".type 32")?;
+                    self.directive(".endef")?;
+                }
+            }
+        }
+        self.emit_label(name)?;
+        Ok(())
+    }
+    /// Like [`Self::begin_function`], padding the code first so the function starts aligned.
+    pub fn begin_aligned_function(
+        &mut self,
+        name: &str,
+        binding: SymbolBinding,
+        alignment: Alignment,
+    ) -> io::Result<()> {
+        let Alignment { power, max_skip } = alignment;
+        self.directive(format_args!(".p2align {power},,{max_skip}"))?;
+        self.begin_function(name, binding)
+    }
+    /// Ends the function begun last, which extends up to the current location. Fails if
+    /// none is open.
+    pub fn end_function(&mut self) -> io::Result<()> {
+        let Some(name) = self.open_function().take() else {
+            let error = X64WriterError::OutOfOrder("no function was begun".into());
+            return Err(error.into());
+        };
+        if self.function_symbols() && self.platform() == Platform::Linux {
+            self.directive(format_args!(".size {name}, .-{name}"))?;
+        }
+        Ok(())
+    }
+}
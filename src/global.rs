@@ -0,0 +1,97 @@
+use super::{
+    error::X64WriterError,
+    io::{self, Write},
+    platform::Platform,
+    section::Section,
+    symbol::SymbolBinding,
+    writer::AsmWriter,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
+/// The size of an x86-64 huge page, the largest alignment a global can have.
+pub const HUGE_PAGE_SIZE: u64 = 2 << 20;
+
+/// A global variable begun by [`AsmWriter::begin_global`] and not yet ended.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct OpenGlobal {
+    name: String,
+    align: u64,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Starts the global variable `name` in `section`, aligned to `align` bytes, to be
+    /// followed by its data and [`Self::end_global`]. On ELF, the symbol is typed as an
+    /// object and given its size. Alignments of a page or more let the variable start a
+    /// page of its own, up to [`HUGE_PAGE_SIZE`] for one backed by a huge page. Fails while
+    /// another global is open, for executable sections and for alignments the platform
+    /// does not have.
+    pub fn begin_global(
+        &mut self,
+        name: &str,
+        binding: SymbolBinding,
+        section: Section,
+        align: u64,
+    ) -> io::Result<()> {
+        if let Some(open) = &self.open_global() {
+            let message = format!("cannot begin {name} before ending {}", open.name);
+            return Err(X64WriterError::OutOfOrder(message).into());
+        }
+        self.check_alignment(align)?;
+        if section.is_executable() {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "cannot begin {name} in {}, which is executable",
+                section.name()
+            ))
+            .into());
+        }
+        *self.open_global() = Some(OpenGlobal {
+            name: name.to_string(),
+            align,
+        });
+        self.push_section(section)?;
+        self.emit_balign(align)?;
+        self.declare_binding(name, binding)?;
+        if self.platform() == Platform::Linux {
+            self.directive(format_args!(".type {name}, @object"))?;
+        }
+        self.emit_label(name)?;
+        Ok(())
+    }
+    /// Ends the global begun last, which extends up to the current location, and returns
+    /// to the section before it. With `pad`, the section is padded up to the alignment of
+    /// the global, so a page-aligned one shares its last page with nothing else. Fails if
+    /// none is open.
+    pub fn end_global(&mut self, pad: bool) -> io::Result<()> {
+        let Some(OpenGlobal { name, align }) = self.open_global().take() else {
+            let error = X64WriterError::OutOfOrder("no global was begun".into());
+            return Err(error.into());
+        };
+        if self.platform() == Platform::Linux {
+            self.directive(format_args!(".size {name}, .-{name}"))?;
+        }
+        if pad {
+            self.emit_balign(align)?;
+        }
+        self.pop_section()
+    }
+
+    /// Checks that `align` is a power of two no larger than the platform allows.
+    pub(crate) fn check_alignment(&self, align: u64) -> Result<(), X64WriterError> {
+        if !align.is_power_of_two() {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "alignment {align} is not a power of two"
+            )));
+        }
+        let max = self.platform().max_alignment();
+        if align > max {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "alignment {align} exceeds the maximum of {max} on {:?}",
+                self.platform()
+            )));
+        }
+        Ok(())
+    }
+}
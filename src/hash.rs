@@ -0,0 +1,240 @@
+use super::{
+    args::{Arg, ConstInt, MemoryKind, Scale, Segment},
+    function::{Function, Item},
+    instruction::Instruction,
+    island::ConstantValue,
+    register::{Register, RegisterSize},
+};
+use alloc::string::{String, ToString};
+use core::hash::Hasher;
+
+/// 64-bit FNV-1a, which unlike the standard library hashers gives the same result in every
+/// build and on every platform.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StableHasher(u64);
+impl StableHasher {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Instruction<'_> {
+    /// A hash of the instruction that stays the same across crate versions and platforms.
+    ///
+    /// The instruction is encoded as its `lock` or repeat prefix if it has one, the
+    /// mnemonic name without size suffix, the condition suffix for conditional instructions
+    /// or the operand size in bytes for string instructions, and the number of explicit
+    /// operands followed by each operand, and hashed with [`StableHasher`]. Vector
+    /// instructions then add the masking as written in assembly, such as `{%k1}{z}` or
+    /// nothing, and a `1` byte if the memory operand is broadcast (else `0`). Strings are
+    /// terminated by a zero byte and integers are little endian. An operand is encoded as
+    /// a tag byte followed by:
+    ///
+    /// - `r`: the register name as in 64-bit code without `%`, and its size in bytes.
+    /// - `x`: the number of the vector register as a byte, and its size in bytes.
+    /// - `k`: the number of the mask register as a byte.
+    /// - `i`: the size of the immediate in bytes, a signedness byte, and the value as 8 bytes.
+    /// - `l`: the label.
+    /// - `s`: the label and the offset.
+    /// - `m`: the access size in bytes (0 if unspecified), the segment (`f`, `g` or 0),
+    ///   the label displacement if any behind a `1` byte (else `0`), the constant
+    ///   displacement as an `i` operand behind a `1` byte (else `0`), and either `p` for
+    ///   rip-relative addressing, or `b` followed by the optional base and index registers
+    ///   each behind a `1` byte (else `0`), the index followed by the scale.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.hash_content(&mut hasher);
+        hasher.finish()
+    }
+    fn hash_content(&self, hasher: &mut StableHasher) {
+        match *self {
+            Self::Locked { .. } => write_str(hasher, "lock"),
+            Self::String { rep: Some(rep), .. } => write_str(hasher, rep.prefix()),
+            _ => (),
+        }
+        write_str(hasher, self.mnemonic().name());
+        match *self {
+            Self::CMov { condition, .. }
+            | Self::SetCC { condition, .. }
+            | Self::CJmp { condition, .. } => {
+                write_str(hasher, condition.suffix());
+            }
+            Self::String { size, .. } => hasher.write_u8(size.bytes()),
+            _ => (),
+        }
+        let operands = self.operands();
+        hasher.write(&(operands.len() as u64).to_le_bytes());
+        for operand in operands {
+            hash_arg(hasher, &operand);
+        }
+        if let Self::Vector {
+            masking, broadcast, ..
+        } = *self
+        {
+            let masking = masking.map_or(String::new(), |masking| masking.to_string());
+            write_str(hasher, &masking);
+            hasher.write_u8(broadcast.into());
+        }
+    }
+}
+
+impl Function<'_> {
+    /// A hash of the name, linkage, target features, labels, instructions and constants
+    /// of the function that stays the same across crate versions and platforms.
+    /// Comments and instruction tags do not contribute.
+    ///
+    /// The function is encoded as its name, a `1` byte if global (else `0`), the names of its
+    /// target features joined by `,`, and each item in order: `L` and the label, preceded
+    /// by `A`, the power and the maximum skip as 4 bytes if the label has an alignment hint,
+    /// or `I` and the encoding of the instruction described in [`Instruction::content_hash`].
+    /// Each constant then follows as `C`, its label, its size in bytes and its value as
+    /// 16 bytes.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        write_str(&mut hasher, self.name.label);
+        hasher.write_u8(self.global.into());
+        write_str(&mut hasher, &self.features.to_string());
+        for item in &self.items {
+            match item {
+                Item::Label(label) => {
+                    if let Some(alignment) = self.alignment_of(*label) {
+                        hasher.write_u8(b'A');
+                        hasher.write_u8(alignment.power);
+                        hasher.write(&alignment.max_skip.to_le_bytes());
+                    }
+                    hasher.write_u8(b'L');
+                    write_str(&mut hasher, label.label);
+                }
+                Item::Instruction(instruction, _) => {
+                    hasher.write_u8(b'I');
+                    instruction.hash_content(&mut hasher);
+                }
+                Item::Comment(_) => (),
+            }
+        }
+        for constant in &self.constants {
+            hasher.write_u8(b'C');
+            write_str(&mut hasher, constant.label.label);
+            hasher.write_u8(constant.value.size() as u8);
+            let value = match constant.value {
+                ConstantValue::Long(v) => v.into(),
+                ConstantValue::Quad(v) => v.into(),
+                ConstantValue::Octa(v) => v,
+            };
+            hasher.write(&value.to_le_bytes());
+        }
+        hasher.finish()
+    }
+}
+
+fn write_str(hasher: &mut StableHasher, s: &str) {
+    hasher.write(s.as_bytes());
+    hasher.write_u8(0);
+}
+fn write_register(hasher: &mut StableHasher, register: Register) {
+    let name = register.0.quad().to_string();
+    write_str(hasher, &name[1..]);
+    hasher.write_u8(register.1.in_bytes() as u8);
+}
+fn write_int(hasher: &mut StableHasher, value: ConstInt) {
+    let (size, signed, value): (u8, bool, [u8; 8]) = match value {
+        ConstInt::I8(v) => (1, true, i64::from(v).to_le_bytes()),
+        ConstInt::U8(v) => (1, false, u64::from(v).to_le_bytes()),
+        ConstInt::I32(v) => (4, true, i64::from(v).to_le_bytes()),
+        ConstInt::U32(v) => (4, false, u64::from(v).to_le_bytes()),
+        ConstInt::I64(v) => (8, true, v.to_le_bytes()),
+        ConstInt::U64(v) => (8, false, v.to_le_bytes()),
+    };
+    hasher.write_u8(b'i');
+    hasher.write_u8(size);
+    hasher.write_u8(signed.into());
+    hasher.write(&value);
+}
+fn hash_arg(hasher: &mut StableHasher, arg: &Arg) {
+    match *arg {
+        Arg::Register(register) => {
+            hasher.write_u8(b'r');
+            write_register(hasher, register);
+        }
+        Arg::Virtual(register) => {
+            hasher.write_u8(b'v');
+            hasher.write(&register.0.to_le_bytes());
+            hasher.write_u8(register.1.in_bytes() as u8);
+        }
+        Arg::Vector(register) => {
+            hasher.write_u8(b'x');
+            hasher.write_u8(register.0);
+            hasher.write_u8(register.1.in_bytes());
+        }
+        Arg::Mask(register) => {
+            hasher.write_u8(b'k');
+            hasher.write_u8(register.0);
+        }
+        Arg::Int(value) => write_int(hasher, value),
+        Arg::Label(label) => {
+            hasher.write_u8(b'l');
+            write_str(hasher, label.label);
+        }
+        Arg::SymExpr(expr) => {
+            hasher.write_u8(b's');
+            write_str(hasher, expr.label.label);
+            hasher.write(&expr.offset.to_le_bytes());
+        }
+        Arg::Memory(memory) => {
+            hasher.write_u8(b'm');
+            let size = memory
+                .size
+                .map_or(0, |size| RegisterSize::from(size).in_bytes());
+            hasher.write_u8(size as u8);
+            hasher.write_u8(match memory.segment {
+                Some(Segment::Fs) => b'f',
+                Some(Segment::Gs) => b'g',
+                None => 0,
+            });
+            hasher.write_u8(memory.displacement_label.is_some().into());
+            if let Some(label) = memory.displacement_label {
+                write_str(hasher, label.label);
+            }
+            hasher.write_u8(memory.displacement_constant.is_some().into());
+            if let Some(constant) = memory.displacement_constant {
+                write_int(hasher, constant);
+            }
+            match memory.kind {
+                MemoryKind::Rip => hasher.write_u8(b'p'),
+                MemoryKind::Sib(sib) => {
+                    hasher.write_u8(b'b');
+                    hasher.write_u8(sib.base().is_some().into());
+                    if let Some(base) = sib.base() {
+                        write_register(hasher, base);
+                    }
+                    hasher.write_u8(sib.index().is_some().into());
+                    if let Some((index, scale)) = sib.index() {
+                        write_register(hasher, index);
+                        hasher.write_u8(match scale {
+                            Scale::One => 1,
+                            Scale::Two => 2,
+                            Scale::Four => 4,
+                            Scale::Eight => 8,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::{
+    args::{Arg, ArgSize, Memory},
+    error::X64WriterError,
+    instruction::Instruction,
+    io::Write,
+    label::{Label, SymExpr},
+    register::RegisterName,
+    writer::{AsmWriter, BinaryOp, BinaryOpSingle, Condition},
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// An inline cache stub, with the offsets of its patchable fields from its label. The
+/// stub is emitted as bytes, so its layout does not depend on the assembler, and each
+/// field is naturally aligned to be patched with a single store.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InlineCache {
+    /// The label at the entry of the stub.
+    pub label: String,
+    /// The offset of the 8-byte class pointer the receiver's class is compared with.
+    pub class_offset: Option<u32>,
+    /// The offset of the 4-byte displacement of the jump or call to the target, relative
+    /// to the end of the field.
+    pub target_offset: u32,
+    pub size: u32,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits a monomorphic inline cache: it jumps to `target` if the class pointer at
+    /// `class` equals `expected`, and to `miss` otherwise. Clobbers `%r11` and the flags.
+    ///
+    /// The stub starts with `movabs $expected, %r11` at offset 0, so the class pointer is
+    /// always at offset 2. It ends with the jump to the target.
+    pub fn emit_monomorphic_ic<'a>(
+        &mut self,
+        class: Memory<'a>,
+        expected: u64,
+        target: impl Into<Label<'a>>,
+        miss: impl Into<Label<'a>>,
+    ) -> Result<InlineCache, X64WriterError> {
+        let scratch = RegisterName::R11.quad();
+        if class.address_registers().iter().any(|r| r.0 == scratch.0) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "the class pointer cannot be addressed with {scratch}, which the stub clobbers"
+            )));
+        }
+        // Padding so that the class pointer at offset 2 is 8-byte aligned.
+        self.directive(".p2align 3")?;
+        self.emit_nops(6)?;
+        let label = self.begin_inline_cache("monomorphic")?;
+
+        self.comment(format!("movabs ${expected:#x}, {scratch}"))?;
+        self.emit_bytes(&[0x49, 0xbb])?;
+        self.emit_quad(expected)?;
+        let mut size = 10;
+        size += self.emit_encoded(&Instruction::Binary {
+            op: BinaryOp::Single(BinaryOpSingle::Cmp),
+            dst: Arg::Memory(class.size(ArgSize::Quad)),
+            src: Arg::Register(scratch),
+        })?;
+        size += self.emit_encoded(&Instruction::CJmp {
+            condition: Condition::NotEqual,
+            target: Arg::Label(miss.into()),
+        })?;
+        // Padding so that the displacement after the opcode byte is 4-byte aligned, given
+        // that the label is 6 bytes past an 8-byte boundary.
+        let padding = (4 - (6 + size + 1) % 4) % 4;
+        self.emit_nops(padding)?;
+        size += padding;
+        size += self.emit_encoded(&Instruction::Jmp {
+            target: Arg::Label(target.into()),
+        })?;
+
+        Ok(InlineCache {
+            label,
+            class_offset: Some(2),
+            target_offset: size - 4,
+            size,
+        })
+    }
+    /// Emits a call to `target`, typically the runtime's miss handler, to be patched into
+    /// a call to a stub specialized for the classes seen.
+    pub fn emit_ic_call<'a>(
+        &mut self,
+        target: impl Into<Label<'a>>,
+    ) -> Result<InlineCache, X64WriterError> {
+        // Padding so that the displacement at offset 1 is 4-byte aligned.
+        self.directive(".p2align 2")?;
+        self.emit_nops(3)?;
+        let label = self.begin_inline_cache("call")?;
+        let size = self.emit_encoded(&Instruction::Call {
+            target: Arg::Label(target.into()),
+        })?;
+
+        Ok(InlineCache {
+            label,
+            class_offset: None,
+            target_offset: 1,
+            size,
+        })
+    }
+
+    fn begin_inline_cache(&mut self, shape: &str) -> Result<String, X64WriterError> {
+        let count = self.inline_caches();
+        let label = format!(".Lic{count}");
+        *count += 1;
+        self.emit_label(label.as_str())?;
+        self.comment(format!("{shape} inline cache"))?;
+        Ok(label)
+    }
+    /// Emits the machine code of the instruction as bytes and returns its size.
+    fn emit_encoded(&mut self, instruction: &Instruction) -> Result<u32, X64WriterError> {
+        let mut code = Vec::new();
+        let relocations = instruction
+            .encode(&mut code)
+            .map_err(|error| X64WriterError::InvalidOperand(error.to_string()))?;
+        self.comment(instruction.to_string())?;
+        let mut start = 0;
+        for relocation in relocations {
+            if start < relocation.offset {
+                self.emit_bytes(&code[start..relocation.offset])?;
+            }
+            self.reference_symbol(relocation.symbol.label);
+            let value = SymExpr::new(relocation.symbol, relocation.addend);
+            match relocation.kind.is_pc_relative() {
+                true => self.directive(format_args!(".long {value}-."))?,
+                false => self.emit_long(value)?,
+            }
+            start = relocation.offset + 4;
+        }
+        if start < code.len() {
+            self.emit_bytes(&code[start..])?;
+        }
+        Ok(code.len() as u32)
+    }
+}
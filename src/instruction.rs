@@ -0,0 +1,947 @@
+use super::{
+    args::{Arg, ArgSize},
+    features::{Feature, TargetFeatures},
+    flags::{Flags, FlagsEffect},
+    label::Label,
+    meta::{Access, InstructionInfo, Mnemonic},
+    register::{
+        MaskRegister, Masking, Register, RegisterName, RegisterSet, RegisterSize, VectorRegister,
+        VectorSize,
+    },
+    writer::{
+        BinaryOp, BinaryOpDouble, BinaryOpSingle, Condition, LockedOp, NonaryOp, RepPrefix, SseOp,
+        StringOp, TernaryOp, UnaryOp, UnaryOpSingle, VectorOp,
+    },
+};
+use alloc::{vec, vec::Vec};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction<'a> {
+    Mov {
+        dst: Arg<'a>,
+        src: Arg<'a>,
+    },
+    CMov {
+        condition: Condition,
+        dst: Arg<'a>,
+        src: Arg<'a>,
+    },
+    SetCC {
+        condition: Condition,
+        dst: Arg<'a>,
+    },
+    Push {
+        src: Arg<'a>,
+    },
+    Pop {
+        dst: Arg<'a>,
+    },
+    Binary {
+        op: BinaryOp,
+        dst: Arg<'a>,
+        src: Arg<'a>,
+    },
+    Unary {
+        op: UnaryOp,
+        dst: Arg<'a>,
+    },
+    Ternary {
+        op: TernaryOp,
+        dst: Arg<'a>,
+        src: Arg<'a>,
+        src2: Arg<'a>,
+    },
+    /// `dst = lhs op rhs`, which has no encoding until
+    /// [`Function::legalize_two_address`](crate::function::Function::legalize_two_address)
+    /// turned it into a copy and a [`Self::Binary`].
+    ThreeAddress {
+        op: BinaryOpSingle,
+        dst: Arg<'a>,
+        lhs: Arg<'a>,
+        rhs: Arg<'a>,
+    },
+    Call {
+        target: Arg<'a>,
+    },
+    Jmp {
+        target: Arg<'a>,
+    },
+    CJmp {
+        condition: Condition,
+        target: Arg<'a>,
+    },
+    Nonary {
+        op: NonaryOp,
+    },
+    /// An AVX or AVX-512 operation, written with its operands reversed like [`Self::Ternary`].
+    Vector {
+        op: VectorOp,
+        dst: Arg<'a>,
+        /// The first source, which operations with a single source leave out.
+        src1: Option<Arg<'a>>,
+        src2: Arg<'a>,
+        masking: Option<Masking>,
+        /// Whether `src2` is a single element in memory, repeated in every lane.
+        broadcast: bool,
+    },
+    /// A read-modify-write of the memory operand `dst` made atomic by a `lock` prefix,
+    /// see [`Self::locked`]. Unary operations have no `src`.
+    Locked {
+        op: LockedOp,
+        dst: Arg<'a>,
+        src: Option<Arg<'a>>,
+    },
+    /// A string instruction on `size` sized elements, which has no explicit operands.
+    String {
+        op: StringOp,
+        size: ArgSize,
+        rep: Option<RepPrefix>,
+    },
+}
+impl<'a> Instruction<'a> {
+    pub fn mnemonic(&self) -> Mnemonic {
+        match *self {
+            Self::Mov { .. } => Mnemonic::Mov,
+            Self::CMov { .. } => Mnemonic::CMov,
+            Self::SetCC { .. } => Mnemonic::SetCC,
+            Self::Push { .. } => Mnemonic::Push,
+            Self::Pop { .. } => Mnemonic::Pop,
+            Self::Binary {
+                op: BinaryOp::Single(op),
+                ..
+            }
+            | Self::ThreeAddress { op, .. } => match op {
+                BinaryOpSingle::Add => Mnemonic::Add,
+                BinaryOpSingle::Adc => Mnemonic::Adc,
+                BinaryOpSingle::Sub => Mnemonic::Sub,
+                BinaryOpSingle::Sbb => Mnemonic::Sbb,
+                BinaryOpSingle::IMul => Mnemonic::IMul,
+                BinaryOpSingle::And => Mnemonic::And,
+                BinaryOpSingle::Or => Mnemonic::Or,
+                BinaryOpSingle::Xor => Mnemonic::Xor,
+                BinaryOpSingle::Lea => Mnemonic::Lea,
+                BinaryOpSingle::Cmp => Mnemonic::Cmp,
+                BinaryOpSingle::Test => Mnemonic::Test,
+                BinaryOpSingle::Shl => Mnemonic::Shl,
+                BinaryOpSingle::Shr => Mnemonic::Shr,
+                BinaryOpSingle::Sar => Mnemonic::Sar,
+                BinaryOpSingle::Xchg => Mnemonic::Xchg,
+                BinaryOpSingle::Movbe => Mnemonic::Movbe,
+                BinaryOpSingle::Popcnt => Mnemonic::Popcnt,
+                BinaryOpSingle::Lzcnt => Mnemonic::Lzcnt,
+                BinaryOpSingle::Tzcnt => Mnemonic::Tzcnt,
+                BinaryOpSingle::Bsf => Mnemonic::Bsf,
+                BinaryOpSingle::Bsr => Mnemonic::Bsr,
+                BinaryOpSingle::Bt => Mnemonic::Bt,
+                BinaryOpSingle::Bts => Mnemonic::Bts,
+                BinaryOpSingle::Btr => Mnemonic::Btr,
+                BinaryOpSingle::Btc => Mnemonic::Btc,
+                BinaryOpSingle::Xadd => Mnemonic::Xadd,
+                BinaryOpSingle::Cmpxchg => Mnemonic::Cmpxchg,
+            },
+            Self::Binary {
+                op: BinaryOp::Double(op),
+                ..
+            } => match op {
+                BinaryOpDouble::Movzx => Mnemonic::Movzx,
+                BinaryOpDouble::Movsx => Mnemonic::Movsx,
+            },
+            Self::Binary {
+                op: BinaryOp::Sse(op),
+                ..
+            } => match op {
+                SseOp::Movss => Mnemonic::Movss,
+                SseOp::Movsd => Mnemonic::Movsd,
+                SseOp::Movaps => Mnemonic::Movaps,
+                SseOp::Movups => Mnemonic::Movups,
+                SseOp::Movapd => Mnemonic::Movapd,
+                SseOp::Movupd => Mnemonic::Movupd,
+                SseOp::Movd => Mnemonic::Movd,
+                SseOp::Movq => Mnemonic::Movq,
+                SseOp::Addss => Mnemonic::Addss,
+                SseOp::Addsd => Mnemonic::Addsd,
+                SseOp::Addps => Mnemonic::Addps,
+                SseOp::Addpd => Mnemonic::Addpd,
+                SseOp::Subss => Mnemonic::Subss,
+                SseOp::Subsd => Mnemonic::Subsd,
+                SseOp::Subps => Mnemonic::Subps,
+                SseOp::Subpd => Mnemonic::Subpd,
+                SseOp::Mulss => Mnemonic::Mulss,
+                SseOp::Mulsd => Mnemonic::Mulsd,
+                SseOp::Mulps => Mnemonic::Mulps,
+                SseOp::Mulpd => Mnemonic::Mulpd,
+                SseOp::Divss => Mnemonic::Divss,
+                SseOp::Divsd => Mnemonic::Divsd,
+                SseOp::Divps => Mnemonic::Divps,
+                SseOp::Divpd => Mnemonic::Divpd,
+                SseOp::Sqrtss => Mnemonic::Sqrtss,
+                SseOp::Sqrtsd => Mnemonic::Sqrtsd,
+                SseOp::Andps => Mnemonic::Andps,
+                SseOp::Andpd => Mnemonic::Andpd,
+                SseOp::Xorps => Mnemonic::Xorps,
+                SseOp::Xorpd => Mnemonic::Xorpd,
+                SseOp::Ucomiss => Mnemonic::Ucomiss,
+                SseOp::Ucomisd => Mnemonic::Ucomisd,
+                SseOp::Cvtsi2ss => Mnemonic::Cvtsi2ss,
+                SseOp::Cvtsi2sd => Mnemonic::Cvtsi2sd,
+                SseOp::Cvttss2si => Mnemonic::Cvttss2si,
+                SseOp::Cvttsd2si => Mnemonic::Cvttsd2si,
+                SseOp::Cvtss2sd => Mnemonic::Cvtss2sd,
+                SseOp::Cvtsd2ss => Mnemonic::Cvtsd2ss,
+            },
+            Self::Unary {
+                op: UnaryOp::Single(op),
+                ..
+            } => match op {
+                UnaryOpSingle::Inc => Mnemonic::Inc,
+                UnaryOpSingle::Dec => Mnemonic::Dec,
+                UnaryOpSingle::Neg => Mnemonic::Neg,
+                UnaryOpSingle::Not => Mnemonic::Not,
+                UnaryOpSingle::Mul => Mnemonic::Mul,
+                UnaryOpSingle::IMul => Mnemonic::UnaryIMul,
+                UnaryOpSingle::Div => Mnemonic::Div,
+                UnaryOpSingle::IDiv => Mnemonic::IDiv,
+                UnaryOpSingle::Bswap => Mnemonic::Bswap,
+            },
+            Self::Unary {
+                op: UnaryOp::Cmpxchg16b,
+                ..
+            } => Mnemonic::Cmpxchg16b,
+            Self::Ternary { op, .. } => match op {
+                TernaryOp::Bextr => Mnemonic::Bextr,
+                TernaryOp::Shld => Mnemonic::Shld,
+                TernaryOp::Shrd => Mnemonic::Shrd,
+                TernaryOp::Shlx => Mnemonic::Shlx,
+                TernaryOp::Shrx => Mnemonic::Shrx,
+                TernaryOp::Sarx => Mnemonic::Sarx,
+            },
+            Self::Call { .. } => Mnemonic::Call,
+            Self::Jmp { .. } => Mnemonic::Jmp,
+            Self::CJmp { .. } => Mnemonic::CJmp,
+            Self::Nonary { op } => match op {
+                NonaryOp::Ret => Mnemonic::Ret,
+                NonaryOp::Cpuid => Mnemonic::Cpuid,
+                NonaryOp::Xgetbv => Mnemonic::Xgetbv,
+                NonaryOp::Rdmsr => Mnemonic::Rdmsr,
+                NonaryOp::Wrmsr => Mnemonic::Wrmsr,
+                NonaryOp::Swapgs => Mnemonic::Swapgs,
+                NonaryOp::Iretq => Mnemonic::Iretq,
+                NonaryOp::Cld => Mnemonic::Cld,
+                NonaryOp::Sysretq => Mnemonic::Sysretq,
+                NonaryOp::Cltq => Mnemonic::Cltq,
+                NonaryOp::Cbtw => Mnemonic::Cbtw,
+                NonaryOp::Cwtl => Mnemonic::Cwtl,
+                NonaryOp::Cwtd => Mnemonic::Cwtd,
+                NonaryOp::Cltd => Mnemonic::Cltd,
+                NonaryOp::Cqto => Mnemonic::Cqto,
+                NonaryOp::Mfence => Mnemonic::Mfence,
+                NonaryOp::Lfence => Mnemonic::Lfence,
+                NonaryOp::Sfence => Mnemonic::Sfence,
+            },
+            Self::Locked { .. } => self.unlocked().mnemonic(),
+            Self::String { op, .. } => match op {
+                StringOp::Movs => Mnemonic::Movs,
+                StringOp::Stos => Mnemonic::Stos,
+                StringOp::Lods => Mnemonic::Lods,
+                StringOp::Scas => Mnemonic::Scas,
+                StringOp::Cmps => Mnemonic::Cmps,
+            },
+            Self::Vector { op, .. } => match op {
+                VectorOp::Vaddps => Mnemonic::Vaddps,
+                VectorOp::Vaddpd => Mnemonic::Vaddpd,
+                VectorOp::Vsubps => Mnemonic::Vsubps,
+                VectorOp::Vsubpd => Mnemonic::Vsubpd,
+                VectorOp::Vmulps => Mnemonic::Vmulps,
+                VectorOp::Vmulpd => Mnemonic::Vmulpd,
+                VectorOp::Vdivps => Mnemonic::Vdivps,
+                VectorOp::Vdivpd => Mnemonic::Vdivpd,
+                VectorOp::Vminps => Mnemonic::Vminps,
+                VectorOp::Vminpd => Mnemonic::Vminpd,
+                VectorOp::Vmaxps => Mnemonic::Vmaxps,
+                VectorOp::Vmaxpd => Mnemonic::Vmaxpd,
+                VectorOp::Vandps => Mnemonic::Vandps,
+                VectorOp::Vandpd => Mnemonic::Vandpd,
+                VectorOp::Vxorps => Mnemonic::Vxorps,
+                VectorOp::Vxorpd => Mnemonic::Vxorpd,
+                VectorOp::Vaddss => Mnemonic::Vaddss,
+                VectorOp::Vaddsd => Mnemonic::Vaddsd,
+                VectorOp::Vsubss => Mnemonic::Vsubss,
+                VectorOp::Vsubsd => Mnemonic::Vsubsd,
+                VectorOp::Vmulss => Mnemonic::Vmulss,
+                VectorOp::Vmulsd => Mnemonic::Vmulsd,
+                VectorOp::Vdivss => Mnemonic::Vdivss,
+                VectorOp::Vdivsd => Mnemonic::Vdivsd,
+                VectorOp::Vfmadd231ps => Mnemonic::Vfmadd231ps,
+                VectorOp::Vfmadd231pd => Mnemonic::Vfmadd231pd,
+                VectorOp::Vfmadd231ss => Mnemonic::Vfmadd231ss,
+                VectorOp::Vfmadd231sd => Mnemonic::Vfmadd231sd,
+                VectorOp::Vpaddd => Mnemonic::Vpaddd,
+                VectorOp::Vpaddq => Mnemonic::Vpaddq,
+                VectorOp::Vpsubd => Mnemonic::Vpsubd,
+                VectorOp::Vpsubq => Mnemonic::Vpsubq,
+                VectorOp::Vpmulld => Mnemonic::Vpmulld,
+                VectorOp::Vpand => Mnemonic::Vpand,
+                VectorOp::Vpandd => Mnemonic::Vpandd,
+                VectorOp::Vpandq => Mnemonic::Vpandq,
+                VectorOp::Vpxor => Mnemonic::Vpxor,
+                VectorOp::Vpxord => Mnemonic::Vpxord,
+                VectorOp::Vpxorq => Mnemonic::Vpxorq,
+                VectorOp::Vsqrtps => Mnemonic::Vsqrtps,
+                VectorOp::Vsqrtpd => Mnemonic::Vsqrtpd,
+                VectorOp::Vmovaps => Mnemonic::Vmovaps,
+                VectorOp::Vmovapd => Mnemonic::Vmovapd,
+                VectorOp::Vmovups => Mnemonic::Vmovups,
+                VectorOp::Vmovupd => Mnemonic::Vmovupd,
+                VectorOp::Vmovdqu => Mnemonic::Vmovdqu,
+                VectorOp::Vmovdqu32 => Mnemonic::Vmovdqu32,
+                VectorOp::Vmovdqu64 => Mnemonic::Vmovdqu64,
+                VectorOp::Vbroadcastss => Mnemonic::Vbroadcastss,
+                VectorOp::Vbroadcastsd => Mnemonic::Vbroadcastsd,
+                VectorOp::Kmovw => Mnemonic::Kmovw,
+                VectorOp::Kmovq => Mnemonic::Kmovq,
+            },
+        }
+    }
+    /// The explicit operands, destination first.
+    pub fn operands(&self) -> Vec<Arg<'a>> {
+        match *self {
+            Self::Mov { dst, src }
+            | Self::CMov { dst, src, .. }
+            | Self::Binary { dst, src, .. } => vec![dst, src],
+            Self::Ternary { dst, src, src2, .. } => vec![dst, src, src2],
+            Self::ThreeAddress { dst, lhs, rhs, .. } => vec![dst, lhs, rhs],
+            Self::Push { src: arg }
+            | Self::Pop { dst: arg }
+            | Self::SetCC { dst: arg, .. }
+            | Self::Unary { dst: arg, .. }
+            | Self::Call { target: arg }
+            | Self::Jmp { target: arg }
+            | Self::CJmp { target: arg, .. } => vec![arg],
+            Self::Nonary { .. } | Self::String { .. } => Vec::new(),
+            Self::Vector {
+                dst, src1, src2, ..
+            } => [Some(dst), src1, Some(src2)]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Self::Locked { dst, src, .. } => [Some(dst), src].into_iter().flatten().collect(),
+        }
+    }
+    /// Replaces the explicit operands, given in the order of [`Self::operands`].
+    pub(crate) fn with_operands(self, operands: &[Arg<'a>]) -> Self {
+        match self {
+            Self::Mov { .. } => Self::Mov {
+                dst: operands[0],
+                src: operands[1],
+            },
+            Self::CMov { condition, .. } => Self::CMov {
+                condition,
+                dst: operands[0],
+                src: operands[1],
+            },
+            Self::SetCC { condition, .. } => Self::SetCC {
+                condition,
+                dst: operands[0],
+            },
+            Self::Push { .. } => Self::Push { src: operands[0] },
+            Self::Pop { .. } => Self::Pop { dst: operands[0] },
+            Self::Binary { op, .. } => Self::Binary {
+                op,
+                dst: operands[0],
+                src: operands[1],
+            },
+            Self::Unary { op, .. } => Self::Unary {
+                op,
+                dst: operands[0],
+            },
+            Self::Ternary { op, .. } => Self::Ternary {
+                op,
+                dst: operands[0],
+                src: operands[1],
+                src2: operands[2],
+            },
+            Self::ThreeAddress { op, .. } => Self::ThreeAddress {
+                op,
+                dst: operands[0],
+                lhs: operands[1],
+                rhs: operands[2],
+            },
+            Self::Call { .. } => Self::Call {
+                target: operands[0],
+            },
+            Self::Jmp { .. } => Self::Jmp {
+                target: operands[0],
+            },
+            Self::CJmp { condition, .. } => Self::CJmp {
+                condition,
+                target: operands[0],
+            },
+            Self::Nonary { .. } | Self::String { .. } => self,
+            Self::Vector {
+                op,
+                src1,
+                masking,
+                broadcast,
+                ..
+            } => Self::Vector {
+                op,
+                dst: operands[0],
+                src1: src1.map(|_| operands[1]),
+                src2: operands[operands.len() - 1],
+                masking,
+                broadcast,
+            },
+            Self::Locked { op, src, .. } => Self::Locked {
+                op,
+                dst: operands[0],
+                src: src.map(|_| operands[1]),
+            },
+        }
+    }
+    /// Replaces every register in the explicit operands, leaving implicit ones untouched.
+    pub fn map_registers(self, f: impl Fn(Register) -> Register) -> Self {
+        match self {
+            Self::Mov { dst, src } => Self::Mov {
+                dst: dst.map_registers(&f),
+                src: src.map_registers(&f),
+            },
+            Self::CMov {
+                condition,
+                dst,
+                src,
+            } => Self::CMov {
+                condition,
+                dst: dst.map_registers(&f),
+                src: src.map_registers(&f),
+            },
+            Self::SetCC { condition, dst } => Self::SetCC {
+                condition,
+                dst: dst.map_registers(f),
+            },
+            Self::Push { src } => Self::Push {
+                src: src.map_registers(f),
+            },
+            Self::Pop { dst } => Self::Pop {
+                dst: dst.map_registers(f),
+            },
+            Self::Binary { op, dst, src } => Self::Binary {
+                op,
+                dst: dst.map_registers(&f),
+                src: src.map_registers(&f),
+            },
+            Self::Unary { op, dst } => Self::Unary {
+                op,
+                dst: dst.map_registers(f),
+            },
+            Self::Ternary { op, dst, src, src2 } => Self::Ternary {
+                op,
+                dst: dst.map_registers(&f),
+                src: src.map_registers(&f),
+                src2: src2.map_registers(&f),
+            },
+            Self::ThreeAddress { op, dst, lhs, rhs } => Self::ThreeAddress {
+                op,
+                dst: dst.map_registers(&f),
+                lhs: lhs.map_registers(&f),
+                rhs: rhs.map_registers(&f),
+            },
+            Self::Call { target } => Self::Call {
+                target: target.map_registers(f),
+            },
+            Self::Jmp { target } => Self::Jmp {
+                target: target.map_registers(f),
+            },
+            Self::CJmp { condition, target } => Self::CJmp {
+                condition,
+                target: target.map_registers(f),
+            },
+            Self::Nonary { .. } | Self::String { .. } => self,
+            Self::Vector {
+                op,
+                dst,
+                src1,
+                src2,
+                masking,
+                broadcast,
+            } => Self::Vector {
+                op,
+                dst: dst.map_registers(&f),
+                src1: src1.map(|src1| src1.map_registers(&f)),
+                src2: src2.map_registers(&f),
+                masking,
+                broadcast,
+            },
+            Self::Locked { op, dst, src } => Self::Locked {
+                op,
+                dst: dst.map_registers(&f),
+                src: src.map(|src| src.map_registers(&f)),
+            },
+        }
+    }
+    /// Replaces every label in the operands, possibly with one of a different lifetime.
+    pub fn map_labels<'b>(self, f: impl Fn(Label<'a>) -> Label<'b>) -> Instruction<'b> {
+        match self {
+            Self::Mov { dst, src } => Instruction::Mov {
+                dst: dst.map_labels(&f),
+                src: src.map_labels(&f),
+            },
+            Self::CMov {
+                condition,
+                dst,
+                src,
+            } => Instruction::CMov {
+                condition,
+                dst: dst.map_labels(&f),
+                src: src.map_labels(&f),
+            },
+            Self::SetCC { condition, dst } => Instruction::SetCC {
+                condition,
+                dst: dst.map_labels(f),
+            },
+            Self::Push { src } => Instruction::Push {
+                src: src.map_labels(f),
+            },
+            Self::Pop { dst } => Instruction::Pop {
+                dst: dst.map_labels(f),
+            },
+            Self::Binary { op, dst, src } => Instruction::Binary {
+                op,
+                dst: dst.map_labels(&f),
+                src: src.map_labels(&f),
+            },
+            Self::Unary { op, dst } => Instruction::Unary {
+                op,
+                dst: dst.map_labels(f),
+            },
+            Self::Ternary { op, dst, src, src2 } => Instruction::Ternary {
+                op,
+                dst: dst.map_labels(&f),
+                src: src.map_labels(&f),
+                src2: src2.map_labels(&f),
+            },
+            Self::ThreeAddress { op, dst, lhs, rhs } => Instruction::ThreeAddress {
+                op,
+                dst: dst.map_labels(&f),
+                lhs: lhs.map_labels(&f),
+                rhs: rhs.map_labels(&f),
+            },
+            Self::Call { target } => Instruction::Call {
+                target: target.map_labels(f),
+            },
+            Self::Jmp { target } => Instruction::Jmp {
+                target: target.map_labels(f),
+            },
+            Self::CJmp { condition, target } => Instruction::CJmp {
+                condition,
+                target: target.map_labels(f),
+            },
+            Self::Nonary { op } => Instruction::Nonary { op },
+            Self::String { op, size, rep } => Instruction::String { op, size, rep },
+            Self::Vector {
+                op,
+                dst,
+                src1,
+                src2,
+                masking,
+                broadcast,
+            } => Instruction::Vector {
+                op,
+                dst: dst.map_labels(&f),
+                src1: src1.map(|src1| src1.map_labels(&f)),
+                src2: src2.map_labels(&f),
+                masking,
+                broadcast,
+            },
+            Self::Locked { op, dst, src } => Instruction::Locked {
+                op,
+                dst: dst.map_labels(&f),
+                src: src.map(|src| src.map_labels(&f)),
+            },
+        }
+    }
+    /// The instruction with a `lock` prefix, if it is a read-modify-write of memory that
+    /// can take one.
+    pub fn locked(self) -> Option<Self> {
+        let (op, dst, src) = match self {
+            Self::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src,
+            } => (LockedOp::Binary(op), dst, Some(src)),
+            Self::Unary { op, dst } => (LockedOp::Unary(op), dst, None),
+            _ => return None,
+        };
+        (op.is_lockable() && dst.is_memory()).then_some(Self::Locked { op, dst, src })
+    }
+    /// The instruction without its `lock` prefix, if it has one.
+    pub fn unlocked(self) -> Self {
+        match self {
+            Self::Locked {
+                op: LockedOp::Binary(op),
+                dst,
+                src,
+            } => Self::Binary {
+                op: op.into(),
+                dst,
+                src: src.expect("locked binary operations have a source"),
+            },
+            Self::Locked {
+                op: LockedOp::Unary(op),
+                dst,
+                ..
+            } => Self::Unary { op, dst },
+            _ => self,
+        }
+    }
+
+    /// The metadata of [`Self::mnemonic`], refined for the operand sizes and condition.
+    pub fn info(&self) -> InstructionInfo {
+        use RegisterName::A;
+        let mut info = self.mnemonic().info();
+        match *self {
+            Self::Locked { .. } => info = self.unlocked().info(),
+            Self::CMov { condition, .. }
+            | Self::SetCC { condition, .. }
+            | Self::CJmp { condition, .. } => {
+                info.flags = FlagsEffect::reads(condition.flags());
+            }
+            Self::Unary {
+                op: UnaryOp::Single(op),
+                dst,
+            } if dst.size() == Some(ArgSize::Byte) && op.is_multiply_or_divide() => {
+                info.implicit_reads = RegisterSet::from_slice(&[A]);
+                info.implicit_writes = RegisterSet::from_slice(&[A]);
+            }
+            Self::ThreeAddress { .. } => info = info.three_address(),
+            Self::String {
+                op, rep: Some(_), ..
+            } => {
+                info.implicit_reads.insert(RegisterName::C);
+                info.implicit_writes.insert(RegisterName::C);
+                // With `%rcx` zero nothing is compared, and the flags keep their value.
+                if op.compares() {
+                    info.flags.read = Flags::all();
+                }
+            }
+            Self::Vector {
+                masking: Some(masking),
+                ..
+            } if !masking.zeroing => info = info.merging(),
+            _ => (),
+        }
+        info
+    }
+
+    /// The instruction set extensions needed to execute this instruction.
+    pub fn required_features(&self) -> TargetFeatures {
+        if let Self::Binary {
+            op: BinaryOp::Sse(op),
+            ..
+        } = self
+        {
+            return TargetFeatures::from_slice(&[op.feature()]);
+        }
+        if let Self::Vector { op, src2, .. } = *self {
+            return self.vector_features(op, src2);
+        }
+        match self.mnemonic() {
+            Mnemonic::Bextr | Mnemonic::Tzcnt => TargetFeatures::from_slice(&[Feature::Bmi1]),
+            Mnemonic::Shlx | Mnemonic::Shrx | Mnemonic::Sarx => {
+                TargetFeatures::from_slice(&[Feature::Bmi2])
+            }
+            Mnemonic::Lzcnt => TargetFeatures::from_slice(&[Feature::Lzcnt]),
+            Mnemonic::Movbe => TargetFeatures::from_slice(&[Feature::Movbe]),
+            Mnemonic::Popcnt => TargetFeatures::from_slice(&[Feature::Popcnt]),
+            Mnemonic::Cmpxchg16b => TargetFeatures::from_slice(&[Feature::Cx16]),
+            Mnemonic::Mfence | Mnemonic::Lfence => TargetFeatures::from_slice(&[Feature::Sse2]),
+            Mnemonic::Sfence => TargetFeatures::from_slice(&[Feature::Sse]),
+            _ => TargetFeatures::none(),
+        }
+    }
+
+    fn vector_features(&self, op: VectorOp, src2: Arg) -> TargetFeatures {
+        use VectorOp::*;
+        let size = self.vector_size();
+        let mut features = match op {
+            Kmovw => TargetFeatures::from(Feature::Avx512F),
+            Kmovq => TargetFeatures::from(Feature::Avx512Bw),
+            _ if self.requires_evex() => {
+                let mut features = TargetFeatures::from(Feature::Avx512F);
+                if !op.is_scalar() && size != Some(VectorSize::Zmm) {
+                    features.insert(Feature::Avx512Vl);
+                }
+                if matches!(op, Vandps | Vandpd | Vxorps | Vxorpd) {
+                    features.insert(Feature::Avx512Dq);
+                }
+                features
+            }
+            _ if op.accumulates() => TargetFeatures::from(Feature::Fma),
+            _ => TargetFeatures::from(Feature::Avx),
+        };
+        let integer = matches!(
+            op,
+            Vpaddd | Vpaddq | Vpsubd | Vpsubq | Vpmulld | Vpand | Vpxor
+        );
+        let broadcasts_register = matches!(op, Vbroadcastss | Vbroadcastsd) && src2.is_vector();
+        if !self.requires_evex()
+            && ((integer && size == Some(VectorSize::Ymm)) || broadcasts_register)
+        {
+            features.insert(Feature::Avx2);
+        }
+        features
+    }
+    /// Whether the instruction needs an EVEX encoding, because of masking, a broadcast,
+    /// a `%zmm` operand, a register beyond `%xmm15` or an operation without VEX encoding.
+    pub fn requires_evex(&self) -> bool {
+        let Self::Vector {
+            op,
+            masking,
+            broadcast,
+            ..
+        } = *self
+        else {
+            return false;
+        };
+        let extended = self.operands().into_iter().any(|arg| match arg {
+            Arg::Vector(register) => register.0 >= 16 || register.1 == VectorSize::Zmm,
+            _ => false,
+        });
+        op.is_evex_only() || masking.is_some() || broadcast || extended
+    }
+    /// The widest vector register operand.
+    pub fn vector_size(&self) -> Option<VectorSize> {
+        let operands = self.operands().into_iter();
+        operands
+            .filter_map(|arg| match arg {
+                Arg::Vector(register) => Some(register.1),
+                _ => None,
+            })
+            .max()
+    }
+
+    /// Whether the instruction or an operand can only be encoded in 64-bit mode.
+    pub fn requires_long_mode(&self) -> bool {
+        let quad_string = matches!(
+            self,
+            Self::String {
+                size: ArgSize::Quad,
+                ..
+            }
+        );
+        matches!(
+            self.mnemonic(),
+            Mnemonic::Cltq | Mnemonic::Cqto | Mnemonic::Cmpxchg16b
+        ) || quad_string
+            || self.operands().iter().any(Arg::requires_long_mode)
+    }
+
+    /// The registers read without appearing as operands, such as `%rax` for `mulq`.
+    pub fn implicit_uses(&self) -> Vec<Register> {
+        let names = self.info().implicit_reads;
+        names
+            .iter()
+            .map(|name| name.with_size(self.implicit_size(name, false)))
+            .collect()
+    }
+    /// The registers written or clobbered without appearing as operands.
+    pub fn implicit_defs(&self) -> Vec<Register> {
+        let names = self.info().implicit_writes;
+        names
+            .iter()
+            .map(|name| name.with_size(self.implicit_size(name, true)))
+            .collect()
+    }
+    fn implicit_size(&self, name: RegisterName, def: bool) -> RegisterSize {
+        match self.mnemonic() {
+            _ if name == RegisterName::SP => RegisterSize::Quad,
+            Mnemonic::Stos | Mnemonic::Lods | Mnemonic::Scas if name == RegisterName::A => {
+                let Self::String { size, .. } = *self else {
+                    unreachable!()
+                };
+                size.into()
+            }
+            Mnemonic::Mul | Mnemonic::UnaryIMul | Mnemonic::Div | Mnemonic::IDiv => {
+                let size = self.operands()[0].size().map(RegisterSize::from);
+                let divides = matches!(self.mnemonic(), Mnemonic::Div | Mnemonic::IDiv);
+                match size {
+                    Some(RegisterSize::Byte) if def || divides => RegisterSize::Word,
+                    Some(size) => size,
+                    None => RegisterSize::Quad,
+                }
+            }
+            Mnemonic::Cmpxchg => self.operands()[0]
+                .size()
+                .map_or(RegisterSize::Quad, RegisterSize::from),
+            Mnemonic::Cpuid | Mnemonic::Xgetbv | Mnemonic::Rdmsr | Mnemonic::Wrmsr => {
+                RegisterSize::Double
+            }
+            Mnemonic::Cbtw if !def => RegisterSize::Byte,
+            Mnemonic::Cwtl if !def => RegisterSize::Word,
+            Mnemonic::Cltq if !def => RegisterSize::Double,
+            Mnemonic::Cbtw | Mnemonic::Cwtd => RegisterSize::Word,
+            Mnemonic::Cwtl | Mnemonic::Cltd => RegisterSize::Double,
+            _ => RegisterSize::Quad,
+        }
+    }
+
+    /// All registers read, explicitly, implicitly or to compute an address.
+    pub fn uses(&self) -> Vec<Register> {
+        if self.is_zeroing_idiom() {
+            return Vec::new();
+        }
+
+        let info = self.info();
+        let mut uses = Vec::new();
+        for (arg, operand) in self.operands().into_iter().zip(info.operands) {
+            match arg {
+                Arg::Register(r) if operand.access.reads() => uses.push(r),
+                Arg::Memory(m) => uses.extend(m.address_registers()),
+                _ => (),
+            }
+        }
+        uses.extend(self.implicit_uses());
+        uses
+    }
+    /// All registers written, explicitly or implicitly.
+    /// 8 and 16-bit registers are only partially written, and 32-bit ones are zero-extended.
+    pub fn defs(&self) -> Vec<Register> {
+        let info = self.info();
+        let mut defs = Vec::new();
+        for (arg, operand) in self.operands().into_iter().zip(info.operands) {
+            if let Arg::Register(r) = arg {
+                if operand.access.writes() {
+                    defs.push(r);
+                }
+            }
+        }
+        defs.extend(self.implicit_defs());
+        defs
+    }
+
+    /// The vector registers read by explicit operands. Registers of different sizes but
+    /// the same number overlap.
+    pub fn vector_uses(&self) -> Vec<VectorRegister> {
+        if self.is_zeroing_idiom() {
+            return Vec::new();
+        }
+        self.register_operands(Access::reads, vector_register)
+    }
+    /// The vector registers written by explicit operands.
+    pub fn vector_defs(&self) -> Vec<VectorRegister> {
+        self.register_operands(Access::writes, vector_register)
+    }
+    /// The mask registers read, including the one masking the destination.
+    pub fn mask_uses(&self) -> Vec<MaskRegister> {
+        let mut uses = self.register_operands(Access::reads, mask_register);
+        if let Self::Vector {
+            masking: Some(masking),
+            ..
+        } = self
+        {
+            uses.push(masking.mask);
+        }
+        uses
+    }
+    /// The mask registers written by explicit operands.
+    pub fn mask_defs(&self) -> Vec<MaskRegister> {
+        self.register_operands(Access::writes, mask_register)
+    }
+    fn register_operands<T>(
+        &self,
+        accesses: impl Fn(Access) -> bool,
+        register: impl Fn(Arg) -> Option<T>,
+    ) -> Vec<T> {
+        let info = self.info();
+        let operands = self.operands().into_iter().zip(info.operands);
+        operands
+            .filter(|(_, operand)| accesses(operand.access))
+            .filter_map(|(arg, _)| register(arg))
+            .collect()
+    }
+
+    /// The general purpose registers whose value this instruction depends on.
+    pub fn reads(&self) -> RegisterSet {
+        self.uses().into_iter().map(|r| r.0).collect()
+    }
+    /// The general purpose registers this instruction modifies.
+    pub fn writes(&self) -> RegisterSet {
+        self.defs().into_iter().map(|r| r.0).collect()
+    }
+    /// Whether the instruction loads from memory, including the stack.
+    pub fn reads_memory(&self) -> bool {
+        let implicit = matches!(
+            self.mnemonic(),
+            Mnemonic::Pop
+                | Mnemonic::Ret
+                | Mnemonic::Iretq
+                | Mnemonic::Movs
+                | Mnemonic::Lods
+                | Mnemonic::Scas
+                | Mnemonic::Cmps
+        );
+        implicit || self.accesses_memory(|access| access.reads())
+    }
+    /// Whether the instruction stores to memory, including the stack.
+    pub fn writes_memory(&self) -> bool {
+        let implicit = matches!(
+            self.mnemonic(),
+            Mnemonic::Push | Mnemonic::Call | Mnemonic::Movs | Mnemonic::Stos
+        );
+        implicit || self.accesses_memory(|access| access.writes())
+    }
+    fn accesses_memory(&self, accesses: impl Fn(Access) -> bool) -> bool {
+        let info = self.info();
+        self.operands()
+            .iter()
+            .zip(info.operands)
+            .any(|(arg, operand)| arg.is_memory() && accesses(operand.access))
+    }
+
+    pub fn flags_effect(&self) -> FlagsEffect {
+        self.info().flags
+    }
+
+    /// Whether this instruction only exists to set the flags.
+    pub fn is_comparison(&self) -> bool {
+        matches!(self.mnemonic(), Mnemonic::Cmp | Mnemonic::Test)
+    }
+    /// Whether this is `xor` or `sub` of a register with itself, or an exclusive or of a
+    /// vector register with itself, which does not depend on the previous value of the
+    /// register.
+    pub fn is_zeroing_idiom(&self) -> bool {
+        use VectorOp::{Vpxor, Vpxord, Vpxorq, Vxorpd, Vxorps};
+        match *self {
+            Self::Binary {
+                op: BinaryOp::Single(BinaryOpSingle::Xor | BinaryOpSingle::Sub),
+                dst,
+                src,
+            } => dst == src && (dst.is_register() || dst.is_virtual()),
+            Self::Binary {
+                op: BinaryOp::Sse(SseOp::Xorps | SseOp::Xorpd),
+                dst,
+                src,
+            } => dst == src && dst.is_vector(),
+            Self::Vector {
+                op: Vxorps | Vxorpd | Vpxor | Vpxord | Vpxorq,
+                src1: Some(src1),
+                src2,
+                masking: None,
+                ..
+            } => src1 == src2 && src2.is_vector(),
+            _ => false,
+        }
+    }
+}
+
+fn vector_register(arg: Arg) -> Option<VectorRegister> {
+    match arg {
+        Arg::Vector(register) => Some(register),
+        _ => None,
+    }
+}
+fn mask_register(arg: Arg) -> Option<MaskRegister> {
+    match arg {
+        Arg::Mask(register) => Some(register),
+        _ => None,
+    }
+}
@@ -0,0 +1,138 @@
+use super::{
+    args::ArgSize,
+    builder::InstructionBuilder,
+    error::X64WriterError,
+    function::{Function, FunctionBuilder},
+    label::Label,
+    register::{rdi, rsp, RegisterName},
+    writer::Condition,
+};
+use alloc::{format, string::String};
+
+/// Builds an interrupt or exception entry stub that saves all general purpose registers,
+/// calls a System V handler and returns with `iretq`.
+///
+/// The handler receives a pointer to the saved registers in `%rdi`, laid out in the order
+/// of [`Self::SAVED_REGISTERS`], followed by the error code (zero if the CPU pushes none)
+/// and the frame pushed by the CPU.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InterruptFrameBuilder<'a> {
+    name: Label<'a>,
+    handler: Label<'a>,
+    global: bool,
+    error_code: bool,
+    swapgs: bool,
+    /// The labels skipping the `swapgs` on entry and on exit, named after the stub.
+    from_kernel: [String; 2],
+}
+impl<'a> InterruptFrameBuilder<'a> {
+    /// The saved registers from the lowest address up.
+    pub const SAVED_REGISTERS: [RegisterName; 15] = [
+        RegisterName::R15,
+        RegisterName::R14,
+        RegisterName::R13,
+        RegisterName::R12,
+        RegisterName::BP,
+        RegisterName::B,
+        RegisterName::R11,
+        RegisterName::R10,
+        RegisterName::R9,
+        RegisterName::R8,
+        RegisterName::DI,
+        RegisterName::SI,
+        RegisterName::D,
+        RegisterName::C,
+        RegisterName::A,
+    ];
+
+    pub fn new(name: impl Into<Label<'a>>, handler: impl Into<Label<'a>>) -> Self {
+        let name = name.into();
+        Self {
+            name,
+            handler: handler.into(),
+            global: false,
+            error_code: false,
+            swapgs: false,
+            from_kernel: [
+                format!(".L{}.entry_from_kernel", name.label),
+                format!(".L{}.exit_to_kernel", name.label),
+            ],
+        }
+    }
+
+    pub fn set_global(&mut self, global: bool) {
+        self.global = global;
+    }
+    /// Whether the CPU pushes an error code for this vector, such as for page faults.
+    pub fn set_error_code(&mut self, error_code: bool) {
+        self.error_code = error_code;
+    }
+    /// Swaps to the kernel `%gs` base when interrupting user mode, and back before returning.
+    pub fn set_swapgs(&mut self, swapgs: bool) {
+        self.swapgs = swapgs;
+    }
+
+    pub fn build(&'a self) -> Function<'a> {
+        let mut builder = FunctionBuilder::new(self.name);
+        builder.set_global(self.global);
+        // Every register holds a value of the interrupted code.
+        for name in RegisterName::ALL {
+            builder.declare_argument(name.quad());
+        }
+        self.emit(&mut builder)
+            .expect("building into a function does not fail");
+        builder
+            .end_function()
+            .expect("all registers are saved before they are written")
+    }
+    /// Emits the body of the stub, without the label of its name.
+    pub fn emit(&'a self, builder: &mut impl InstructionBuilder<'a>) -> Result<(), X64WriterError> {
+        let frame_size = 5 * 8;
+        let saved_size = Self::SAVED_REGISTERS.len() as i64 * 8;
+        // The CPU aligns the stack to 16 bytes before pushing its frame.
+        let padding = (frame_size + 8 + saved_size) % 16;
+
+        if !self.error_code {
+            builder.build_push(0i64)?;
+        }
+        if self.swapgs {
+            emit_swapgs(builder, &self.from_kernel[0])?;
+        }
+        for name in Self::SAVED_REGISTERS.into_iter().rev() {
+            builder.build_push(name.quad())?;
+        }
+
+        builder.build_cld()?;
+        builder.build_mov(rdi(), rsp())?;
+        if padding != 0 {
+            builder.build_sub(rsp(), padding)?;
+        }
+        builder.build_call(self.handler)?;
+        if padding != 0 {
+            builder.build_add(rsp(), padding)?;
+        }
+
+        for name in Self::SAVED_REGISTERS {
+            builder.build_pop(name.quad())?;
+        }
+        if self.swapgs {
+            emit_swapgs(builder, &self.from_kernel[1])?;
+        }
+        builder.build_add(rsp(), 8i64)?;
+        builder.build_iretq()
+    }
+}
+
+/// Emits `swapgs` if the interrupted code ran in user mode, with the stack pointer pointing
+/// at the error code, jumping to `from_kernel` otherwise.
+fn emit_swapgs<'a>(
+    builder: &mut impl InstructionBuilder<'a>,
+    from_kernel: &'a str,
+) -> Result<(), X64WriterError> {
+    let cs = rsp().memory().offset(16).size(ArgSize::Byte);
+    builder.build_test(cs, 3u8)?;
+    builder.build_cjmp(Condition::Zero, from_kernel)?;
+    builder.build_swapgs()?;
+    builder.emit_label(from_kernel)?;
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use core::fmt;
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result, Write};
+
+/// An [`io::Write`](Write) writing into a [`fmt::Write`], which has to receive UTF-8 text.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FmtWriter<W>(pub W);
+impl<W> FmtWriter<W> {
+    pub fn into_inner(self) -> W {
+        self.0
+    }
+}
+impl<W: fmt::Write> FmtWriter<W> {
+    fn write_str(&mut self, buf: &[u8]) -> Result<()> {
+        let text =
+            core::str::from_utf8(buf).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+        self.0.write_str(text).map_err(formatter_error)
+    }
+}
+#[cfg(feature = "std")]
+impl<W: fmt::Write> Write for FmtWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write_str(buf)?;
+        Ok(buf.len())
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.write_str(buf)
+    }
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+        self.0.write_fmt(args).map_err(formatter_error)
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+#[cfg(not(feature = "std"))]
+impl<W: fmt::Write> Write for FmtWriter<W> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.write_str(buf)
+    }
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+        self.0.write_fmt(args).map_err(formatter_error)
+    }
+}
+fn formatter_error(_: fmt::Error) -> Error {
+    Error::other("formatter error")
+}
+
+#[cfg(not(feature = "std"))]
+pub use no_std::{Error, ErrorKind, Result, Write};
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::{boxed::Box, vec::Vec};
+    use core::{error, fmt};
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The subset of `std::io::ErrorKind` the writer reports.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        InvalidInput,
+        InvalidData,
+        Unsupported,
+        WriteZero,
+        Other,
+    }
+
+    /// A failure of the output or of the writer, like `std::io::Error`.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        error: Box<dyn error::Error + Send + Sync>,
+    }
+    impl Error {
+        pub fn new(kind: ErrorKind, error: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self {
+            Self {
+                kind,
+                error: error.into(),
+            }
+        }
+        pub fn other(error: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self {
+            Self::new(ErrorKind::Other, error)
+        }
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.error)
+        }
+    }
+    impl error::Error for Error {
+        fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+            self.error.source()
+        }
+    }
+
+    /// A sink of bytes, like `std::io::Write` without partial writes.
+    pub trait Write {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()>;
+        fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+            struct Adapter<'a, W: ?Sized> {
+                inner: &'a mut W,
+                error: Option<Error>,
+            }
+            impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    self.inner.write_all(s.as_bytes()).map_err(|error| {
+                        self.error = Some(error);
+                        fmt::Error
+                    })
+                }
+            }
+
+            let mut adapter = Adapter {
+                inner: self,
+                error: None,
+            };
+            match fmt::write(&mut adapter, args) {
+                Ok(()) => Ok(()),
+                Err(_) => Err(adapter
+                    .error
+                    .unwrap_or_else(|| Error::other("formatter error"))),
+            }
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+    impl Write for Vec<u8> {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            self.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            (**self).write_all(buf)
+        }
+        fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+            (**self).write_fmt(args)
+        }
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+    impl<W: Write + ?Sized> Write for Box<W> {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            (**self).write_all(buf)
+        }
+        fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+            (**self).write_fmt(args)
+        }
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+}
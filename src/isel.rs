@@ -0,0 +1,426 @@
+use super::{
+    args::{Arg, ArgSize, Memory, Scale},
+    builder::{sized_immediate, InstructionBuilder},
+    error::X64WriterError,
+    register::{Register, RegisterName, RegisterSize},
+};
+use alloc::{boxed::Box, vec, vec::Vec};
+
+/// Integer arithmetic to select instructions for, computed at the size of the destination.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Const(i64),
+    /// Read at the size the value is needed at: the destination size for values, 64 bits
+    /// for addresses.
+    Register(RegisterName),
+    /// A value of the destination size, loaded from the address.
+    Load(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+}
+impl Expr {
+    pub fn add(lhs: impl Into<Expr>, rhs: impl Into<Expr>) -> Self {
+        Self::Add(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+    pub fn mul(lhs: impl Into<Expr>, rhs: impl Into<Expr>) -> Self {
+        Self::Mul(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+    pub fn load(address: impl Into<Expr>) -> Self {
+        Self::Load(Box::new(address.into()))
+    }
+
+    /// Whether computing the expression reads `name`.
+    pub fn uses(&self, name: RegisterName) -> bool {
+        match self {
+            Self::Const(_) => false,
+            Self::Register(register) => *register == name,
+            Self::Load(address) => address.uses(name),
+            Self::Add(lhs, rhs) | Self::Mul(lhs, rhs) => lhs.uses(name) || rhs.uses(name),
+        }
+    }
+
+    /// Computes the expression into `dst`, folding sums and scaled terms into `lea` and
+    /// the addresses of loads where it can. Intermediate values go into `scratch`, which
+    /// must not overlap `dst` or any register the expression reads.
+    pub fn select<'a>(
+        &self,
+        builder: &mut impl InstructionBuilder<'a>,
+        dst: Register,
+        scratch: &[RegisterName],
+    ) -> Result<(), X64WriterError> {
+        assert!(
+            matches!(dst.1, RegisterSize::Double | RegisterSize::Quad),
+            "only 32 and 64-bit expressions are supported"
+        );
+        assert!(!scratch.contains(&dst.0), "scratch registers overlap dst");
+        let mut selector = Selector {
+            builder,
+            scratch: scratch.iter().rev().copied().collect(),
+        };
+        selector.select(self, dst)
+    }
+}
+impl From<i64> for Expr {
+    fn from(value: i64) -> Self {
+        Self::Const(value)
+    }
+}
+impl From<RegisterName> for Expr {
+    fn from(value: RegisterName) -> Self {
+        Self::Register(value)
+    }
+}
+impl From<Register> for Expr {
+    fn from(value: Register) -> Self {
+        Self::Register(value.0)
+    }
+}
+
+/// The summands of a sum, with the constant ones added up.
+struct Sum<'e> {
+    terms: Vec<&'e Expr>,
+    constant: i64,
+}
+impl<'e> Sum<'e> {
+    fn new(expr: &'e Expr) -> Self {
+        let mut sum = Self {
+            terms: Vec::new(),
+            constant: 0,
+        };
+        sum.add(expr);
+        sum
+    }
+    fn add(&mut self, expr: &'e Expr) {
+        match expr {
+            Expr::Add(lhs, rhs) => {
+                self.add(lhs);
+                self.add(rhs);
+            }
+            Expr::Const(value) => self.constant = self.constant.wrapping_add(*value),
+            _ => self.terms.push(expr),
+        }
+    }
+    /// Removes and returns the first term `f` accepts.
+    fn take<T>(&mut self, f: impl Fn(&'e Expr) -> Option<T>) -> Option<T> {
+        let (i, value) = self
+            .terms
+            .iter()
+            .enumerate()
+            .find_map(|(i, term)| f(term).map(|value| (i, value)))?;
+        self.terms.remove(i);
+        Some(value)
+    }
+    /// The constant if it fits a displacement or immediate.
+    fn displacement(&self) -> Option<i32> {
+        i32::try_from(self.constant).ok()
+    }
+}
+
+struct Selector<'b, B> {
+    builder: &'b mut B,
+    /// Popped from the end, so the first given is used first.
+    scratch: Vec<RegisterName>,
+}
+impl<'a, B: InstructionBuilder<'a>> Selector<'_, B> {
+    fn take(&mut self) -> RegisterName {
+        self.scratch
+            .pop()
+            .expect("the expression needs more scratch registers")
+    }
+    fn release(&mut self, held: Vec<RegisterName>) {
+        self.scratch.extend(held.into_iter().rev());
+    }
+
+    fn select(&mut self, expr: &Expr, dst: Register) -> Result<(), X64WriterError> {
+        match expr {
+            Expr::Const(0) => {
+                let zero = dst.0.double();
+                self.builder.build_xor(zero, zero)
+            }
+            &Expr::Const(value) => self.builder.build_mov(dst, sized_immediate(dst.1, value)),
+            &Expr::Register(register) if register == dst.0 => Ok(()),
+            &Expr::Register(register) => self.builder.build_mov(dst, register.with_size(dst.1)),
+            Expr::Load(address) => {
+                let (memory, held) = self.address(address)?;
+                self.builder.build_mov(dst, memory.size(arg_size(dst.1)))?;
+                self.release(held);
+                Ok(())
+            }
+            Expr::Mul(lhs, rhs) => match (small_constant(lhs), small_constant(rhs)) {
+                (Some(lhs), Some(rhs)) => {
+                    let product = i64::from(lhs).wrapping_mul(rhs.into());
+                    self.select(&Expr::Const(product), dst)
+                }
+                (_, Some(k)) => self.mul_const(lhs, k, dst),
+                (Some(k), _) => self.mul_const(rhs, k, dst),
+                _ => self.binary(expr, lhs, rhs, dst),
+            },
+            Expr::Add(..) => self.add(expr, dst),
+        }
+    }
+
+    fn mul_const(&mut self, expr: &Expr, k: i32, dst: Register) -> Result<(), X64WriterError> {
+        let src = match *expr {
+            Expr::Register(register) => register.with_size(dst.1),
+            _ => {
+                self.select(expr, dst)?;
+                dst
+            }
+        };
+        self.builder.emit_mul_const(dst, src, k)
+    }
+
+    /// A product of two computed values, with the one that needs a register computed first.
+    fn binary(
+        &mut self,
+        expr: &Expr,
+        lhs: &Expr,
+        rhs: &Expr,
+        dst: Register,
+    ) -> Result<(), X64WriterError> {
+        let (first, second) = match needs_register(rhs) && !needs_register(lhs) {
+            true => (rhs, lhs),
+            false => (lhs, rhs),
+        };
+        let (first, second) = match second.uses(dst.0) && !first.uses(dst.0) {
+            true => (second, first),
+            false => (first, second),
+        };
+        if second.uses(dst.0) {
+            return self.through_temporary(expr, dst);
+        }
+
+        self.select(first, dst)?;
+        let (src, held) = self.operand(second, dst.1)?;
+        self.builder.build_imul(dst, src)?;
+        self.release(held);
+        Ok(())
+    }
+
+    fn add(&mut self, expr: &Expr, dst: Register) -> Result<(), X64WriterError> {
+        let mut sum = Sum::new(expr);
+        let displacement = sum.displacement().filter(|&d| d != 0);
+
+        // A `lea` pays off if it scales the index or combines at least two of base, index
+        // and displacement.
+        let index = sum.take(scaled_register);
+        let base = sum.take(register);
+        let index = index.or_else(|| Some((sum.take(register)?, Scale::One)));
+        let scaled = index.is_some_and(|(_, scale)| scale != Scale::One);
+        let parts = [base.is_some(), index.is_some(), displacement.is_some()];
+        if !scaled && parts.iter().filter(|&&part| part).count() < 2 {
+            return self.add_terms(expr, dst);
+        }
+
+        let constant = Expr::Const(sum.constant);
+        if sum.displacement().is_none() {
+            sum.terms.push(&constant);
+        }
+        let later = sum.terms;
+        if later.iter().any(|term| term.uses(dst.0)) {
+            return self.through_temporary(expr, dst);
+        }
+
+        self.builder
+            .build_lea(dst, address(base, index, displacement))?;
+        self.add_operands(&later, dst)
+    }
+
+    /// Adds up the terms of `expr` one by one, starting with one that has to be computed
+    /// anyway.
+    fn add_terms(&mut self, expr: &Expr, dst: Register) -> Result<(), X64WriterError> {
+        let sum = Sum::new(expr);
+        let mut terms = sum.terms.clone();
+        terms.sort_by_key(|term| !needs_register(term));
+        let constant = Expr::Const(sum.constant);
+        match sum.displacement() {
+            Some(_) => (),
+            None => terms.push(&constant),
+        }
+
+        let Some((first, later)) = terms.split_first() else {
+            return self.select(&constant, dst);
+        };
+        if later.iter().any(|term| term.uses(dst.0)) {
+            return self.through_temporary(expr, dst);
+        }
+        self.select(first, dst)?;
+        self.add_operands(later, dst)?;
+        match sum.displacement() {
+            Some(0) | None => Ok(()),
+            Some(displacement) => {
+                let displacement = sized_immediate(dst.1, displacement.into());
+                self.builder.build_add(dst, displacement)
+            }
+        }
+    }
+
+    fn add_operands(&mut self, operands: &[&Expr], dst: Register) -> Result<(), X64WriterError> {
+        for operand in operands {
+            let (src, held) = self.operand(operand, dst.1)?;
+            self.builder.build_add(dst, src)?;
+            self.release(held);
+        }
+        Ok(())
+    }
+
+    /// Computes `expr` into a scratch register first, for when parts of it read `dst`
+    /// after it was written.
+    fn through_temporary(&mut self, expr: &Expr, dst: Register) -> Result<(), X64WriterError> {
+        let temporary = self.take().with_size(dst.1);
+        self.select(expr, temporary)?;
+        self.release(vec![temporary.0]);
+        self.builder.build_mov(dst, temporary)
+    }
+
+    /// The operand for `expr` as the source of an instruction of `size`, and the scratch
+    /// registers to release once it was used.
+    fn operand(
+        &mut self,
+        expr: &Expr,
+        size: RegisterSize,
+    ) -> Result<(Arg<'a>, Vec<RegisterName>), X64WriterError> {
+        match *expr {
+            Expr::Const(value) if i32::try_from(value).is_ok() => {
+                Ok((sized_immediate(size, value).into(), Vec::new()))
+            }
+            Expr::Register(register) => Ok((register.with_size(size).into(), Vec::new())),
+            Expr::Load(ref address) => {
+                let (memory, held) = self.address(address)?;
+                Ok((memory.size(arg_size(size)).into(), held))
+            }
+            _ => {
+                let register = self.take();
+                self.select(expr, register.with_size(size))?;
+                Ok((register.with_size(size).into(), vec![register]))
+            }
+        }
+    }
+
+    /// The memory operand for the address `expr`, and the scratch registers to release
+    /// once it was used.
+    fn address(&mut self, expr: &Expr) -> Result<(Memory<'a>, Vec<RegisterName>), X64WriterError> {
+        let mut sum = Sum::new(expr);
+        let constant = Expr::Const(sum.constant);
+        if sum.displacement().is_none() {
+            sum.terms.push(&constant);
+        }
+        let displacement = sum.displacement().filter(|&d| d != 0);
+        let mut held = Vec::new();
+
+        let mut index = sum.take(scaled_register);
+        if index.is_none() {
+            if let Some((value, scale)) = sum.take(scaled) {
+                index = Some((self.value_register(value, &mut held)?, scale));
+            }
+        }
+        let mut base = sum.take(register);
+        let mut terms = sum.terms.into_iter();
+        if base.is_none() {
+            if let Some(term) = terms.next() {
+                base = Some(self.value_register(term, &mut held)?);
+            }
+        }
+        if index.is_none() {
+            if let Some(term) = terms.next() {
+                index = Some((self.value_register(term, &mut held)?, Scale::One));
+            }
+        }
+
+        // Whatever does not fit the address is added up in a scratch base.
+        let rest: Vec<_> = terms.collect();
+        if !rest.is_empty() {
+            let total = self.take().quad();
+            self.builder.build_lea(total, address(base, index, None))?;
+            self.add_operands(&rest, total)?;
+            held.push(total.0);
+            base = Some(total.0);
+            index = None;
+        }
+
+        Ok((address(base, index, displacement), held))
+    }
+    /// The register holding the 64-bit value of `expr`, computed into a scratch register
+    /// unless it is one already.
+    fn value_register(
+        &mut self,
+        expr: &Expr,
+        held: &mut Vec<RegisterName>,
+    ) -> Result<RegisterName, X64WriterError> {
+        if let Some(register) = register(expr) {
+            return Ok(register);
+        }
+        let register = self.take();
+        self.select(expr, register.quad())?;
+        held.push(register);
+        Ok(register)
+    }
+}
+
+fn address<'a>(
+    base: Option<RegisterName>,
+    index: Option<(RegisterName, Scale)>,
+    displacement: Option<i32>,
+) -> Memory<'a> {
+    let mut memory = Memory::sib();
+    if let Some(base) = base {
+        memory = memory.base(base.quad());
+    }
+    if let Some((index, scale)) = index {
+        memory = memory.index(index.quad(), scale);
+    }
+    match displacement {
+        Some(displacement) => memory.offset(displacement),
+        None => memory,
+    }
+}
+fn small_constant(expr: &Expr) -> Option<i32> {
+    match *expr {
+        Expr::Const(value) => i32::try_from(value).ok(),
+        _ => None,
+    }
+}
+fn register(expr: &Expr) -> Option<RegisterName> {
+    match *expr {
+        Expr::Register(register) => Some(register),
+        _ => None,
+    }
+}
+/// A product with a factor an index register can be scaled by.
+fn scaled(expr: &Expr) -> Option<(&Expr, Scale)> {
+    let Expr::Mul(lhs, rhs) = expr else {
+        return None;
+    };
+    let scale = |k| match small_constant(k)? {
+        1 => Some(Scale::One),
+        2 => Some(Scale::Two),
+        4 => Some(Scale::Four),
+        8 => Some(Scale::Eight),
+        _ => None,
+    };
+    match (scale(lhs), scale(rhs)) {
+        (_, Some(scale)) => Some((lhs, scale)),
+        (Some(scale), _) => Some((rhs, scale)),
+        _ => None,
+    }
+}
+fn scaled_register(expr: &Expr) -> Option<(RegisterName, Scale)> {
+    let (value, scale) = scaled(expr)?;
+    Some((register(value)?, scale))
+}
+/// Whether the value cannot be an operand as is.
+fn needs_register(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Const(value) => i32::try_from(value).is_err(),
+        Expr::Register(_) | Expr::Load(_) => false,
+        _ => true,
+    }
+}
+fn arg_size(size: RegisterSize) -> ArgSize {
+    match size {
+        RegisterSize::Byte => ArgSize::Byte,
+        RegisterSize::Word => ArgSize::Word,
+        RegisterSize::Double => ArgSize::Double,
+        RegisterSize::Quad => ArgSize::Quad,
+    }
+}
@@ -0,0 +1,95 @@
+use super::{
+    args::ArgSize,
+    io::{self, Write},
+    label::Label,
+    writer::AsmWriter,
+};
+use alloc::vec::Vec;
+
+/// A constant too large for an immediate operand, stored next to the code using it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ConstantValue {
+    Long(u32),
+    Quad(u64),
+    Octa(u128),
+}
+impl ConstantValue {
+    pub fn size(self) -> usize {
+        match self {
+            Self::Long(_) => 4,
+            Self::Quad(_) => 8,
+            Self::Octa(_) => 16,
+        }
+    }
+    /// The operand size of a load of the whole constant, if there is a general purpose one.
+    pub fn arg_size(self) -> Option<ArgSize> {
+        match self {
+            Self::Long(_) => Some(ArgSize::Double),
+            Self::Quad(_) => Some(ArgSize::Quad),
+            Self::Octa(_) => None,
+        }
+    }
+}
+impl From<u32> for ConstantValue {
+    fn from(value: u32) -> Self {
+        Self::Long(value)
+    }
+}
+impl From<u64> for ConstantValue {
+    fn from(value: u64) -> Self {
+        Self::Quad(value)
+    }
+}
+impl From<u128> for ConstantValue {
+    fn from(value: u128) -> Self {
+        Self::Octa(value)
+    }
+}
+impl From<f32> for ConstantValue {
+    fn from(value: f32) -> Self {
+        Self::Long(value.to_bits())
+    }
+}
+impl From<f64> for ConstantValue {
+    fn from(value: f64) -> Self {
+        Self::Quad(value.to_bits())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Constant<'a> {
+    pub label: Label<'a>,
+    pub value: ConstantValue,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits `constants` naturally aligned, largest first to avoid padding between them.
+    /// Constants of the same value share their storage, with all of their labels on it.
+    pub(crate) fn emit_constant_island(&mut self, constants: &[Constant]) -> io::Result<()> {
+        let mut constants = constants.to_vec();
+        constants.sort_by_key(|constant| core::cmp::Reverse(constant.value.size()));
+
+        let mut alignment = 0;
+        let mut emitted = Vec::new();
+        for constant in &constants {
+            if emitted.contains(&constant.value) {
+                continue;
+            }
+            emitted.push(constant.value);
+            let size = constant.value.size();
+            if size != alignment {
+                self.directive(format_args!(".p2align {}", size.trailing_zeros()))?;
+                alignment = size;
+            }
+            for alias in constants.iter().filter(|c| c.value == constant.value) {
+                self.emit_label(alias.label)?;
+            }
+            match constant.value {
+                ConstantValue::Long(v) => self.directive(format_args!(".long {v:#010x}"))?,
+                ConstantValue::Quad(v) => self.directive(format_args!(".quad {v:#018x}"))?,
+                ConstantValue::Octa(v) => self.directive(format_args!(".octa {v:#034x}"))?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,97 @@
 use super::args::Memory;
-use std::fmt::Display;
+use alloc::{format, string::String};
+use core::fmt::Display;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+/// How a reference to a symbol is relocated, written after it like `foo@PLT`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RelocationModifier {
+    /// The PLT entry of a function, for calls and jumps.
+    Plt,
+    /// The GOT entry holding the address, rip-relative.
+    GotPcRel,
+    /// The offset of the GOT entry from the GOT.
+    Got,
+    /// The offset of the symbol from the GOT.
+    GotOff,
+    /// The offset of a thread-local variable from the thread pointer, as in `%fs:foo@tpoff`.
+    TpOff,
+    /// The offset of a thread-local variable within its module's TLS block.
+    DtpOff,
+    /// The GOT entry holding the thread pointer offset, rip-relative.
+    GotTpOff,
+    /// The GOT entries for a `__tls_get_addr` call of the general dynamic model.
+    TlsGd,
+    /// The GOT entries for a `__tls_get_addr` call of the local dynamic model.
+    TlsLd,
+}
+impl RelocationModifier {
+    pub const ALL: [RelocationModifier; 9] = [
+        RelocationModifier::Plt,
+        RelocationModifier::GotPcRel,
+        RelocationModifier::Got,
+        RelocationModifier::GotOff,
+        RelocationModifier::TpOff,
+        RelocationModifier::DtpOff,
+        RelocationModifier::GotTpOff,
+        RelocationModifier::TlsGd,
+        RelocationModifier::TlsLd,
+    ];
+
+    /// The suffix after the `@`, in the case GCC writes it.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Self::Plt => "PLT",
+            Self::GotPcRel => "GOTPCREL",
+            Self::Got => "GOT",
+            Self::GotOff => "GOTOFF",
+            Self::TpOff => "tpoff",
+            Self::DtpOff => "dtpoff",
+            Self::GotTpOff => "gottpoff",
+            Self::TlsGd => "tlsgd",
+            Self::TlsLd => "tlsld",
+        }
+    }
+    /// Parses a suffix regardless of its case, as `as` does.
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|modifier| modifier.suffix().eq_ignore_ascii_case(suffix))
+    }
+}
+impl Display for RelocationModifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "@{}", self.suffix())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Label<'a> {
     pub label: &'a str,
+    pub modifier: Option<RelocationModifier>,
 }
 impl<'a> Label<'a> {
     pub fn new(label: &'a str) -> Self {
-        Self { label }
+        Self {
+            label,
+            modifier: None,
+        }
+    }
+    pub fn with_modifier(self, modifier: RelocationModifier) -> Self {
+        Self {
+            modifier: Some(modifier),
+            ..self
+        }
+    }
+    /// The label without a relocation modifier, as it is defined.
+    pub fn symbol(self) -> Self {
+        Self::new(self.label)
     }
 
     pub fn rip(&self) -> Memory<'a> {
-        Memory::rip().label(self.label)
+        Memory::rip().label(*self)
+    }
+    pub fn offset(self, offset: i64) -> SymExpr<'a> {
+        SymExpr::new(self, offset)
     }
 }
 impl<'a> From<&'a str> for Label<'a> {
@@ -21,11 +101,93 @@ impl<'a> From<&'a str> for Label<'a> {
 }
 impl<'a> From<&'a String> for Label<'a> {
     fn from(value: &'a String) -> Self {
-        Self { label: value }
+        Self::new(value)
     }
 }
 impl Display for Label<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.label)
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.label)?;
+        match self.modifier {
+            Some(modifier) => write!(f, "{modifier}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An owned local label, as handed out by a [`LabelAllocator`]. Borrow it as a
+/// [`Label`] or operand with `&label`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LocalLabel(String);
+impl LocalLabel {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    pub fn label(&self) -> Label<'_> {
+        Label::new(&self.0)
+    }
+    pub fn rip(&self) -> Memory<'_> {
+        self.label().rip()
+    }
+}
+impl<'a> From<&'a LocalLabel> for Label<'a> {
+    fn from(value: &'a LocalLabel) -> Self {
+        value.label()
+    }
+}
+impl Display for LocalLabel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Hands out local labels that are unique among those it allocated, by numbering them
+/// across all stems. Share one allocator for everything emitted into the same output.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LabelAllocator {
+    next: usize,
+}
+impl LabelAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A fresh label named after `stem`, such as `.Lloop_head_17`.
+    pub fn allocate(&mut self, stem: &str) -> LocalLabel {
+        assert!(
+            stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            "label stem {stem:?} is not an identifier"
+        );
+        let number = self.next;
+        self.next += 1;
+        LocalLabel(format!(".L{stem}_{number}"))
+    }
+}
+
+/// A label plus a constant byte offset, such as `stub+16`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SymExpr<'a> {
+    pub label: Label<'a>,
+    pub offset: i64,
+}
+impl<'a> SymExpr<'a> {
+    pub fn new(label: impl Into<Label<'a>>, offset: i64) -> Self {
+        Self {
+            label: label.into(),
+            offset,
+        }
+    }
+}
+impl<'a> From<Label<'a>> for SymExpr<'a> {
+    fn from(value: Label<'a>) -> Self {
+        Self::new(value, 0)
+    }
+}
+impl Display for SymExpr<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.label)?;
+        match self.offset {
+            0 => Ok(()),
+            offset if offset < 0 => write!(f, "{offset}"),
+            offset => write!(f, "+{offset}"),
+        }
     }
 }
@@ -0,0 +1,100 @@
+use super::{
+    args::Arg,
+    function::{Function, Item, Tags},
+    instruction::Instruction,
+    register::VirtualRegister,
+    writer::{BinaryOp, BinaryOpSingle},
+};
+
+impl Function<'_> {
+    /// Rewrites every three-address instruction into a copy and a two-address one.
+    /// Where the destination cannot double as the first source, such as when it is the
+    /// second source of a subtraction, the result goes through a new virtual register,
+    /// so this has to run before [`Self::allocate_registers`].
+    pub fn legalize_two_address(&mut self) {
+        let mut next_virtual = self
+            .instructions()
+            .flat_map(Instruction::operands)
+            .filter_map(|arg| match arg {
+                Arg::Virtual(v) => Some(v.0 + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        let items = core::mem::take(&mut self.items);
+        for item in items {
+            let Item::Instruction(Instruction::ThreeAddress { op, dst, lhs, rhs }, tags) = item
+            else {
+                self.items.push(item);
+                continue;
+            };
+
+            let (copy, src) = if lhs == dst {
+                (None, rhs)
+            } else if rhs == dst && is_commutative(op) {
+                (None, lhs)
+            } else {
+                (Some(lhs), rhs)
+            };
+            // Memory destinations take neither memory sources nor products, and the
+            // destination must not be overwritten before the source is read.
+            let needs_temporary = (rhs == dst && copy.is_some())
+                || (copy.is_some() && overlaps(src, dst))
+                || (dst.is_memory()
+                    && (op == BinaryOpSingle::IMul
+                        || src.is_memory()
+                        || copy.is_some_and(|c| c.is_memory())));
+
+            let mut push =
+                |instruction, tags| self.items.push(Item::Instruction(instruction, tags));
+            let binary = |dst, src| Instruction::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src,
+            };
+            if needs_temporary {
+                let size = dst.size().expect("three-address destination needs a size");
+                let temporary = Arg::Virtual(VirtualRegister(next_virtual, size.into()));
+                next_virtual += 1;
+                let copy = copy.unwrap_or(dst);
+                push(
+                    Instruction::Mov {
+                        dst: temporary,
+                        src: copy,
+                    },
+                    Tags::new(),
+                );
+                push(binary(temporary, src), tags);
+                push(
+                    Instruction::Mov {
+                        dst,
+                        src: temporary,
+                    },
+                    Tags::new(),
+                );
+            } else {
+                if let Some(copy) = copy {
+                    push(Instruction::Mov { dst, src: copy }, Tags::new());
+                }
+                push(binary(dst, src), tags);
+            }
+        }
+    }
+}
+
+fn is_commutative(op: BinaryOpSingle) -> bool {
+    use BinaryOpSingle::*;
+    matches!(op, Add | Adc | IMul | And | Or | Xor)
+}
+
+/// Whether writing the register `dst` changes `src`, such as `%rcx` for a count in `%cl`.
+fn overlaps(src: Arg, dst: Arg) -> bool {
+    match (src, dst) {
+        (Arg::Register(src), Arg::Register(register)) => src.0 == register.0,
+        (Arg::Memory(memory), Arg::Register(register)) => {
+            memory.address_registers().iter().any(|r| r.0 == register.0)
+        }
+        _ => false,
+    }
+}
@@ -1,4 +1,63 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+
+pub mod analysis;
 pub mod args;
+pub mod boot;
+pub mod builder;
+pub mod cfg;
+pub mod cfi;
+pub mod convention;
+pub mod data;
+pub mod debug;
+pub mod deopt;
+pub mod descriptor;
+pub mod diff;
+pub mod dispatch;
+pub mod eh;
+pub mod elf;
+pub mod encode;
+pub mod error;
+pub mod features;
+pub mod flags;
+pub mod fold;
+pub mod function;
+pub mod global;
+pub mod hash;
+pub mod ic;
+pub mod instruction;
+pub mod interrupt;
+pub mod io;
+pub mod isel;
+pub mod island;
 pub mod label;
+pub mod legalize;
+pub mod meta;
+pub mod module;
+pub mod msr;
+pub mod note;
+pub mod paging;
+pub mod parse;
+pub mod patchpoint;
+pub mod peephole;
+pub mod phi;
+pub mod platform;
+pub mod preset;
+pub mod profile;
+pub mod propagate;
+pub mod regalloc;
 pub mod register;
+pub mod report;
+pub mod schedule;
+pub mod section;
+pub mod shim;
+pub mod snapshot;
+pub mod split;
+pub mod stackmap;
+pub mod symbol;
+pub mod syscall;
+pub mod trace;
+pub mod unreachable;
+pub mod validate;
 pub mod writer;
+pub mod xray;
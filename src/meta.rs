@@ -0,0 +1,802 @@
+use super::{
+    flags::{Flags, FlagsEffect},
+    register::{RegisterName, RegisterSet},
+};
+use core::fmt::Display;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Mnemonic {
+    Mov,
+    CMov,
+    SetCC,
+    Push,
+    Pop,
+    Add,
+    Adc,
+    Sub,
+    Sbb,
+    IMul,
+    And,
+    Or,
+    Xor,
+    Lea,
+    Cmp,
+    Test,
+    Shl,
+    Shr,
+    Sar,
+    Xchg,
+    Movzx,
+    Movsx,
+    Movbe,
+    Popcnt,
+    Lzcnt,
+    Tzcnt,
+    Bsf,
+    Bsr,
+    Bt,
+    Bts,
+    Btr,
+    Btc,
+    Xadd,
+    Cmpxchg,
+    Bextr,
+    Shld,
+    Shrd,
+    Shlx,
+    Shrx,
+    Sarx,
+    Inc,
+    Dec,
+    Neg,
+    Not,
+    Mul,
+    UnaryIMul,
+    Div,
+    IDiv,
+    Bswap,
+    Cmpxchg16b,
+    Call,
+    Jmp,
+    CJmp,
+    Ret,
+    Cpuid,
+    Xgetbv,
+    Rdmsr,
+    Wrmsr,
+    Swapgs,
+    Iretq,
+    Cld,
+    Sysretq,
+    Cltq,
+    Cbtw,
+    Cwtl,
+    Cwtd,
+    Cltd,
+    Cqto,
+    Mfence,
+    Lfence,
+    Sfence,
+    Movs,
+    Stos,
+    Lods,
+    Scas,
+    Cmps,
+    Movss,
+    Movsd,
+    Movaps,
+    Movups,
+    Movapd,
+    Movupd,
+    Movd,
+    Movq,
+    Addss,
+    Addsd,
+    Addps,
+    Addpd,
+    Subss,
+    Subsd,
+    Subps,
+    Subpd,
+    Mulss,
+    Mulsd,
+    Mulps,
+    Mulpd,
+    Divss,
+    Divsd,
+    Divps,
+    Divpd,
+    Sqrtss,
+    Sqrtsd,
+    Andps,
+    Andpd,
+    Xorps,
+    Xorpd,
+    Ucomiss,
+    Ucomisd,
+    Cvtsi2ss,
+    Cvtsi2sd,
+    Cvttss2si,
+    Cvttsd2si,
+    Cvtss2sd,
+    Cvtsd2ss,
+    Vaddps,
+    Vaddpd,
+    Vsubps,
+    Vsubpd,
+    Vmulps,
+    Vmulpd,
+    Vdivps,
+    Vdivpd,
+    Vminps,
+    Vminpd,
+    Vmaxps,
+    Vmaxpd,
+    Vandps,
+    Vandpd,
+    Vxorps,
+    Vxorpd,
+    Vaddss,
+    Vaddsd,
+    Vsubss,
+    Vsubsd,
+    Vmulss,
+    Vmulsd,
+    Vdivss,
+    Vdivsd,
+    Vfmadd231ps,
+    Vfmadd231pd,
+    Vfmadd231ss,
+    Vfmadd231sd,
+    Vpaddd,
+    Vpaddq,
+    Vpsubd,
+    Vpsubq,
+    Vpmulld,
+    Vpand,
+    Vpandd,
+    Vpandq,
+    Vpxor,
+    Vpxord,
+    Vpxorq,
+    Vsqrtps,
+    Vsqrtpd,
+    Vmovaps,
+    Vmovapd,
+    Vmovups,
+    Vmovupd,
+    Vmovdqu,
+    Vmovdqu32,
+    Vmovdqu64,
+    Vbroadcastss,
+    Vbroadcastsd,
+    Kmovw,
+    Kmovq,
+}
+impl Mnemonic {
+    pub const ALL: [Mnemonic; 166] = [
+        Mnemonic::Mov,
+        Mnemonic::CMov,
+        Mnemonic::SetCC,
+        Mnemonic::Push,
+        Mnemonic::Pop,
+        Mnemonic::Add,
+        Mnemonic::Adc,
+        Mnemonic::Sub,
+        Mnemonic::Sbb,
+        Mnemonic::IMul,
+        Mnemonic::And,
+        Mnemonic::Or,
+        Mnemonic::Xor,
+        Mnemonic::Lea,
+        Mnemonic::Cmp,
+        Mnemonic::Test,
+        Mnemonic::Shl,
+        Mnemonic::Shr,
+        Mnemonic::Sar,
+        Mnemonic::Xchg,
+        Mnemonic::Movzx,
+        Mnemonic::Movsx,
+        Mnemonic::Movbe,
+        Mnemonic::Popcnt,
+        Mnemonic::Lzcnt,
+        Mnemonic::Tzcnt,
+        Mnemonic::Bsf,
+        Mnemonic::Bsr,
+        Mnemonic::Bt,
+        Mnemonic::Bts,
+        Mnemonic::Btr,
+        Mnemonic::Btc,
+        Mnemonic::Xadd,
+        Mnemonic::Cmpxchg,
+        Mnemonic::Bextr,
+        Mnemonic::Shld,
+        Mnemonic::Shrd,
+        Mnemonic::Shlx,
+        Mnemonic::Shrx,
+        Mnemonic::Sarx,
+        Mnemonic::Inc,
+        Mnemonic::Dec,
+        Mnemonic::Neg,
+        Mnemonic::Not,
+        Mnemonic::Mul,
+        Mnemonic::UnaryIMul,
+        Mnemonic::Div,
+        Mnemonic::IDiv,
+        Mnemonic::Bswap,
+        Mnemonic::Cmpxchg16b,
+        Mnemonic::Call,
+        Mnemonic::Jmp,
+        Mnemonic::CJmp,
+        Mnemonic::Ret,
+        Mnemonic::Cpuid,
+        Mnemonic::Xgetbv,
+        Mnemonic::Rdmsr,
+        Mnemonic::Wrmsr,
+        Mnemonic::Swapgs,
+        Mnemonic::Iretq,
+        Mnemonic::Cld,
+        Mnemonic::Sysretq,
+        Mnemonic::Cltq,
+        Mnemonic::Cbtw,
+        Mnemonic::Cwtl,
+        Mnemonic::Cwtd,
+        Mnemonic::Cltd,
+        Mnemonic::Cqto,
+        Mnemonic::Mfence,
+        Mnemonic::Lfence,
+        Mnemonic::Sfence,
+        Mnemonic::Movs,
+        Mnemonic::Stos,
+        Mnemonic::Lods,
+        Mnemonic::Scas,
+        Mnemonic::Cmps,
+        Mnemonic::Movss,
+        Mnemonic::Movsd,
+        Mnemonic::Movaps,
+        Mnemonic::Movups,
+        Mnemonic::Movapd,
+        Mnemonic::Movupd,
+        Mnemonic::Movd,
+        Mnemonic::Movq,
+        Mnemonic::Addss,
+        Mnemonic::Addsd,
+        Mnemonic::Addps,
+        Mnemonic::Addpd,
+        Mnemonic::Subss,
+        Mnemonic::Subsd,
+        Mnemonic::Subps,
+        Mnemonic::Subpd,
+        Mnemonic::Mulss,
+        Mnemonic::Mulsd,
+        Mnemonic::Mulps,
+        Mnemonic::Mulpd,
+        Mnemonic::Divss,
+        Mnemonic::Divsd,
+        Mnemonic::Divps,
+        Mnemonic::Divpd,
+        Mnemonic::Sqrtss,
+        Mnemonic::Sqrtsd,
+        Mnemonic::Andps,
+        Mnemonic::Andpd,
+        Mnemonic::Xorps,
+        Mnemonic::Xorpd,
+        Mnemonic::Ucomiss,
+        Mnemonic::Ucomisd,
+        Mnemonic::Cvtsi2ss,
+        Mnemonic::Cvtsi2sd,
+        Mnemonic::Cvttss2si,
+        Mnemonic::Cvttsd2si,
+        Mnemonic::Cvtss2sd,
+        Mnemonic::Cvtsd2ss,
+        Mnemonic::Vaddps,
+        Mnemonic::Vaddpd,
+        Mnemonic::Vsubps,
+        Mnemonic::Vsubpd,
+        Mnemonic::Vmulps,
+        Mnemonic::Vmulpd,
+        Mnemonic::Vdivps,
+        Mnemonic::Vdivpd,
+        Mnemonic::Vminps,
+        Mnemonic::Vminpd,
+        Mnemonic::Vmaxps,
+        Mnemonic::Vmaxpd,
+        Mnemonic::Vandps,
+        Mnemonic::Vandpd,
+        Mnemonic::Vxorps,
+        Mnemonic::Vxorpd,
+        Mnemonic::Vaddss,
+        Mnemonic::Vaddsd,
+        Mnemonic::Vsubss,
+        Mnemonic::Vsubsd,
+        Mnemonic::Vmulss,
+        Mnemonic::Vmulsd,
+        Mnemonic::Vdivss,
+        Mnemonic::Vdivsd,
+        Mnemonic::Vfmadd231ps,
+        Mnemonic::Vfmadd231pd,
+        Mnemonic::Vfmadd231ss,
+        Mnemonic::Vfmadd231sd,
+        Mnemonic::Vpaddd,
+        Mnemonic::Vpaddq,
+        Mnemonic::Vpsubd,
+        Mnemonic::Vpsubq,
+        Mnemonic::Vpmulld,
+        Mnemonic::Vpand,
+        Mnemonic::Vpandd,
+        Mnemonic::Vpandq,
+        Mnemonic::Vpxor,
+        Mnemonic::Vpxord,
+        Mnemonic::Vpxorq,
+        Mnemonic::Vsqrtps,
+        Mnemonic::Vsqrtpd,
+        Mnemonic::Vmovaps,
+        Mnemonic::Vmovapd,
+        Mnemonic::Vmovups,
+        Mnemonic::Vmovupd,
+        Mnemonic::Vmovdqu,
+        Mnemonic::Vmovdqu32,
+        Mnemonic::Vmovdqu64,
+        Mnemonic::Vbroadcastss,
+        Mnemonic::Vbroadcastsd,
+        Mnemonic::Kmovw,
+        Mnemonic::Kmovq,
+    ];
+
+    /// The mnemonic without size suffix, with `cc` standing in for the condition.
+    pub fn name(self) -> &'static str {
+        use Mnemonic::*;
+        match self {
+            Mov => "mov",
+            CMov => "cmovcc",
+            SetCC => "setcc",
+            Push => "push",
+            Pop => "pop",
+            Add => "add",
+            Adc => "adc",
+            Sub => "sub",
+            Sbb => "sbb",
+            IMul | UnaryIMul => "imul",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Lea => "lea",
+            Cmp => "cmp",
+            Test => "test",
+            Shl => "shl",
+            Shr => "shr",
+            Sar => "sar",
+            Xchg => "xchg",
+            Movzx => "movz",
+            Movsx => "movs",
+            Movbe => "movbe",
+            Popcnt => "popcnt",
+            Lzcnt => "lzcnt",
+            Tzcnt => "tzcnt",
+            Bsf => "bsf",
+            Bsr => "bsr",
+            Bt => "bt",
+            Bts => "bts",
+            Btr => "btr",
+            Btc => "btc",
+            Xadd => "xadd",
+            Cmpxchg => "cmpxchg",
+            Bextr => "bextr",
+            Shld => "shld",
+            Shrd => "shrd",
+            Shlx => "shlx",
+            Shrx => "shrx",
+            Sarx => "sarx",
+            Inc => "inc",
+            Dec => "dec",
+            Neg => "neg",
+            Not => "not",
+            Mul => "mul",
+            Div => "div",
+            IDiv => "idiv",
+            Bswap => "bswap",
+            Cmpxchg16b => "cmpxchg16b",
+            Call => "call",
+            Jmp => "jmp",
+            CJmp => "jcc",
+            Ret => "ret",
+            Cpuid => "cpuid",
+            Xgetbv => "xgetbv",
+            Rdmsr => "rdmsr",
+            Wrmsr => "wrmsr",
+            Swapgs => "swapgs",
+            Iretq => "iretq",
+            Cld => "cld",
+            Sysretq => "sysretq",
+            Cltq => "cltq",
+            Cbtw => "cbtw",
+            Cwtl => "cwtl",
+            Cwtd => "cwtd",
+            Cltd => "cltd",
+            Cqto => "cqto",
+            Mfence => "mfence",
+            Lfence => "lfence",
+            Sfence => "sfence",
+            Movs => "movs",
+            Stos => "stos",
+            Lods => "lods",
+            Scas => "scas",
+            Cmps => "cmps",
+            Movss => "movss",
+            Movsd => "movsd",
+            Movaps => "movaps",
+            Movups => "movups",
+            Movapd => "movapd",
+            Movupd => "movupd",
+            Movd => "movd",
+            Movq => "movq",
+            Addss => "addss",
+            Addsd => "addsd",
+            Addps => "addps",
+            Addpd => "addpd",
+            Subss => "subss",
+            Subsd => "subsd",
+            Subps => "subps",
+            Subpd => "subpd",
+            Mulss => "mulss",
+            Mulsd => "mulsd",
+            Mulps => "mulps",
+            Mulpd => "mulpd",
+            Divss => "divss",
+            Divsd => "divsd",
+            Divps => "divps",
+            Divpd => "divpd",
+            Sqrtss => "sqrtss",
+            Sqrtsd => "sqrtsd",
+            Andps => "andps",
+            Andpd => "andpd",
+            Xorps => "xorps",
+            Xorpd => "xorpd",
+            Ucomiss => "ucomiss",
+            Ucomisd => "ucomisd",
+            Cvtsi2ss => "cvtsi2ss",
+            Cvtsi2sd => "cvtsi2sd",
+            Cvttss2si => "cvttss2si",
+            Cvttsd2si => "cvttsd2si",
+            Cvtss2sd => "cvtss2sd",
+            Cvtsd2ss => "cvtsd2ss",
+            Vaddps => "vaddps",
+            Vaddpd => "vaddpd",
+            Vsubps => "vsubps",
+            Vsubpd => "vsubpd",
+            Vmulps => "vmulps",
+            Vmulpd => "vmulpd",
+            Vdivps => "vdivps",
+            Vdivpd => "vdivpd",
+            Vminps => "vminps",
+            Vminpd => "vminpd",
+            Vmaxps => "vmaxps",
+            Vmaxpd => "vmaxpd",
+            Vandps => "vandps",
+            Vandpd => "vandpd",
+            Vxorps => "vxorps",
+            Vxorpd => "vxorpd",
+            Vaddss => "vaddss",
+            Vaddsd => "vaddsd",
+            Vsubss => "vsubss",
+            Vsubsd => "vsubsd",
+            Vmulss => "vmulss",
+            Vmulsd => "vmulsd",
+            Vdivss => "vdivss",
+            Vdivsd => "vdivsd",
+            Vfmadd231ps => "vfmadd231ps",
+            Vfmadd231pd => "vfmadd231pd",
+            Vfmadd231ss => "vfmadd231ss",
+            Vfmadd231sd => "vfmadd231sd",
+            Vpaddd => "vpaddd",
+            Vpaddq => "vpaddq",
+            Vpsubd => "vpsubd",
+            Vpsubq => "vpsubq",
+            Vpmulld => "vpmulld",
+            Vpand => "vpand",
+            Vpandd => "vpandd",
+            Vpandq => "vpandq",
+            Vpxor => "vpxor",
+            Vpxord => "vpxord",
+            Vpxorq => "vpxorq",
+            Vsqrtps => "vsqrtps",
+            Vsqrtpd => "vsqrtpd",
+            Vmovaps => "vmovaps",
+            Vmovapd => "vmovapd",
+            Vmovups => "vmovups",
+            Vmovupd => "vmovupd",
+            Vmovdqu => "vmovdqu",
+            Vmovdqu32 => "vmovdqu32",
+            Vmovdqu64 => "vmovdqu64",
+            Vbroadcastss => "vbroadcastss",
+            Vbroadcastsd => "vbroadcastsd",
+            Kmovw => "kmovw",
+            Kmovq => "kmovq",
+        }
+    }
+
+    /// Describes the operands and side effects of the instruction. Operands are listed in
+    /// the order of the [`Instruction`](crate::instruction::Instruction) fields, destination first.
+    ///
+    /// Multiplications and divisions are described in their 16 to 64 bit forms, and flag
+    /// consumers as reading all flags; [`Instruction::info`](crate::instruction::Instruction::info)
+    /// refines both for a concrete instruction.
+    pub fn info(self) -> InstructionInfo {
+        use Mnemonic::*;
+        use RegisterName::{A, B, C, D, DI, R10, R11, R8, R9, SI, SP};
+
+        let arithmetic = FlagsEffect::writes(Flags::all());
+        let logic = FlagsEffect {
+            written: Flags::all().difference(Flags::AUXILIARY),
+            undefined: Flags::AUXILIARY,
+            ..FlagsEffect::none()
+        };
+        let multiply = FlagsEffect {
+            written: Flags::CARRY.union(Flags::OVERFLOW),
+            undefined: Flags::all().difference(Flags::CARRY.union(Flags::OVERFLOW)),
+            ..FlagsEffect::none()
+        };
+        let shift = FlagsEffect {
+            written: Flags::CARRY
+                .union(Flags::PARITY)
+                .union(Flags::ZERO)
+                .union(Flags::SIGN),
+            undefined: Flags::AUXILIARY.union(Flags::OVERFLOW),
+            ..FlagsEffect::none()
+        };
+        let bit_test = FlagsEffect {
+            written: Flags::CARRY,
+            undefined: Flags::all().difference(Flags::CARRY.union(Flags::ZERO)),
+            ..FlagsEffect::none()
+        };
+
+        let info = InstructionInfo::new(self);
+        match self {
+            Mov | Movzx | Movsx | Movbe => info.operands(&[WRITE, READ]),
+            CMov => info
+                .operands(&[READ_WRITE, READ])
+                .flags(FlagsEffect::reads(Flags::all())),
+            SetCC => info
+                .operands(&[WRITE])
+                .flags(FlagsEffect::reads(Flags::all())),
+            Push => info.operands(&[READ]).implicit(&[SP], &[SP]),
+            Pop => info.operands(&[WRITE]).implicit(&[SP], &[SP]),
+            Add | Sub => info.operands(&[READ_WRITE, READ]).flags(arithmetic),
+            Adc | Sbb => info.operands(&[READ_WRITE, READ]).flags(FlagsEffect {
+                read: Flags::CARRY,
+                ..arithmetic
+            }),
+            And | Or | Xor => info.operands(&[READ_WRITE, READ]).flags(logic),
+            IMul => info.operands(&[READ_WRITE, READ]).flags(multiply),
+            Lea => info.operands(&[WRITE, ADDRESS]),
+            Cmp => info.operands(&[READ, READ]).flags(arithmetic),
+            Test => info.operands(&[READ, READ]).flags(logic),
+            Shl | Shr | Sar => info.operands(&[READ_WRITE, SHIFT_COUNT]).flags(shift),
+            Xchg => info.operands(&[READ_WRITE, READ_WRITE]),
+            Popcnt => info.operands(&[WRITE, READ]).flags(arithmetic),
+            Lzcnt | Tzcnt => info.operands(&[WRITE, READ]).flags(FlagsEffect {
+                written: Flags::CARRY.union(Flags::ZERO),
+                undefined: Flags::all().difference(Flags::CARRY.union(Flags::ZERO)),
+                ..FlagsEffect::none()
+            }),
+            // The destination keeps its value if the source is zero.
+            Bsf | Bsr => info.operands(&[READ_WRITE, READ]).flags(FlagsEffect {
+                written: Flags::ZERO,
+                undefined: Flags::all().difference(Flags::ZERO),
+                ..FlagsEffect::none()
+            }),
+            Bt => info.operands(&[READ, READ]).flags(bit_test),
+            Bts | Btr | Btc => info.operands(&[READ_WRITE, READ]).flags(bit_test),
+            Xadd => info.operands(&[READ_WRITE, READ_WRITE]).flags(arithmetic),
+            Cmpxchg => info
+                .operands(&[READ_WRITE, READ])
+                .implicit(&[A], &[A])
+                .flags(arithmetic),
+            Bextr => info.operands(&[WRITE, READ, READ]).flags(FlagsEffect {
+                written: Flags::CARRY.union(Flags::ZERO).union(Flags::OVERFLOW),
+                undefined: Flags::AUXILIARY.union(Flags::SIGN).union(Flags::PARITY),
+                ..FlagsEffect::none()
+            }),
+            Shld | Shrd => info.operands(&[READ_WRITE, READ, SHIFT_COUNT]).flags(shift),
+            Shlx | Shrx | Sarx => info.operands(&[WRITE, READ, READ]),
+            Inc | Dec => info
+                .operands(&[READ_WRITE])
+                .flags(FlagsEffect::writes(Flags::all().difference(Flags::CARRY))),
+            Neg => info.operands(&[READ_WRITE]).flags(arithmetic),
+            Not | Bswap => info.operands(&[READ_WRITE]),
+            Cmpxchg16b => info
+                .operands(&[READ_WRITE])
+                .implicit(&[A, B, C, D], &[A, D])
+                .flags(FlagsEffect::writes(Flags::ZERO)),
+            Mul | UnaryIMul => info
+                .operands(&[READ])
+                .implicit(&[A], &[A, D])
+                .flags(multiply),
+            Div | IDiv => info
+                .operands(&[READ])
+                .implicit(&[A, D], &[A, D])
+                .flags(FlagsEffect::clobbers(Flags::all())),
+            Call => info
+                .operands(&[READ])
+                .implicit(&[SP], &[A, C, D, SI, DI, R8, R9, R10, R11])
+                .flags(FlagsEffect::clobbers(Flags::all()))
+                .control_flow(ControlFlow::Call),
+            Jmp => info.operands(&[READ]).control_flow(ControlFlow::Jump),
+            CJmp => info
+                .operands(&[READ])
+                .flags(FlagsEffect::reads(Flags::all()))
+                .control_flow(ControlFlow::ConditionalJump),
+            Ret => info
+                .implicit(&[SP], &[SP])
+                .control_flow(ControlFlow::Return),
+            Sysretq => info
+                .implicit(&[C, R11], &[])
+                .flags(FlagsEffect::writes(Flags::all()))
+                .control_flow(ControlFlow::Return),
+            Cpuid => info.implicit(&[A, C], &[A, B, C, D]),
+            Xgetbv | Rdmsr => info.implicit(&[C], &[A, D]),
+            Wrmsr => info.implicit(&[A, C, D], &[]),
+            Cbtw | Cwtl | Cltq => info.implicit(&[A], &[A]),
+            Cwtd | Cltd | Cqto => info.implicit(&[A], &[D]),
+            Swapgs | Cld | Mfence | Lfence | Sfence => info,
+            Movs => info.implicit(&[SI, DI], &[SI, DI]),
+            Stos => info.implicit(&[A, DI], &[DI]),
+            Lods => info.implicit(&[SI], &[A, SI]),
+            Scas => info.implicit(&[A, DI], &[DI]).flags(arithmetic),
+            Cmps => info.implicit(&[SI, DI], &[SI, DI]).flags(arithmetic),
+            Iretq => info
+                .implicit(&[SP], &[SP])
+                .flags(FlagsEffect::writes(Flags::all()))
+                .control_flow(ControlFlow::Return),
+            Movss | Movsd | Movaps | Movups | Movapd | Movupd | Movd | Movq | Cvtsi2ss
+            | Cvtsi2sd | Cvttss2si | Cvttsd2si | Cvtss2sd | Cvtsd2ss => {
+                info.operands(&[WRITE, READ])
+            }
+            Addss | Addsd | Addps | Addpd | Subss | Subsd | Subps | Subpd | Mulss | Mulsd
+            | Mulps | Mulpd | Divss | Divsd | Divps | Divpd | Sqrtss | Sqrtsd | Andps | Andpd
+            | Xorps | Xorpd => info.operands(&[READ_WRITE, READ]),
+            Ucomiss | Ucomisd => info
+                .operands(&[READ, READ])
+                .flags(FlagsEffect::writes(Flags::all())),
+            Vsqrtps | Vsqrtpd | Vmovaps | Vmovapd | Vmovups | Vmovupd | Vmovdqu | Vmovdqu32
+            | Vmovdqu64 | Vbroadcastss | Vbroadcastsd | Kmovw | Kmovq => {
+                info.operands(&[WRITE, READ])
+            }
+            Vfmadd231ps | Vfmadd231pd | Vfmadd231ss | Vfmadd231sd => {
+                info.operands(&[READ_WRITE, READ, READ])
+            }
+            Vaddps | Vaddpd | Vsubps | Vsubpd | Vmulps | Vmulpd | Vdivps | Vdivpd | Vminps
+            | Vminpd | Vmaxps | Vmaxpd | Vandps | Vandpd | Vxorps | Vxorpd | Vaddss | Vaddsd
+            | Vsubss | Vsubsd | Vmulss | Vmulsd | Vdivss | Vdivsd | Vpaddd | Vpaddq | Vpsubd
+            | Vpsubq | Vpmulld | Vpand | Vpandd | Vpandq | Vpxor | Vpxord | Vpxorq => {
+                info.operands(&[WRITE, READ, READ])
+            }
+        }
+    }
+}
+impl Display for Mnemonic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How an instruction uses one of its explicit operands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+    /// Only the address of the memory operand is computed, memory is not accessed.
+    Address,
+}
+impl Access {
+    pub fn reads(self) -> bool {
+        matches!(self, Self::Read | Self::ReadWrite)
+    }
+    pub fn writes(self) -> bool {
+        matches!(self, Self::Write | Self::ReadWrite)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OperandInfo {
+    pub access: Access,
+    /// The only register allowed if the operand is a register, such as `%cl` for shift counts.
+    pub fixed: Option<RegisterName>,
+}
+impl OperandInfo {
+    pub const fn new(access: Access) -> Self {
+        Self {
+            access,
+            fixed: None,
+        }
+    }
+    pub const fn fixed(access: Access, register: RegisterName) -> Self {
+        Self {
+            access,
+            fixed: Some(register),
+        }
+    }
+}
+
+const READ: OperandInfo = OperandInfo::new(Access::Read);
+const WRITE: OperandInfo = OperandInfo::new(Access::Write);
+const READ_WRITE: OperandInfo = OperandInfo::new(Access::ReadWrite);
+const ADDRESS: OperandInfo = OperandInfo::new(Access::Address);
+const SHIFT_COUNT: OperandInfo = OperandInfo::fixed(Access::Read, RegisterName::C);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ControlFlow {
+    /// Execution continues with the next instruction.
+    None,
+    Jump,
+    ConditionalJump,
+    Call,
+    Return,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstructionInfo {
+    pub mnemonic: Mnemonic,
+    pub operands: &'static [OperandInfo],
+    /// Registers read that do not appear as operands.
+    pub implicit_reads: RegisterSet,
+    /// Registers written or clobbered that do not appear as operands.
+    pub implicit_writes: RegisterSet,
+    pub flags: FlagsEffect,
+    pub control_flow: ControlFlow,
+}
+impl InstructionInfo {
+    fn new(mnemonic: Mnemonic) -> Self {
+        Self {
+            mnemonic,
+            operands: &[],
+            implicit_reads: RegisterSet::new(),
+            implicit_writes: RegisterSet::new(),
+            flags: FlagsEffect::none(),
+            control_flow: ControlFlow::None,
+        }
+    }
+    fn operands(mut self, operands: &'static [OperandInfo]) -> Self {
+        self.operands = operands;
+        self
+    }
+    fn implicit(mut self, reads: &[RegisterName], writes: &[RegisterName]) -> Self {
+        self.implicit_reads = RegisterSet::from_slice(reads);
+        self.implicit_writes = RegisterSet::from_slice(writes);
+        self
+    }
+    fn flags(mut self, flags: FlagsEffect) -> Self {
+        self.flags = flags;
+        self
+    }
+    fn control_flow(mut self, control_flow: ControlFlow) -> Self {
+        self.control_flow = control_flow;
+        self
+    }
+    /// The same operation with a destination that is only written, in front of both sources.
+    pub(crate) fn three_address(mut self) -> Self {
+        self.operands = match self.operands[1].fixed {
+            Some(_) => &[WRITE, READ, SHIFT_COUNT],
+            None => &[WRITE, READ, READ],
+        };
+        self
+    }
+    /// The same operation under merge masking, which keeps part of the destination.
+    pub(crate) fn merging(mut self) -> Self {
+        self.operands = match self.operands.len() {
+            2 => &[READ_WRITE, READ],
+            _ => &[READ_WRITE, READ, READ],
+        };
+        self
+    }
+
+    /// Whether execution may continue somewhere other than the next instruction,
+    /// not counting calls that return.
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self.control_flow,
+            ControlFlow::Jump | ControlFlow::ConditionalJump | ControlFlow::Return
+        )
+    }
+}
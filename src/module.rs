@@ -0,0 +1,252 @@
+use super::{
+    dispatch::{DispatchMethod, DispatchVariant},
+    function::{Alignment, Function},
+    io::{self, FmtWriter, Write},
+    label::Label,
+    section::Section,
+    writer::AsmWriter,
+};
+use alloc::{
+    collections::BTreeSet,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Module<'a> {
+    functions: Vec<Function<'a>>,
+    target_clones: Vec<TargetClones<'a>>,
+}
+impl<'a> Module<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_function(&mut self, function: Function<'a>) {
+        self.functions.push(function);
+    }
+    /// Registers several bodies of one function, selected at load time by the features
+    /// each clone was built for. Clones are tried in order; `default` is used if none match.
+    pub fn add_target_clones(&mut self, default: Function<'a>, clones: Vec<Function<'a>>) {
+        self.target_clones.push(TargetClones { default, clones });
+    }
+
+    pub fn functions(&self) -> &[Function<'a>] {
+        &self.functions
+    }
+    pub fn target_clones(&self) -> &[TargetClones<'a>] {
+        &self.target_clones
+    }
+    /// Aligns the loop headers of every function, see [`Function::align_loop_headers`].
+    pub fn align_loop_headers(&mut self, alignment: Alignment) {
+        let clones = self.target_clones.iter_mut();
+        let bodies =
+            clones.flat_map(|clones| clones.clones.iter_mut().chain([&mut clones.default]));
+        for function in self.functions.iter_mut().chain(bodies) {
+            function.align_loop_headers(alignment);
+        }
+    }
+
+    pub fn write<O: Write>(&self, writer: &mut AsmWriter<O>) -> io::Result<()> {
+        for function in &self.functions {
+            writer.emit_function(function)?;
+        }
+        for clones in &self.target_clones {
+            clones.write(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The bodies of a function multi-versioned like GCC's `target_clones` attribute.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetClones<'a> {
+    pub default: Function<'a>,
+    pub clones: Vec<Function<'a>>,
+}
+impl<'a> TargetClones<'a> {
+    pub fn name(&self) -> Label<'a> {
+        self.default.name
+    }
+
+    /// The symbol names of the clones, followed by the name of the default body.
+    pub fn clone_names(&self) -> Vec<String> {
+        let name = self.name();
+        let default_features = self.default.features;
+
+        let mut names: Vec<_> = self
+            .clones
+            .iter()
+            .enumerate()
+            .map(|(i, clone)| {
+                let features = clone.features.difference(default_features);
+                let suffix = features.to_string().replace([',', '.'], "_");
+                format!("{name}.{suffix}.{i}")
+            })
+            .collect();
+        names.push(format!("{name}.default.{}", self.clones.len()));
+        names
+    }
+
+    pub(crate) fn write<O: Write>(&self, writer: &mut AsmWriter<O>) -> io::Result<()> {
+        let name = self.name().label;
+        let names = self.clone_names();
+        let (default_name, clone_names) = names.split_last().unwrap();
+
+        for (clone, clone_name) in self.clones.iter().zip(clone_names) {
+            writer.emit_function_as(clone, clone_name, false)?;
+        }
+        writer.emit_function_as(&self.default, default_name, false)?;
+
+        let variants: Vec<_> = self
+            .clones
+            .iter()
+            .zip(clone_names)
+            .map(|(clone, clone_name)| {
+                let features = clone.features.difference(self.default.features);
+                DispatchVariant::new(features, clone_name)
+            })
+            .collect();
+
+        if self.default.global {
+            writer.declare_global(name)?;
+        }
+        writer.emit_cpu_dispatch(name, &variants, default_name, DispatchMethod::IFunc)
+    }
+}
+
+/// The output of a [`ModuleBuilder`], a buffer for each section written to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SectionBuffers {
+    buffers: Vec<(Section, String)>,
+    /// The index of the buffer written to.
+    current: usize,
+}
+impl SectionBuffers {
+    fn select(&mut self, section: &Section) {
+        self.current = match self.buffers.iter().position(|(s, _)| s == section) {
+            Some(i) => i,
+            None => {
+                self.buffers.push((section.clone(), String::new()));
+                self.buffers.len() - 1
+            }
+        };
+    }
+}
+impl fmt::Write for SectionBuffers {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.buffers[self.current].1.push_str(s);
+        Ok(())
+    }
+}
+
+/// Collects code and data into a buffer per section, which can be switched between at any
+/// point, even within a function, and assembles them into one file at the end. All
+/// sections share one [`AsmWriter`], so labels, symbols and debug information are the
+/// same as if they were written in order.
+pub struct ModuleBuilder {
+    writer: AsmWriter<FmtWriter<SectionBuffers>>,
+}
+impl ModuleBuilder {
+    pub fn new() -> Self {
+        let buffers = SectionBuffers {
+            buffers: vec![(Section::Text, String::new())],
+            current: 0,
+        };
+        Self {
+            writer: AsmWriter::new(FmtWriter(buffers)),
+        }
+    }
+
+    /// The writer of the current section, also for configuring it before the first
+    /// output. Switching with its section directives writes the switch into the current
+    /// buffer; [`Self::section`] switches buffers instead.
+    pub fn writer(&mut self) -> &mut AsmWriter<FmtWriter<SectionBuffers>> {
+        &mut self.writer
+    }
+    /// Switches to the buffer of `section` and returns the writer for it. Panics while a
+    /// section pushed on the writer is not popped.
+    pub fn section(&mut self, section: Section) -> &mut AsmWriter<FmtWriter<SectionBuffers>> {
+        let sections = self.writer.sections();
+        assert!(
+            sections.len() == 1,
+            "cannot switch to {section} before popping the pushed sections"
+        );
+        sections[0] = section.clone();
+        self.writer.output_mut().0.select(&section);
+        &mut self.writer
+    }
+    pub fn text(&mut self) -> &mut AsmWriter<FmtWriter<SectionBuffers>> {
+        self.section(Section::Text)
+    }
+    pub fn rodata(&mut self) -> &mut AsmWriter<FmtWriter<SectionBuffers>> {
+        self.section(Section::Rodata)
+    }
+    pub fn data(&mut self) -> &mut AsmWriter<FmtWriter<SectionBuffers>> {
+        self.section(Section::Data)
+    }
+    pub fn bss(&mut self) -> &mut AsmWriter<FmtWriter<SectionBuffers>> {
+        self.section(Section::Bss)
+    }
+
+    /// Writes the sections to `out`, `.text`, `.rodata`, `.data` and `.bss` first and the
+    /// others in the order they were first written to, each after its directive once.
+    /// Empty sections are left out, as are repeated declarations of a symbol's binding or
+    /// visibility.
+    pub fn write<O: Write>(&self, out: &mut O) -> io::Result<()> {
+        let platform = self.writer.platform();
+        let mut buffers: Vec<_> = self.writer_buffers().iter().collect();
+        buffers.sort_by_key(|(section, _)| match section {
+            Section::Text => 0,
+            Section::Rodata => 1,
+            Section::Data => 2,
+            Section::Bss => 3,
+            Section::Named { .. } => 4,
+        });
+
+        let mut declared = BTreeSet::new();
+        for (section, text) in buffers {
+            if text.is_empty() {
+                continue;
+            }
+            writeln!(out, "\t{}", section.directive(platform))?;
+            for line in text.lines() {
+                if is_declaration(line) && !declared.insert(line) {
+                    continue;
+                }
+                writeln!(out, "{line}")?;
+            }
+        }
+        Ok(())
+    }
+    fn writer_buffers(&self) -> &[(Section, String)] {
+        &self.writer.output().0.buffers
+    }
+}
+impl Default for ModuleBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `line` gives a symbol its binding or visibility, which has to happen only once.
+fn is_declaration(line: &str) -> bool {
+    let directive = line.split_whitespace().next().unwrap_or("");
+    matches!(
+        directive,
+        ".global"
+            | ".globl"
+            | ".weak"
+            | ".local"
+            | ".hidden"
+            | ".internal"
+            | ".protected"
+            | ".private_extern"
+            | ".weak_definition"
+            | ".weak_reference"
+    )
+}
@@ -0,0 +1,15 @@
+/// The index of a model specific register, as loaded into `%ecx` by `rdmsr` and `wrmsr`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Msr(pub u32);
+impl Msr {
+    pub const APIC_BASE: Msr = Msr(0x1b);
+    pub const EFER: Msr = Msr(0xc000_0080);
+    pub const STAR: Msr = Msr(0xc000_0081);
+    pub const LSTAR: Msr = Msr(0xc000_0082);
+    pub const CSTAR: Msr = Msr(0xc000_0083);
+    pub const FMASK: Msr = Msr(0xc000_0084);
+    pub const FS_BASE: Msr = Msr(0xc000_0100);
+    pub const GS_BASE: Msr = Msr(0xc000_0101);
+    pub const KERNEL_GS_BASE: Msr = Msr(0xc000_0102);
+    pub const TSC_AUX: Msr = Msr(0xc000_0103);
+}
@@ -0,0 +1,142 @@
+use super::{
+    features::TargetFeatures,
+    io::{self, Write},
+    section::{Section, SectionType},
+    writer::AsmWriter,
+};
+use alloc::vec::Vec;
+
+const NT_GNU_BUILD_ID: u32 = 3;
+const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc000_0002;
+const GNU_PROPERTY_X86_ISA_1_USED: u32 = 0xc001_0002;
+const GNU_PROPERTY_X86_FEATURE_1_IBT: u32 = 1 << 0;
+const GNU_PROPERTY_X86_FEATURE_1_SHSTK: u32 = 1 << 1;
+
+/// The x86 properties recorded in a `.note.gnu.property` section.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GnuProperties {
+    /// Whether every indirect branch target starts with `endbr64`.
+    pub ibt: bool,
+    /// Whether the code is compatible with shadow stacks.
+    pub shstk: bool,
+    /// The features the code assumes, recorded as the x86-64 ISA levels they cover.
+    pub isa_used: Option<TargetFeatures>,
+}
+impl GnuProperties {
+    /// The `GNU_PROPERTY_X86_FEATURE_1_AND` bits, `None` if the code supports neither.
+    pub fn feature_1_and(&self) -> Option<u32> {
+        let mut bits = 0;
+        if self.ibt {
+            bits |= GNU_PROPERTY_X86_FEATURE_1_IBT;
+        }
+        if self.shstk {
+            bits |= GNU_PROPERTY_X86_FEATURE_1_SHSTK;
+        }
+        (bits != 0).then_some(bits)
+    }
+    /// The `GNU_PROPERTY_X86_ISA_1_USED` bits, one for each level up to the highest
+    /// the features include.
+    pub fn isa_1_used(&self) -> Option<u32> {
+        let features = self.isa_used?;
+        let levels = [
+            TargetFeatures::x86_64(),
+            TargetFeatures::x86_64_v2(),
+            TargetFeatures::x86_64_v3(),
+            TargetFeatures::x86_64_v4(),
+        ];
+        let count = levels
+            .iter()
+            .take_while(|&&level| features.is_superset(level))
+            .count();
+        // Code for x86-64 uses at least the baseline, even if the features leave out SSE.
+        Some((1 << count.max(1)) - 1)
+    }
+
+    /// The properties as `(type, value)` pairs, sorted by type as the ABI requires.
+    fn entries(&self) -> Vec<(u32, u32)> {
+        let feature = self
+            .feature_1_and()
+            .map(|bits| (GNU_PROPERTY_X86_FEATURE_1_AND, bits));
+        let isa = self
+            .isa_1_used()
+            .map(|bits| (GNU_PROPERTY_X86_ISA_1_USED, bits));
+        feature.into_iter().chain(isa).collect()
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits an ELF note with `name` as owner, such as `"GNU"`, into the note section
+    /// `section`, then returns to the current section. The name and descriptor are padded
+    /// to 4 bytes, as tools expect of notes other than `.note.gnu.property`.
+    pub fn emit_note(
+        &mut self,
+        section: &str,
+        name: &str,
+        kind: u32,
+        descriptor: &[u8],
+    ) -> io::Result<()> {
+        self.begin_note(section, name, kind, descriptor.len() as u32, 4)?;
+        if !descriptor.is_empty() {
+            self.emit_bytes(descriptor)?;
+            self.emit_balign(4)?;
+        }
+        self.pop_section()
+    }
+    /// Emits a `.note.gnu.build-id` note identifying the build by `id`, usually a hash of
+    /// the linked output.
+    pub fn emit_build_id_note(&mut self, id: &[u8]) -> io::Result<()> {
+        self.emit_note(".note.gnu.build-id", "GNU", NT_GNU_BUILD_ID, id)
+    }
+    /// Switches to `section` and emits the note header and name, padded to `alignment`.
+    fn begin_note(
+        &mut self,
+        section: &str,
+        name: &str,
+        kind: u32,
+        descriptor_size: u32,
+        alignment: u64,
+    ) -> io::Result<()> {
+        assert!(
+            !name.as_bytes().contains(&0),
+            "note names cannot contain zero bytes"
+        );
+        self.push_section(Section::named(section, "a", SectionType::Note)?)?;
+        self.emit_balign(alignment)?;
+        self.emit_long(name.len() as u32 + 1)?; // namesz
+        self.emit_long(descriptor_size)?; // descsz
+        self.directive(format_args!(".long {kind:#x}"))?;
+        self.emit_asciz(name)?;
+        self.emit_balign(alignment)
+    }
+
+    /// Marks the stack as non-executable with an empty `.note.GNU-stack` section.
+    pub fn emit_gnu_stack_note(&mut self) -> io::Result<()> {
+        self.push_section(Section::named(
+            ".note.GNU-stack",
+            "",
+            SectionType::Progbits,
+        )?)?;
+        self.pop_section()
+    }
+
+    /// Emits a `.note.gnu.property` section holding `properties`, then returns to the
+    /// current section. Nothing is emitted if there are no properties to record.
+    pub fn emit_gnu_property_note(&mut self, properties: &GnuProperties) -> io::Result<()> {
+        let entries = properties.entries();
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // Each property is its type, size and value, padded to 8 bytes.
+        let size = entries.len() as u32 * 16;
+        self.begin_note(".note.gnu.property", "GNU", NT_GNU_PROPERTY_TYPE_0, size, 8)?;
+        for (kind, value) in entries {
+            self.directive(format_args!(".long {kind:#x}"))?;
+            self.emit_long(4u32)?;
+            self.directive(format_args!(".long {value:#x}"))?;
+            self.emit_balign(8)?;
+        }
+        self.pop_section()
+    }
+}
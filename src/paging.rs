@@ -0,0 +1,138 @@
+use super::{data::DataValue, error::X64WriterError, io::Write, label::Label, writer::AsmWriter};
+use alloc::{format, vec::Vec};
+
+pub const PAGE_TABLE_ENTRIES: usize = 512;
+/// The bits of an entry holding the physical address of the next level or the page.
+pub const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The attribute bits of a 4-level paging entry.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PageFlags(u64);
+impl PageFlags {
+    pub const PRESENT: PageFlags = PageFlags(1 << 0);
+    pub const WRITABLE: PageFlags = PageFlags(1 << 1);
+    pub const USER: PageFlags = PageFlags(1 << 2);
+    pub const WRITE_THROUGH: PageFlags = PageFlags(1 << 3);
+    pub const CACHE_DISABLE: PageFlags = PageFlags(1 << 4);
+    pub const ACCESSED: PageFlags = PageFlags(1 << 5);
+    pub const DIRTY: PageFlags = PageFlags(1 << 6);
+    /// Maps a 2 MiB or 1 GiB page instead of pointing to the next level.
+    pub const HUGE: PageFlags = PageFlags(1 << 7);
+    pub const GLOBAL: PageFlags = PageFlags(1 << 8);
+    pub const NO_EXECUTE: PageFlags = PageFlags(1 << 63);
+
+    pub fn none() -> Self {
+        Self(0)
+    }
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+impl PageSize {
+    pub fn in_bytes(self) -> u64 {
+        match self {
+            Self::Size4K => 1 << 12,
+            Self::Size2M => 1 << 21,
+            Self::Size1G => 1 << 30,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PageTarget<'a> {
+    Physical(u64),
+    /// A lower level table emitted in the same image, which must be identity mapped.
+    Table(Label<'a>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PageTableEntry<'a> {
+    pub target: PageTarget<'a>,
+    pub flags: PageFlags,
+}
+impl<'a> PageTableEntry<'a> {
+    /// Maps the page at `address`, whose low bits are masked off.
+    pub fn page(address: u64, flags: PageFlags) -> Self {
+        Self {
+            target: PageTarget::Physical(address),
+            flags,
+        }
+    }
+    pub fn table(table: impl Into<Label<'a>>, flags: PageFlags) -> Self {
+        Self {
+            target: PageTarget::Table(table.into()),
+            flags,
+        }
+    }
+
+    pub fn value(&self) -> DataValue<'a> {
+        let flags = self.flags.bits();
+        match self.target {
+            PageTarget::Physical(address) => (address & ADDRESS_MASK | flags).into(),
+            PageTarget::Table(table) => table.offset(flags as i64).into(),
+        }
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits a page aligned table at `label` with `entries` first and the remaining
+    /// entries zeroed.
+    pub fn emit_page_table<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        entries: &[PageTableEntry<'a>],
+    ) -> Result<(), X64WriterError> {
+        if entries.len() > PAGE_TABLE_ENTRIES {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "a page table has {PAGE_TABLE_ENTRIES} entries, not {}",
+                entries.len()
+            )));
+        }
+
+        self.emit_balign(PageSize::Size4K.in_bytes())?;
+        self.emit_label(label.into())?;
+        for entry in entries {
+            self.emit_quad(entry.value())?;
+        }
+        let remaining = PAGE_TABLE_ENTRIES - entries.len();
+        if remaining != 0 {
+            self.skip(remaining as u64 * 8, 0)?;
+        }
+        Ok(())
+    }
+    /// Emits a table at `label` identity mapping `count` pages of `size` starting at `start`.
+    pub fn emit_identity_map<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        start: u64,
+        count: usize,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<(), X64WriterError> {
+        let flags = match size {
+            PageSize::Size4K => flags,
+            PageSize::Size2M | PageSize::Size1G => flags.union(PageFlags::HUGE),
+        };
+        let entries: Vec<_> = (0..count as u64)
+            .map(|i| PageTableEntry::page(start + i * size.in_bytes(), flags))
+            .collect();
+        self.emit_page_table(label, &entries)
+    }
+}
@@ -0,0 +1,704 @@
+use super::{
+    args::{Arg, ArgSize, ConstInt, Memory, Scale, Segment},
+    features::TargetFeatures,
+    function::{Alignment, Function, Item, Tags},
+    instruction::Instruction,
+    island::{Constant, ConstantValue},
+    label::{Label, RelocationModifier, SymExpr},
+    module::Module,
+    register::{
+        MaskRegister, Masking, Register, RegisterName, RegisterSet, RegisterSize, VectorRegister,
+        VectorSize,
+    },
+    writer::{
+        BinaryOpDouble, BinaryOpSingle, Condition, NonaryOp, RepPrefix, SseOp, StringOp, TernaryOp,
+        UnaryOp, UnaryOpSingle, VectorOp,
+    },
+};
+use alloc::{
+    collections::BTreeSet,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{error::Error, fmt::Display};
+
+/// A line of assembly that is not part of what the crate emits for functions.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+impl Error for ParseError {}
+
+/// Reads functions back from assembly previously emitted with [`AsmWriter::emit_function`]
+/// or [`Module::write`], borrowing labels from `text`.
+///
+/// This is best effort and limited to the instructions and directives the crate emits for
+/// functions. Every label that is neither local (`.L` prefixed or numeric) nor followed
+/// by a constant starts a new function. Memory operands take the size of the mnemonic
+/// suffix, and tags are only recovered if the writer annotated them. Function attributes
+/// that do not show up in the text, such as target features and argument registers, take
+/// their defaults.
+///
+/// [`AsmWriter::emit_function`]: crate::writer::AsmWriter::emit_function
+pub fn parse_module(text: &str) -> Result<Module<'_>, ParseError> {
+    let mut parser = Parser {
+        module: Module::new(),
+        function: None,
+        globals: BTreeSet::new(),
+        pending_labels: Vec::new(),
+        pending_alignment: None,
+    };
+    for (i, line) in text.lines().enumerate() {
+        parser.parse_line(line).map_err(|message| ParseError {
+            line: i + 1,
+            message,
+        })?;
+    }
+    parser.finish_function();
+    Ok(parser.module)
+}
+
+struct Parser<'a> {
+    module: Module<'a>,
+    function: Option<Function<'a>>,
+    globals: BTreeSet<&'a str>,
+    /// Labels that might turn out to be the start of a constant, which deduplicated
+    /// constants have several of.
+    pending_labels: Vec<&'a str>,
+    /// An alignment hint for the next label of the body.
+    pending_alignment: Option<Alignment>,
+}
+impl<'a> Parser<'a> {
+    fn parse_line(&mut self, line: &'a str) -> Result<(), String> {
+        let (code, comment) = match line.find('#') {
+            Some(i) => (line[..i].trim(), Some(line[i + 1..].trim())),
+            None => (line.trim(), None),
+        };
+        if code.is_empty() {
+            if let Some(comment) = comment {
+                self.flush_labels()?;
+                self.items()?.push(Item::Comment(comment.to_string()));
+            }
+            return Ok(());
+        }
+
+        if let Some(label) = code.strip_suffix(':') {
+            self.pending_labels.push(label);
+            return Ok(());
+        }
+        if code.starts_with('.') {
+            return self.parse_directive(code);
+        }
+
+        self.flush_labels()?;
+        let instruction = parse_instruction(code)?;
+        let mut tags = Tags::new();
+        let mut comments = comment.into_iter().flat_map(|c| c.split('#'));
+        if let Some(tag_list) = comments.find_map(|c| c.trim().strip_prefix("tags:")) {
+            for tag in tag_list.split_whitespace() {
+                tags.push(tag);
+            }
+        }
+        self.items()?.push(Item::Instruction(instruction, tags));
+        Ok(())
+    }
+
+    fn parse_directive(&mut self, code: &'a str) -> Result<(), String> {
+        let (name, operands) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+        let operands = operands.trim();
+        match name {
+            ".p2align" => {
+                // Constant islands align without a maximum skip.
+                if let Some((power, max_skip)) = operands.split_once(",,") {
+                    let power = match power.trim().parse() {
+                        Ok(power) if power < 16 => power,
+                        _ => return Err(format!("invalid alignment {power}")),
+                    };
+                    let max_skip = parse_u128(max_skip.trim())? as u32;
+                    self.pending_alignment = Some(Alignment::with_max_skip(power, max_skip));
+                }
+                Ok(())
+            }
+            ".file" | ".text" | ".type" | ".size" => Ok(()),
+            ".global" | ".globl" => {
+                self.globals.insert(operands);
+                Ok(())
+            }
+            ".long" | ".quad" | ".octa" => {
+                if self.pending_labels.is_empty() {
+                    return Err(format!("{name} outside of a constant"));
+                }
+                let value = parse_u128(operands)?;
+                let value = match name {
+                    ".long" => ConstantValue::Long(value as u32),
+                    ".quad" => ConstantValue::Quad(value as u64),
+                    _ => ConstantValue::Octa(value),
+                };
+                let function = self
+                    .function
+                    .as_mut()
+                    .ok_or("constant outside of a function")?;
+                for label in self.pending_labels.drain(..) {
+                    function.constants.push(Constant {
+                        label: Label::new(label),
+                        value,
+                    });
+                }
+                Ok(())
+            }
+            _ => Err(format!("unsupported directive {name}")),
+        }
+    }
+
+    fn flush_labels(&mut self) -> Result<(), String> {
+        for label in core::mem::take(&mut self.pending_labels) {
+            self.define_label(label)?;
+        }
+        Ok(())
+    }
+    fn define_label(&mut self, label: &'a str) -> Result<(), String> {
+        let local = label.starts_with(".L") || label.bytes().all(|b| b.is_ascii_digit());
+        if local {
+            self.items()?.push(Item::Label(Label::new(label)));
+            if let Some(alignment) = self.pending_alignment.take() {
+                let function = self.function.as_mut().unwrap();
+                function.align_label(label, alignment);
+            }
+        } else {
+            self.finish_function();
+            self.function = Some(Function {
+                name: Label::new(label),
+                global: self.globals.contains(label),
+                arguments: RegisterSet::new(),
+                features: TargetFeatures::x86_64(),
+                items: Vec::new(),
+                constants: Vec::new(),
+                alignments: Vec::new(),
+            });
+        }
+        Ok(())
+    }
+    fn finish_function(&mut self) {
+        if let Some(function) = self.function.take() {
+            self.module.add_function(function);
+        }
+    }
+    fn items(&mut self) -> Result<&mut Vec<Item<'a>>, String> {
+        match &mut self.function {
+            Some(function) => Ok(&mut function.items),
+            None => Err("code outside of a function".to_string()),
+        }
+    }
+}
+
+fn parse_instruction(code: &str) -> Result<Instruction<'_>, String> {
+    let (mnemonic, operands) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+    if mnemonic == "lock" {
+        let instruction = parse_instruction(operands.trim())?;
+        return instruction
+            .locked()
+            .ok_or_else(|| format!("{} cannot take a lock prefix", instruction.mnemonic()));
+    }
+    let rep = match mnemonic {
+        "rep" => Some(RepPrefix::Rep),
+        "repe" | "repz" => Some(RepPrefix::Repe),
+        "repne" | "repnz" => Some(RepPrefix::Repne),
+        _ => None,
+    };
+    if let Some(rep) = rep {
+        let operation = operands.trim();
+        let (op, size) = parse_string_op(operation)
+            .ok_or_else(|| format!("{operation} cannot take a {} prefix", rep.prefix()))?;
+        // Rejected like the builder does, though the assembler would take it.
+        op.check_prefix(rep).map_err(|error| error.to_string())?;
+        let rep = Some(rep);
+        return Ok(Instruction::String { op, size, rep });
+    }
+    let operands = split_operands(operands.trim());
+    // Without operands, movsl and friends are string instructions.
+    if let ([], Some((op, size))) = (operands.as_slice(), parse_string_op(mnemonic)) {
+        let rep = None;
+        return Ok(Instruction::String { op, size, rep });
+    }
+
+    if let Some(op) = NonaryOp::ALL
+        .into_iter()
+        .find(|op| op.mnemonic() == mnemonic)
+    {
+        return Ok(Instruction::Nonary { op });
+    }
+    match (mnemonic, operands.as_slice()) {
+        ("call", [target]) => {
+            return Ok(Instruction::Call {
+                target: parse_target(target)?,
+            })
+        }
+        ("jmp", [target]) => {
+            return Ok(Instruction::Jmp {
+                target: parse_target(target)?,
+            })
+        }
+        ("cmpxchg16b", [dst]) => {
+            return Ok(Instruction::Unary {
+                op: UnaryOp::Cmpxchg16b,
+                dst: parse_operand(dst, None)?,
+            })
+        }
+        _ => (),
+    }
+    if let Some(condition) = mnemonic.strip_prefix('j').and_then(Condition::from_suffix) {
+        let [target] = operands.as_slice() else {
+            return Err(format!("{mnemonic} takes one operand"));
+        };
+        let target = parse_target(target)?;
+        return Ok(Instruction::CJmp { condition, target });
+    }
+    if let Some(condition) = mnemonic
+        .strip_prefix("cmov")
+        .and_then(Condition::from_suffix)
+    {
+        let [src, dst] = operands.as_slice() else {
+            return Err(format!("{mnemonic} takes two operands"));
+        };
+        return Ok(Instruction::CMov {
+            condition,
+            dst: parse_operand(dst, None)?,
+            src: parse_operand(src, None)?,
+        });
+    }
+    if let Some(condition) = mnemonic
+        .strip_prefix("set")
+        .and_then(Condition::from_suffix)
+    {
+        let [dst] = operands.as_slice() else {
+            return Err(format!("{mnemonic} takes one operand"));
+        };
+        return Ok(Instruction::SetCC {
+            condition,
+            dst: parse_operand(dst, Some(ArgSize::Byte))?,
+        });
+    }
+
+    if let Some(op) = VectorOp::ALL
+        .into_iter()
+        .find(|op| op.mnemonic() == mnemonic)
+    {
+        return parse_vector(op, &operands);
+    }
+    // Without an %xmm operand, movq is the plain 64-bit mov.
+    let sse = parse_sse_mnemonic(mnemonic).filter(|&(op, _)| {
+        op != SseOp::Movq || operands.iter().any(|operand| operand.starts_with("%xmm"))
+    });
+    if let Some((op, size)) = sse {
+        let [src, dst] = operands.as_slice() else {
+            return Err(format!("{mnemonic} takes two operands"));
+        };
+        let src = parse_operand(src, None)?;
+        return Ok(Instruction::Binary {
+            op: op.into(),
+            dst: parse_operand(dst, None)?,
+            src: size.map_or(src, |size| with_size(src, size)),
+        });
+    }
+
+    if let Some(op) = BinaryOpDouble::ALL
+        .into_iter()
+        .find(|op| mnemonic.len() == 6 && mnemonic.starts_with(op.mnemonic()))
+    {
+        let [src, dst] = operands.as_slice() else {
+            return Err(format!("{mnemonic} takes two operands"));
+        };
+        let src_size = mnemonic.get(4..5).and_then(suffix_size);
+        let dst_size = mnemonic.get(5..).and_then(suffix_size);
+        let (Some(src_size), Some(dst_size)) = (src_size, dst_size) else {
+            return Err(format!("unknown instruction {mnemonic}"));
+        };
+        return Ok(Instruction::Binary {
+            op: op.into(),
+            dst: parse_sized_operand(dst, dst_size)?,
+            src: parse_sized_operand(src, src_size)?,
+        });
+    }
+
+    let (base, size) = split_suffix(mnemonic)?;
+    match (base, operands.as_slice()) {
+        ("mov", [src, dst]) => {
+            let (dst, src) = parse_pair(dst, src, size)?;
+            Ok(Instruction::Mov { dst, src })
+        }
+        ("push", [src]) => Ok(Instruction::Push {
+            src: parse_sized_operand(src, size)?,
+        }),
+        ("pop", [dst]) => Ok(Instruction::Pop {
+            dst: parse_sized_operand(dst, size)?,
+        }),
+        (base, [src2, src, dst]) => {
+            let op = TernaryOp::ALL
+                .into_iter()
+                .find(|op| op.mnemonic() == base)
+                .ok_or_else(|| format!("unknown instruction {mnemonic}"))?;
+            let (dst, src) = parse_pair(dst, src, size)?;
+            Ok(Instruction::Ternary {
+                op,
+                dst,
+                src,
+                // Only the shift counts can be immediates, which take a single byte.
+                src2: parse_operand(src2, Some(ArgSize::Byte))?,
+            })
+        }
+        (base, [src, dst]) => {
+            let op = BinaryOpSingle::ALL
+                .into_iter()
+                .find(|op| op.mnemonic() == base)
+                .ok_or_else(|| format!("unknown instruction {mnemonic}"))?;
+            let (dst, src) = match op {
+                // The address operand of lea is never accessed, so it has no size.
+                BinaryOpSingle::Lea => (parse_operand(dst, None)?, parse_operand(src, None)?),
+                _ => parse_pair(dst, src, size)?,
+            };
+            Ok(Instruction::Binary {
+                op: op.into(),
+                dst,
+                src,
+            })
+        }
+        (base, [dst]) => {
+            let op = UnaryOpSingle::ALL
+                .into_iter()
+                .find(|op| op.mnemonic() == base)
+                .ok_or_else(|| format!("unknown instruction {mnemonic}"))?;
+            Ok(Instruction::Unary {
+                op: op.into(),
+                dst: parse_sized_operand(dst, size)?,
+            })
+        }
+        _ => Err(format!("unknown instruction {mnemonic}")),
+    }
+}
+
+/// Parses the operands of a vector instruction, with `{1toN}` behind a broadcast memory
+/// operand and `{%kN}` or `{%kN}{z}` behind a masked destination.
+fn parse_vector<'a>(op: VectorOp, operands: &[&'a str]) -> Result<Instruction<'a>, String> {
+    let (src2, src1, dst) = match *operands {
+        [src2, dst] if op.has_one_source() => (src2, None, dst),
+        [src2, src1, dst] if !op.has_one_source() => (src2, Some(src1), dst),
+        _ => return Err(format!("wrong number of operands for {}", op.mnemonic())),
+    };
+    let (src2, broadcast) = match src2.strip_suffix('}').and_then(|s| s.rsplit_once("{1to")) {
+        Some((src2, _)) => (src2, true),
+        None => (src2, false),
+    };
+    let (dst, zeroing) = match dst.strip_suffix("{z}") {
+        Some(dst) => (dst, true),
+        None => (dst, false),
+    };
+    let (dst, masking) = match dst.strip_suffix('}').and_then(|d| d.rsplit_once("{%k")) {
+        Some((dst, number)) => match number.parse() {
+            Ok(number) if number < 8 => {
+                let mask = MaskRegister(number);
+                (dst, Some(Masking { mask, zeroing }))
+            }
+            _ => return Err(format!("unknown mask register %k{number}")),
+        },
+        None if zeroing => return Err("zeroing without a mask register".to_string()),
+        None => (dst, None),
+    };
+    Ok(Instruction::Vector {
+        op,
+        dst: parse_operand(dst, None)?,
+        src1: src1.map(|src1| parse_operand(src1, None)).transpose()?,
+        src2: parse_operand(src2, None)?,
+        masking,
+        broadcast,
+    })
+}
+
+/// Splits at commas outside of parentheses.
+fn split_operands(operands: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in operands.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(operands[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if !operands.is_empty() {
+        parts.push(operands[start..].trim());
+    }
+    parts
+}
+
+/// The SSE operation and, for conversions from integers, the size given by the suffix.
+fn parse_sse_mnemonic(mnemonic: &str) -> Option<(SseOp, Option<ArgSize>)> {
+    SseOp::ALL.into_iter().find_map(|op| {
+        if op.mnemonic() == mnemonic {
+            return Some((op, None));
+        }
+        let suffix = mnemonic.strip_prefix(op.mnemonic())?;
+        let size = suffix_size(suffix).filter(|_| op.converts_from_integer())?;
+        Some((op, Some(size)))
+    })
+}
+
+fn parse_string_op(mnemonic: &str) -> Option<(StringOp, ArgSize)> {
+    StringOp::ALL.into_iter().find_map(|op| {
+        let suffix = mnemonic.strip_prefix(op.mnemonic())?;
+        Some((op, suffix_size(suffix)?))
+    })
+}
+
+fn split_suffix(mnemonic: &str) -> Result<(&str, ArgSize), String> {
+    let split = mnemonic.len().saturating_sub(1);
+    match suffix_size(&mnemonic[split..]) {
+        Some(size) => Ok((&mnemonic[..split], size)),
+        None => Err(format!("missing size suffix on {mnemonic}")),
+    }
+}
+fn suffix_size(suffix: &str) -> Option<ArgSize> {
+    match suffix {
+        "b" => Some(ArgSize::Byte),
+        "w" => Some(ArgSize::Word),
+        "l" => Some(ArgSize::Double),
+        "q" => Some(ArgSize::Quad),
+        _ => None,
+    }
+}
+
+/// Parses the operands of a two operand instruction, giving memory operands the size of
+/// the suffix.
+fn parse_pair<'a>(dst: &'a str, src: &'a str, size: ArgSize) -> Result<(Arg<'a>, Arg<'a>), String> {
+    let dst = parse_sized_operand(dst, size)?;
+    let src = parse_sized_operand(src, size)?;
+    Ok((dst, src))
+}
+fn parse_sized_operand(operand: &str, size: ArgSize) -> Result<Arg<'_>, String> {
+    let arg = parse_operand(operand, Some(size))?;
+    Ok(with_size(arg, size))
+}
+fn with_size(arg: Arg<'_>, size: ArgSize) -> Arg<'_> {
+    match arg {
+        Arg::Memory(memory) => Arg::Memory(memory.size(size)),
+        arg => arg,
+    }
+}
+
+fn parse_target(target: &str) -> Result<Arg<'_>, String> {
+    match target.strip_prefix('*') {
+        Some(indirect) => parse_operand(indirect, None),
+        None => parse_operand(target, None),
+    }
+}
+
+/// Parses an operand, choosing the immediate type from the operand size of the instruction.
+fn parse_operand(operand: &str, size: Option<ArgSize>) -> Result<Arg<'_>, String> {
+    if let Some(name) = operand.strip_prefix('%') {
+        for (prefix, size) in [
+            ("xmm", VectorSize::Xmm),
+            ("ymm", VectorSize::Ymm),
+            ("zmm", VectorSize::Zmm),
+        ] {
+            if let Some(number) = name.strip_prefix(prefix) {
+                return match number.parse() {
+                    Ok(number) if number < 32 => Ok(Arg::Vector(VectorRegister(number, size))),
+                    _ => Err(format!("unknown register {operand}")),
+                };
+            }
+        }
+        if let Some(number) = name.strip_prefix('k') {
+            return match number.parse() {
+                Ok(number) if number < 8 => Ok(Arg::Mask(MaskRegister(number))),
+                _ => Err(format!("unknown register {operand}")),
+            };
+        }
+        if !name.contains(':') {
+            return parse_register(operand).map(Arg::Register);
+        }
+    }
+    if let Some(immediate) = operand.strip_prefix('$') {
+        let size = size.ok_or("immediate without operand size")?;
+        return parse_immediate(immediate, size).map(Arg::Int);
+    }
+    if operand.contains('(') || operand.starts_with('%') {
+        return parse_memory(operand).map(Arg::Memory);
+    }
+    if let Ok(value) = parse_i64(operand) {
+        let memory = Memory::sib().offset(value);
+        return Ok(Arg::Memory(memory));
+    }
+
+    let (label, offset) = split_symbol(operand)?;
+    Ok(match offset {
+        0 => Arg::Label(label),
+        offset => Arg::SymExpr(SymExpr::new(label, offset)),
+    })
+}
+
+fn parse_register(name: &str) -> Result<Register, String> {
+    RegisterName::ALL
+        .into_iter()
+        .flat_map(|name| RegisterSize::ALL.map(|size| name.with_size(size)))
+        .find(|register| register.to_string() == name)
+        .ok_or_else(|| format!("unknown register {name}"))
+}
+
+fn parse_immediate(immediate: &str, size: ArgSize) -> Result<ConstInt, String> {
+    let value = parse_i128(immediate)?;
+    let negative = value < 0;
+    let int = match (size, negative) {
+        (ArgSize::Byte, true) => i8::try_from(value).ok().map(ConstInt::I8),
+        (ArgSize::Byte, false) => u8::try_from(value).ok().map(ConstInt::U8),
+        (ArgSize::Word | ArgSize::Double, true) => i32::try_from(value).ok().map(ConstInt::I32),
+        (ArgSize::Word | ArgSize::Double, false) => u32::try_from(value).ok().map(ConstInt::U32),
+        (ArgSize::Quad, _) => i64::try_from(value)
+            .map(ConstInt::I64)
+            .or_else(|_| u64::try_from(value).map(ConstInt::U64))
+            .ok(),
+    };
+    int.ok_or_else(|| format!("immediate {immediate} out of range"))
+}
+
+fn parse_memory(operand: &str) -> Result<Memory<'_>, String> {
+    let (segment, rest) = match operand.split_once(':') {
+        Some(("%fs", rest)) => (Some(Segment::Fs), rest),
+        Some(("%gs", rest)) => (Some(Segment::Gs), rest),
+        Some((segment, _)) => return Err(format!("unknown segment {segment}")),
+        None => (None, operand),
+    };
+    let (displacement, address) = match rest.find('(') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+
+    let mut memory = if address == "(%rip)" {
+        Memory::rip()
+    } else {
+        let mut memory = Memory::sib();
+        if let Some(inner) = address.strip_prefix('(').and_then(|a| a.strip_suffix(')')) {
+            let parts: Vec<_> = inner.split(',').map(str::trim).collect();
+            if !parts[0].is_empty() {
+                memory = memory.base(parse_register(parts[0])?);
+            }
+            if let Some(index) = parts.get(1) {
+                let scale = match parts.get(2).copied().unwrap_or("1") {
+                    "1" => Scale::One,
+                    "2" => Scale::Two,
+                    "4" => Scale::Four,
+                    "8" => Scale::Eight,
+                    scale => return Err(format!("invalid scale {scale}")),
+                };
+                memory = memory.index(parse_register(index)?, scale);
+            }
+        } else if !address.is_empty() {
+            return Err(format!("invalid address {address}"));
+        }
+        memory
+    };
+    if let Some(segment) = segment {
+        memory = memory.segment(segment);
+    }
+
+    if !displacement.is_empty() {
+        if let Ok(value) = parse_i64(displacement) {
+            memory = memory.offset(displacement_int(value));
+        } else {
+            let (label, offset) = split_symbol(displacement)?;
+            memory = memory.label(label);
+            if offset != 0 {
+                memory = memory.offset(displacement_int(offset));
+            }
+        }
+    }
+    Ok(memory)
+}
+fn displacement_int(value: i64) -> ConstInt {
+    match i32::try_from(value) {
+        Ok(value) => ConstInt::I32(value),
+        Err(_) => ConstInt::I64(value),
+    }
+}
+
+/// Splits `label+16` or `label-4` into the label and the offset.
+fn split_symbol(symbol: &str) -> Result<(Label<'_>, i64), String> {
+    let split = symbol
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '+' || c == '-');
+    let (label, offset) = match split {
+        Some((i, '+')) => (&symbol[..i], parse_i64(&symbol[i + 1..])?),
+        Some((i, _)) => (&symbol[..i], parse_i64(&symbol[i..])?),
+        None => (symbol, 0),
+    };
+    let (label, modifier) = match label.split_once('@') {
+        Some((label, modifier)) => (label, Some(modifier)),
+        None => (label, None),
+    };
+    let valid = label
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$'));
+    if label.is_empty() || !valid {
+        return Err(format!("invalid operand {symbol}"));
+    }
+    let label = match modifier {
+        Some(suffix) => match RelocationModifier::from_suffix(suffix) {
+            Some(modifier) => Label::new(label).with_modifier(modifier),
+            None => return Err(format!("unknown relocation modifier @{suffix}")),
+        },
+        None => Label::new(label),
+    };
+    Ok((label, offset))
+}
+
+fn parse_i64(s: &str) -> Result<i64, String> {
+    let value = parse_i128(s)?;
+    i64::try_from(value).map_err(|_| format!("{s} out of range"))
+}
+fn parse_u128(s: &str) -> Result<u128, String> {
+    let value = parse_i128(s)?;
+    u128::try_from(value).map_err(|_| format!("{s} out of range"))
+}
+fn parse_i128(s: &str) -> Result<i128, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("invalid number {s}"))?;
+    let value = i128::try_from(value).map_err(|_| format!("{s} out of range"))?;
+    Ok(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::AsmWriter;
+
+    #[test]
+    fn string_instructions_round_trip() {
+        let text = "\t.type f, @function\nf:\n\trep movsb\n\trepe cmpsq\n\trepne scasl\n\
+                    \trep stosw\n\tlodsb\n\tret\n\t.size f, .-f\n";
+        let module = parse_module(text).unwrap();
+        let mut out = Vec::new();
+        module.write(&mut AsmWriter::new(&mut out)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+
+        let error = parse_module("f:\n\trepe movsb\n").unwrap_err();
+        assert_eq!(error.message, "movs cannot take a repe prefix");
+    }
+}
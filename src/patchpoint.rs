@@ -0,0 +1,74 @@
+use super::{
+    encode::nops,
+    io::{self, Write},
+    section::{Section, SectionType},
+    writer::AsmWriter,
+};
+use alloc::{format, string::String, vec::Vec};
+
+/// The section of the patchpoint table. Its name is a C identifier, so the linker defines
+/// `__start_patchpoints` and `__stop_patchpoints` around the table of the whole program.
+pub const PATCHPOINT_SECTION: &str = "patchpoints";
+
+/// Code reserved for patching at run time, such as with a call into a newly compiled tier.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Patchpoint {
+    pub id: u64,
+    /// The label at the start of the reserved bytes.
+    pub label: String,
+    pub size: u32,
+}
+
+/// The patchpoints of an [`AsmWriter`] not yet written to a table.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct PatchpointState {
+    pending: Vec<Patchpoint>,
+    /// The patchpoints reserved so far, which number their labels.
+    reserved: usize,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits `size` bytes of padding as multi-byte NOPs, the ones `as` pads with.
+    pub fn emit_nops(&mut self, size: u32) -> io::Result<()> {
+        self.emit_bytes(&nops(size as usize))
+    }
+    /// Reserves `size` bytes of NOPs at the current location, to be patched at run time,
+    /// and returns the label at their start. The patchpoint is written to the table by
+    /// [`Self::emit_patchpoint_table`].
+    pub fn reserve_patchpoint(&mut self, id: u64, size: u32) -> io::Result<String> {
+        assert!(size > 0, "a patchpoint needs at least one byte");
+        let state = self.patchpoints();
+        let label = format!(".Lpatchpoint{}", state.reserved);
+        state.reserved += 1;
+        state.pending.push(Patchpoint {
+            id,
+            label: label.clone(),
+            size,
+        });
+        self.emit_label(label.as_str())?;
+        self.comment(format!("patchpoint {id}, {size} bytes"))?;
+        self.emit_nops(size)?;
+        Ok(label)
+    }
+    /// Emits the patchpoints reserved since the last table to [`PATCHPOINT_SECTION`], as
+    /// 24-byte entries of the 8-byte id, the 8-byte address and the 4-byte size.
+    pub fn emit_patchpoint_table(&mut self) -> io::Result<()> {
+        let patchpoints = core::mem::take(&mut self.patchpoints().pending);
+        if patchpoints.is_empty() {
+            return Ok(());
+        }
+        self.push_section(Section::named(
+            PATCHPOINT_SECTION,
+            "a",
+            SectionType::Progbits,
+        )?)?;
+        self.emit_balign(8)?;
+        for patchpoint in &patchpoints {
+            self.emit_quad(patchpoint.id)?;
+            self.emit_quad(patchpoint.label.as_str())?;
+            self.emit_long(patchpoint.size)?;
+            self.emit_long(0u32)?;
+        }
+        self.pop_section()
+    }
+}
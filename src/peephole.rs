@@ -0,0 +1,33 @@
+use super::{
+    args::Arg,
+    function::{Function, Item},
+    instruction::Instruction,
+    writer::{BinaryOp, BinaryOpSingle},
+};
+
+impl Function<'_> {
+    /// Rewrites comparisons of a register against zero to the shorter `test` of the
+    /// register with itself, which sets the flags the same way except for the unused
+    /// auxiliary carry.
+    pub fn canonicalize_zero_comparisons(&mut self) {
+        for item in &mut self.items {
+            let Item::Instruction(instruction, _) = item else {
+                continue;
+            };
+            if let Instruction::Binary {
+                op: BinaryOp::Single(BinaryOpSingle::Cmp),
+                dst: Arg::Register(register),
+                src: Arg::Int(value),
+            } = *instruction
+            {
+                if value.is_zero() {
+                    *instruction = Instruction::Binary {
+                        op: BinaryOpSingle::Test.into(),
+                        dst: Arg::Register(register),
+                        src: Arg::Register(register),
+                    };
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use super::{
+    analysis::split_blocks,
+    args::Arg,
+    builder::InstructionBuilder,
+    error::X64WriterError,
+    function::{Function, Item, Items, Tags},
+    instruction::Instruction,
+    label::Label,
+    meta::ControlFlow,
+};
+use alloc::{format, vec::Vec};
+use core::cmp::Reverse;
+
+/// The copies the phis of block `to` perform when it is entered from block `from`.
+/// Blocks are named by the label they start with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PhiEdge<'a> {
+    pub from: Label<'a>,
+    pub to: Label<'a>,
+    /// `(dst, src)` pairs, with every source read before any destination is written.
+    pub copies: Vec<(Arg<'a>, Arg<'a>)>,
+}
+
+impl<'a> Function<'a> {
+    /// Turns the copies of every edge into moves that run exactly when the edge is taken:
+    /// at the end of `from` if `to` is its only successor, or else at the start of `to` if
+    /// `from` is its only predecessor. Critical edges have to be split first, see
+    /// [`Self::split_critical_edges`].
+    pub fn resolve_phis(&mut self, edges: &[PhiEdge<'a>]) -> Result<(), X64WriterError> {
+        let blocks = split_blocks(&self.items);
+        let block_of = |label: Label| {
+            let starts_with = |start| self.items.get(start) == Some(&Item::Label(label));
+            blocks
+                .iter()
+                .position(|block| starts_with(block.start))
+                .ok_or_else(|| {
+                    X64WriterError::InvalidOperand(format!("no block starts with {label}"))
+                })
+        };
+        let successors = |from: usize| {
+            let successors = blocks.iter().filter(|b| b.predecessors.contains(&from));
+            successors.count()
+        };
+
+        let mut insertions = Vec::new();
+        for edge in edges {
+            let from = block_of(edge.from)?;
+            let to = block_of(edge.to)?;
+            let mut predecessors = blocks[to].predecessors.clone();
+            predecessors.dedup();
+            if !predecessors.contains(&from) {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "{} does not lead to {}",
+                    edge.from, edge.to
+                )));
+            }
+
+            let position = if successors(from) == 1 {
+                // Moves leave the flags alone, so they can go before a conditional jump.
+                let last = blocks[from].instructions(&self.items).last();
+                match last {
+                    Some((i, Instruction::Jmp { .. } | Instruction::CJmp { .. })) => i,
+                    _ => blocks[from].end,
+                }
+            } else if predecessors.len() == 1 {
+                blocks[to].start + 1
+            } else {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "the edge from {} to {} is critical and has to be split first",
+                    edge.from, edge.to
+                )));
+            };
+
+            let mut copies = Items::new(self.features);
+            copies.emit_parallel_copy(&edge.copies)?;
+            insertions.push((position, copies.items));
+        }
+
+        insertions.sort_by_key(|&(position, _)| Reverse(position));
+        for (position, items) in insertions {
+            self.items.splice(position..position, items);
+        }
+        Ok(())
+    }
+
+    /// Splits every edge from a block with several successors to a block with several
+    /// predecessors by routing it through a new empty block named by `new_label`, which
+    /// has to return labels not used anywhere else.
+    ///
+    /// Blocks on the fall through path are inserted in place, those for a taken conditional
+    /// jump are appended to the body and jump on to the original target. If the body could
+    /// fall through its end, a jump to a new label after them skips them.
+    pub fn split_critical_edges(&mut self, mut new_label: impl FnMut() -> Label<'a>) {
+        let cfg = self.cfg();
+        let mut insertions = Vec::new();
+        let mut appended = Vec::new();
+        for (i, block) in cfg.blocks.iter().enumerate() {
+            if block.successors.len() < 2 {
+                continue;
+            }
+            for &successor in &block.successors {
+                if cfg.blocks[successor].predecessors.len() < 2 {
+                    continue;
+                }
+                let label = new_label();
+                if successor == i + 1 {
+                    insertions.push((block.end, label));
+                    continue;
+                }
+
+                let (jump, _) = block.instructions(&self.items).last().unwrap();
+                let Item::Instruction(Instruction::CJmp { target, .. }, _) = &mut self.items[jump]
+                else {
+                    unreachable!("only conditional jumps have several successors");
+                };
+                let original = core::mem::replace(target, Arg::Label(label));
+                appended.push(Item::Label(label));
+                appended.push(Item::Instruction(
+                    Instruction::Jmp { target: original },
+                    Tags::new(),
+                ));
+            }
+        }
+
+        insertions.sort_by_key(|&(position, _)| Reverse(position));
+        for (position, label) in insertions {
+            self.items.insert(position, Item::Label(label));
+        }
+        if appended.is_empty() {
+            return;
+        }
+        let last = self
+            .items
+            .iter()
+            .rfind(|item| !matches!(item, Item::Comment(_)));
+        let falls_through = match last {
+            Some(Item::Instruction(instruction, _)) => !matches!(
+                instruction.info().control_flow,
+                ControlFlow::Jump | ControlFlow::Return
+            ),
+            _ => true,
+        };
+        if falls_through {
+            let end = new_label();
+            self.items.push(Item::Instruction(
+                Instruction::Jmp {
+                    target: Arg::Label(end),
+                },
+                Tags::new(),
+            ));
+            appended.push(Item::Label(end));
+        }
+        self.items.extend(appended);
+    }
+}
@@ -0,0 +1,53 @@
+use super::global::HUGE_PAGE_SIZE;
+use alloc::{borrow::Cow, format};
+
+/// The operating system the output is assembled for, which decides the object file format
+/// and the spelling of symbols, sections and directives.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// ELF, assembled by GNU as.
+    #[default]
+    Linux,
+    /// Mach-O, assembled by clang.
+    MacOs,
+    /// COFF, assembled by the GNU as of MinGW or clang.
+    Windows,
+}
+impl Platform {
+    /// The name the symbol `name` has in the assembly. Mach-O prefixes C symbols with an
+    /// underscore and starts assembler-local labels with `L` rather than `.L`.
+    pub fn symbol_name(self, name: &str) -> Cow<'_, str> {
+        if self != Self::MacOs || is_numeric_label(name) {
+            return Cow::Borrowed(name);
+        }
+        match name.strip_prefix(".L") {
+            Some(local) => Cow::Owned(format!("L{local}")),
+            None => Cow::Owned(format!("_{name}")),
+        }
+    }
+    /// The directive making a symbol global.
+    pub fn global_directive(self) -> &'static str {
+        match self {
+            Self::Linux => ".global",
+            Self::MacOs | Self::Windows => ".globl",
+        }
+    }
+    /// Whether `.comm` takes its alignment in bytes, rather than as a power of two.
+    pub fn common_alignment_in_bytes(self) -> bool {
+        self == Self::Linux
+    }
+    /// The largest alignment in bytes a section can have. COFF silently caps larger ones,
+    /// and elsewhere it is the size of a huge page, enough to back data with one.
+    pub fn max_alignment(self) -> u64 {
+        match self {
+            Self::Linux | Self::MacOs => HUGE_PAGE_SIZE,
+            Self::Windows => 8 << 10,
+        }
+    }
+}
+
+/// Whether `name` is a numeric local label such as `1` or a reference to one such as `1f`.
+fn is_numeric_label(name: &str) -> bool {
+    let digits = name.strip_suffix(['b', 'f']).unwrap_or(name);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
@@ -0,0 +1,33 @@
+use super::{
+    features::TargetFeatures,
+    io::{self, Write},
+    writer::{AsmWriter, CodeMode},
+};
+use alloc::{format, vec::Vec};
+
+/// The offset of the signature in a BIOS boot sector.
+pub const BOOT_SIGNATURE_OFFSET: u64 = 510;
+pub const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// A strict writer for real mode code that is linked into a flat binary, such as a boot sector.
+/// Instructions that need 64-bit mode or any instruction set extension are rejected.
+pub fn flat_binary_16bit<O: Write>(out: O) -> io::Result<AsmWriter<O>> {
+    let mut writer = AsmWriter::new(out);
+    writer.set_target_features(TargetFeatures::none());
+    writer.set_strict(true);
+    writer.set_code_mode(CodeMode::Code16)?;
+    Ok(writer)
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Pads the current section with zeroes up to `offset` and places `magic` there.
+    pub fn emit_trailer(&mut self, offset: u64, magic: &[u8]) -> io::Result<()> {
+        self.org(offset, 0)?;
+        let bytes: Vec<_> = magic.iter().map(|b| format!("{b:#04x}")).collect();
+        self.directive(format_args!(".byte {}", bytes.join(", ")))
+    }
+    /// Pads a boot sector to 510 bytes and ends it with the `0x55 0xaa` signature.
+    pub fn emit_boot_signature(&mut self) -> io::Result<()> {
+        self.emit_trailer(BOOT_SIGNATURE_OFFSET, &BOOT_SIGNATURE)
+    }
+}
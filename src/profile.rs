@@ -0,0 +1,177 @@
+use super::{
+    args::{Arg, ArgSize},
+    cfg::Cfg,
+    flags::Flags,
+    function::{Function, Item, Tags},
+    instruction::Instruction,
+    io::{self, Write},
+    label::Label,
+    section::Section,
+    symbol::SymbolBinding,
+    writer::{AsmWriter, UnaryOp, UnaryOpSingle},
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// An array of 8-byte execution counters of basic blocks, and the names of the blocks.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockCounters<'a> {
+    /// The symbol of the counter array.
+    pub counters: Label<'a>,
+    /// The symbol of the name table: the number of counters, then a pointer to the
+    /// NUL-terminated name of each, all 8 bytes.
+    pub names: Label<'a>,
+    /// The name of each counter, the function followed by the label or index of the block.
+    pub entries: Vec<String>,
+}
+impl<'a> BlockCounters<'a> {
+    pub fn new(counters: impl Into<Label<'a>>, names: impl Into<Label<'a>>) -> Self {
+        Self {
+            counters: counters.into(),
+            names: names.into(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Function<'a> {
+    /// Counts the executions of every block with an `incq` of its own counter in
+    /// `counters`, with a lock prefix if `atomic`. The increments are tagged `profile`.
+    ///
+    /// As `inc` changes every status flag but CF, each increment goes at the first point
+    /// of its block where those flags are dead. Blocks without such a point, like a
+    /// conditional jump reusing the comparison of the block before, are not counted.
+    pub fn insert_block_counters(&mut self, counters: &mut BlockCounters<'a>, atomic: bool) {
+        let cfg = self.cfg();
+        let live_out = flags_live_out(&self.items, &cfg);
+        let clobbered = Flags::all().difference(Flags::CARRY);
+
+        let mut increments = Vec::new();
+        for (index, (block, mut live)) in cfg.blocks.iter().zip(live_out).enumerate() {
+            let label = block.label(&self.items);
+            let first = block.start + usize::from(label.is_some());
+            if block.start == block.end {
+                continue;
+            }
+            // Whether the flags are dead before each item, from the end of the block.
+            let mut dead = vec![false; block.end - first + 1];
+            dead[block.end - first] = !live.intersects(clobbered);
+            for i in (first..block.end).rev() {
+                if let Item::Instruction(instruction, _) = &self.items[i] {
+                    live = flags_live_before(instruction, live);
+                }
+                dead[i - first] = !live.intersects(clobbered);
+            }
+            // Counting after the terminator would not count anything.
+            let last = match self.items[block.end - 1] {
+                Item::Instruction(ref instruction, _) if instruction.info().is_terminator() => {
+                    block.end - 1
+                }
+                _ => block.end,
+            };
+            let Some(position) = (first..=last).find(|&i| dead[i - first]) else {
+                continue;
+            };
+
+            let name = match label {
+                Some(label) => format!("{}:{}", self.name, label),
+                None if index == 0 => self.name.to_string(),
+                None => format!("{}:{index}", self.name),
+            };
+            let offset = 8 * counters.entries.len() as u64;
+            counters.entries.push(name);
+            let counter = counters.counters.rip().offset(offset).size(ArgSize::Quad);
+            let mut increment = Instruction::Unary {
+                op: UnaryOp::Single(UnaryOpSingle::Inc),
+                dst: Arg::Memory(counter),
+            };
+            if atomic {
+                increment = increment.locked().unwrap();
+            }
+            increments.push((position, increment));
+        }
+
+        for (position, increment) in increments.into_iter().rev() {
+            let mut tags = Tags::new();
+            tags.push("profile");
+            self.items
+                .insert(position, Item::Instruction(increment, tags));
+        }
+    }
+}
+
+/// The flags live at the end of each block, assuming none are live after returns and
+/// jumps out of the function.
+fn flags_live_out(items: &[Item], cfg: &Cfg) -> Vec<Flags> {
+    let blocks = &cfg.blocks;
+    let mut live_in = vec![Flags::none(); blocks.len()];
+    let mut live_out = vec![Flags::none(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, block) in blocks.iter().enumerate().rev() {
+            let out = block
+                .successors
+                .iter()
+                .fold(Flags::none(), |live, &s| live.union(live_in[s]));
+            let instructions: Vec<_> = block.instructions(items).collect();
+            let live = instructions
+                .into_iter()
+                .rev()
+                .fold(out, |live, (_, instruction)| {
+                    flags_live_before(instruction, live)
+                });
+            live_out[i] = out;
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+fn flags_live_before(instruction: &Instruction, live_after: Flags) -> Flags {
+    let effect = instruction.flags_effect();
+    live_after.difference(effect.modified()).union(effect.read)
+}
+impl<O: Write> AsmWriter<O> {
+    /// Emits the zeroed counter array to `.bss` and the name table to `.data.rel.ro`,
+    /// with the names themselves in `.rodata`.
+    pub fn emit_block_counters(
+        &mut self,
+        counters: &BlockCounters,
+        binding: SymbolBinding,
+    ) -> io::Result<()> {
+        let count = counters.entries.len() as u64;
+        self.push_section(Section::Bss)?;
+        self.emit_balign(8)?;
+        self.declare_binding(counters.counters, binding)?;
+        self.emit_label(counters.counters)?;
+        self.emit_zero(8 * count)?;
+        self.pop_section()?;
+
+        let names = counters.names.label;
+        let name_label = |i: usize| format!(".L{names}.{i}");
+        self.push_section(Section::Rodata)?;
+        for (i, name) in counters.entries.iter().enumerate() {
+            self.emit_label(name_label(i).as_str())?;
+            self.emit_asciz(name)?;
+        }
+        self.pop_section()?;
+
+        let relro = Section::data_rel_ro();
+        self.push_section(relro)?;
+        self.emit_balign(8)?;
+        self.declare_binding(counters.names, binding)?;
+        self.emit_label(counters.names)?;
+        self.emit_quad(count)?;
+        for i in 0..counters.entries.len() {
+            self.emit_quad(name_label(i).as_str())?;
+        }
+        self.pop_section()
+    }
+}
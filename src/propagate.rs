@@ -0,0 +1,119 @@
+use super::{
+    args::Arg,
+    function::{Function, Item},
+    instruction::Instruction,
+    register::{Register, RegisterName, RegisterSize},
+};
+use core::cell::Cell;
+
+impl<'a> Function<'a> {
+    /// Removes register to register moves whose destination is read exactly once before
+    /// being overwritten in the same block, by having that reader use the source instead.
+    /// Moves of a register to itself are removed too, unless they zero-extend.
+    /// The tags of removed moves are added to the instruction using the forwarded register.
+    pub fn propagate_copies(&mut self) {
+        let mut i = 0;
+        while i < self.items.len() {
+            let Item::Instruction(instruction, _) = &self.items[i] else {
+                i += 1;
+                continue;
+            };
+            let Instruction::Mov {
+                dst: Arg::Register(dst),
+                src: Arg::Register(src),
+            } = *instruction
+            else {
+                i += 1;
+                continue;
+            };
+
+            if dst == src && dst.1 != RegisterSize::Double {
+                self.items.remove(i);
+            } else if let Some((user, forwarded)) = self.find_forwarding(i, src, dst) {
+                let Item::Instruction(_, tags) = self.items.remove(i) else {
+                    unreachable!()
+                };
+                let Item::Instruction(instruction, user_tags) = &mut self.items[user - 1] else {
+                    unreachable!()
+                };
+                *instruction = forwarded;
+                user_tags.extend(tags);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Finds the single reader of `dst` after the move at `index`, returning it with
+    /// `src` substituted, if `dst` is overwritten afterwards and `src` is not.
+    fn find_forwarding(
+        &self,
+        index: usize,
+        src: Register,
+        dst: Register,
+    ) -> Option<(usize, Instruction<'a>)> {
+        if src.0 == dst.0 {
+            return None;
+        }
+
+        let mut user = None;
+        for (i, item) in self.items.iter().enumerate().skip(index + 1) {
+            let instruction = match item {
+                Item::Label(_) => return None,
+                Item::Comment(_) => continue,
+                Item::Instruction(instruction, _) => instruction,
+            };
+
+            if instruction.reads().contains(dst.0) {
+                if user.is_some() || instruction.writes().contains(dst.0) {
+                    return None;
+                }
+                user = Some((i, forward(instruction, src, dst)?));
+            } else if instruction.writes().contains(dst.0) {
+                // A partial write merges with the forwarded value.
+                return if overwrites(instruction, dst.0) {
+                    user
+                } else {
+                    None
+                };
+            }
+            if user.is_none() && instruction.writes().contains(src.0) {
+                return None;
+            }
+            // Whether `dst` is read after leaving the block is unknown.
+            if instruction.info().is_terminator() {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+/// `instruction` reading `src` instead of `dst`, if all its reads of `dst` are explicit
+/// and no wider than the move.
+fn forward<'a>(
+    instruction: &Instruction<'a>,
+    src: Register,
+    dst: Register,
+) -> Option<Instruction<'a>> {
+    let fits = Cell::new(true);
+    let forwarded = instruction.map_registers(|r| {
+        if r.0 != dst.0 {
+            return r;
+        }
+        if r.1.in_bytes() > dst.1.in_bytes() {
+            fits.set(false);
+        }
+        src.0.with_size(r.1)
+    });
+
+    let complete = !forwarded.reads().contains(dst.0);
+    (fits.get() && complete).then_some(forwarded)
+}
+
+/// Whether `instruction` replaces the whole value of `name` without reading it.
+fn overwrites(instruction: &Instruction, name: RegisterName) -> bool {
+    let full =
+        |r: &Register| r.0 == name && matches!(r.1, RegisterSize::Double | RegisterSize::Quad);
+    !instruction.reads().contains(name) && instruction.defs().iter().any(full)
+}
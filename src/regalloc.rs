@@ -0,0 +1,485 @@
+use super::{
+    analysis::split_blocks,
+    args::{Arg, ConstInt, Memory},
+    builder::InstructionBuilder,
+    convention::{CallingConvention, StackFrame},
+    error::X64WriterError,
+    function::{Function, Item, Items, Tags},
+    instruction::Instruction,
+    meta::{ControlFlow, Mnemonic},
+    register::{RegisterName, RegisterSet, RegisterSize, VirtualRegister},
+};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format, vec,
+    vec::Vec,
+};
+
+/// Where [`Function::allocate_registers`] put a virtual register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    Register(RegisterName),
+    /// A stack slot in the frame, addressed relative to `%rsp`.
+    Spilled(Memory<'static>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Allocation {
+    /// The location of every virtual register in the body, by number.
+    pub locations: BTreeMap<u32, Location>,
+    /// The frame wrapped around the body, if it needed one.
+    pub frame: Option<StackFrame>,
+}
+
+/// The items from the first to the last one a virtual register is live at.
+struct Interval {
+    register: u32,
+    start: usize,
+    end: usize,
+}
+
+/// How an instruction accesses a virtual register operand.
+struct VirtualOperand {
+    index: usize,
+    register: VirtualRegister,
+    reads: bool,
+    writes: bool,
+}
+
+impl Function<'_> {
+    /// Replaces every virtual register with one of the `available` registers or a stack
+    /// slot using linear scan. Registers the body names, explicitly or implicitly, are
+    /// never handed out, and values live across a call only get registers callee-saved
+    /// in `convention`.
+    ///
+    /// If the body calls, spills or uses callee-saved registers, it is wrapped in
+    /// [`emit_prologue`](InstructionBuilder::emit_prologue) and every `ret` becomes
+    /// [`emit_epilogue`](InstructionBuilder::emit_epilogue), so the body must not move
+    /// `%rsp` itself. Virtual registers in operands that only take one register, like the
+    /// count of a shift, are copied through that register.
+    pub fn allocate_registers(
+        &mut self,
+        convention: &CallingConvention,
+        available: RegisterSet,
+    ) -> Result<Allocation, X64WriterError> {
+        self.items = precolor(core::mem::take(&mut self.items));
+        let mut pool = available.without(RegisterName::SP);
+        let mut calls = Vec::new();
+        for (i, item) in self.items.iter().enumerate() {
+            let Item::Instruction(instruction, _) = item else {
+                continue;
+            };
+            let control_flow = instruction.info().control_flow;
+            if instruction.writes().contains(RegisterName::SP)
+                && !matches!(control_flow, ControlFlow::Call | ControlFlow::Return)
+            {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "{instruction} moves %rsp, which the allocated frame relies on"
+                )));
+            }
+            if control_flow == ControlFlow::Call {
+                calls.push(i);
+            }
+            pool = pool.difference(named_registers(instruction));
+        }
+
+        let intervals = live_intervals(&self.items);
+        let callee_saved = convention.callee_saved;
+        let mut assigned = linear_scan(&intervals, &calls, pool, callee_saved);
+        let mut scratch = Vec::new();
+        if assigned.values().any(Option::is_none) {
+            // Reserve registers for instructions that cannot take a spilled operand from memory.
+            scratch = pool.difference(callee_saved).iter().collect();
+            scratch.reverse();
+            scratch.extend(pool.intersection(callee_saved).iter());
+            scratch.truncate(2);
+            if scratch.len() != 2 {
+                return Err(X64WriterError::InvalidOperand(
+                    "too few registers to spill to the stack".into(),
+                ));
+            }
+            let pool = scratch.iter().fold(pool, |pool, &name| pool.without(name));
+            assigned = linear_scan(&intervals, &calls, pool, callee_saved);
+        }
+
+        let mut saved = RegisterSet::new();
+        let mut slots = 0;
+        let mut locations = BTreeMap::new();
+        for (&register, &name) in &assigned {
+            let location = match name {
+                Some(name) => {
+                    saved.insert(name);
+                    Location::Register(name)
+                }
+                None => {
+                    slots += 1;
+                    let slot = 8 * (slots - 1) + convention.shadow_space;
+                    Location::Spilled(rsp_memory().offset(i64::from(slot)))
+                }
+            };
+            locations.insert(register, location);
+        }
+        saved = saved
+            .union(scratch.iter().copied().collect())
+            .intersection(callee_saved);
+
+        let mut prologue = Items::new(self.features);
+        let frame = if !saved.is_empty() || slots != 0 || !calls.is_empty() {
+            let frame = prologue.emit_prologue(convention, saved, 8 * slots);
+            Some(frame.expect("building items cannot fail"))
+        } else {
+            None
+        };
+
+        let mut items = prologue.items;
+        for item in self.items.drain(..) {
+            let Item::Instruction(instruction, tags) = item else {
+                items.push(item);
+                continue;
+            };
+            match &frame {
+                Some(frame) if instruction.mnemonic() == Mnemonic::Ret => {
+                    let mut epilogue = Items::new(self.features);
+                    epilogue
+                        .emit_epilogue(frame)
+                        .expect("building items cannot fail");
+                    items.extend(epilogue.items);
+                    if let Some(Item::Instruction(_, ret)) = items.last_mut() {
+                        *ret = tags;
+                    }
+                }
+                _ => rewrite(instruction, tags, &locations, &scratch, &mut items)?,
+            }
+        }
+        self.items = items;
+
+        Ok(Allocation { locations, frame })
+    }
+}
+
+/// Replaces virtual registers in operands that take a fixed register with that register,
+/// copying the value in before and out after the instruction.
+fn precolor(items: Vec<Item<'_>>) -> Vec<Item<'_>> {
+    let mut precolored = Vec::with_capacity(items.len());
+    for item in items {
+        let Item::Instruction(instruction, tags) = item else {
+            precolored.push(item);
+            continue;
+        };
+        let mut operands = instruction.operands();
+        let mut copies = Vec::new();
+        for (arg, operand) in operands.iter_mut().zip(instruction.info().operands) {
+            if let (Arg::Virtual(register), Some(fixed)) = (*arg, operand.fixed) {
+                let fixed = fixed.with_size(register.1);
+                copies.push((register, fixed, operand.access));
+                *arg = Arg::Register(fixed);
+            }
+        }
+        if copies.is_empty() {
+            precolored.push(Item::Instruction(instruction, tags));
+            continue;
+        }
+
+        let mov = |dst, src| Item::Instruction(Instruction::Mov { dst, src }, Tags::new());
+        for &(register, fixed, access) in &copies {
+            if access.reads() {
+                precolored.push(mov(fixed.into(), register.into()));
+            }
+        }
+        precolored.push(Item::Instruction(
+            instruction.with_operands(&operands),
+            tags,
+        ));
+        for &(register, fixed, access) in &copies {
+            if access.writes() {
+                precolored.push(mov(register.into(), fixed.into()));
+            }
+        }
+    }
+    precolored
+}
+
+/// The registers named by the operands, and the implicit ones of anything but calls.
+fn named_registers(instruction: &Instruction) -> RegisterSet {
+    let mut named = RegisterSet::new();
+    for arg in instruction.operands() {
+        match arg {
+            Arg::Register(r) => named.insert(r.0),
+            Arg::Memory(m) => {
+                named = named.union(m.address_registers().into_iter().map(|r| r.0).collect())
+            }
+            _ => (),
+        }
+    }
+    if instruction.info().control_flow != ControlFlow::Call {
+        let info = instruction.info();
+        named = named.union(info.implicit_reads).union(info.implicit_writes);
+    }
+    named
+}
+
+fn virtual_operands(instruction: &Instruction) -> Vec<VirtualOperand> {
+    let zeroing = instruction.is_zeroing_idiom();
+    let info = instruction.info();
+    let operands = instruction.operands().into_iter().zip(info.operands);
+    operands
+        .enumerate()
+        .filter_map(|(index, (arg, operand))| {
+            let Arg::Virtual(register) = arg else {
+                return None;
+            };
+            // 8 and 16-bit writes keep the rest of the register.
+            let partial = register.1 < RegisterSize::Double && operand.access.writes();
+            Some(VirtualOperand {
+                index,
+                register,
+                reads: (operand.access.reads() && !zeroing) || partial,
+                writes: operand.access.writes(),
+            })
+        })
+        .collect()
+}
+
+fn live_intervals(items: &[Item]) -> Vec<Interval> {
+    let blocks = split_blocks(items);
+    let mut successors = vec![Vec::new(); blocks.len()];
+    for (i, block) in blocks.iter().enumerate() {
+        for &predecessor in &block.predecessors {
+            successors[predecessor].push(i);
+        }
+    }
+
+    // The registers read before being written in a block, and those written in it.
+    let mut uses = vec![BTreeSet::new(); blocks.len()];
+    let mut defs = vec![BTreeSet::new(); blocks.len()];
+    for (b, block) in blocks.iter().enumerate() {
+        for (_, instruction) in block.instructions(items) {
+            let operands = virtual_operands(instruction);
+            for operand in operands.iter().filter(|o| o.reads) {
+                if !defs[b].contains(&operand.register.0) {
+                    uses[b].insert(operand.register.0);
+                }
+            }
+            for operand in operands.iter().filter(|o| o.writes) {
+                defs[b].insert(operand.register.0);
+            }
+        }
+    }
+
+    let mut live_in = uses.clone();
+    let mut live_out = vec![BTreeSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..blocks.len()).rev() {
+            let out: BTreeSet<u32> = successors[b]
+                .iter()
+                .flat_map(|&s| live_in[s].iter().copied())
+                .collect();
+            let mut live = uses[b].clone();
+            live.extend(out.difference(&defs[b]));
+            if live != live_in[b] || out != live_out[b] {
+                live_in[b] = live;
+                live_out[b] = out;
+                changed = true;
+            }
+        }
+    }
+
+    let mut extents: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+    let mut extend = |register, position| {
+        let extent = extents.entry(register).or_insert((position, position));
+        extent.0 = extent.0.min(position);
+        extent.1 = extent.1.max(position);
+    };
+    for (b, block) in blocks.iter().enumerate() {
+        for (i, instruction) in block.instructions(items) {
+            for operand in virtual_operands(instruction) {
+                extend(operand.register.0, i);
+            }
+        }
+        for &register in &live_in[b] {
+            extend(register, block.start);
+        }
+        for &register in &live_out[b] {
+            extend(register, block.end.max(block.start + 1) - 1);
+        }
+    }
+
+    let mut intervals: Vec<_> = extents
+        .into_iter()
+        .map(|(register, (start, end))| Interval {
+            register,
+            start,
+            end,
+        })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.register));
+    intervals
+}
+
+/// Assigns a register from `pool` to every interval, or `None` if it is spilled.
+fn linear_scan(
+    intervals: &[Interval],
+    calls: &[usize],
+    pool: RegisterSet,
+    callee_saved: RegisterSet,
+) -> BTreeMap<u32, Option<RegisterName>> {
+    let mut assigned = BTreeMap::new();
+    let mut active: Vec<(usize, u32, RegisterName)> = Vec::new();
+    let mut free = pool;
+    for interval in intervals {
+        active.retain(|&(end, _, name)| {
+            let expired = end < interval.start;
+            if expired {
+                free.insert(name);
+            }
+            !expired
+        });
+
+        let crosses_call = calls
+            .iter()
+            .any(|&call| interval.start < call && call < interval.end);
+        let (allowed, preferred) = if crosses_call {
+            let allowed = free.intersection(callee_saved);
+            (allowed, allowed)
+        } else {
+            (free, free.difference(callee_saved))
+        };
+        if let Some(name) = preferred.iter().chain(allowed.iter()).next() {
+            free.remove(name);
+            active.push((interval.end, interval.register, name));
+            assigned.insert(interval.register, Some(name));
+            continue;
+        }
+
+        // Spill whichever competing interval ends last.
+        let victim = active
+            .iter_mut()
+            .filter(|(_, _, name)| !crosses_call || callee_saved.contains(*name))
+            .max_by_key(|(end, _, _)| *end);
+        match victim {
+            Some(victim) if victim.0 > interval.end => {
+                assigned.insert(victim.1, None);
+                assigned.insert(interval.register, Some(victim.2));
+                *victim = (interval.end, interval.register, victim.2);
+            }
+            _ => {
+                assigned.insert(interval.register, None);
+            }
+        }
+    }
+    assigned
+}
+
+/// Pushes `instruction` with its virtual registers replaced, loading spilled ones into
+/// `scratch` registers and storing them back where memory operands are not allowed.
+fn rewrite<'a>(
+    instruction: Instruction<'a>,
+    tags: Tags,
+    locations: &BTreeMap<u32, Location>,
+    scratch: &[RegisterName],
+    items: &mut Vec<Item<'a>>,
+) -> Result<(), X64WriterError> {
+    let virtuals = virtual_operands(&instruction);
+    if virtuals.is_empty() {
+        items.push(Item::Instruction(instruction, tags));
+        return Ok(());
+    }
+
+    let mut operands = instruction.operands();
+    let mut spilled = Vec::new();
+    for operand in &virtuals {
+        let register = operand.register;
+        let arg = &mut operands[operand.index];
+        *arg = match locations[&register.0] {
+            Location::Register(name) => Arg::Register(name.with_size(register.1)),
+            Location::Spilled(slot) => {
+                spilled.push(operand);
+                Arg::Memory(slot.size(arg.size().unwrap()))
+            }
+        };
+    }
+
+    let memory = operands.iter().filter(|arg| arg.is_memory()).count();
+    let mut reloaded: Vec<&VirtualOperand> = spilled
+        .iter()
+        .copied()
+        .filter(|operand| requires_register(&instruction, operand.index))
+        .collect();
+    for &operand in &spilled {
+        if memory - reloaded.len() > 1 && !reloaded.iter().any(|r| r.index == operand.index) {
+            reloaded.push(operand);
+        }
+    }
+
+    // Each spilled register gets one scratch register, even if it appears twice.
+    let mut reloads: Vec<(VirtualRegister, Memory, RegisterName)> = Vec::new();
+    for operand in reloaded {
+        let register = operand.register;
+        let name = match reloads.iter().find(|(r, _, _)| r.0 == register.0) {
+            Some(&(_, _, name)) => name,
+            None => {
+                let Arg::Memory(slot) = operands[operand.index] else {
+                    unreachable!()
+                };
+                let name = *scratch.get(reloads.len()).ok_or_else(|| {
+                    X64WriterError::InvalidOperand(format!(
+                        "{instruction} needs too many scratch registers"
+                    ))
+                })?;
+                reloads.push((register, slot, name));
+                name
+            }
+        };
+        operands[operand.index] = Arg::Register(name.with_size(register.1));
+    }
+
+    let mov = |dst, src| Item::Instruction(Instruction::Mov { dst, src }, Tags::new());
+    let accesses = |register: VirtualRegister, access: fn(&VirtualOperand) -> bool| {
+        let same = |o: &&VirtualOperand| o.register.0 == register.0;
+        virtuals.iter().filter(same).any(access)
+    };
+    for &(register, slot, name) in &reloads {
+        if accesses(register, |o| o.reads) {
+            items.push(mov(name.with_size(register.1).into(), slot.into()));
+        }
+    }
+    items.push(Item::Instruction(
+        instruction.with_operands(&operands),
+        tags,
+    ));
+    for &(register, slot, name) in &reloads {
+        if accesses(register, |o| o.writes) {
+            items.push(mov(slot.into(), name.with_size(register.1).into()));
+        }
+    }
+    Ok(())
+}
+/// Whether the operand at `index` has to be a register once it is not an immediate.
+fn requires_register(instruction: &Instruction, index: usize) -> bool {
+    use Mnemonic::*;
+    let mnemonic = instruction.mnemonic();
+    match index {
+        0 => {
+            let wide_immediate = match instruction.operands().get(1) {
+                Some(Arg::Int(ConstInt::I64(value))) => i32::try_from(*value).is_err(),
+                Some(Arg::Int(ConstInt::U64(value))) => i32::try_from(*value).is_err(),
+                _ => false,
+            };
+            wide_immediate
+                || matches!(
+                    mnemonic,
+                    CMov | IMul | Lea | Movzx | Movsx | Popcnt | Lzcnt | Tzcnt | Bsf | Bsr
+                )
+                || matches!(mnemonic, Bextr | Shlx | Shrx | Sarx)
+        }
+        1 => matches!(mnemonic, Shld | Shrd | Xadd | Cmpxchg),
+        2 => matches!(mnemonic, Bextr | Shlx | Shrx | Sarx),
+        _ => false,
+    }
+}
+
+fn rsp_memory() -> Memory<'static> {
+    RegisterName::SP.quad().memory()
+}
@@ -1,5 +1,5 @@
 use super::args::Memory;
-use std::fmt::Display;
+use core::fmt::Display;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Register(pub RegisterName, pub RegisterSize);
@@ -7,9 +7,15 @@ impl Register {
     pub fn memory(self) -> Memory<'static> {
         Memory::sib().base(self)
     }
+    /// Whether the register can only be encoded in 64-bit mode, such as `%r8w` or `%sil`.
+    pub fn requires_long_mode(self) -> bool {
+        self.0.is_numbered()
+            || self.1 == RegisterSize::Quad
+            || (self.0.is_pointer() && self.1 == RegisterSize::Byte)
+    }
 }
 impl Display for Register {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let get_affixes = if self.0.is_sandwich() {
             RegisterSize::sandwich_affixes
         } else if self.0.is_pointer() {
@@ -26,7 +32,107 @@ impl Display for Register {
     }
 }
 
+/// A numbered placeholder for a general purpose register, assigned by
+/// [`Function::allocate_registers`](crate::function::Function::allocate_registers).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VirtualRegister(pub u32, pub RegisterSize);
+impl VirtualRegister {
+    pub fn with_size(self, size: RegisterSize) -> Self {
+        Self(self.0, size)
+    }
+}
+impl Display for VirtualRegister {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (_, suffix) = self.1.numbered_affixes();
+        write!(f, "%v{}{suffix}", self.0)
+    }
+}
+
+/// How much of a vector register an operand covers, from the low end.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum VectorSize {
+    Xmm,
+    Ymm,
+    Zmm,
+}
+impl VectorSize {
+    pub fn in_bytes(self) -> u8 {
+        match self {
+            Self::Xmm => 16,
+            Self::Ymm => 32,
+            Self::Zmm => 64,
+        }
+    }
+}
+
+/// One of the vector registers `%xmm0` to `%zmm31`. SSE only reaches `%xmm0` to `%xmm15`,
+/// and registers 16 and up as well as the `%zmm` size need AVX-512.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VectorRegister(pub u8, pub VectorSize);
+impl VectorRegister {
+    pub fn with_size(self, size: VectorSize) -> Self {
+        Self(self.0, size)
+    }
+    /// Whether the register can only be encoded in 64-bit mode, which is `%xmm8` and up.
+    pub fn requires_long_mode(self) -> bool {
+        self.0 >= 8
+    }
+}
+impl Display for VectorRegister {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let prefix = match self.1 {
+            VectorSize::Xmm => "x",
+            VectorSize::Ymm => "y",
+            VectorSize::Zmm => "z",
+        };
+        write!(f, "%{prefix}mm{}", self.0)
+    }
+}
+
+/// One of the AVX-512 mask registers `%k0` to `%k7`. `%k0` cannot mask an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaskRegister(pub u8);
+impl MaskRegister {
+    /// Masking that keeps the previous value of destination lanes whose mask bit is clear.
+    pub fn merge(self) -> Masking {
+        assert!(self.0 != 0, "%k0 cannot mask an instruction");
+        Masking {
+            mask: self,
+            zeroing: false,
+        }
+    }
+    /// Masking that clears destination lanes whose mask bit is clear.
+    pub fn zero(self) -> Masking {
+        Masking {
+            zeroing: true,
+            ..self.merge()
+        }
+    }
+}
+impl Display for MaskRegister {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "%k{}", self.0)
+    }
+}
+
+/// The mask applied to the destination of an AVX-512 instruction, written `{%k1}` or
+/// `{%k1}{z}` behind it.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Masking {
+    pub mask: MaskRegister,
+    pub zeroing: bool,
+}
+impl Display for Masking {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{{{}}}", self.mask)?;
+        if self.zeroing {
+            write!(f, "{{z}}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum RegisterName {
     A,
     B,
@@ -46,6 +152,28 @@ pub enum RegisterName {
     R15,
 }
 impl RegisterName {
+    pub const ALL: [RegisterName; 16] = [
+        RegisterName::A,
+        RegisterName::B,
+        RegisterName::C,
+        RegisterName::D,
+        RegisterName::SI,
+        RegisterName::DI,
+        RegisterName::SP,
+        RegisterName::BP,
+        RegisterName::R8,
+        RegisterName::R9,
+        RegisterName::R10,
+        RegisterName::R11,
+        RegisterName::R12,
+        RegisterName::R13,
+        RegisterName::R14,
+        RegisterName::R15,
+    ];
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
     fn name(&self) -> &'static str {
         use RegisterName::*;
         match self {
@@ -69,23 +197,37 @@ impl RegisterName {
     }
     fn is_sandwich(self) -> bool {
         use RegisterName::*;
-        match self {
-            A | B | C | D => true,
-            _ => false,
-        }
+        matches!(self, A | B | C | D)
     }
     fn is_pointer(self) -> bool {
         use RegisterName::*;
-        match self {
-            SI | DI | SP | BP => true,
-            _ => false,
-        }
+        matches!(self, SI | DI | SP | BP)
     }
-    fn is_numbered(self) -> bool {
+    pub(crate) fn is_numbered(self) -> bool {
+        use RegisterName::*;
+        matches!(self, R8 | R9 | R10 | R11 | R12 | R13 | R14 | R15)
+    }
+
+    /// The number of the register in DWARF call frame and debug information.
+    pub fn dwarf_number(self) -> u8 {
         use RegisterName::*;
         match self {
-            R8 | R9 | R10 | R11 | R12 | R13 | R14 | R15 => true,
-            _ => false,
+            A => 0,
+            D => 1,
+            C => 2,
+            B => 3,
+            SI => 4,
+            DI => 5,
+            BP => 6,
+            SP => 7,
+            R8 => 8,
+            R9 => 9,
+            R10 => 10,
+            R11 => 11,
+            R12 => 12,
+            R13 => 13,
+            R14 => 14,
+            R15 => 15,
         }
     }
 
@@ -106,7 +248,7 @@ impl RegisterName {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum RegisterSize {
     Byte,
     Word,
@@ -114,6 +256,13 @@ pub enum RegisterSize {
     Quad,
 }
 impl RegisterSize {
+    pub const ALL: [RegisterSize; 4] = [
+        RegisterSize::Byte,
+        RegisterSize::Word,
+        RegisterSize::Double,
+        RegisterSize::Quad,
+    ];
+
     fn sandwich_affixes(self) -> (&'static str, &'static str) {
         use RegisterSize::*;
         match self {
@@ -305,3 +454,80 @@ pub fn rxw(x: u8) -> Register {
 pub fn rxb(x: u8) -> Register {
     rx_name(x).byte()
 }
+
+pub fn xmm(x: u8) -> VectorRegister {
+    assert!(x < 32, "{x} is not the number of a vector register");
+    VectorRegister(x, VectorSize::Xmm)
+}
+pub fn ymm(x: u8) -> VectorRegister {
+    xmm(x).with_size(VectorSize::Ymm)
+}
+pub fn zmm(x: u8) -> VectorRegister {
+    xmm(x).with_size(VectorSize::Zmm)
+}
+pub fn k(x: u8) -> MaskRegister {
+    assert!(x < 8, "{x} is not the number of a mask register");
+    MaskRegister(x)
+}
+
+/// A set of general purpose registers, irrespective of their size.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RegisterSet(u16);
+impl RegisterSet {
+    pub fn new() -> Self {
+        Self(0)
+    }
+    pub fn all() -> Self {
+        Self(u16::MAX)
+    }
+    pub fn from_slice(names: &[RegisterName]) -> Self {
+        names.iter().fold(Self::new(), |set, &name| set.with(name))
+    }
+    /// The registers a System V function has to preserve, excluding `%rsp`.
+    pub fn sysv_callee_saved() -> Self {
+        use RegisterName::*;
+        Self::from_slice(&[B, BP, R12, R13, R14, R15])
+    }
+
+    pub fn contains(self, name: RegisterName) -> bool {
+        self.0 & name.bit() != 0
+    }
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+    pub fn with(self, name: RegisterName) -> Self {
+        Self(self.0 | name.bit())
+    }
+    pub fn without(self, name: RegisterName) -> Self {
+        Self(self.0 & !name.bit())
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+    pub fn insert(&mut self, name: RegisterName) {
+        *self = self.with(name);
+    }
+    pub fn remove(&mut self, name: RegisterName) {
+        *self = self.without(name);
+    }
+    pub fn iter(self) -> impl Iterator<Item = RegisterName> {
+        RegisterName::ALL
+            .into_iter()
+            .filter(move |&name| self.contains(name))
+    }
+}
+impl FromIterator<RegisterName> for RegisterSet {
+    fn from_iter<T: IntoIterator<Item = RegisterName>>(iter: T) -> Self {
+        iter.into_iter()
+            .fold(Self::new(), |set, name| set.with(name))
+    }
+}
@@ -0,0 +1,316 @@
+use super::{
+    args::{Arg, ArgSize, ConstInt, Memory, MemoryKind},
+    function::{Function, Item},
+    instruction::Instruction,
+    meta::Mnemonic,
+    module::Module,
+    register::{Register, RegisterName},
+    writer::BinaryOp,
+};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Display, Write};
+
+/// Code size statistics of one function, for tracking code generation without assembling.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionReport {
+    pub name: String,
+    pub instructions: usize,
+    /// The approximate size of the machine code and constant island in bytes.
+    pub estimated_size: usize,
+    pub calls: usize,
+    /// The deepest the stack pointer gets below its value on entry, not counting the
+    /// return address.
+    pub stack_frame: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ModuleReport {
+    pub functions: Vec<FunctionReport>,
+}
+impl ModuleReport {
+    pub fn largest_stack_frame(&self) -> u64 {
+        self.functions
+            .iter()
+            .map(|f| f.stack_frame)
+            .max()
+            .unwrap_or(0)
+    }
+    pub fn estimated_size(&self) -> usize {
+        self.functions.iter().map(|f| f.estimated_size).sum()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\"functions\":[");
+        for (i, function) in self.functions.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"name\":{},\"instructions\":{},\"estimated_size\":{},\"calls\":{},\"stack_frame\":{}}}",
+                JsonString(&function.name),
+                function.instructions,
+                function.estimated_size,
+                function.calls,
+                function.stack_frame,
+            )
+            .unwrap();
+        }
+        write!(
+            json,
+            "],\"estimated_size\":{},\"largest_stack_frame\":{}}}",
+            self.estimated_size(),
+            self.largest_stack_frame()
+        )
+        .unwrap();
+        json
+    }
+}
+
+impl Module<'_> {
+    /// Reports on every function, with target clones under the names they are emitted as.
+    pub fn report(&self) -> ModuleReport {
+        let mut functions: Vec<_> = self
+            .functions()
+            .iter()
+            .map(|function| function.report(function.name.label))
+            .collect();
+        for clones in self.target_clones() {
+            let bodies = clones.clones.iter().chain([&clones.default]);
+            for (body, name) in bodies.zip(clones.clone_names()) {
+                functions.push(body.report(&name));
+            }
+        }
+        ModuleReport { functions }
+    }
+}
+
+impl Function<'_> {
+    pub fn report(&self, name: &str) -> FunctionReport {
+        let code: usize = self.instructions().map(Instruction::estimated_size).sum();
+        let mut values = Vec::new();
+        for constant in &self.constants {
+            if !values.contains(&constant.value) {
+                values.push(constant.value);
+            }
+        }
+        let constants: usize = values.iter().map(|value| value.size()).sum();
+        let calls = self
+            .instructions()
+            .filter(|inst| inst.mnemonic() == Mnemonic::Call)
+            .count();
+
+        FunctionReport {
+            name: name.to_string(),
+            instructions: self.instructions().count(),
+            estimated_size: code + constants,
+            calls,
+            stack_frame: self.stack_frame(),
+        }
+    }
+
+    /// Follows pushes, pops and constant adjustments of `%rsp` through the items in order.
+    fn stack_frame(&self) -> u64 {
+        let mut depth: i64 = 0;
+        let mut deepest = 0;
+        for item in &self.items {
+            let Item::Instruction(instruction, _) = item else {
+                continue;
+            };
+            depth += match *instruction {
+                Instruction::Push { .. } => 8,
+                Instruction::Pop { .. } => -8,
+                Instruction::Binary {
+                    dst: Arg::Register(dst),
+                    src: Arg::Int(amount),
+                    ..
+                } if dst.0 == RegisterName::SP => match instruction.mnemonic() {
+                    Mnemonic::Sub => const_value(amount),
+                    Mnemonic::Add => -const_value(amount),
+                    _ => 0,
+                },
+                _ => 0,
+            };
+            deepest = deepest.max(depth);
+        }
+        deepest as u64
+    }
+}
+
+impl Instruction<'_> {
+    /// A rough size of the encoded instruction in bytes, assuming near jumps and
+    /// 32-bit displacements for labels.
+    pub fn estimated_size(&self) -> usize {
+        if let Instruction::String { size, rep, .. } = *self {
+            // The operand size and repeat prefixes, REX.W and the opcode.
+            let word = size == ArgSize::Word;
+            let quad = size == ArgSize::Quad;
+            return usize::from(word) + usize::from(rep.is_some()) + usize::from(quad) + 1;
+        }
+        let operands = self.operands();
+        let size = operands.iter().find_map(Arg::size);
+
+        let prefixes = match size {
+            Some(ArgSize::Word) => 1,
+            _ => 0,
+        } + usize::from(self.needs_rex())
+            + usize::from(matches!(self, Instruction::Locked { .. }));
+        let opcode = match self.mnemonic() {
+            Mnemonic::CMov | Mnemonic::IMul | Mnemonic::CJmp | Mnemonic::Cltq => 2,
+            Mnemonic::Cbtw | Mnemonic::Cwtd | Mnemonic::Cqto => 2,
+            Mnemonic::SetCC => 2,
+            Mnemonic::Movzx | Mnemonic::Movsx | Mnemonic::Shld | Mnemonic::Shrd => 2,
+            Mnemonic::Bswap => 2,
+            Mnemonic::Bt | Mnemonic::Bts | Mnemonic::Btr | Mnemonic::Btc => 2,
+            Mnemonic::Bsf | Mnemonic::Bsr => 2,
+            Mnemonic::Xadd | Mnemonic::Cmpxchg => 2,
+            // REX.W and the two opcode bytes.
+            Mnemonic::Cmpxchg16b => 3,
+            Mnemonic::Mfence | Mnemonic::Lfence | Mnemonic::Sfence => 3,
+            Mnemonic::Movbe | Mnemonic::Popcnt | Mnemonic::Lzcnt | Mnemonic::Tzcnt => 3,
+            // The three byte VEX prefix and the opcode.
+            Mnemonic::Bextr | Mnemonic::Shlx | Mnemonic::Shrx | Mnemonic::Sarx => 4,
+            Mnemonic::Cpuid | Mnemonic::Rdmsr | Mnemonic::Wrmsr | Mnemonic::Iretq => 2,
+            Mnemonic::Xgetbv | Mnemonic::Swapgs | Mnemonic::Sysretq => 3,
+            // The escape byte and opcode, after a mandatory prefix unless packed single.
+            Mnemonic::Movaps | Mnemonic::Movups | Mnemonic::Addps | Mnemonic::Subps => 2,
+            Mnemonic::Mulps | Mnemonic::Divps | Mnemonic::Andps | Mnemonic::Xorps => 2,
+            Mnemonic::Ucomiss => 2,
+            // The four byte EVEX prefix, or a VEX prefix of usually three bytes, and the
+            // opcode.
+            _ if self.requires_evex() => 5,
+            _ if matches!(self, Instruction::Vector { .. }) => 4,
+            _ if matches!(
+                self,
+                Instruction::Binary {
+                    op: BinaryOp::Sse(_),
+                    ..
+                }
+            ) =>
+            {
+                3
+            }
+            _ => 1,
+        };
+
+        let operand_bytes: usize = match *self {
+            Instruction::Call { target }
+            | Instruction::Jmp { target }
+            | Instruction::CJmp { target, .. } => match target {
+                Arg::Label(_) | Arg::SymExpr(_) => 4,
+                _ => modrm_size(&target),
+            },
+            Instruction::Push {
+                src: Arg::Register(_),
+            }
+            | Instruction::Pop {
+                dst: Arg::Register(_),
+            } => 0,
+            _ => {
+                let modrm = operands
+                    .iter()
+                    .find(|arg| !matches!(arg, Arg::Int(_)))
+                    .map_or(0, modrm_size);
+                let immediate: usize = operands
+                    .iter()
+                    .map(|arg| match arg {
+                        Arg::Int(c) => immediate_size(*c, size),
+                        _ => 0,
+                    })
+                    .sum();
+                modrm + immediate
+            }
+        };
+
+        prefixes + opcode + operand_bytes
+    }
+    fn needs_rex(&self) -> bool {
+        // Stack operations and indirect branches default to 64 bits.
+        let default_quad = matches!(
+            self.mnemonic(),
+            Mnemonic::Push | Mnemonic::Pop | Mnemonic::Call | Mnemonic::Jmp
+        );
+        let register = |r: Register| r.0.is_numbered() || (!default_quad && r.requires_long_mode());
+        // VEX and EVEX prefixes contain the REX bits.
+        if matches!(self, Instruction::Vector { .. }) {
+            return false;
+        }
+        self.operands().iter().any(|arg| match *arg {
+            Arg::Register(r) => register(r),
+            Arg::Vector(r) => r.requires_long_mode(),
+            Arg::Memory(m) => {
+                let quad = m.size == Some(ArgSize::Quad) && !default_quad;
+                quad || m.address_registers().iter().any(|r| r.0.is_numbered())
+            }
+            _ => false,
+        })
+    }
+}
+
+fn modrm_size(arg: &Arg) -> usize {
+    match arg {
+        Arg::Memory(memory) => 1 + memory_size(memory),
+        _ => 1,
+    }
+}
+fn memory_size(memory: &Memory) -> usize {
+    let MemoryKind::Sib(sib) = memory.kind else {
+        return 4;
+    };
+    let base = sib.base();
+    let sib_byte = sib.index().is_some()
+        || base.map_or(true, |b| {
+            matches!(b.0, RegisterName::SP | RegisterName::R12)
+        });
+    let displacement = match (memory.displacement_label, memory.displacement_constant) {
+        (Some(_), _) => 4,
+        (None, _) if base.is_none() => 4,
+        (None, Some(c)) if !c.is_zero() => match i8::try_from(const_value(c)) {
+            Ok(_) => 1,
+            Err(_) => 4,
+        },
+        (None, _) if base.is_some_and(|b| matches!(b.0, RegisterName::BP | RegisterName::R13)) => 1,
+        (None, _) => 0,
+    };
+    usize::from(sib_byte) + displacement + usize::from(memory.segment.is_some())
+}
+fn immediate_size(value: ConstInt, size: Option<ArgSize>) -> usize {
+    let value = const_value(value);
+    match size {
+        Some(ArgSize::Byte) => 1,
+        _ if i8::try_from(value).is_ok() => 1,
+        Some(ArgSize::Word) => 2,
+        Some(ArgSize::Quad) if i32::try_from(value).is_err() => 8,
+        _ => 4,
+    }
+}
+fn const_value(value: ConstInt) -> i64 {
+    match value {
+        ConstInt::I8(v) => v.into(),
+        ConstInt::U8(v) => v.into(),
+        ConstInt::I32(v) => v.into(),
+        ConstInt::U32(v) => v.into(),
+        ConstInt::I64(v) => v,
+        ConstInt::U64(v) => v as i64,
+    }
+}
+
+struct JsonString<'s>(&'s str);
+impl Display for JsonString<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
@@ -0,0 +1,215 @@
+use super::{
+    flags::Flags,
+    function::{Function, Item},
+    instruction::Instruction,
+    meta::Mnemonic,
+    register::VectorRegister,
+};
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+use core::cmp::Reverse;
+
+impl Function<'_> {
+    /// Reorders instructions within each basic block so that long latency instructions,
+    /// such as loads and multiplications, start as early as their dependencies allow.
+    ///
+    /// Register, flag and memory dependencies are taken from the instruction metadata.
+    /// Memory accesses are assumed to alias unless both only load. Comments move with
+    /// the instruction following them, and instructions that are not plain data
+    /// operations, such as calls and jumps, are never moved across.
+    pub fn schedule(&mut self) {
+        let mut items = Vec::with_capacity(self.items.len());
+        let mut block = Vec::new();
+        for item in self.items.drain(..) {
+            let is_barrier = match &item {
+                Item::Label(_) => true,
+                Item::Instruction(instruction, _) => is_barrier(instruction),
+                Item::Comment(_) => false,
+            };
+            if is_barrier {
+                items.extend(schedule_block(core::mem::take(&mut block)));
+                items.push(item);
+            } else {
+                block.push(item);
+            }
+        }
+        items.extend(schedule_block(block));
+        self.items = items;
+    }
+}
+
+/// An instruction together with the comments preceding it.
+struct Unit<'a> {
+    items: Vec<Item<'a>>,
+    instruction: Instruction<'a>,
+}
+
+fn schedule_block(items: Vec<Item>) -> Vec<Item> {
+    let mut units = Vec::new();
+    let mut pending = Vec::new();
+    for item in items {
+        if let Item::Instruction(instruction, _) = item {
+            pending.push(item);
+            units.push(Unit {
+                items: core::mem::take(&mut pending),
+                instruction,
+            });
+        } else {
+            pending.push(item);
+        }
+    }
+
+    let predecessors = dependencies(&units);
+    let mut heights = vec![0; units.len()];
+    for i in (0..units.len()).rev() {
+        let successors = (i + 1..units.len()).filter(|&j| predecessors[j].contains(&i));
+        let height = successors.map(|j| heights[j]).max().unwrap_or(0);
+        heights[i] = latency(&units[i].instruction) + height;
+    }
+
+    let mut remaining: Vec<_> = predecessors.iter().map(BTreeSet::len).collect();
+    let mut scheduled = vec![false; units.len()];
+    let mut order = Vec::with_capacity(units.len());
+    while order.len() < units.len() {
+        let next = (0..units.len())
+            .filter(|&i| !scheduled[i] && remaining[i] == 0)
+            .max_by_key(|&i| (heights[i], Reverse(i)))
+            .expect("dependencies only point backwards");
+        scheduled[next] = true;
+        order.push(next);
+        for (j, predecessors) in predecessors.iter().enumerate() {
+            if predecessors.contains(&next) {
+                remaining[j] -= 1;
+            }
+        }
+    }
+
+    let mut units: Vec<_> = units.into_iter().map(Some).collect();
+    let mut items: Vec<_> = order
+        .into_iter()
+        .flat_map(|i| units[i].take().unwrap().items)
+        .collect();
+    items.extend(pending);
+    items
+}
+
+/// For each instruction, the earlier instructions it has to stay behind.
+fn dependencies(units: &[Unit]) -> Vec<BTreeSet<usize>> {
+    let mut predecessors = vec![BTreeSet::new(); units.len()];
+    for (j, later) in units.iter().enumerate() {
+        let later = &later.instruction;
+        for (i, earlier) in units[..j].iter().enumerate() {
+            let earlier = &earlier.instruction;
+            let registers = earlier
+                .writes()
+                .intersection(later.reads())
+                .union(earlier.reads().intersection(later.writes()))
+                .union(earlier.writes().intersection(later.writes()));
+            // Vector registers of the same number overlap whatever their size.
+            let vectors = |registers: Vec<VectorRegister>| registers.iter().map(|r| r.0).collect();
+            let vectors = conflict(
+                (
+                    vectors(earlier.vector_defs()),
+                    vectors(earlier.vector_uses()),
+                ),
+                (vectors(later.vector_defs()), vectors(later.vector_uses())),
+            );
+            let masks = conflict(
+                (earlier.mask_defs(), earlier.mask_uses()),
+                (later.mask_defs(), later.mask_uses()),
+            );
+            let memory = (earlier.writes_memory()
+                && (later.reads_memory() || later.writes_memory()))
+                || (earlier.reads_memory() && later.writes_memory());
+            if !registers.is_empty() || vectors || masks || memory {
+                predecessors[j].insert(i);
+            }
+        }
+    }
+
+    flags_dependencies(units, &mut predecessors);
+    predecessors
+}
+
+/// Whether a later instruction reads or writes what an earlier one writes, or writes what
+/// it reads, given the defs and uses of both.
+fn conflict<T: PartialEq>(earlier: (Vec<T>, Vec<T>), later: (Vec<T>, Vec<T>)) -> bool {
+    let ((earlier_defs, earlier_uses), (later_defs, later_uses)) = (earlier, later);
+    let overlap = |a: &[T], b: &[T]| a.iter().any(|r| b.contains(r));
+    overlap(&earlier_defs, &later_uses)
+        || overlap(&earlier_uses, &later_defs)
+        || overlap(&earlier_defs, &later_defs)
+}
+
+/// Keeps every flags consumer behind the instruction producing its flags, without any other
+/// writer of those flags moving in between. The last writer of each flag stays last, as the
+/// flags may be read after the block.
+fn flags_dependencies(units: &[Unit], predecessors: &mut [BTreeSet<usize>]) {
+    let modified = |i: usize| units[i].instruction.flags_effect().modified();
+
+    for (reader, unit) in units.iter().enumerate() {
+        let read = unit.instruction.flags_effect().read;
+        if read.is_empty() {
+            continue;
+        }
+
+        let producer = (0..reader).rev().find(|&i| modified(i).intersects(read));
+        if let Some(producer) = producer {
+            predecessors[reader].insert(producer);
+            for i in (0..producer).filter(|&i| modified(i).intersects(read)) {
+                predecessors[producer].insert(i);
+            }
+        }
+        for i in (reader + 1..units.len()).filter(|&i| modified(i).intersects(read)) {
+            predecessors[i].insert(reader);
+        }
+    }
+
+    for flag in [
+        Flags::CARRY,
+        Flags::PARITY,
+        Flags::AUXILIARY,
+        Flags::ZERO,
+        Flags::SIGN,
+        Flags::OVERFLOW,
+    ] {
+        let writers: Vec<_> = (0..units.len())
+            .filter(|&i| modified(i).intersects(flag))
+            .collect();
+        if let Some((&last, earlier)) = writers.split_last() {
+            predecessors[last].extend(earlier);
+        }
+    }
+}
+
+fn is_barrier(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Mov { .. }
+            | Instruction::CMov { .. }
+            | Instruction::SetCC { .. }
+            | Instruction::Push { .. }
+            | Instruction::Pop { .. }
+            | Instruction::Binary { .. }
+            | Instruction::Unary { .. }
+            | Instruction::Ternary { .. }
+            | Instruction::ThreeAddress { .. }
+    )
+}
+
+/// A rough estimate of the cycles until the result is available.
+fn latency(instruction: &Instruction) -> u32 {
+    match instruction.mnemonic() {
+        _ if instruction.reads_memory() => 4,
+        Mnemonic::IMul | Mnemonic::Mul | Mnemonic::UnaryIMul => 3,
+        Mnemonic::Div | Mnemonic::IDiv => 20,
+        Mnemonic::Popcnt | Mnemonic::Lzcnt | Mnemonic::Tzcnt | Mnemonic::Bsf | Mnemonic::Bsr => 3,
+        Mnemonic::Divss | Mnemonic::Divsd | Mnemonic::Divps | Mnemonic::Divpd => 14,
+        Mnemonic::Sqrtss | Mnemonic::Sqrtsd => 14,
+        Mnemonic::Addss | Mnemonic::Addsd | Mnemonic::Addps | Mnemonic::Addpd => 4,
+        Mnemonic::Subss | Mnemonic::Subsd | Mnemonic::Subps | Mnemonic::Subpd => 4,
+        Mnemonic::Mulss | Mnemonic::Mulsd | Mnemonic::Mulps | Mnemonic::Mulpd => 4,
+        Mnemonic::Cvtsi2ss | Mnemonic::Cvtsi2sd | Mnemonic::Cvtss2sd | Mnemonic::Cvtsd2ss => 4,
+        Mnemonic::Cvttss2si | Mnemonic::Cvttsd2si => 4,
+        _ => 1,
+    }
+}
@@ -0,0 +1,501 @@
+use super::{
+    error::X64WriterError,
+    io::{self, Write},
+    platform::Platform,
+    writer::AsmWriter,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::{
+    fmt::{Debug, Display},
+    str::FromStr,
+};
+
+/// The type of an ELF section, which tells the linker what it holds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SectionType {
+    Progbits,
+    /// Takes no space in the file, like `.bss`.
+    Nobits,
+    Note,
+    InitArray,
+    FiniArray,
+    PreinitArray,
+}
+impl Display for SectionType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Self::Progbits => "progbits",
+            Self::Nobits => "nobits",
+            Self::Note => "note",
+            Self::InitArray => "init_array",
+            Self::FiniArray => "fini_array",
+            Self::PreinitArray => "preinit_array",
+        };
+        write!(f, "@{name}")
+    }
+}
+
+/// The attributes of an ELF section, written as the flags of `.section`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SectionFlags {
+    allocatable: bool,
+    writable: bool,
+    executable: bool,
+    tls: bool,
+    large: bool,
+    mergeable: bool,
+    /// The size of the entities the linker may merge duplicates of.
+    entity_size: Option<u64>,
+    strings: bool,
+}
+impl SectionFlags {
+    /// No flags, for sections that are not loaded, such as debug information.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Loaded into memory when the program runs.
+    pub fn allocatable(self) -> Self {
+        Self {
+            allocatable: true,
+            ..self
+        }
+    }
+    pub fn writable(self) -> Self {
+        Self {
+            writable: true,
+            ..self
+        }
+    }
+    pub fn executable(self) -> Self {
+        Self {
+            executable: true,
+            ..self
+        }
+    }
+    /// The initial image of thread-local storage.
+    pub fn tls(self) -> Self {
+        Self { tls: true, ..self }
+    }
+    /// An x86-64 large section, which the medium code model places after the other data so
+    /// it can exceed 2 GiB.
+    pub fn large(self) -> Self {
+        Self {
+            large: true,
+            ..self
+        }
+    }
+    /// Lets the linker merge the identical constants of `entity_size` bytes the section
+    /// consists of, across object files. Fails for empty entities.
+    pub fn merge(self, entity_size: u64) -> Result<Self, X64WriterError> {
+        if entity_size == 0 {
+            return Err(X64WriterError::InvalidOperand(
+                "mergeable entities cannot be empty".into(),
+            ));
+        }
+        Ok(Self {
+            mergeable: true,
+            entity_size: Some(entity_size),
+            ..self
+        })
+    }
+    /// Lets the linker merge the identical zero-terminated strings of characters of
+    /// `char_size` bytes the section consists of, and strings that are the tail of others.
+    pub fn strings(self, char_size: u64) -> Result<Self, X64WriterError> {
+        Ok(Self {
+            strings: true,
+            ..self.merge(char_size)?
+        })
+    }
+
+    pub fn is_allocatable(self) -> bool {
+        self.allocatable
+    }
+    pub fn is_writable(self) -> bool {
+        self.writable
+    }
+    pub fn is_executable(self) -> bool {
+        self.executable
+    }
+    pub fn is_tls(self) -> bool {
+        self.tls
+    }
+    pub fn is_large(self) -> bool {
+        self.large
+    }
+    /// The size of the entities the linker may merge, `None` if it may not.
+    pub fn entity_size(self) -> Option<u64> {
+        self.entity_size
+    }
+    pub fn is_strings(self) -> bool {
+        self.strings
+    }
+}
+/// Parses the flags of `.section`. Fails for `M` and `S`, which need the entity size that
+/// [`SectionFlags::merge`] and [`SectionFlags::strings`] take, as the assembler drops
+/// them without one, and for other letters.
+impl FromStr for SectionFlags {
+    type Err = X64WriterError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .chars()
+            .try_fold(Self::new(), |flags, flag| match flag {
+                'a' => Ok(flags.allocatable()),
+                'w' => Ok(flags.writable()),
+                'x' => Ok(flags.executable()),
+                'T' => Ok(flags.tls()),
+                'l' => Ok(flags.large()),
+                'M' | 'S' => Err(X64WriterError::InvalidOperand(format!(
+                    "section flag {flag} needs an entity size"
+                ))),
+                flag => Err(X64WriterError::InvalidOperand(format!(
+                    "unknown section flag {flag}"
+                ))),
+            })
+    }
+}
+impl TryFrom<&str> for SectionFlags {
+    type Error = X64WriterError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl Display for SectionFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let flags = [
+            (self.allocatable, 'a'),
+            (self.writable, 'w'),
+            (self.executable, 'x'),
+            (self.mergeable, 'M'),
+            (self.strings, 'S'),
+            (self.tls, 'T'),
+            (self.large, 'l'),
+        ];
+        for (_, flag) in flags.into_iter().filter(|(set, _)| *set) {
+            write!(f, "{flag}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A section to emit code or data into. Displays as the operands of `.section`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Section {
+    Text,
+    Data,
+    Rodata,
+    Bss,
+    Named {
+        name: String,
+        flags: SectionFlags,
+        kind: SectionType,
+        /// The COMDAT group, of which the linker keeps one copy across object files.
+        group: Option<String>,
+    },
+}
+impl Section {
+    /// Takes the flags as [`SectionFlags`] or as the letters of `.section`, such as `"aw"`.
+    /// Fails for letters that are not flags.
+    pub fn named<F>(
+        name: impl Into<String>,
+        flags: F,
+        kind: SectionType,
+    ) -> Result<Self, X64WriterError>
+    where
+        F: TryInto<SectionFlags>,
+        X64WriterError: From<F::Error>,
+    {
+        Ok(Self::with_flags(name, flags.try_into()?, kind))
+    }
+    fn with_flags(name: impl Into<String>, flags: SectionFlags, kind: SectionType) -> Self {
+        Self::Named {
+            name: name.into(),
+            flags,
+            kind,
+            group: None,
+        }
+    }
+    /// Puts the section into the COMDAT group `group`. Fails for the standard sections.
+    pub fn comdat(self, group: impl Into<String>) -> Result<Self, X64WriterError> {
+        match self {
+            Self::Named {
+                name, flags, kind, ..
+            } => Ok(Self::Named {
+                name,
+                flags,
+                kind,
+                group: Some(group.into()),
+            }),
+            section => Err(X64WriterError::InvalidOperand(format!(
+                "{section} cannot be in a COMDAT group"
+            ))),
+        }
+    }
+    /// Data with relocations, which the dynamic linker makes read-only once it has
+    /// applied them.
+    pub fn data_rel_ro() -> Self {
+        let flags = SectionFlags::new().allocatable().writable();
+        Self::with_flags(".data.rel.ro", flags, SectionType::Progbits)
+    }
+    /// The section GCC puts zero-terminated string literals of characters of `char_size`
+    /// bytes into, whose duplicates the linker merges.
+    pub fn strings(char_size: u64) -> Result<Self, X64WriterError> {
+        let flags = SectionFlags::new().allocatable().strings(char_size)?;
+        let name = format!(".rodata.str{char_size}.{char_size}");
+        Ok(Self::with_flags(name, flags, SectionType::Progbits))
+    }
+    /// The section GCC puts constants of `size` bytes into, whose duplicates the linker
+    /// merges.
+    pub fn constants(size: u64) -> Result<Self, X64WriterError> {
+        let flags = SectionFlags::new().allocatable().merge(size)?;
+        Ok(Self::with_flags(
+            format!(".rodata.cst{size}"),
+            flags,
+            SectionType::Progbits,
+        ))
+    }
+    /// The section of its own the symbol `symbol` gets in the section, like with GCC's
+    /// `-ffunction-sections` and `-fdata-sections`, so the linker can discard it if unused.
+    pub fn for_symbol(self, symbol: &str) -> Self {
+        let name = format!("{}.{symbol}", self.name());
+        let (data, code) = (
+            SectionFlags::new().allocatable().writable(),
+            SectionFlags::new().allocatable().executable(),
+        );
+        match self {
+            Self::Text => Self::with_flags(name, code, SectionType::Progbits),
+            Self::Data => Self::with_flags(name, data, SectionType::Progbits),
+            Self::Rodata => Self::with_flags(
+                name,
+                SectionFlags::new().allocatable(),
+                SectionType::Progbits,
+            ),
+            Self::Bss => Self::with_flags(name, data, SectionType::Nobits),
+            Self::Named {
+                flags, kind, group, ..
+            } => Self::Named {
+                name,
+                flags,
+                kind,
+                group,
+            },
+        }
+    }
+    /// The `.gnu.linkonce` section of the symbol `symbol` in the section, of which the
+    /// linker keeps one copy across object files like a COMDAT group, for linkers that
+    /// predate them. Fails for named sections, which have none.
+    pub fn linkonce(self, symbol: &str) -> Result<Self, X64WriterError> {
+        let allocatable = SectionFlags::new().allocatable();
+        let (letter, flags, kind) = match self {
+            Self::Text => ('t', allocatable.executable(), SectionType::Progbits),
+            Self::Data => ('d', allocatable.writable(), SectionType::Progbits),
+            Self::Rodata => ('r', allocatable, SectionType::Progbits),
+            Self::Bss => ('b', allocatable.writable(), SectionType::Nobits),
+            section => {
+                return Err(X64WriterError::InvalidOperand(format!(
+                    "{section} has no .gnu.linkonce section"
+                )));
+            }
+        };
+        let name = format!(".gnu.linkonce.{letter}.{symbol}");
+        Ok(Self::with_flags(name, flags, kind))
+    }
+
+    /// The x86-64 ELF large section of the data, which the medium code model places after
+    /// the other data so it can exceed 2 GiB, such as `.ldata` for `.data`. Mach-O and COFF
+    /// only keep the name.
+    pub fn large(self) -> Self {
+        let data = SectionFlags::new().allocatable().writable().large();
+        match self {
+            Self::Data => Self::with_flags(".ldata", data, SectionType::Progbits),
+            Self::Rodata => {
+                let flags = SectionFlags::new().allocatable().large();
+                Self::with_flags(".lrodata", flags, SectionType::Progbits)
+            }
+            Self::Bss => Self::with_flags(".lbss", data, SectionType::Nobits),
+            Self::Named {
+                name,
+                flags,
+                kind,
+                group,
+            } if !flags.is_executable() => Self::Named {
+                name,
+                flags: flags.large(),
+                kind,
+                group,
+            },
+            section => panic!("{section} holds code, which cannot be in a large section"),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Text => ".text",
+            Self::Data => ".data",
+            Self::Rodata => ".rodata",
+            Self::Bss => ".bss",
+            Self::Named { name, .. } => name,
+        }
+    }
+    pub fn is_executable(&self) -> bool {
+        match self {
+            Self::Text => true,
+            Self::Named { flags, .. } => flags.is_executable(),
+            _ => false,
+        }
+    }
+    pub fn is_writable(&self) -> bool {
+        match self {
+            Self::Data | Self::Bss => true,
+            Self::Named { flags, .. } => flags.is_writable(),
+            _ => false,
+        }
+    }
+    /// The directive switching to the section on `platform`. Mach-O sections named without
+    /// a segment go into `__TEXT` if executable and into `__DATA` otherwise. Panics for
+    /// COMDAT groups on Mach-O, which has none.
+    /// Mergeable strings and constants go into the literal sections of Mach-O, and
+    /// `.data.rel.ro` into `__DATA,__const`.
+    pub fn directive(&self, platform: Platform) -> String {
+        match (platform, self) {
+            (Platform::Linux, Self::Text | Self::Data | Self::Bss) => self.name().to_string(),
+            (Platform::Linux, section) => format!(".section {section}"),
+            (Platform::MacOs, Self::Text) => {
+                ".section __TEXT,__text,regular,pure_instructions".to_string()
+            }
+            (Platform::MacOs, Self::Data) => ".section __DATA,__data".to_string(),
+            (Platform::MacOs, Self::Rodata) => ".section __TEXT,__const".to_string(),
+            // Zero-fill sections cannot be created with `.section`.
+            (Platform::MacOs, Self::Bss) => ".bss".to_string(),
+            (Platform::MacOs, Self::Named { group: Some(_), .. }) => {
+                panic!("{self} cannot be in a COMDAT group on Mach-O")
+            }
+            (Platform::MacOs, Self::Named { flags, .. }) if flags.entity_size().is_some() => {
+                match (flags.is_strings(), flags.entity_size()) {
+                    (true, Some(1)) => ".section __TEXT,__cstring,cstring_literals".to_string(),
+                    (false, Some(size @ (4 | 8 | 16))) => {
+                        format!(".section __TEXT,__literal{size},{size}byte_literals")
+                    }
+                    _ => ".section __TEXT,__const".to_string(),
+                }
+            }
+            (Platform::MacOs, Self::Named { name, .. }) if name.starts_with(".data.rel.ro") => {
+                ".section __DATA,__const".to_string()
+            }
+            (Platform::MacOs, Self::Named { name, .. }) => {
+                let section = match name.split_once(',') {
+                    Some(_) => name.clone(),
+                    None if self.is_executable() => {
+                        let name = mach_o_section_name(name);
+                        format!("__TEXT,{name},regular,pure_instructions")
+                    }
+                    None => format!("__DATA,{}", mach_o_section_name(name)),
+                };
+                format!(".section {section}")
+            }
+            (Platform::Windows, Self::Rodata) => ".section .rdata,\"dr\"".to_string(),
+            (Platform::Windows, Self::Named { name, group, .. }) => {
+                let flags = if self.is_executable() {
+                    "xr"
+                } else if self.is_nobits() {
+                    "bw"
+                } else if self.is_writable() {
+                    "dw"
+                } else {
+                    "dr"
+                };
+                match group {
+                    Some(group) => format!(".section {name},\"{flags}\",discard,{group}"),
+                    None => format!(".section {name},\"{flags}\""),
+                }
+            }
+            (Platform::Windows, section) => section.name().to_string(),
+        }
+    }
+    /// Whether the section only reserves space, so anything but zeros cannot go there.
+    pub fn is_nobits(&self) -> bool {
+        match self {
+            Self::Bss => true,
+            Self::Named { kind, .. } => *kind == SectionType::Nobits,
+            _ => false,
+        }
+    }
+}
+impl Display for Section {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Self::Named {
+            name,
+            flags,
+            kind,
+            group,
+        } = self
+        else {
+            return write!(f, "{}", self.name());
+        };
+        let comdat = if group.is_some() { "G" } else { "" };
+        write!(f, "{name},\"{flags}{comdat}\",{kind}")?;
+        if let Some(size) = flags.entity_size() {
+            write!(f, ",{size}")?;
+        }
+        match group {
+            Some(group) => write!(f, ",{group},comdat"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Switches to `section`, replacing the current one.
+    pub fn begin_section(&mut self, section: Section) -> io::Result<()> {
+        self.directive(section.directive(self.platform()))?;
+        *self.sections().last_mut().unwrap() = section;
+        Ok(())
+    }
+    /// Switches to `section` until [`Self::pop_section`] returns to the current one. Off
+    /// Linux, both switch with the directive of the section, as not every assembler has
+    /// `.pushsection`.
+    pub fn push_section(&mut self, section: Section) -> io::Result<()> {
+        match self.platform() {
+            Platform::Linux => self.directive(format_args!(".pushsection {section}"))?,
+            platform => self.directive(section.directive(platform))?,
+        }
+        self.sections().push(section);
+        Ok(())
+    }
+    /// Returns to the section before the last [`Self::push_section`]. Fails if there was none.
+    pub fn pop_section(&mut self) -> io::Result<()> {
+        let sections = self.sections();
+        if sections.len() < 2 {
+            let error = X64WriterError::OutOfOrder("no section was pushed".into());
+            return Err(error.into());
+        }
+        sections.pop();
+        match self.platform() {
+            Platform::Linux => self.directive(".popsection"),
+            platform => {
+                let directive = self.current_section().directive(platform);
+                self.directive(directive)
+            }
+        }
+    }
+    /// Panics unless the current section holds data rather than code.
+    pub fn assert_data_section(&self) {
+        let section = self.current_section();
+        assert!(
+            !section.is_executable(),
+            "expected a data section, but the current section is {}",
+            section.name()
+        );
+    }
+}
+
+/// The section part of a Mach-O section name, which starts with two underscores by
+/// convention and has at most 16 characters.
+fn mach_o_section_name(name: &str) -> String {
+    let name = format!("__{}", name.trim_start_matches(['.', '_']));
+    name.chars().take(16).collect()
+}
@@ -0,0 +1,98 @@
+use super::{
+    builder::InstructionBuilder,
+    convention::CallingConvention,
+    error::X64WriterError,
+    function::{Function, FunctionBuilder},
+    label::Label,
+    register::RegisterName,
+};
+use alloc::vec::Vec;
+
+/// Builds a stub that can be called with the System V ABI, such as through the PLT, and
+/// calls a function of another [`CallingConvention`].
+///
+/// The stub moves the arguments into the registers the target expects, saves the System V
+/// callee-saved registers the target does not preserve, and moves the results back into
+/// `%rax` and `%rdx`. Only register arguments are supported.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CallShimBuilder<'a> {
+    name: Label<'a>,
+    target: Label<'a>,
+    global: bool,
+    convention: CallingConvention,
+}
+impl<'a> CallShimBuilder<'a> {
+    pub fn new(
+        name: impl Into<Label<'a>>,
+        target: impl Into<Label<'a>>,
+        convention: CallingConvention,
+    ) -> Self {
+        let sysv = CallingConvention::sysv();
+        assert!(
+            convention.arguments.len() <= sysv.arguments.len(),
+            "only register arguments are supported"
+        );
+        assert!(
+            convention.returns.len() <= sysv.returns.len(),
+            "only two results are supported"
+        );
+        assert!(
+            convention.stack_alignment <= sysv.stack_alignment,
+            "the stack is only aligned as much as System V guarantees"
+        );
+        let used = convention.arguments.iter().chain(&convention.returns);
+        assert!(
+            used.copied().all(|name| name != RegisterName::SP),
+            "the stack pointer cannot carry values"
+        );
+
+        Self {
+            name: name.into(),
+            target: target.into(),
+            global: false,
+            convention,
+        }
+    }
+
+    pub fn set_global(&mut self, global: bool) {
+        self.global = global;
+    }
+
+    pub fn build(&self) -> Function<'a> {
+        let sysv = CallingConvention::sysv();
+        let mut builder = FunctionBuilder::new(self.name);
+        builder.set_global(self.global);
+        builder.set_calling_convention(&sysv);
+        for name in &sysv.arguments[..self.convention.arguments.len()] {
+            builder.declare_argument(name.quad());
+        }
+        self.emit(&mut builder)
+            .expect("building into a function does not fail");
+        builder
+            .end_function()
+            .expect("callee-saved registers are pushed before they are written")
+    }
+    /// Emits the body of the stub, without the label of its name.
+    pub fn emit(&self, builder: &mut impl InstructionBuilder<'a>) -> Result<(), X64WriterError> {
+        let sysv = CallingConvention::sysv();
+        let convention = &self.convention;
+        let saved = sysv.callee_saved.difference(convention.callee_saved);
+
+        // The stub is entered through System V, so the frame is aligned from its guarantee,
+        // while the target gets its shadow space.
+        let incoming = CallingConvention {
+            stack_alignment: sysv.stack_alignment,
+            ..convention.clone()
+        };
+        let frame = builder.emit_prologue(&incoming, saved, 0)?;
+        let arguments = &sysv.arguments[..convention.arguments.len()];
+        builder.emit_call_with(convention, self.target, arguments)?;
+        let returns = sysv
+            .returns
+            .iter()
+            .copied()
+            .zip(convention.returns.iter().copied());
+        builder.emit_parallel_move(&returns.collect::<Vec<_>>())?;
+        builder.emit_epilogue(&frame)
+    }
+}
@@ -0,0 +1,119 @@
+use super::{
+    hash::StableHasher,
+    io::{self, Write},
+    module::{Module, TargetClones},
+    writer::AsmWriter,
+};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{error::Error, fmt::Display, hash::Hasher, str::FromStr};
+
+/// The hashes of the functions of a module, to find what changed in a later version of it.
+///
+/// Snapshots are meant to be stored between builds, one `hash name` pair per line.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ModuleSnapshot {
+    hashes: BTreeMap<String, u64>,
+}
+impl ModuleSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.hashes.get(name).copied()
+    }
+    pub fn insert(&mut self, name: impl Into<String>, hash: u64) {
+        self.hashes.insert(name.into(), hash);
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.hashes
+            .iter()
+            .map(|(name, &hash)| (name.as_str(), hash))
+    }
+}
+impl Display for ModuleSnapshot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (name, hash) in &self.hashes {
+            writeln!(f, "{hash:016x} {name}")?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for ModuleSnapshot {
+    type Err = ParseSnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut snapshot = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = ParseSnapshotError { line: i + 1 };
+            let (hash, name) = line.split_once(' ').ok_or(error)?;
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| error)?;
+            snapshot.insert(name, hash);
+        }
+        Ok(snapshot)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParseSnapshotError {
+    pub line: usize,
+}
+impl Display for ParseSnapshotError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "malformed module snapshot in line {}", self.line)
+    }
+}
+impl Error for ParseSnapshotError {}
+
+impl Module<'_> {
+    /// The hashes of all functions and target clone groups, keyed by symbol name.
+    pub fn snapshot(&self) -> ModuleSnapshot {
+        let mut snapshot = ModuleSnapshot::new();
+        for function in self.functions() {
+            snapshot.insert(function.name.label, function.content_hash());
+        }
+        for clones in self.target_clones() {
+            snapshot.insert(clones.name().label, target_clones_hash(clones));
+        }
+        snapshot
+    }
+
+    /// Writes only the functions and target clone groups that are new or differ from
+    /// `previous`, returning their names.
+    pub fn write_changed<O: Write>(
+        &self,
+        writer: &mut AsmWriter<O>,
+        previous: &ModuleSnapshot,
+    ) -> io::Result<Vec<String>> {
+        let mut changed = Vec::new();
+        for function in self.functions() {
+            let name = function.name.label;
+            if previous.get(name) != Some(function.content_hash()) {
+                writer.emit_function(function)?;
+                changed.push(name.to_string());
+            }
+        }
+        for clones in self.target_clones() {
+            let name = clones.name().label;
+            if previous.get(name) != Some(target_clones_hash(clones)) {
+                clones.write(writer)?;
+                changed.push(name.to_string());
+            }
+        }
+        Ok(changed)
+    }
+}
+
+fn target_clones_hash(clones: &TargetClones) -> u64 {
+    let mut hasher = StableHasher::new();
+    for function in clones.clones.iter().chain([&clones.default]) {
+        hasher.write(&function.content_hash().to_le_bytes());
+    }
+    hasher.finish()
+}
@@ -0,0 +1,170 @@
+use super::{function::Function, module::Module};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::cmp::Reverse;
+
+/// How [`Module::split`] deals with a function that is not global but referenced from
+/// another function.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PrivateSymbols {
+    /// Makes the function global if the reference crosses into another part.
+    Promote,
+    /// Keeps the function in the same part as everything referencing it.
+    CoLocate,
+}
+
+/// A module split into parts to be written to separate files and assembled in parallel.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleSet<'a> {
+    parts: Vec<Module<'a>>,
+    promoted: Vec<String>,
+}
+impl<'a> ModuleSet<'a> {
+    pub fn parts(&self) -> &[Module<'a>] {
+        &self.parts
+    }
+    pub fn into_parts(self) -> Vec<Module<'a>> {
+        self.parts
+    }
+    /// The functions made global because they are referenced from another part.
+    pub fn promoted(&self) -> &[String] {
+        &self.promoted
+    }
+}
+
+/// A function or target clone group, which is never split up.
+struct Unit<'a> {
+    name: &'a str,
+    global: bool,
+    size: usize,
+    references: BTreeSet<&'a str>,
+}
+
+impl<'a> Module<'a> {
+    /// Distributes the functions and target clone groups over `parts` modules, balancing
+    /// their estimated code size. Parts may end up empty if there are too few functions.
+    pub fn split(&self, parts: usize, private: PrivateSymbols) -> ModuleSet<'a> {
+        assert!(parts > 0, "cannot split a module into zero parts");
+
+        let mut units: Vec<_> = self
+            .functions()
+            .iter()
+            .map(|function| Unit::new(function.name.label, function.global, vec![function]))
+            .collect();
+        for clones in self.target_clones() {
+            let bodies = clones.clones.iter().chain([&clones.default]).collect();
+            units.push(Unit::new(
+                clones.name().label,
+                clones.default.global,
+                bodies,
+            ));
+        }
+        let owners: BTreeMap<_, _> = units.iter().enumerate().map(|(i, u)| (u.name, i)).collect();
+        let private_references = |i: usize| {
+            units[i]
+                .references
+                .iter()
+                .filter_map(|r| owners.get(r).copied())
+                .filter(|&target| target != i && !units[target].global)
+                .collect::<Vec<_>>()
+        };
+
+        // Units that have to end up in the same part, keyed by a representative.
+        let mut representative: Vec<_> = (0..units.len()).collect();
+        if private == PrivateSymbols::CoLocate {
+            for i in 0..units.len() {
+                for target in private_references(i) {
+                    let a = find(&mut representative, i);
+                    let b = find(&mut representative, target);
+                    representative[a.max(b)] = a.min(b);
+                }
+            }
+        }
+        let mut groups: BTreeMap<usize, (usize, Vec<usize>)> = BTreeMap::new();
+        for (i, unit) in units.iter().enumerate() {
+            let group = groups.entry(find(&mut representative, i)).or_default();
+            group.0 += unit.size;
+            group.1.push(i);
+        }
+
+        // Largest groups first, each into the part with the least code so far.
+        let mut groups: Vec<_> = groups.into_values().collect();
+        groups.sort_by_key(|&(size, ref members)| (Reverse(size), members[0]));
+        let mut sizes = vec![0; parts];
+        let mut part_of = vec![0; units.len()];
+        for (size, members) in groups {
+            let part = (0..parts).min_by_key(|&p| sizes[p]).unwrap();
+            sizes[part] += size;
+            for member in members {
+                part_of[member] = part;
+            }
+        }
+
+        let mut promoted = BTreeSet::new();
+        for i in 0..units.len() {
+            for target in private_references(i) {
+                if part_of[target] != part_of[i] {
+                    promoted.insert(target);
+                }
+            }
+        }
+
+        let mut modules = vec![Module::new(); parts];
+        for function in self.functions() {
+            let i = owners[function.name.label];
+            let mut function = function.clone();
+            function.global |= promoted.contains(&i);
+            modules[part_of[i]].add_function(function);
+        }
+        for clones in self.target_clones() {
+            let i = owners[clones.name().label];
+            let mut default = clones.default.clone();
+            default.global |= promoted.contains(&i);
+            modules[part_of[i]].add_target_clones(default, clones.clones.clone());
+        }
+
+        ModuleSet {
+            parts: modules,
+            promoted: promoted
+                .into_iter()
+                .map(|i| units[i].name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl<'a> Unit<'a> {
+    fn new(name: &'a str, global: bool, bodies: Vec<&Function<'a>>) -> Self {
+        let mut size = 0;
+        let mut references = BTreeSet::new();
+        for body in bodies {
+            size += body.report(name).estimated_size;
+            for instruction in body.instructions() {
+                let symbols = instruction
+                    .operands()
+                    .into_iter()
+                    .filter_map(|a| a.symbol());
+                references.extend(symbols.map(|label| label.label));
+            }
+        }
+        Self {
+            name,
+            global,
+            size,
+            references,
+        }
+    }
+}
+
+fn find(representative: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while representative[root] != root {
+        root = representative[root];
+    }
+    representative[i] = root;
+    root
+}
@@ -0,0 +1,216 @@
+use super::{
+    io::{self, Write},
+    label::Label,
+    register::{Register, RegisterName},
+    section::{Section, SectionType},
+    writer::AsmWriter,
+};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// The section LLVM emits its stack maps to, which garbage collectors look up by name.
+pub const STACK_MAP_SECTION: &str = ".llvm_stackmaps";
+const STACK_MAP_VERSION: u8 = 3;
+
+/// Where a value, typically a GC reference, is at a safepoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    Register(Register),
+    /// In the `size` bytes at `offset` from `base`, such as a spill slot.
+    Indirect {
+        base: RegisterName,
+        offset: i32,
+        size: u16,
+    },
+    /// The address `offset` from `base` itself, such as an object on the stack.
+    Direct {
+        base: RegisterName,
+        offset: i32,
+    },
+    Constant(i64),
+}
+impl Location {
+    /// The 8-byte stack slot `offset` bytes above `%rsp` after the call returned.
+    pub fn stack_slot(offset: i32) -> Self {
+        Self::Indirect {
+            base: RegisterName::SP,
+            offset,
+            size: 8,
+        }
+    }
+
+    pub(crate) fn kind(self) -> u8 {
+        match self {
+            Self::Register(_) => 1,
+            Self::Direct { .. } => 2,
+            Self::Indirect { .. } => 3,
+            Self::Constant(value) if i32::try_from(value).is_ok() => 4,
+            Self::Constant(_) => 5,
+        }
+    }
+    pub(crate) fn size(self) -> u16 {
+        match self {
+            Self::Register(register) => register.1.in_bytes() as u16,
+            Self::Indirect { size, .. } => size,
+            Self::Direct { .. } | Self::Constant(_) => 8,
+        }
+    }
+    pub(crate) fn register(self) -> Option<RegisterName> {
+        match self {
+            Self::Register(register) => Some(register.0),
+            Self::Indirect { base, .. } | Self::Direct { base, .. } => Some(base),
+            Self::Constant(_) => None,
+        }
+    }
+}
+
+/// A call at which the garbage collector may run, and where the references it has to
+/// know about are while it does.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Safepoint<'a> {
+    /// Identifies the safepoint to the runtime, LLVM's patch point ID.
+    pub id: u64,
+    /// The label right after the call, at its return address.
+    pub return_address: Label<'a>,
+    pub locations: Vec<Location>,
+}
+impl<'a> Safepoint<'a> {
+    pub fn new(id: u64, return_address: impl Into<Label<'a>>) -> Self {
+        Self {
+            id,
+            return_address: return_address.into(),
+            locations: Vec::new(),
+        }
+    }
+    pub fn location(mut self, location: Location) -> Self {
+        self.locations.push(location);
+        self
+    }
+}
+
+/// The safepoints of one function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionStackMap<'a> {
+    pub function: Label<'a>,
+    /// The size of the fixed stack frame, `None` if it changes at run time.
+    pub stack_size: Option<u64>,
+    pub safepoints: Vec<Safepoint<'a>>,
+}
+
+/// Stack maps in LLVM's version 3 format, as emitted for statepoints and patch points.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StackMaps<'a> {
+    pub functions: Vec<FunctionStackMap<'a>>,
+}
+impl<'a> StackMaps<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_function(&mut self, function: impl Into<Label<'a>>, stack_size: Option<u64>) {
+        self.functions.push(FunctionStackMap {
+            function: function.into(),
+            stack_size,
+            safepoints: Vec::new(),
+        });
+    }
+    /// Adds a safepoint to the function added last.
+    pub fn add_safepoint(&mut self, safepoint: Safepoint<'a>) {
+        let function = self.functions.last_mut().expect("no function was added");
+        function.safepoints.push(safepoint);
+    }
+
+    /// Every label the stack maps refer to.
+    pub fn labels(&self) -> impl Iterator<Item = Label<'a>> + '_ {
+        self.functions.iter().flat_map(|function| {
+            let safepoints = function.safepoints.iter();
+            [function.function]
+                .into_iter()
+                .chain(safepoints.map(|safepoint| safepoint.return_address))
+        })
+    }
+    /// The constants too large for a location, in the order of their indices.
+    pub fn constants(&self) -> Vec<i64> {
+        let mut constants = Vec::new();
+        let safepoints = self.functions.iter().flat_map(|f| &f.safepoints);
+        for location in safepoints.flat_map(|safepoint| &safepoint.locations) {
+            if let Location::Constant(value) = *location {
+                if i32::try_from(value).is_err() && !constants.contains(&value) {
+                    constants.push(value);
+                }
+            }
+        }
+        constants
+    }
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits the stack maps to [`STACK_MAP_SECTION`], after the code they describe.
+    pub fn emit_stack_maps(&mut self, maps: &StackMaps) -> io::Result<()> {
+        let names = self.rename_local_labels(maps.labels());
+        let name = |label: Label| -> String {
+            let renamed = names.as_ref().and_then(|names| names.get(label.label));
+            renamed.map_or(label.label, String::as_str).to_string()
+        };
+        let constants = maps.constants();
+        let records: usize = maps.functions.iter().map(|f| f.safepoints.len()).sum();
+
+        self.push_section(Section::named(
+            STACK_MAP_SECTION,
+            "a",
+            SectionType::Progbits,
+        )?)?;
+        self.emit_balign(8)?;
+        self.emit_byte(STACK_MAP_VERSION)?;
+        self.emit_byte(0u8)?;
+        self.emit_word(0u16)?;
+        self.emit_long(maps.functions.len() as u32)?;
+        self.emit_long(constants.len() as u32)?;
+        self.emit_long(records as u32)?;
+        for function in &maps.functions {
+            self.emit_quad(name(function.function).as_str())?;
+            self.emit_quad(function.stack_size.unwrap_or(u64::MAX))?;
+            self.emit_quad(function.safepoints.len() as u64)?;
+        }
+        for &constant in &constants {
+            self.emit_quad(constant)?;
+        }
+
+        for function in &maps.functions {
+            let start = name(function.function);
+            for safepoint in &function.safepoints {
+                self.emit_quad(safepoint.id)?;
+                let offset = name(safepoint.return_address);
+                self.directive(format_args!(".long {offset}-{start}"))?;
+                self.emit_word(0u16)?;
+                self.emit_word(safepoint.locations.len() as u16)?;
+                for &location in &safepoint.locations {
+                    self.emit_byte(location.kind())?;
+                    self.emit_byte(0u8)?;
+                    self.emit_word(location.size())?;
+                    let register = location.register().map_or(0, RegisterName::dwarf_number);
+                    self.emit_word(u16::from(register))?;
+                    self.emit_word(0u16)?;
+                    let offset = match location {
+                        Location::Register(_) => 0,
+                        Location::Indirect { offset, .. } | Location::Direct { offset, .. } => {
+                            offset
+                        }
+                        Location::Constant(value) => match i32::try_from(value) {
+                            Ok(value) => value,
+                            Err(_) => constants.iter().position(|&c| c == value).unwrap() as i32,
+                        },
+                    };
+                    self.emit_long(offset)?;
+                }
+                // No live-out registers follow the padding.
+                self.emit_balign(8)?;
+                self.emit_word(0u16)?;
+                self.emit_word(0u16)?;
+                self.emit_balign(8)?;
+            }
+        }
+        self.pop_section()
+    }
+}
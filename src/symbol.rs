@@ -0,0 +1,252 @@
+use super::{
+    error::X64WriterError,
+    io::{self, Write},
+    label::{Label, RelocationModifier},
+    platform::Platform,
+    register::RegisterName,
+    writer::{AsmWriter, Condition},
+};
+use alloc::{
+    collections::BTreeSet,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// Whether a symbol can be referenced from other object files.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SymbolBinding {
+    /// Only visible within the object file, the default for labels.
+    #[default]
+    Local,
+    Global,
+    /// Global, but a global definition elsewhere takes precedence, and an undefined weak
+    /// symbol is zero rather than an error.
+    Weak,
+}
+impl SymbolBinding {
+    /// The directive giving a symbol the binding.
+    pub fn directive(self) -> &'static str {
+        match self {
+            Self::Local => ".local",
+            Self::Global => ".global",
+            Self::Weak => ".weak",
+        }
+    }
+}
+
+/// Which other components a global symbol is visible to once linked.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SymbolVisibility {
+    /// As its binding says, and preemptible by other shared objects.
+    #[default]
+    Default,
+    /// Only within the linked component, with no further guarantees.
+    Internal,
+    /// Only within the linked component.
+    Hidden,
+    /// Visible to other components, but references within this one are not preempted.
+    Protected,
+}
+impl SymbolVisibility {
+    /// The directive giving a symbol the visibility, `None` for the default.
+    pub fn directive(self) -> Option<&'static str> {
+        match self {
+            Self::Default => None,
+            Self::Internal => Some(".internal"),
+            Self::Hidden => Some(".hidden"),
+            Self::Protected => Some(".protected"),
+        }
+    }
+}
+
+/// What a call to a weak symbol does if the symbol is undefined, which makes its address
+/// zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WeakCallGuard<'a> {
+    /// Calls unconditionally, for symbols known to be defined whenever the call runs.
+    #[default]
+    Unguarded,
+    /// Skips the call.
+    Skip,
+    /// Jumps to the label instead of calling, such as to a fallback.
+    Fallback(Label<'a>),
+}
+
+/// The symbols an [`AsmWriter`] has defined and referenced.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct SymbolTable {
+    defined: BTreeSet<String>,
+    referenced: BTreeSet<String>,
+    /// The symbols declared weak.
+    weak: BTreeSet<String>,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// The symbols referenced so far but not defined, which the linker has to find in other
+    /// object files or libraries. Assembler-local labels are left out, and the names are
+    /// the ones given to the writer, see [`Self::symbol_name`] for those in the output.
+    pub fn external_symbols(&self) -> Vec<&str> {
+        let symbols = self.symbol_table();
+        let undefined = symbols.referenced.difference(&symbols.defined);
+        undefined
+            .map(String::as_str)
+            .filter(|name| {
+                !name.starts_with(".L") && !name.starts_with(|c: char| c.is_ascii_digit())
+            })
+            .collect()
+    }
+    pub(crate) fn define_symbol(&mut self, name: &str) {
+        let symbols = self.symbol_table_mut();
+        if !symbols.defined.contains(name) {
+            symbols.defined.insert(name.to_string());
+        }
+    }
+    pub(crate) fn reference_symbol(&mut self, name: &str) {
+        let symbols = self.symbol_table_mut();
+        if !symbols.referenced.contains(name) {
+            symbols.referenced.insert(name.to_string());
+        }
+    }
+
+    /// Gives `label`, defined in this file, the binding, which has to happen before the end
+    /// of the file. Local binding is the default, so nothing is emitted for it.
+    pub fn declare_binding<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        binding: SymbolBinding,
+    ) -> io::Result<()> {
+        let label = label.into();
+        match (binding, self.platform()) {
+            (SymbolBinding::Local, _) => Ok(()),
+            (SymbolBinding::Global, _) => self.declare_global(label),
+            // Weak definitions are global ones on Mach-O, and `.weak` is for references.
+            (SymbolBinding::Weak, Platform::MacOs) => {
+                self.declare_global(label)?;
+                let label = self.symbol_name(label.label);
+                self.directive(format_args!(".weak_definition {label}"))
+            }
+            (SymbolBinding::Weak, _) => self.declare_weak(label),
+        }
+    }
+    /// Makes `label` weak. On Mach-O, this is a weak reference to a symbol defined
+    /// elsewhere, and a definition is made weak with [`Self::declare_binding`].
+    pub fn declare_weak<'a>(&mut self, label: impl Into<Label<'a>>) -> io::Result<()> {
+        let label = label.into().label;
+        self.symbol_table_mut().weak.insert(label.to_string());
+        let label = self.symbol_name(label);
+        match self.platform() {
+            Platform::MacOs => self.directive(format_args!(".weak_reference {label}")),
+            Platform::Linux | Platform::Windows => self.directive(format_args!(".weak {label}")),
+        }
+    }
+    /// Makes `label` local explicitly, such as before [`Self::declare_common`]. Only ELF
+    /// has a directive for it, symbols are local unless declared otherwise elsewhere.
+    pub fn declare_local<'a>(&mut self, label: impl Into<Label<'a>>) -> io::Result<()> {
+        if self.platform() != Platform::Linux {
+            return Ok(());
+        }
+        let label = label.into().label;
+        self.directive(format_args!(".local {label}"))
+    }
+    /// Calls `target` through a weak reference, declaring it weak the first time, so that
+    /// the program links without a definition. A guard loads the address of `target` from
+    /// the GOT into `%r11`, clobbering it and the flags, and calls through it if it is not
+    /// zero. COFF has no GOT, so guards panic on Windows.
+    pub fn emit_weak_call<'a>(
+        &mut self,
+        target: impl Into<Label<'a>>,
+        guard: WeakCallGuard<'a>,
+    ) -> Result<(), X64WriterError> {
+        let target = target.into();
+        if !self.symbol_table().weak.contains(target.label) {
+            self.declare_weak(target)?;
+        }
+        let fallback = match guard {
+            WeakCallGuard::Unguarded => return self.build_call(target),
+            WeakCallGuard::Skip => None,
+            WeakCallGuard::Fallback(label) => Some(label),
+        };
+        assert!(
+            self.platform() != Platform::Windows,
+            "cannot guard the weak call of {target} without a GOT"
+        );
+
+        let address = RegisterName::R11.quad();
+        let entry = target.with_modifier(RelocationModifier::GotPcRel);
+        self.build_mov(address, entry.rip())?;
+        self.build_test(address, address)?;
+        match fallback {
+            Some(fallback) => {
+                self.build_cjmp(Condition::Zero, fallback)?;
+                self.build_call(address)
+            }
+            None => {
+                let skip = self.label_allocator().allocate("weak_skip");
+                self.build_cjmp(Condition::Zero, &skip)?;
+                self.build_call(address)?;
+                Ok(self.emit_label(&skip)?)
+            }
+        }
+    }
+    /// Gives `label` the visibility. Mach-O only has hidden symbols, which internal ones
+    /// become, and COFF only exports symbols explicitly, so nothing is emitted for it.
+    pub fn declare_visibility<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        visibility: SymbolVisibility,
+    ) -> io::Result<()> {
+        let label = self.symbol_name(label.into().label);
+        let directive = match self.platform() {
+            Platform::Linux => visibility.directive(),
+            Platform::MacOs => match visibility {
+                SymbolVisibility::Internal | SymbolVisibility::Hidden => Some(".private_extern"),
+                SymbolVisibility::Default | SymbolVisibility::Protected => None,
+            },
+            Platform::Windows => None,
+        };
+        match directive {
+            Some(directive) => self.directive(format_args!("{directive} {label}")),
+            None => Ok(()),
+        }
+    }
+    /// Declares a common symbol of `size` zero bytes, which the linker merges with the
+    /// common symbols of the same name in other object files and allocates in `.bss`.
+    pub fn declare_common<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        size: u64,
+        align: u64,
+    ) -> io::Result<()> {
+        self.check_alignment(align)?;
+        let label = label.into().label;
+        self.define_symbol(label);
+        let label = self.symbol_name(label);
+        let align = match self.platform().common_alignment_in_bytes() {
+            true => align,
+            false => u64::from(align.trailing_zeros()),
+        };
+        self.directive(format_args!(".comm {label}, {size}, {align}"))
+    }
+    /// Allocates `size` zero bytes in `.bss` for the local symbol `label`. On ELF, this is
+    /// [`Self::declare_common`] after `.local`, as `.lcomm` takes no alignment there.
+    pub fn declare_local_common<'a>(
+        &mut self,
+        label: impl Into<Label<'a>>,
+        size: u64,
+        align: u64,
+    ) -> io::Result<()> {
+        self.check_alignment(align)?;
+        let label = label.into();
+        let align = match self.platform() {
+            Platform::Linux => {
+                self.declare_local(label)?;
+                return self.declare_common(label, size, align);
+            }
+            Platform::MacOs => u64::from(align.trailing_zeros()),
+            Platform::Windows => align,
+        };
+        self.define_symbol(label.label);
+        let label = self.symbol_name(label.label);
+        self.directive(format_args!(".lcomm {label}, {size}, {align}"))
+    }
+}
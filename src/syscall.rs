@@ -0,0 +1,117 @@
+use super::{
+    args::{ArgSize, Memory, Segment},
+    builder::InstructionBuilder,
+    error::X64WriterError,
+    function::{Function, FunctionBuilder},
+    label::Label,
+    register::{rdi, rsp, RegisterName},
+};
+
+/// Builds the entry point `syscall` jumps to, which switches to the kernel stack, saves the
+/// user registers, calls a System V handler and returns to user mode with `sysretq`.
+///
+/// The stack pointers are exchanged through per-CPU data addressed relative to the kernel
+/// `%gs` base. The handler receives a pointer to the saved registers in `%rdi`, laid out in
+/// the order of [`Self::SAVED_REGISTERS`] followed by the user `%rsp`, and its return value
+/// is passed to user mode in `%rax`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SyscallEntryBuilder<'a> {
+    name: Label<'a>,
+    handler: Label<'a>,
+    global: bool,
+    user_stack_offset: i32,
+    kernel_stack_offset: i32,
+}
+impl<'a> SyscallEntryBuilder<'a> {
+    /// The saved registers from the lowest address up, `%rcx` and `%r11` holding the user
+    /// `%rip` and `%rflags`, and `%rax` the system call number.
+    pub const SAVED_REGISTERS: [RegisterName; 15] = [
+        RegisterName::R15,
+        RegisterName::R14,
+        RegisterName::R13,
+        RegisterName::R12,
+        RegisterName::BP,
+        RegisterName::B,
+        RegisterName::R9,
+        RegisterName::R8,
+        RegisterName::R10,
+        RegisterName::D,
+        RegisterName::SI,
+        RegisterName::DI,
+        RegisterName::A,
+        RegisterName::C,
+        RegisterName::R11,
+    ];
+
+    /// `user_stack_offset` and `kernel_stack_offset` are the offsets of the scratch slot for
+    /// the user stack pointer and of the kernel stack top within the per-CPU data.
+    pub fn new(
+        name: impl Into<Label<'a>>,
+        handler: impl Into<Label<'a>>,
+        user_stack_offset: i32,
+        kernel_stack_offset: i32,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            handler: handler.into(),
+            global: false,
+            user_stack_offset,
+            kernel_stack_offset,
+        }
+    }
+
+    pub fn set_global(&mut self, global: bool) {
+        self.global = global;
+    }
+
+    pub fn build(&self) -> Function<'a> {
+        let mut builder = FunctionBuilder::new(self.name);
+        builder.set_global(self.global);
+        // Every register holds a value of the user program.
+        for name in RegisterName::ALL {
+            builder.declare_argument(name.quad());
+        }
+        self.emit(&mut builder)
+            .expect("building into a function does not fail");
+        builder
+            .end_function()
+            .expect("all registers are saved before they are written")
+    }
+    /// Emits the body of the entry point, without the label of its name.
+    /// The kernel stack top must be 16-byte aligned.
+    pub fn emit(&self, builder: &mut impl InstructionBuilder<'a>) -> Result<(), X64WriterError> {
+        let user_stack = per_cpu(self.user_stack_offset);
+        let kernel_stack = per_cpu(self.kernel_stack_offset);
+
+        builder.build_swapgs()?;
+        builder.build_mov(user_stack, rsp())?;
+        builder.build_mov(rsp(), kernel_stack)?;
+        builder.build_push(user_stack)?;
+        for name in Self::SAVED_REGISTERS.into_iter().rev() {
+            builder.build_push(name.quad())?;
+        }
+
+        builder.build_cld()?;
+        builder.build_mov(rdi(), rsp())?;
+        builder.build_call(self.handler)?;
+
+        for name in Self::SAVED_REGISTERS {
+            if name == RegisterName::A {
+                // Keep the return value of the handler.
+                builder.build_add(rsp(), 8i64)?;
+            } else {
+                builder.build_pop(name.quad())?;
+            }
+        }
+        builder.build_pop(rsp())?;
+        builder.build_swapgs()?;
+        builder.build_sysretq()
+    }
+}
+
+fn per_cpu(offset: i32) -> Memory<'static> {
+    Memory::sib()
+        .segment(Segment::Gs)
+        .offset(offset)
+        .size(ArgSize::Quad)
+}
@@ -0,0 +1,81 @@
+use super::{
+    builder::InstructionBuilder,
+    error::X64WriterError,
+    features::TargetFeatures,
+    function::{Item, Items},
+    label::Label,
+    register::{rdi, rsi, rsp, RegisterName, RegisterSet},
+};
+use alloc::vec::Vec;
+
+/// A function called on entry to or before returning from a traced function, see
+/// [`FunctionBuilder::set_entry_hook`](crate::function::FunctionBuilder::set_entry_hook).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TraceHook<'a> {
+    pub target: Label<'a>,
+    /// Whether the hook preserves every register itself, so it is called without saving
+    /// any or passing arguments.
+    pub preserves_registers: bool,
+}
+impl<'a> TraceHook<'a> {
+    /// `__fentry__`, the hook that ftrace and perf find and patch at run time.
+    pub fn fentry() -> Self {
+        Self::raw("__fentry__")
+    }
+    /// A hook preserving every register, like `__fentry__`.
+    pub fn raw(target: impl Into<Label<'a>>) -> Self {
+        Self {
+            target: target.into(),
+            preserves_registers: true,
+        }
+    }
+    /// A System V function taking the address of the traced function and the return
+    /// address, like `__cyg_profile_func_enter` and `__cyg_profile_func_exit` with GCC's
+    /// `-finstrument-functions`. The general purpose registers it may change are saved
+    /// around the call, vector registers are not.
+    pub fn function(target: impl Into<Label<'a>>) -> Self {
+        Self {
+            target: target.into(),
+            preserves_registers: false,
+        }
+    }
+
+    /// The call of the hook from `function`, where `%rsp` points at the return address.
+    pub(crate) fn call(
+        self,
+        function: Label<'a>,
+        callee_saved: RegisterSet,
+        features: TargetFeatures,
+    ) -> Result<Vec<Item<'a>>, X64WriterError> {
+        let mut items = Items::new(features);
+        if self.preserves_registers {
+            items.build_call(self.target)?;
+            return Ok(items.items);
+        }
+
+        let saved: Vec<_> = RegisterSet::all()
+            .difference(callee_saved)
+            .without(RegisterName::SP)
+            .iter()
+            .collect();
+        for &register in &saved {
+            items.build_push(register.quad())?;
+        }
+        // The return address leaves the stack 8 bytes off the alignment of calls.
+        let padding = if saved.len() % 2 == 0 { 8 } else { 0 };
+        if padding != 0 {
+            items.build_sub(rsp(), padding)?;
+        }
+        let return_address = 8 * saved.len() as i64 + padding;
+        items.build_lea(rdi(), function.rip())?;
+        items.build_mov(rsi(), rsp().memory().offset(return_address))?;
+        items.build_call(self.target)?;
+        if padding != 0 {
+            items.build_add(rsp(), padding)?;
+        }
+        for &register in saved.iter().rev() {
+            items.build_pop(register.quad())?;
+        }
+        Ok(items.items)
+    }
+}
@@ -0,0 +1,84 @@
+use super::{
+    args::Arg,
+    function::{Function, Item},
+    instruction::Instruction,
+};
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+
+impl Function<'_> {
+    /// Removes the blocks control cannot reach from the entry, then the local labels that
+    /// only the removed code referred to.
+    ///
+    /// Blocks starting with a label that is not local (`.L` prefixed), or whose label is
+    /// called or has its address taken, count as reachable, as they may be entered in ways
+    /// the control flow graph does not show.
+    pub fn remove_unreachable_blocks(&mut self) {
+        let cfg = self.cfg();
+        let escaping = escaping_labels(self);
+        let is_root = |block: usize| match cfg.blocks[block].label(&self.items) {
+            _ if block == 0 => true,
+            Some(label) => !is_local(label.label) || escaping.contains(label.label),
+            None => false,
+        };
+
+        let mut reachable = vec![false; cfg.blocks.len()];
+        let mut stack: Vec<_> = (0..cfg.blocks.len()).filter(|&b| is_root(b)).collect();
+        while let Some(block) = stack.pop() {
+            if !core::mem::replace(&mut reachable[block], true) {
+                stack.extend(&cfg.blocks[block].successors);
+            }
+        }
+
+        let referenced_before = referenced_labels(self);
+        let mut keep = vec![true; self.items.len()];
+        for (block, reachable) in cfg.blocks.iter().zip(reachable) {
+            if !reachable {
+                keep[block.start..block.end].fill(false);
+            }
+        }
+        let mut keep = keep.into_iter();
+        self.items.retain(|_| keep.next().unwrap());
+
+        let referenced_after = referenced_labels(self);
+        self.items.retain(|item| match item {
+            Item::Label(label) => {
+                let label = label.label;
+                !is_local(label)
+                    || !referenced_before.contains(label)
+                    || referenced_after.contains(label)
+            }
+            _ => true,
+        });
+    }
+}
+
+fn is_local(label: &str) -> bool {
+    label.starts_with(".L")
+}
+/// Every label the instructions refer to.
+fn referenced_labels<'a>(function: &Function<'a>) -> BTreeSet<&'a str> {
+    function
+        .instructions()
+        .flat_map(|instruction| instruction.operands())
+        .filter_map(|arg| arg.symbol())
+        .map(|label| label.label)
+        .collect()
+}
+/// The labels referred to other than as the target of a jump.
+fn escaping_labels<'a>(function: &Function<'a>) -> BTreeSet<&'a str> {
+    function
+        .instructions()
+        .flat_map(|instruction| match *instruction {
+            Instruction::Jmp {
+                target: Arg::Label(_),
+            }
+            | Instruction::CJmp {
+                target: Arg::Label(_),
+                ..
+            } => Vec::new(),
+            _ => instruction.operands(),
+        })
+        .filter_map(|arg| arg.symbol())
+        .map(|label| label.label)
+        .collect()
+}
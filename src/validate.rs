@@ -0,0 +1,484 @@
+use super::{
+    args::{Arg, ArgSize, ConstInt},
+    error::X64WriterError,
+    instruction::Instruction,
+    register::{Register, RegisterName, RegisterSize, VectorSize},
+    writer::{
+        BinaryOp, BinaryOpDouble, BinaryOpSingle, SseOp, TernaryOp, UnaryOp, UnaryOpSingle,
+        VectorOp,
+    },
+};
+use alloc::{format, vec, vec::Vec};
+use core::fmt::Display;
+
+/// The class of an operand, as far as which combinations instructions take.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OperandKind {
+    Register,
+    Memory,
+    Immediate,
+    /// A symbol, the target of a branch or an absolute memory address.
+    Label,
+    Vector,
+    Mask,
+}
+impl OperandKind {
+    pub fn of(arg: &Arg) -> Self {
+        match arg {
+            Arg::Register(_) | Arg::Virtual(_) => Self::Register,
+            Arg::Memory(_) => Self::Memory,
+            Arg::Int(_) => Self::Immediate,
+            Arg::Label(_) | Arg::SymExpr(_) => Self::Label,
+            Arg::Vector(_) => Self::Vector,
+            Arg::Mask(_) => Self::Mask,
+        }
+    }
+    /// Whether the operand is in memory, as a label is outside of branches.
+    pub fn is_memory(self) -> bool {
+        matches!(self, Self::Memory | Self::Label)
+    }
+    /// Whether the operand goes in the ModRM r/m field of a general purpose instruction.
+    fn is_register_or_memory(self) -> bool {
+        self == Self::Register || self.is_memory()
+    }
+}
+impl Display for OperandKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Self::Register => "register",
+            Self::Memory => "memory",
+            Self::Immediate => "immediate",
+            Self::Label => "label",
+            Self::Vector => "vector register",
+            Self::Mask => "mask register",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Instruction<'_> {
+    /// Checks that the instruction has a form taking its kinds and sizes of operands, and
+    /// that its immediates fit. The assembler rejects instructions that fail this.
+    pub fn validate_operands(&self) -> Result<(), X64WriterError> {
+        let kinds: Vec<_> = self.operands().iter().rev().map(OperandKind::of).collect();
+        let illegal = || X64WriterError::IllegalOperands {
+            mnemonic: self.mnemonic(),
+            operands: kinds.clone(),
+        };
+        if kinds.iter().filter(|kind| kind.is_memory()).count() > 1 {
+            return Err(illegal());
+        }
+
+        use OperandKind::*;
+        let legal = match *self {
+            Self::Mov { dst, src } => {
+                let (dst, src) = (OperandKind::of(&dst), OperandKind::of(&src));
+                dst.is_register_or_memory() && (src.is_register_or_memory() || src == Immediate)
+            }
+            Self::CMov { dst, src, .. } => {
+                OperandKind::of(&dst) == Register && OperandKind::of(&src).is_register_or_memory()
+            }
+            Self::SetCC { dst, .. } | Self::Pop { dst } => {
+                OperandKind::of(&dst).is_register_or_memory()
+            }
+            Self::Push { src } => {
+                let src = OperandKind::of(&src);
+                src.is_register_or_memory() || src == Immediate
+            }
+            Self::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src,
+            } => binary_is_legal(op, dst, src),
+            Self::Binary {
+                op: BinaryOp::Double(_),
+                dst,
+                src,
+            } => OperandKind::of(&dst) == Register && OperandKind::of(&src).is_register_or_memory(),
+            Self::Binary {
+                op: BinaryOp::Sse(op),
+                dst,
+                src,
+            } => sse_is_legal(op, dst, src),
+            Self::Unary {
+                op: UnaryOp::Single(UnaryOpSingle::Bswap),
+                dst,
+            } => OperandKind::of(&dst) == Register,
+            Self::Unary {
+                op: UnaryOp::Single(_),
+                dst,
+            } => OperandKind::of(&dst).is_register_or_memory(),
+            Self::Unary {
+                op: UnaryOp::Cmpxchg16b,
+                dst,
+            } => OperandKind::of(&dst).is_memory(),
+            Self::Ternary { op, dst, src, src2 } => {
+                let (dst, src) = (OperandKind::of(&dst), OperandKind::of(&src));
+                match op {
+                    TernaryOp::Shld | TernaryOp::Shrd => {
+                        dst.is_register_or_memory() && src == Register && is_shift_count(src2)
+                    }
+                    TernaryOp::Bextr | TernaryOp::Shlx | TernaryOp::Shrx | TernaryOp::Sarx => {
+                        dst == Register
+                            && src.is_register_or_memory()
+                            && OperandKind::of(&src2) == Register
+                    }
+                }
+            }
+            // Checked once legalized to two operands.
+            Self::ThreeAddress { .. } => true,
+            Self::Call { target } | Self::Jmp { target } => {
+                OperandKind::of(&target).is_register_or_memory()
+            }
+            Self::CJmp { target, .. } => OperandKind::of(&target) == Label,
+            Self::Vector {
+                op,
+                dst,
+                src1,
+                src2,
+                ..
+            } => src1.is_none() == op.has_one_source() && vector_is_legal(op, dst, src1, src2),
+            Self::Locked { dst, .. } => {
+                if !OperandKind::of(&dst).is_memory() {
+                    return Err(illegal());
+                }
+                return self.unlocked().validate_operands();
+            }
+            Self::String { op, rep, .. } => {
+                if let Some(rep) = rep {
+                    op.check_prefix(rep)?;
+                }
+                true
+            }
+            Self::Nonary { .. } => true,
+        };
+        if !legal {
+            return Err(illegal());
+        }
+        self.validate_sizes()?;
+        self.validate_vector()?;
+        self.validate_immediate()
+    }
+
+    /// Checks that the instruction has a form for the sizes of its operands, which have
+    /// to agree where the instruction takes them of one size.
+    fn validate_sizes(&self) -> Result<(), X64WriterError> {
+        use ArgSize::*;
+        // Immediates can be narrower than the operation.
+        let sizes = |args: &[Arg]| -> Vec<ArgSize> {
+            let sized = args.iter().filter(|arg| !matches!(arg, Arg::Int(_)));
+            sized.filter_map(Arg::size).collect()
+        };
+        let (args, allowed): (Vec<Arg>, &[ArgSize]) = match *self {
+            Self::CMov { dst, src, .. } => (vec![dst, src], &[Word, Double, Quad]),
+            Self::SetCC { dst, .. } => (vec![dst], &[Byte]),
+            // Long mode branches only through 64-bit addresses.
+            Self::Call { target } | Self::Jmp { target } => (vec![target], &[Quad]),
+            Self::Push { src: arg } | Self::Pop { dst: arg } => {
+                // Long mode has no 32-bit stack slots, not even for immediates.
+                return match arg.size() {
+                    None | Some(Word | Quad) => Ok(()),
+                    Some(size) => Err(self.no_form(size)),
+                };
+            }
+            Self::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src,
+            } => {
+                use BinaryOpSingle::*;
+                match op {
+                    // Bit offsets in registers have the size of the operand.
+                    IMul | Lea | Movbe | Popcnt | Lzcnt | Tzcnt | Bsf | Bsr | Bt | Bts | Btr
+                    | Btc => (vec![dst, src], &[Word, Double, Quad]),
+                    _ => return Ok(()),
+                }
+            }
+            Self::Binary {
+                op: BinaryOp::Double(op),
+                dst,
+                src,
+            } => {
+                // There is no zero extension from 32 bits, a 32-bit mov does it.
+                let sources: &[ArgSize] = match op {
+                    BinaryOpDouble::Movzx => &[Byte, Word],
+                    BinaryOpDouble::Movsx => &[Byte, Word, Double],
+                };
+                return match (src.size(), dst.size()) {
+                    (Some(src), _) if !sources.contains(&src) => Err(self.no_form(src)),
+                    (Some(src), Some(dst)) if dst.bytes() <= src.bytes() => {
+                        Err(X64WriterError::InvalidOperand(format!(
+                            "{} needs a destination wider than its {}-bit source",
+                            self.mnemonic(),
+                            src.bytes() * 8
+                        )))
+                    }
+                    _ => Ok(()),
+                };
+            }
+            Self::Binary {
+                op: BinaryOp::Sse(op),
+                dst,
+                src,
+            } => {
+                use SseOp::*;
+                let (integer, allowed): (Arg, &[ArgSize]) = match op {
+                    Cvtsi2ss | Cvtsi2sd => (src, &[Double, Quad]),
+                    Cvttss2si | Cvttsd2si => (dst, &[Double, Quad]),
+                    Movd if !dst.is_vector() => (dst, &[Double, Quad]),
+                    Movd => (src, &[Double, Quad]),
+                    Movq if src.is_register() => (src, &[Quad]),
+                    Movq if dst.is_register() => (dst, &[Quad]),
+                    _ => return Ok(()),
+                };
+                (vec![integer], allowed)
+            }
+            Self::Unary {
+                op: UnaryOp::Single(UnaryOpSingle::Bswap),
+                dst,
+            } => (vec![dst], &[Double, Quad]),
+            Self::Ternary { op, dst, src, src2 } => match op {
+                TernaryOp::Shld | TernaryOp::Shrd => (vec![dst, src], &[Word, Double, Quad]),
+                _ => (vec![dst, src, src2], &[Double, Quad]),
+            },
+            _ => return Ok(()),
+        };
+        let sizes = sizes(&args);
+        if let Some(&size) = sizes.iter().find(|size| !allowed.contains(size)) {
+            return Err(self.no_form(size));
+        }
+        match sizes.iter().find(|&&size| size != sizes[0]) {
+            Some(&found) => Err(X64WriterError::SizeMismatch {
+                expected: sizes[0],
+                found,
+            }),
+            None => Ok(()),
+        }
+    }
+    /// Checks the sizes of vector operands, and that VEX-only operations use nothing that
+    /// needs EVEX.
+    fn validate_vector(&self) -> Result<(), X64WriterError> {
+        use VectorOp::*;
+        let Self::Vector {
+            op,
+            dst,
+            src2,
+            masking,
+            broadcast,
+            ..
+        } = *self
+        else {
+            return Ok(());
+        };
+        let mnemonic = op.mnemonic();
+        let invalid = |message: &str| {
+            Err(X64WriterError::InvalidOperand(format!(
+                "{mnemonic} {message}"
+            )))
+        };
+        if matches!(op, Kmovw | Kmovq) {
+            let size = if op == Kmovq {
+                ArgSize::Quad
+            } else {
+                ArgSize::Double
+            };
+            if masking.is_some() || broadcast {
+                return invalid("cannot be masked or broadcast");
+            }
+            let registers = [dst, src2].into_iter().filter(|arg| arg.is_register());
+            return match registers
+                .filter_map(|arg| arg.size())
+                .find(|&found| found != size)
+            {
+                Some(found) => Err(self.no_form(found)),
+                None => Ok(()),
+            };
+        }
+        if self.requires_evex() && op.is_vex_only() {
+            return invalid(
+                "has no encoding with masking, broadcasts, %zmm or registers beyond 15",
+            );
+        }
+        let sizes: Vec<_> = self
+            .operands()
+            .into_iter()
+            .filter_map(|arg| match arg {
+                Arg::Vector(register) => Some(register.1),
+                _ => None,
+            })
+            .collect();
+        let broadcasts = matches!(op, Vbroadcastss | Vbroadcastsd);
+        if op.is_scalar() && sizes.iter().any(|&size| size != VectorSize::Xmm) {
+            return invalid("takes %xmm registers");
+        }
+        if broadcasts && matches!(src2, Arg::Vector(register) if register.1 != VectorSize::Xmm) {
+            return invalid("broadcasts from %xmm or memory");
+        }
+        if op == Vbroadcastsd
+            && matches!(dst, Arg::Vector(register) if register.1 == VectorSize::Xmm)
+        {
+            return invalid("needs a %ymm or %zmm destination");
+        }
+        if !broadcasts && sizes.iter().any(|&size| size != sizes[0]) {
+            return invalid("needs vector operands of the same size");
+        }
+        let memory = OperandKind::of(&src2).is_memory();
+        if broadcast && (!memory || op.element_size().is_none() || op.is_scalar() || op.is_move()) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{mnemonic} cannot broadcast {src2}"
+            )));
+        }
+        if OperandKind::of(&dst).is_memory() && masking.is_some_and(|masking| masking.zeroing) {
+            return invalid("cannot zero lanes in memory");
+        }
+        Ok(())
+    }
+    fn no_form(&self, size: ArgSize) -> X64WriterError {
+        X64WriterError::InvalidOperand(format!(
+            "{} has no form with {}-bit operands",
+            self.mnemonic(),
+            size.bytes() * 8
+        ))
+    }
+
+    /// Checks that the immediate fits the field the instruction encodes it in.
+    fn validate_immediate(&self) -> Result<(), X64WriterError> {
+        let (c, size) = match *self {
+            // Only a move into a register takes a full 64-bit immediate.
+            Self::Mov {
+                dst: dst @ Arg::Register(_),
+                src: Arg::Int(c),
+            } if dst.size() == Some(ArgSize::Quad) => return self.check_immediate(c, 64, false),
+            Self::Mov {
+                dst,
+                src: src @ Arg::Int(c),
+            } => (c, dst.size().or(src.size())),
+            // Pushes sign-extend their immediate to the 64 bits of a stack slot.
+            Self::Push { src: Arg::Int(c) } => (c, Some(ArgSize::Quad)),
+            Self::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src: src @ Arg::Int(c),
+            } => match op {
+                BinaryOpSingle::Shl
+                | BinaryOpSingle::Shr
+                | BinaryOpSingle::Sar
+                | BinaryOpSingle::Bt
+                | BinaryOpSingle::Bts
+                | BinaryOpSingle::Btr
+                | BinaryOpSingle::Btc => return self.check_immediate(c, 8, false),
+                _ => (c, dst.size().or(src.size())),
+            },
+            Self::Ternary {
+                src2: Arg::Int(c), ..
+            } => return self.check_immediate(c, 8, false),
+            _ => return Ok(()),
+        };
+        match size {
+            Some(ArgSize::Quad) => self.check_immediate(c, 32, true),
+            Some(size) => self.check_immediate(c, size.bytes() * 8, false),
+            None => Ok(()),
+        }
+    }
+    /// Sign-extended immediates have to be in the signed range of `bits`, the others may
+    /// also be in the unsigned one.
+    fn check_immediate(
+        &self,
+        c: ConstInt,
+        bits: u8,
+        sign_extended: bool,
+    ) -> Result<(), X64WriterError> {
+        let value = match c {
+            ConstInt::U64(v) => v.into(),
+            c => i128::from(c.value()),
+        };
+        let min = -(1i128 << (bits - 1));
+        let max = match sign_extended {
+            true => (1i128 << (bits - 1)) - 1,
+            false => (1i128 << bits) - 1,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(()),
+            false => Err(X64WriterError::ImmediateOutOfRange {
+                mnemonic: self.mnemonic(),
+                value,
+                bits,
+            }),
+        }
+    }
+}
+
+fn binary_is_legal(op: BinaryOpSingle, dst: Arg, src: Arg) -> bool {
+    use BinaryOpSingle::*;
+    use OperandKind::*;
+    let (dst_kind, src_kind) = (OperandKind::of(&dst), OperandKind::of(&src));
+    match op {
+        Add | Adc | Sub | Sbb | And | Or | Xor | Cmp | Test => {
+            dst_kind.is_register_or_memory()
+                && (src_kind.is_register_or_memory() || src_kind == Immediate)
+        }
+        IMul => dst_kind == Register && (src_kind.is_register_or_memory() || src_kind == Immediate),
+        Lea => dst_kind == Register && src_kind.is_memory(),
+        Shl | Shr | Sar => dst_kind.is_register_or_memory() && is_shift_count(src),
+        Xchg => dst_kind.is_register_or_memory() && src_kind.is_register_or_memory(),
+        Movbe => {
+            (dst_kind == Register && src_kind.is_memory())
+                || (dst_kind.is_memory() && src_kind == Register)
+        }
+        Popcnt | Lzcnt | Tzcnt | Bsf | Bsr => {
+            dst_kind == Register && src_kind.is_register_or_memory()
+        }
+        Bt | Bts | Btr | Btc => {
+            dst_kind.is_register_or_memory() && matches!(src_kind, Register | Immediate)
+        }
+        Xadd | Cmpxchg => dst_kind.is_register_or_memory() && src_kind == Register,
+    }
+}
+fn sse_is_legal(op: SseOp, dst: Arg, src: Arg) -> bool {
+    use OperandKind::*;
+    use SseOp::*;
+    let (dst, src) = (OperandKind::of(&dst), OperandKind::of(&src));
+    match op {
+        Cvtsi2ss | Cvtsi2sd => dst == Vector && src.is_register_or_memory(),
+        Cvttss2si | Cvttsd2si => dst == Register && (src == Vector || src.is_memory()),
+        Movd | Movq => {
+            let vectors = op == Movq && dst == Vector && src == Vector;
+            vectors
+                || (dst == Vector && src.is_register_or_memory())
+                || (dst.is_register_or_memory() && src == Vector)
+        }
+        Movss | Movsd | Movaps | Movups | Movapd | Movupd => {
+            (dst == Vector && (src == Vector || src.is_memory()))
+                || (dst.is_memory() && src == Vector)
+        }
+        _ => dst == Vector && (src == Vector || src.is_memory()),
+    }
+}
+/// Vector operations take vector registers apart from memory sources and the destination
+/// of moves, and mask moves take mask registers with general purpose ones or memory.
+fn vector_is_legal(op: VectorOp, dst: Arg, src1: Option<Arg>, src2: Arg) -> bool {
+    use OperandKind::*;
+    let (dst, src2) = (OperandKind::of(&dst), OperandKind::of(&src2));
+    match op {
+        VectorOp::Kmovw | VectorOp::Kmovq => {
+            (dst == Mask && (src2 == Mask || src2 == Register || src2.is_memory()))
+                || ((dst == Register || dst.is_memory()) && src2 == Mask)
+        }
+        _ if op.is_move() => {
+            (dst == Vector && (src2 == Vector || src2.is_memory()))
+                || (dst.is_memory() && src2 == Vector)
+        }
+        _ => {
+            dst == Vector
+                && src1.map_or(true, |src1| OperandKind::of(&src1) == Vector)
+                && (src2 == Vector || src2.is_memory())
+        }
+    }
+}
+/// Shift counts are immediates or `%cl`.
+fn is_shift_count(count: Arg) -> bool {
+    matches!(
+        count,
+        Arg::Int(_) | Arg::Register(Register(RegisterName::C, RegisterSize::Byte))
+    )
+}
@@ -1,267 +1,993 @@
 use super::{
     args::{Arg, ArgSize},
-    label::Label,
+    builder::InstructionBuilder,
+    cfi::{CfiMode, CfiState},
+    debug::DebugLineState,
+    error::X64WriterError,
+    features::{Feature, TargetFeatures},
+    flags::Flags,
+    function::Tags,
+    global::OpenGlobal,
+    instruction::Instruction,
+    io::{self, Write},
+    label::{Label, LabelAllocator},
+    meta::Mnemonic,
+    patchpoint::PatchpointState,
+    platform::Platform,
+    section::Section,
+    symbol::SymbolTable,
+    xray::XRayState,
 };
-use std::io::{self, Write};
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Display;
 
 pub struct AsmWriter<O> {
     out: O,
+    features: TargetFeatures,
+    platform: Platform,
+    strict: bool,
+    annotate_implicit: bool,
+    annotate_tags: bool,
+    function_symbols: bool,
+    data_line_width: usize,
+    max_line_length: Option<usize>,
+    /// The `.set` aliases of symbols too long for an instruction to fit the maximum line
+    /// length.
+    line_aliases: BTreeMap<String, String>,
+    leb128_directives: bool,
+    cfi: CfiState,
+    patchpoints: PatchpointState,
+    /// The function begun by [`AsmWriter::begin_function`] and not yet ended.
+    open_function: Option<String>,
+    /// The global begun by [`AsmWriter::begin_global`] and not yet ended.
+    open_global: Option<OpenGlobal>,
+    /// The inline cache stubs emitted so far, which number their labels.
+    inline_caches: usize,
+    /// The current section last, after the ones [`AsmWriter::pop_section`] returns to.
+    sections: Vec<Section>,
+    label_allocator: LabelAllocator,
+    xray: XRayState,
+    debug_line: DebugLineState,
+    symbol_table: SymbolTable,
+    mode: CodeMode,
+    demangler: Option<Box<Demangler>>,
+    /// The dense names given to `.L` labels so far, if they are renamed.
+    local_labels: Option<BTreeMap<String, String>>,
+    minify: bool,
 }
+
+/// Turns a symbol name into a readable one, or returns `None` if it is not mangled.
+pub type Demangler = dyn Fn(&str) -> Option<String>;
+
 impl<O: Write> AsmWriter<O> {
     pub fn new(out: O) -> Self {
-        Self { out }
+        Self {
+            out,
+            features: TargetFeatures::x86_64(),
+            platform: Platform::Linux,
+            strict: false,
+            annotate_implicit: false,
+            annotate_tags: false,
+            function_symbols: true,
+            data_line_width: 80,
+            max_line_length: None,
+            line_aliases: BTreeMap::new(),
+            leb128_directives: false,
+            cfi: CfiState::default(),
+            patchpoints: PatchpointState::default(),
+            open_function: None,
+            open_global: None,
+            inline_caches: 0,
+            sections: vec![Section::Text],
+            label_allocator: LabelAllocator::new(),
+            xray: XRayState::default(),
+            debug_line: DebugLineState::default(),
+            symbol_table: SymbolTable::default(),
+            mode: CodeMode::Code64,
+            demangler: None,
+            local_labels: None,
+            minify: false,
+        }
     }
 
-    pub fn write_filename(&mut self, name: &str) -> io::Result<()> {
-        writeln!(self.out, "\t.file \"{name}\"")
+    pub fn into_inner(self) -> O {
+        self.out
     }
-    pub fn emit_label<'a>(&mut self, label: impl Into<Label<'a>>) -> io::Result<()> {
-        let label = label.into();
-        writeln!(self.out, "{}:", label)
+    pub(crate) fn output(&self) -> &O {
+        &self.out
+    }
+    pub(crate) fn output_mut(&mut self) -> &mut O {
+        &mut self.out
+    }
+
+    pub fn set_target_features(&mut self, features: TargetFeatures) {
+        self.features = features;
+    }
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+    /// In strict mode, instructions requiring features outside of the target set are rejected,
+    /// as are operands the instruction has no form for, see [`Instruction::validate_operands`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    /// Appends a comment listing implicit register operands to instructions that have them.
+    pub fn set_annotate_implicit(&mut self, annotate: bool) {
+        self.annotate_implicit = annotate;
+    }
+    /// Appends a comment listing the tags of instructions emitted from a [`Function`](crate::function::Function).
+    pub fn set_annotate_tags(&mut self, annotate: bool) {
+        self.annotate_tags = annotate;
+    }
+    /// Whether emitted [`Function`](crate::function::Function)s get `.type` and `.size`
+    /// directives, on by default.
+    pub fn set_function_symbols(&mut self, function_symbols: bool) {
+        self.function_symbols = function_symbols;
+    }
+    pub(crate) fn function_symbols(&self) -> bool {
+        self.function_symbols
+    }
+    /// The column at which lists of data values are wrapped onto a new directive.
+    pub fn set_data_line_width(&mut self, width: usize) {
+        self.data_line_width = width;
+    }
+    pub(crate) fn data_line_width(&self) -> usize {
+        self.data_line_width
+            .min(self.max_line_length.unwrap_or(usize::MAX))
+    }
+    /// The columns no line may exceed, with tab stops every 8 columns. Comments are wrapped
+    /// onto lines of their own, and the long symbols of an instruction are replaced with
+    /// aliases; lines that still do not fit are errors. Unlimited by default.
+    pub fn set_max_line_length(&mut self, max: Option<usize>) {
+        self.max_line_length = max;
+    }
+    /// Whether LEB128 values are written with the `.uleb128` and `.sleb128` directives
+    /// instead of as bytes, for assemblers that support them.
+    pub fn set_leb128_directives(&mut self, enabled: bool) {
+        self.leb128_directives = enabled;
+    }
+    pub(crate) fn leb128_directives(&self) -> bool {
+        self.leb128_directives
+    }
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+    /// Adapts symbol names, sections and directives to the assembler of `platform`, Linux by
+    /// default. The ELF-only tables, such as those of [`crate::eh`], are not adapted.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+    }
+    /// The name the symbol `name` has in the output, see [`Platform::symbol_name`].
+    pub fn symbol_name<'n>(&self, name: &'n str) -> Cow<'n, str> {
+        self.platform.symbol_name(name)
+    }
+    pub(crate) fn label_name(&self, label: Label) -> String {
+        let name = self.symbol_name(label.label);
+        match label.modifier {
+            Some(modifier) => format!("{name}{modifier}"),
+            None => name.into_owned(),
+        }
+    }
+    pub fn cfi_mode(&self) -> CfiMode {
+        self.cfi.mode
+    }
+    /// Switches between `.cfi_*` directives and computing `.eh_frame` in the writer.
+    /// Panics in the middle of a function's call frame information.
+    pub fn set_cfi_mode(&mut self, mode: CfiMode) {
+        assert!(
+            !self.cfi.in_frame(),
+            "the CFI mode cannot change between .cfi_startproc and .cfi_endproc"
+        );
+        self.cfi.mode = mode;
+    }
+    pub(crate) fn cfi(&mut self) -> &mut CfiState {
+        &mut self.cfi
+    }
+    pub(crate) fn patchpoints(&mut self) -> &mut PatchpointState {
+        &mut self.patchpoints
+    }
+    pub(crate) fn open_function(&mut self) -> &mut Option<String> {
+        &mut self.open_function
+    }
+    pub(crate) fn open_global(&mut self) -> &mut Option<OpenGlobal> {
+        &mut self.open_global
+    }
+    pub(crate) fn inline_caches(&mut self) -> &mut usize {
+        &mut self.inline_caches
+    }
+    /// The section being written to, `.text` until another is begun.
+    pub fn current_section(&self) -> &Section {
+        self.sections.last().unwrap()
+    }
+    pub(crate) fn sections(&mut self) -> &mut Vec<Section> {
+        &mut self.sections
+    }
+    /// Allocates local labels unique within the output, for code emitted directly as well
+    /// as functions built for it.
+    pub fn label_allocator(&mut self) -> &mut LabelAllocator {
+        &mut self.label_allocator
+    }
+    pub(crate) fn xray(&mut self) -> &mut XRayState {
+        &mut self.xray
+    }
+    pub(crate) fn debug_line(&mut self) -> &mut DebugLineState {
+        &mut self.debug_line
+    }
+    pub(crate) fn symbol_table(&self) -> &SymbolTable {
+        &self.symbol_table
+    }
+    pub(crate) fn symbol_table_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbol_table
+    }
+    /// Leaves out comments, annotations, blank lines, indentation and size suffixes implied
+    /// by a register operand to make the output as small as possible.
+    pub fn set_minify(&mut self, minify: bool) {
+        self.minify = minify;
+    }
+    pub(crate) fn is_minified(&self) -> bool {
+        self.minify
+    }
+    /// Renames the `.L` labels of emitted [`Function`](crate::function::Function)s to
+    /// `.L0`, `.L1` and so on in order of appearance, consistently across the whole output.
+    /// Labels emitted outside of functions keep their names and must not collide.
+    pub fn set_rename_local_labels(&mut self, rename: bool) {
+        self.local_labels = rename.then(BTreeMap::new);
+    }
+    /// The dense name of each `.L` label in `labels`, or `None` if labels are not renamed.
+    pub(crate) fn rename_local_labels<'a>(
+        &mut self,
+        labels: impl IntoIterator<Item = Label<'a>>,
+    ) -> Option<BTreeMap<&'a str, String>> {
+        let names = self.local_labels.as_mut()?;
+        let mut renamed = BTreeMap::new();
+        for label in labels {
+            let label = label.label;
+            if !label.starts_with(".L") {
+                continue;
+            }
+            let next = format!(".L{}", names.len());
+            let name = names.entry(label.to_string()).or_insert(next);
+            renamed.insert(label, name.clone());
+        }
+        Some(renamed)
+    }
+    /// Appends a comment with the demangled name to every label and call target the
+    /// demangler recognizes.
+    pub fn set_demangler(&mut self, demangler: impl Fn(&str) -> Option<String> + 'static) {
+        self.demangler = Some(Box::new(demangler));
+    }
+    pub fn clear_demangler(&mut self) {
+        self.demangler = None;
+    }
+    fn demangled(&self, name: &str) -> Option<String> {
+        let demangled = self.demangler.as_ref()?(name)?;
+        (demangled != name).then_some(demangled)
+    }
+
+    /// Rejects `mnemonic` in strict mode if the target lacks `feature`.
+    pub fn require_feature(
+        &self,
+        mnemonic: Mnemonic,
+        feature: Feature,
+    ) -> Result<(), X64WriterError> {
+        if !self.strict || self.features.contains(feature) {
+            return Ok(());
+        }
+
+        Err(X64WriterError::MissingFeature { mnemonic, feature })
+    }
+
+    pub fn code_mode(&self) -> CodeMode {
+        self.mode
+    }
+    /// Switches the processor mode the following instructions are assembled for.
+    /// In strict mode, instructions that need 64-bit mode are rejected outside of it.
+    pub fn set_code_mode(&mut self, mode: CodeMode) -> io::Result<()> {
+        self.mode = mode;
+        self.directive(mode.directive())
+    }
+    /// Rejects instructions that cannot be written, those the target cannot execute and,
+    /// in strict mode, operand combinations the assembler would reject.
+    pub(crate) fn check_instruction(
+        &self,
+        instruction: &Instruction,
+    ) -> Result<(), X64WriterError> {
+        instruction.check_operands()?;
+        if let Some(register) = instruction.operands().iter().find(|arg| arg.is_virtual()) {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "virtual register {register} was never allocated"
+            )));
+        }
+        if let Instruction::ThreeAddress { .. } = instruction {
+            return Err(X64WriterError::InvalidOperand(format!(
+                "{instruction} was never legalized to two operands"
+            )));
+        }
+        if self.strict {
+            instruction.validate_operands()?;
+        }
+        for feature in instruction.required_features().iter() {
+            self.require_feature(instruction.mnemonic(), feature)?;
+        }
+        self.require_mode(instruction)
+    }
+    fn require_mode(&self, instruction: &Instruction) -> Result<(), X64WriterError> {
+        if !self.strict || self.mode == CodeMode::Code64 || !instruction.requires_long_mode() {
+            return Ok(());
+        }
+
+        Err(X64WriterError::WrongMode {
+            mnemonic: instruction.mnemonic(),
+            mode: self.mode,
+        })
+    }
+
+    pub fn write_filename(&mut self, name: &str) -> io::Result<()> {
+        self.directive(format_args!(".file \"{name}\""))
     }
     pub fn declare_global<'a>(&mut self, label: impl Into<Label<'a>>) -> io::Result<()> {
-        let label = label.into().label;
-        writeln!(self.out, "\t.global {label}")
+        let label = self.symbol_name(label.into().label);
+        let global = self.platform.global_directive();
+        self.directive(format_args!("{global} {label}"))
     }
     pub fn begin_text(&mut self) -> io::Result<()> {
-        writeln!(self.out, "\t.text")
+        self.begin_section(Section::Text)
+    }
+    /// Advances the location counter by `bytes`, filling the gap with `fill`.
+    pub fn skip(&mut self, bytes: u64, fill: u8) -> io::Result<()> {
+        self.directive(format_args!(".skip {bytes}, {fill:#04x}"))
+    }
+    /// Advances the location counter to `offset` within the current section, filling the gap with `fill`.
+    pub fn org(&mut self, offset: u64, fill: u8) -> io::Result<()> {
+        self.directive(format_args!(".org {offset:#x}, {fill:#04x}"))
     }
     pub fn empty_line(&mut self) -> io::Result<()> {
+        if self.minify {
+            return Ok(());
+        }
         writeln!(self.out)
     }
-    pub fn comment(&mut self, comment: impl AsRef<str>) -> io::Result<()> {
-        let comment = comment.as_ref();
-        writeln!(self.out, "\t# {comment}")
+
+    pub fn write_instruction(&mut self, instruction: &Instruction) -> Result<(), X64WriterError> {
+        self.check_instruction(instruction)?;
+        Ok(self.write_tagged_instruction(instruction, &Tags::new())?)
     }
+    /// Writes an instruction that already passed [`check_instruction`](Self::check_instruction).
+    pub(crate) fn write_tagged_instruction(
+        &mut self,
+        instruction: &Instruction,
+        tags: &Tags,
+    ) -> io::Result<()> {
+        self.emit_instruction_loc(tags)?;
+        for label in instruction.operands().iter().filter_map(Arg::symbol) {
+            self.reference_symbol(label.label);
+        }
+        let mut comments = Vec::new();
+        let called = match *instruction {
+            Instruction::Call {
+                target: Arg::Label(label),
+            } => Some(label.label),
+            Instruction::Call {
+                target: Arg::SymExpr(expr),
+            } => Some(expr.label.label),
+            _ => None,
+        };
+        comments.extend(called.and_then(|name| self.demangled(name)));
+        if self.annotate_implicit {
+            comments.extend(implicit_operands(instruction));
+        }
+        if self.annotate_tags && !tags.is_empty() {
+            let mut comment = String::from("tags:");
+            for tag in tags.iter() {
+                comment += &format!(" {tag}");
+            }
+            comments.push(comment);
+        }
+
+        let mut text = self.instruction_text(instruction);
+        if self.max_line_length.is_some_and(|max| columns(&text) > max) {
+            text = self.alias_long_labels(instruction)?;
+        }
+        self.write_line(&text, &comments)
+    }
+    fn instruction_text(&self, instruction: &Instruction) -> String {
+        if self.platform == Platform::MacOs {
+            let labels = instruction
+                .operands()
+                .into_iter()
+                .filter_map(|arg| arg.symbol());
+            let names: BTreeMap<_, _> = labels
+                .map(|label| (label.label, self.symbol_name(label.label)))
+                .collect();
+            let renamed = instruction.map_labels(|label| Label {
+                label: &names[label.label],
+                ..label
+            });
+            return format_instruction(&renamed, self.minify);
+        }
+        format_instruction(instruction, self.minify)
+    }
+    /// The text of `instruction` with its symbols longer than an alias replaced by one.
+    /// Relocations against an alias set with `.set` are against the symbol itself.
+    fn alias_long_labels(&mut self, instruction: &Instruction) -> io::Result<String> {
+        for label in instruction.operands().iter().filter_map(Arg::symbol) {
+            let label = label.label.to_string();
+            let alias = format!(".Lalias{}", self.line_aliases.len());
+            if label.len() <= alias.len() || self.line_aliases.contains_key(&label) {
+                continue;
+            }
+            let (name, alias_name) = (self.symbol_name(&label), self.symbol_name(&alias));
+            self.directive(format_args!(".set {alias_name}, {name}"))?;
+            self.line_aliases.insert(label, alias);
+        }
+        let aliases = &self.line_aliases;
+        let aliased = instruction.map_labels(|label| Label {
+            label: aliases.get(label.label).map_or(label.label, String::as_str),
+            ..label
+        });
+        Ok(self.instruction_text(&aliased))
+    }
+    /// Writes `text` as a line followed by `comments`, which are left out of minified
+    /// output. Comments that would make the line exceed the maximum line length are
+    /// wrapped onto lines of their own before it instead.
+    fn write_line(&mut self, text: &str, comments: &[String]) -> io::Result<()> {
+        let comments = if self.minify { &[] } else { comments };
+        let mut line = text.to_string();
+        for comment in comments {
+            line += &format!("\t# {comment}");
+        }
+        let Some(max) = self.max_line_length.filter(|&max| columns(&line) > max) else {
+            return writeln!(self.out, "{line}");
+        };
+        if columns(text) > max {
+            let columns = columns(text);
+            return Err(X64WriterError::LineTooLong { columns, max }.into());
+        }
+        for comment in comments {
+            self.write_comment(comment)?;
+        }
+        writeln!(self.out, "{text}")
+    }
+    /// Writes `comment` on lines of its own, broken between words to fit the maximum line
+    /// length, and within words longer than a line.
+    fn write_comment(&mut self, comment: &str) -> io::Result<()> {
+        let prefix = columns("\t# ");
+        let Some(max) = self
+            .max_line_length
+            .filter(|&max| prefix + columns(comment) > max)
+        else {
+            return writeln!(self.out, "\t# {comment}");
+        };
+        let width = max.saturating_sub(prefix).max(1);
+        let mut line = String::new();
+        for word in comment.split_whitespace() {
+            if !line.is_empty() && columns(&line) + 1 + columns(word) > width {
+                writeln!(self.out, "\t# {}", core::mem::take(&mut line))?;
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+            // Words have no tabs, so every character takes one column.
+            while let Some((split, _)) = line.char_indices().nth(width) {
+                writeln!(self.out, "\t# {}", &line[..split])?;
+                line.drain(..split);
+            }
+        }
+        writeln!(self.out, "\t# {line}")
+    }
+    pub(crate) fn directive(&mut self, directive: impl Display) -> io::Result<()> {
+        self.commented_directive(directive, None)
+    }
+    pub(crate) fn commented_directive(
+        &mut self,
+        directive: impl Display,
+        comment: Option<String>,
+    ) -> io::Result<()> {
+        let text = match self.minify {
+            true => directive.to_string(),
+            false => format!("\t{directive}"),
+        };
+        self.write_line(&text, comment.as_slice())
+    }
+}
+/// The alternate form `{:#}` leaves out spaces between operands and size suffixes that a
+/// register operand already implies.
+impl Display for Instruction<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Instruction::Vector { .. } = self {
+            return write_vector(f, self);
+        }
+        if let Instruction::Locked { .. } = self {
+            write!(f, "lock ")?;
+            return self.unlocked().fmt(f);
+        }
+        if let Instruction::String { op, size, rep } = *self {
+            if let Some(rep) = rep {
+                write!(f, "{} ", rep.prefix())?;
+            }
+            return write!(f, "{}{}", op.mnemonic(), size.suffix());
+        }
+        // Instructions are checked before they are written. Shown otherwise, one whose size
+        // cannot be told is left without a suffix.
+        let ((mnemonic, infix, size, operands), _) = self.lenient_parts();
+        let compact = f.alternate();
+
+        write!(f, "{mnemonic}{infix}")?;
+        if let Some(size) = size {
+            let implied = match *self {
+                Instruction::Binary {
+                    op:
+                        BinaryOp::Single(
+                            BinaryOpSingle::Shl | BinaryOpSingle::Shr | BinaryOpSingle::Sar,
+                        ),
+                    dst,
+                    ..
+                } => dst.is_register(),
+                // movsl and friends are string instructions.
+                Instruction::Binary {
+                    op: BinaryOp::Double(_),
+                    ..
+                } => false,
+                _ => operands.iter().any(Arg::is_register),
+            };
+            if !(compact && implied) {
+                write!(f, "{}", size.suffix())?;
+            }
+        }
+
+        let star = match self {
+            Instruction::Call { target } | Instruction::Jmp { target } => needs_star(target),
+            _ => false,
+        };
+        for (i, operand) in operands.iter().enumerate() {
+            let separator = match i {
+                0 if star => " *",
+                0 => " ",
+                _ if compact => ",",
+                _ => ", ",
+            };
+            if compact {
+                write!(f, "{separator}{operand:#}")?;
+            } else {
+                write!(f, "{separator}{operand}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The mnemonic, infix, size suffix and operands in AT&T order of a scalar instruction.
+type Parts<'a> = (&'static str, &'static str, Option<ArgSize>, Vec<Arg<'a>>);
+
+impl<'a> Instruction<'a> {
+    /// Checks that the operands have the sizes and kinds needed to write the instruction,
+    /// and that memory displacements fit in 32 bits.
+    pub fn check_operands(&self) -> Result<(), X64WriterError> {
+        for operand in self.operands() {
+            if let Arg::Memory(memory) = operand {
+                memory.check_displacement()?;
+            }
+        }
+        match *self {
+            Instruction::Vector { op, src1, .. } if src1.is_none() != op.has_one_source() => {
+                Err(X64WriterError::InvalidOperand(format!(
+                    "wrong number of sources for {}",
+                    op.mnemonic()
+                )))
+            }
+            Instruction::Vector { .. } => broadcast_lanes(self).map(drop),
+            Instruction::Locked { .. } => self.unlocked().check_operands(),
+            Instruction::String { .. } => Ok(()),
+            _ => self.parts().map(drop),
+        }
+    }
+    fn parts(&self) -> Result<Parts<'a>, X64WriterError> {
+        match self.lenient_parts() {
+            (parts, None) => Ok(parts),
+            (_, Some(error)) => Err(error),
+        }
+    }
+    /// The parts with the sizes that could be told, and the error of the first that could not.
+    fn lenient_parts(&self) -> (Parts<'a>, Option<X64WriterError>) {
+        let mut error = None;
+        let mut size = |size: Result<ArgSize, X64WriterError>| match size {
+            Ok(size) => Some(size),
+            Err(e) => {
+                error.get_or_insert(e);
+                None
+            }
+        };
+        // The infix is the condition code, or the source size of a widening move.
+        let parts = match *self {
+            Instruction::Mov { dst, src } => (
+                "mov",
+                "",
+                size(operand_size("mov", &dst, &src)),
+                vec![src, dst],
+            ),
+            Instruction::CMov {
+                condition,
+                dst,
+                src,
+            } => ("cmov", condition.suffix(), None, vec![src, dst]),
+            Instruction::SetCC { condition, dst } => ("set", condition.suffix(), None, vec![dst]),
+            Instruction::Push { src } => ("push", "", size(sized("push", &src)), vec![src]),
+            Instruction::Pop { dst } => ("pop", "", size(sized("pop", &dst)), vec![dst]),
+            Instruction::Binary {
+                op:
+                    BinaryOp::Single(
+                        op @ (BinaryOpSingle::Shl | BinaryOpSingle::Shr | BinaryOpSingle::Sar),
+                    ),
+                dst,
+                src,
+            } => (
+                op.mnemonic(),
+                "",
+                size(shift_size(op.mnemonic(), &dst, &src)),
+                vec![src, dst],
+            ),
+            // Bit offsets given as immediates take a single byte like shift counts.
+            Instruction::Binary {
+                op:
+                    BinaryOp::Single(
+                        op @ (BinaryOpSingle::Bt
+                        | BinaryOpSingle::Bts
+                        | BinaryOpSingle::Btr
+                        | BinaryOpSingle::Btc),
+                    ),
+                dst,
+                src: src @ Arg::Int(_),
+            } => (
+                op.mnemonic(),
+                "",
+                size(shift_size(op.mnemonic(), &dst, &src)),
+                vec![src, dst],
+            ),
+            Instruction::Binary {
+                op: BinaryOp::Single(op),
+                dst,
+                src,
+            } => (
+                op.mnemonic(),
+                "",
+                size(operand_size(op.mnemonic(), &dst, &src)),
+                vec![src, dst],
+            ),
+            Instruction::Binary {
+                op: BinaryOp::Double(op),
+                dst,
+                src,
+            } => {
+                let src_suffix = size(sized(op.mnemonic(), &src)).map_or("", ArgSize::suffix);
+                (op.mnemonic(), src_suffix, dst.size(), vec![src, dst])
+            }
+            Instruction::Binary {
+                op: BinaryOp::Sse(op),
+                dst,
+                src,
+            } => {
+                let integer = match op.converts_from_integer() {
+                    true => size(sized(op.mnemonic(), &src)),
+                    false => None,
+                };
+                (op.mnemonic(), "", integer, vec![src, dst])
+            }
+            Instruction::Unary {
+                op: UnaryOp::Single(op),
+                dst,
+            } => (
+                op.mnemonic(),
+                "",
+                size(sized(op.mnemonic(), &dst)),
+                vec![dst],
+            ),
+            Instruction::Unary {
+                op: UnaryOp::Cmpxchg16b,
+                dst,
+            } => ("cmpxchg16b", "", None, vec![dst]),
+            Instruction::Ternary { op, dst, src, src2 } => (
+                op.mnemonic(),
+                "",
+                size(operand_size(op.mnemonic(), &dst, &src)),
+                vec![src2, src, dst],
+            ),
+            Instruction::ThreeAddress { op, dst, lhs, rhs } => (
+                op.mnemonic(),
+                "",
+                size(operand_size(op.mnemonic(), &dst, &lhs)),
+                vec![rhs, lhs, dst],
+            ),
+            Instruction::Call { target } => ("call", "", None, vec![target]),
+            Instruction::Jmp { target } => ("jmp", "", None, vec![target]),
+            Instruction::CJmp { condition, target } => {
+                ("j", condition.suffix(), None, vec![target])
+            }
+            Instruction::Nonary { op } => (op.mnemonic(), "", None, Vec::new()),
+            Instruction::Vector { .. }
+            | Instruction::Locked { .. }
+            | Instruction::String { .. } => unreachable!(),
+        };
+        (parts, error)
+    }
+}
 
+/// The instructions of the original builder, callable without importing [`InstructionBuilder`].
+impl<O: Write> AsmWriter<O> {
+    pub fn emit_label<'a>(&mut self, label: impl Into<Label<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::emit_label(self, label)
+    }
+    pub fn comment(&mut self, comment: impl AsRef<str>) -> Result<(), X64WriterError> {
+        InstructionBuilder::comment(self, comment)
+    }
     pub fn build_mov<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        let dst = dst.into();
-        let src = src.into();
-        let suffix = get_size(&dst, &src).suffix();
-        writeln!(self.out, "\tmov{suffix} {src}, {dst}")
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_mov(self, dst, src)
     }
     pub fn build_cmov<'a>(
         &mut self,
         c: Condition,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        let dst = dst.into();
-        let src = src.into();
-        let suffix = c.suffix();
-        writeln!(self.out, "\tcmov{suffix} {src}, {dst}")
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_cmov(self, c, dst, src)
     }
-    pub fn build_push<'a>(&mut self, src: impl Into<Arg<'a>>) -> io::Result<()> {
-        let src: Arg = src.into();
-        let suffix = src.size().unwrap().suffix();
-        writeln!(self.out, "\tpush{suffix} {src}")
+    pub fn build_push<'a>(&mut self, src: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_push(self, src)
     }
-    pub fn build_pop<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        let dst: Arg = dst.into();
-        let suffix = dst.size().unwrap().suffix();
-        writeln!(self.out, "\tpop{suffix} {dst}")
+    pub fn build_pop<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_pop(self, dst)
     }
-
     pub fn build_binary_op<'a>(
         &mut self,
         op: impl Into<BinaryOp>,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        let op = op.into();
-        match op {
-            BinaryOp::Single(s) => self.build_binary_op_single(s, dst, src),
-        }
-    }
-    fn build_binary_op_single<'a>(
-        &mut self,
-        op: BinaryOpSingle,
-        dst: impl Into<Arg<'a>>,
-        src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        let dst = dst.into();
-        let src = src.into();
-        let suffix = get_size(&dst, &src).suffix();
-        let mnemonic = op.mnemonic();
-        writeln!(self.out, "\t{mnemonic}{suffix} {src}, {dst}")
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_binary_op(self, op, dst, src)
     }
-
     pub fn build_add<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Add, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_add(self, dst, src)
     }
     pub fn build_sub<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Sub, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_sub(self, dst, src)
     }
     pub fn build_imul<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::IMul, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_imul(self, dst, src)
     }
     pub fn build_and<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::And, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_and(self, dst, src)
     }
     pub fn build_or<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Or, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_or(self, dst, src)
     }
     pub fn build_xor<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Xor, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_xor(self, dst, src)
     }
     pub fn build_lea<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Lea, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_lea(self, dst, src)
     }
     pub fn build_cmp<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Cmp, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_cmp(self, dst, src)
     }
     pub fn build_test<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
         src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Test, dst, src)
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_test(self, dst, src)
     }
     pub fn build_shl<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
-        src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Shl, dst, src)
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_shl(self, dst, count)
     }
     pub fn build_shr<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
-        src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Shr, dst, src)
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_shr(self, dst, count)
     }
     pub fn build_sar<'a>(
         &mut self,
         dst: impl Into<Arg<'a>>,
-        src: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        self.build_binary_op(BinaryOpSingle::Sar, dst, src)
+        count: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_sar(self, dst, count)
     }
-
     pub fn build_unary_op<'a>(
         &mut self,
         op: impl Into<UnaryOp>,
         dst: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        let op = op.into();
-        match op {
-            UnaryOp::Single(s) => self.build_unary_op_single(s, dst),
-        }
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_unary_op(self, op, dst)
     }
-    fn build_unary_op_single<'a>(
-        &mut self,
-        op: UnaryOpSingle,
-        dst: impl Into<Arg<'a>>,
-    ) -> io::Result<()> {
-        let dst: Arg = dst.into();
-        let suffix = dst.size().unwrap().suffix();
-        let mnemonic = op.mnemonic();
-        writeln!(self.out, "\t{mnemonic}{suffix} {dst}")
+    pub fn build_inc<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_inc(self, dst)
     }
-
-    pub fn build_inc<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::Inc, dst)
+    pub fn build_dec<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_dec(self, dst)
+    }
+    pub fn build_neg<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_neg(self, dst)
+    }
+    pub fn build_not<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_not(self, dst)
+    }
+    pub fn build_mul<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_mul(self, dst)
     }
-    pub fn build_dec<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::Dec, dst)
+    pub fn build_unary_imul<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_unary_imul(self, dst)
     }
-    pub fn build_neg<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::Neg, dst)
+    pub fn build_div<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_div(self, dst)
     }
-    pub fn build_not<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::Not, dst)
+    pub fn build_idiv<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_idiv(self, dst)
     }
-    pub fn build_mul<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::Mul, dst)
+    pub fn build_call<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_call(self, dst)
     }
-    pub fn build_unary_imul<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::IMul, dst)
+    pub fn build_jmp<'a>(&mut self, dst: impl Into<Arg<'a>>) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_jmp(self, dst)
     }
-    pub fn build_div<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::Div, dst)
+    pub fn build_cjmp<'a>(
+        &mut self,
+        c: Condition,
+        dst: impl Into<Arg<'a>>,
+    ) -> Result<(), X64WriterError> {
+        InstructionBuilder::<'a>::build_cjmp(self, c, dst)
     }
-    pub fn build_idiv<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        self.build_unary_op(UnaryOpSingle::IDiv, dst)
+    pub fn build_nonary_op(&mut self, op: NonaryOp) -> Result<(), X64WriterError> {
+        InstructionBuilder::build_nonary_op(self, op)
     }
+    pub fn build_ret(&mut self) -> Result<(), X64WriterError> {
+        InstructionBuilder::build_ret(self)
+    }
+}
 
-    pub fn build_call<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        let dst = dst.into();
-        let needs_star = dst.is_memory() || dst.is_register();
-        let star = if needs_star { "*" } else { "" };
-        writeln!(self.out, "\tcall {star}{dst}")
+impl<'a, O: Write> InstructionBuilder<'a> for AsmWriter<O> {
+    fn build_instruction(&mut self, instruction: Instruction<'a>) -> Result<(), X64WriterError> {
+        self.write_instruction(&instruction)
     }
-    pub fn build_jmp<'a>(&mut self, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        let dst = dst.into();
-        let needs_star = dst.is_memory() || dst.is_register();
-        let star = if needs_star { "*" } else { "" };
-        writeln!(self.out, "\tjmp {star}{dst}")
+    fn emit_label(&mut self, label: impl Into<Label<'a>>) -> Result<(), X64WriterError> {
+        let label = label.into();
+        self.define_symbol(label.label);
+        let demangled = self.demangled(label.label);
+        let name = self.label_name(label);
+        self.write_line(&format!("{name}:"), demangled.as_slice())?;
+        Ok(())
+    }
+    fn comment(&mut self, comment: impl AsRef<str>) -> Result<(), X64WriterError> {
+        if self.minify {
+            return Ok(());
+        }
+        self.write_comment(comment.as_ref())?;
+        Ok(())
     }
-    pub fn build_cjmp<'a>(&mut self, c: Condition, dst: impl Into<Arg<'a>>) -> io::Result<()> {
-        let dst = dst.into();
-        let suffix = c.suffix();
-        writeln!(self.out, "\tj{suffix} {dst}")
+    fn target_features(&self) -> TargetFeatures {
+        self.features
     }
+}
 
-    pub fn build_nonary_op(&mut self, op: NonaryOp) -> io::Result<()> {
-        let mnemonic = op.mnemonic();
-        writeln!(self.out, "\t{mnemonic}")
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CodeMode {
+    Code16,
+    Code32,
+    Code64,
+}
+impl CodeMode {
+    pub fn directive(self) -> &'static str {
+        match self {
+            Self::Code16 => ".code16",
+            Self::Code32 => ".code32",
+            Self::Code64 => ".code64",
+        }
     }
-    pub fn build_ret(&mut self) -> io::Result<()> {
-        self.build_nonary_op(NonaryOp::Ret)
+}
+impl Display for CodeMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Code16 => write!(f, "16-bit"),
+            Self::Code32 => write!(f, "32-bit"),
+            Self::Code64 => write!(f, "64-bit"),
+        }
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BinaryOp {
     Single(BinaryOpSingle),
+    Double(BinaryOpDouble),
+    Sse(SseOp),
 }
 impl From<BinaryOpSingle> for BinaryOp {
     fn from(value: BinaryOpSingle) -> Self {
         Self::Single(value)
     }
 }
+impl From<BinaryOpDouble> for BinaryOp {
+    fn from(value: BinaryOpDouble) -> Self {
+        Self::Double(value)
+    }
+}
+impl From<SseOp> for BinaryOp {
+    fn from(value: SseOp) -> Self {
+        Self::Sse(value)
+    }
+}
+
+/// Binary operations with a size suffix for the source followed by one for the destination
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOpDouble {
+    Movzx,
+    Movsx,
+}
+impl BinaryOpDouble {
+    pub const ALL: [BinaryOpDouble; 2] = [BinaryOpDouble::Movzx, BinaryOpDouble::Movsx];
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Movzx => "movz",
+            Self::Movsx => "movs",
+        }
+    }
+}
 
 /// Binary operations that have a single size suffix
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BinaryOpSingle {
     Add,
+    Adc,
     Sub,
+    Sbb,
     IMul,
     And,
     Or,
@@ -272,13 +998,58 @@ pub enum BinaryOpSingle {
     Shl,
     Shr,
     Sar,
+    Xchg,
+    Movbe,
+    Popcnt,
+    Lzcnt,
+    Tzcnt,
+    Bsf,
+    Bsr,
+    Bt,
+    Bts,
+    Btr,
+    Btc,
+    Xadd,
+    Cmpxchg,
 }
 impl BinaryOpSingle {
+    pub const ALL: [BinaryOpSingle; 27] = [
+        BinaryOpSingle::Add,
+        BinaryOpSingle::Adc,
+        BinaryOpSingle::Sub,
+        BinaryOpSingle::Sbb,
+        BinaryOpSingle::IMul,
+        BinaryOpSingle::And,
+        BinaryOpSingle::Or,
+        BinaryOpSingle::Xor,
+        BinaryOpSingle::Lea,
+        BinaryOpSingle::Cmp,
+        BinaryOpSingle::Test,
+        BinaryOpSingle::Shl,
+        BinaryOpSingle::Shr,
+        BinaryOpSingle::Sar,
+        BinaryOpSingle::Xchg,
+        BinaryOpSingle::Movbe,
+        BinaryOpSingle::Popcnt,
+        BinaryOpSingle::Lzcnt,
+        BinaryOpSingle::Tzcnt,
+        BinaryOpSingle::Bsf,
+        BinaryOpSingle::Bsr,
+        BinaryOpSingle::Bt,
+        BinaryOpSingle::Bts,
+        BinaryOpSingle::Btr,
+        BinaryOpSingle::Btc,
+        BinaryOpSingle::Xadd,
+        BinaryOpSingle::Cmpxchg,
+    ];
+
     pub fn mnemonic(self) -> &'static str {
         use BinaryOpSingle::*;
         match self {
             Add => "add",
+            Adc => "adc",
             Sub => "sub",
+            Sbb => "sbb",
             IMul => "imul",
             And => "and",
             Or => "or",
@@ -289,12 +1060,423 @@ impl BinaryOpSingle {
             Shl => "shl",
             Shr => "shr",
             Sar => "sar",
+            Xchg => "xchg",
+            Movbe => "movbe",
+            Popcnt => "popcnt",
+            Lzcnt => "lzcnt",
+            Tzcnt => "tzcnt",
+            Bsf => "bsf",
+            Bsr => "bsr",
+            Bt => "bt",
+            Bts => "bts",
+            Btr => "btr",
+            Btc => "btc",
+            Xadd => "xadd",
+            Cmpxchg => "cmpxchg",
         }
     }
 }
 
+/// SSE operations on `%xmm` registers, whose mnemonic already implies the operand size
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SseOp {
+    Movss,
+    Movsd,
+    Movaps,
+    Movups,
+    Movapd,
+    Movupd,
+    Movd,
+    Movq,
+    Addss,
+    Addsd,
+    Addps,
+    Addpd,
+    Subss,
+    Subsd,
+    Subps,
+    Subpd,
+    Mulss,
+    Mulsd,
+    Mulps,
+    Mulpd,
+    Divss,
+    Divsd,
+    Divps,
+    Divpd,
+    Sqrtss,
+    Sqrtsd,
+    Andps,
+    Andpd,
+    Xorps,
+    Xorpd,
+    Ucomiss,
+    Ucomisd,
+    Cvtsi2ss,
+    Cvtsi2sd,
+    Cvttss2si,
+    Cvttsd2si,
+    Cvtss2sd,
+    Cvtsd2ss,
+}
+impl SseOp {
+    pub const ALL: [SseOp; 38] = [
+        SseOp::Movss,
+        SseOp::Movsd,
+        SseOp::Movaps,
+        SseOp::Movups,
+        SseOp::Movapd,
+        SseOp::Movupd,
+        SseOp::Movd,
+        SseOp::Movq,
+        SseOp::Addss,
+        SseOp::Addsd,
+        SseOp::Addps,
+        SseOp::Addpd,
+        SseOp::Subss,
+        SseOp::Subsd,
+        SseOp::Subps,
+        SseOp::Subpd,
+        SseOp::Mulss,
+        SseOp::Mulsd,
+        SseOp::Mulps,
+        SseOp::Mulpd,
+        SseOp::Divss,
+        SseOp::Divsd,
+        SseOp::Divps,
+        SseOp::Divpd,
+        SseOp::Sqrtss,
+        SseOp::Sqrtsd,
+        SseOp::Andps,
+        SseOp::Andpd,
+        SseOp::Xorps,
+        SseOp::Xorpd,
+        SseOp::Ucomiss,
+        SseOp::Ucomisd,
+        SseOp::Cvtsi2ss,
+        SseOp::Cvtsi2sd,
+        SseOp::Cvttss2si,
+        SseOp::Cvttsd2si,
+        SseOp::Cvtss2sd,
+        SseOp::Cvtsd2ss,
+    ];
+
+    pub fn mnemonic(self) -> &'static str {
+        use SseOp::*;
+        match self {
+            Movss => "movss",
+            Movsd => "movsd",
+            Movaps => "movaps",
+            Movups => "movups",
+            Movapd => "movapd",
+            Movupd => "movupd",
+            Movd => "movd",
+            Movq => "movq",
+            Addss => "addss",
+            Addsd => "addsd",
+            Addps => "addps",
+            Addpd => "addpd",
+            Subss => "subss",
+            Subsd => "subsd",
+            Subps => "subps",
+            Subpd => "subpd",
+            Mulss => "mulss",
+            Mulsd => "mulsd",
+            Mulps => "mulps",
+            Mulpd => "mulpd",
+            Divss => "divss",
+            Divsd => "divsd",
+            Divps => "divps",
+            Divpd => "divpd",
+            Sqrtss => "sqrtss",
+            Sqrtsd => "sqrtsd",
+            Andps => "andps",
+            Andpd => "andpd",
+            Xorps => "xorps",
+            Xorpd => "xorpd",
+            Ucomiss => "ucomiss",
+            Ucomisd => "ucomisd",
+            Cvtsi2ss => "cvtsi2ss",
+            Cvtsi2sd => "cvtsi2sd",
+            Cvttss2si => "cvttss2si",
+            Cvttsd2si => "cvttsd2si",
+            Cvtss2sd => "cvtss2sd",
+            Cvtsd2ss => "cvtsd2ss",
+        }
+    }
+    /// Whether the operation takes a general purpose integer, whose size the mnemonic
+    /// then needs as suffix.
+    pub fn converts_from_integer(self) -> bool {
+        matches!(self, Self::Cvtsi2ss | Self::Cvtsi2sd)
+    }
+    /// The instruction set extension introducing the operation.
+    pub fn feature(self) -> Feature {
+        use SseOp::*;
+        match self {
+            Movss | Movaps | Movups | Addss | Addps | Subss | Subps | Mulss | Mulps | Divss
+            | Divps | Sqrtss | Andps | Xorps | Ucomiss | Cvtsi2ss | Cvttss2si => Feature::Sse,
+            _ => Feature::Sse2,
+        }
+    }
+}
+
+/// AVX and AVX-512 operations. Unless the operation has a single source, the destination
+/// is separate from both sources.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VectorOp {
+    Vaddps,
+    Vaddpd,
+    Vsubps,
+    Vsubpd,
+    Vmulps,
+    Vmulpd,
+    Vdivps,
+    Vdivpd,
+    Vminps,
+    Vminpd,
+    Vmaxps,
+    Vmaxpd,
+    Vandps,
+    Vandpd,
+    Vxorps,
+    Vxorpd,
+    Vaddss,
+    Vaddsd,
+    Vsubss,
+    Vsubsd,
+    Vmulss,
+    Vmulsd,
+    Vdivss,
+    Vdivsd,
+    Vfmadd231ps,
+    Vfmadd231pd,
+    Vfmadd231ss,
+    Vfmadd231sd,
+    Vpaddd,
+    Vpaddq,
+    Vpsubd,
+    Vpsubq,
+    Vpmulld,
+    Vpand,
+    Vpandd,
+    Vpandq,
+    Vpxor,
+    Vpxord,
+    Vpxorq,
+    Vsqrtps,
+    Vsqrtpd,
+    Vmovaps,
+    Vmovapd,
+    Vmovups,
+    Vmovupd,
+    Vmovdqu,
+    Vmovdqu32,
+    Vmovdqu64,
+    Vbroadcastss,
+    Vbroadcastsd,
+    Kmovw,
+    Kmovq,
+}
+impl VectorOp {
+    pub const ALL: [VectorOp; 52] = [
+        VectorOp::Vaddps,
+        VectorOp::Vaddpd,
+        VectorOp::Vsubps,
+        VectorOp::Vsubpd,
+        VectorOp::Vmulps,
+        VectorOp::Vmulpd,
+        VectorOp::Vdivps,
+        VectorOp::Vdivpd,
+        VectorOp::Vminps,
+        VectorOp::Vminpd,
+        VectorOp::Vmaxps,
+        VectorOp::Vmaxpd,
+        VectorOp::Vandps,
+        VectorOp::Vandpd,
+        VectorOp::Vxorps,
+        VectorOp::Vxorpd,
+        VectorOp::Vaddss,
+        VectorOp::Vaddsd,
+        VectorOp::Vsubss,
+        VectorOp::Vsubsd,
+        VectorOp::Vmulss,
+        VectorOp::Vmulsd,
+        VectorOp::Vdivss,
+        VectorOp::Vdivsd,
+        VectorOp::Vfmadd231ps,
+        VectorOp::Vfmadd231pd,
+        VectorOp::Vfmadd231ss,
+        VectorOp::Vfmadd231sd,
+        VectorOp::Vpaddd,
+        VectorOp::Vpaddq,
+        VectorOp::Vpsubd,
+        VectorOp::Vpsubq,
+        VectorOp::Vpmulld,
+        VectorOp::Vpand,
+        VectorOp::Vpandd,
+        VectorOp::Vpandq,
+        VectorOp::Vpxor,
+        VectorOp::Vpxord,
+        VectorOp::Vpxorq,
+        VectorOp::Vsqrtps,
+        VectorOp::Vsqrtpd,
+        VectorOp::Vmovaps,
+        VectorOp::Vmovapd,
+        VectorOp::Vmovups,
+        VectorOp::Vmovupd,
+        VectorOp::Vmovdqu,
+        VectorOp::Vmovdqu32,
+        VectorOp::Vmovdqu64,
+        VectorOp::Vbroadcastss,
+        VectorOp::Vbroadcastsd,
+        VectorOp::Kmovw,
+        VectorOp::Kmovq,
+    ];
+
+    pub fn mnemonic(self) -> &'static str {
+        use VectorOp::*;
+        match self {
+            Vaddps => "vaddps",
+            Vaddpd => "vaddpd",
+            Vsubps => "vsubps",
+            Vsubpd => "vsubpd",
+            Vmulps => "vmulps",
+            Vmulpd => "vmulpd",
+            Vdivps => "vdivps",
+            Vdivpd => "vdivpd",
+            Vminps => "vminps",
+            Vminpd => "vminpd",
+            Vmaxps => "vmaxps",
+            Vmaxpd => "vmaxpd",
+            Vandps => "vandps",
+            Vandpd => "vandpd",
+            Vxorps => "vxorps",
+            Vxorpd => "vxorpd",
+            Vaddss => "vaddss",
+            Vaddsd => "vaddsd",
+            Vsubss => "vsubss",
+            Vsubsd => "vsubsd",
+            Vmulss => "vmulss",
+            Vmulsd => "vmulsd",
+            Vdivss => "vdivss",
+            Vdivsd => "vdivsd",
+            Vfmadd231ps => "vfmadd231ps",
+            Vfmadd231pd => "vfmadd231pd",
+            Vfmadd231ss => "vfmadd231ss",
+            Vfmadd231sd => "vfmadd231sd",
+            Vpaddd => "vpaddd",
+            Vpaddq => "vpaddq",
+            Vpsubd => "vpsubd",
+            Vpsubq => "vpsubq",
+            Vpmulld => "vpmulld",
+            Vpand => "vpand",
+            Vpandd => "vpandd",
+            Vpandq => "vpandq",
+            Vpxor => "vpxor",
+            Vpxord => "vpxord",
+            Vpxorq => "vpxorq",
+            Vsqrtps => "vsqrtps",
+            Vsqrtpd => "vsqrtpd",
+            Vmovaps => "vmovaps",
+            Vmovapd => "vmovapd",
+            Vmovups => "vmovups",
+            Vmovupd => "vmovupd",
+            Vmovdqu => "vmovdqu",
+            Vmovdqu32 => "vmovdqu32",
+            Vmovdqu64 => "vmovdqu64",
+            Vbroadcastss => "vbroadcastss",
+            Vbroadcastsd => "vbroadcastsd",
+            Kmovw => "kmovw",
+            Kmovq => "kmovq",
+        }
+    }
+    /// Whether the operation only takes the source that can be memory, like moves.
+    pub fn has_one_source(self) -> bool {
+        use VectorOp::*;
+        matches!(
+            self,
+            Vsqrtps
+                | Vsqrtpd
+                | Vmovaps
+                | Vmovapd
+                | Vmovups
+                | Vmovupd
+                | Vmovdqu
+                | Vmovdqu32
+                | Vmovdqu64
+                | Vbroadcastss
+                | Vbroadcastsd
+                | Kmovw
+                | Kmovq
+        )
+    }
+    /// Whether the operation moves whole vectors, which it can also store to memory.
+    pub fn is_move(self) -> bool {
+        use VectorOp::*;
+        matches!(
+            self,
+            Vmovaps | Vmovapd | Vmovups | Vmovupd | Vmovdqu | Vmovdqu32 | Vmovdqu64
+        )
+    }
+    /// Whether the operation only computes the lowest element, keeping the other lanes of
+    /// the first source.
+    pub fn is_scalar(self) -> bool {
+        use VectorOp::*;
+        matches!(
+            self,
+            Vaddss
+                | Vaddsd
+                | Vsubss
+                | Vsubsd
+                | Vmulss
+                | Vmulsd
+                | Vdivss
+                | Vdivsd
+                | Vfmadd231ss
+                | Vfmadd231sd
+        )
+    }
+    /// Whether the destination is also read, as the addend of a fused multiply-add.
+    pub fn accumulates(self) -> bool {
+        use VectorOp::*;
+        matches!(self, Vfmadd231ps | Vfmadd231pd | Vfmadd231ss | Vfmadd231sd)
+    }
+    /// The size of an element in bytes, which is what a broadcast repeats in every lane.
+    /// Operations on plain bits have none.
+    pub fn element_size(self) -> Option<u8> {
+        use VectorOp::*;
+        match self {
+            Vaddps | Vsubps | Vmulps | Vdivps | Vminps | Vmaxps | Vandps | Vxorps | Vaddss
+            | Vsubss | Vmulss | Vdivss | Vfmadd231ps | Vfmadd231ss | Vpaddd | Vpsubd | Vpmulld
+            | Vpandd | Vpxord | Vsqrtps | Vmovaps | Vmovups | Vmovdqu32 | Vbroadcastss => Some(4),
+            Vaddpd | Vsubpd | Vmulpd | Vdivpd | Vminpd | Vmaxpd | Vandpd | Vxorpd | Vaddsd
+            | Vsubsd | Vmulsd | Vdivsd | Vfmadd231pd | Vfmadd231sd | Vpaddq | Vpsubq | Vpandq
+            | Vpxorq | Vsqrtpd | Vmovapd | Vmovupd | Vmovdqu64 | Vbroadcastsd => Some(8),
+            Vpand | Vpxor | Vmovdqu | Kmovw | Kmovq => None,
+        }
+    }
+    /// Whether the operation only has an EVEX encoding, and thus always needs AVX-512.
+    pub fn is_evex_only(self) -> bool {
+        use VectorOp::*;
+        matches!(
+            self,
+            Vpandd | Vpandq | Vpxord | Vpxorq | Vmovdqu32 | Vmovdqu64
+        )
+    }
+    /// Whether the operation has no EVEX encoding, and thus no masking, broadcast,
+    /// `%zmm` operands or registers beyond `%xmm15`.
+    pub fn is_vex_only(self) -> bool {
+        use VectorOp::*;
+        matches!(self, Vpand | Vpxor | Vmovdqu | Kmovw | Kmovq)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum UnaryOp {
     Single(UnaryOpSingle),
+    /// Compares `%rdx:%rax` with the 16 bytes at the memory operand, which it replaces by
+    /// `%rcx:%rbx` if they are equal and loads into `%rdx:%rax` otherwise.
+    Cmpxchg16b,
 }
 impl From<UnaryOpSingle> for UnaryOp {
     fn from(value: UnaryOpSingle) -> Self {
@@ -313,8 +1495,24 @@ pub enum UnaryOpSingle {
     IMul,
     Div,
     IDiv,
+    Bswap,
 }
 impl UnaryOpSingle {
+    pub const ALL: [UnaryOpSingle; 9] = [
+        UnaryOpSingle::Inc,
+        UnaryOpSingle::Dec,
+        UnaryOpSingle::Neg,
+        UnaryOpSingle::Not,
+        UnaryOpSingle::Mul,
+        UnaryOpSingle::IMul,
+        UnaryOpSingle::Div,
+        UnaryOpSingle::IDiv,
+        UnaryOpSingle::Bswap,
+    ];
+
+    pub fn is_multiply_or_divide(self) -> bool {
+        matches!(self, Self::Mul | Self::IMul | Self::Div | Self::IDiv)
+    }
     pub fn mnemonic(self) -> &'static str {
         match self {
             Self::Inc => "inc",
@@ -325,6 +1523,63 @@ impl UnaryOpSingle {
             Self::IMul => "imul",
             Self::Div => "div",
             Self::IDiv => "idiv",
+            Self::Bswap => "bswap",
+        }
+    }
+}
+
+/// The operations a `lock` prefix makes atomic
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LockedOp {
+    Binary(BinaryOpSingle),
+    Unary(UnaryOp),
+}
+impl LockedOp {
+    /// Whether the operation may take a `lock` prefix, given a memory destination.
+    pub fn is_lockable(self) -> bool {
+        use BinaryOpSingle::*;
+        match self {
+            Self::Binary(op) => matches!(
+                op,
+                Add | Adc | Sub | Sbb | And | Or | Xor | Xchg | Bts | Btr | Btc | Xadd | Cmpxchg
+            ),
+            Self::Unary(UnaryOp::Single(op)) => matches!(
+                op,
+                UnaryOpSingle::Inc | UnaryOpSingle::Dec | UnaryOpSingle::Neg | UnaryOpSingle::Not
+            ),
+            Self::Unary(UnaryOp::Cmpxchg16b) => true,
+        }
+    }
+}
+
+/// Operations with a third operand after the source in Intel order, written first in AT&T
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TernaryOp {
+    Bextr,
+    Shld,
+    Shrd,
+    Shlx,
+    Shrx,
+    Sarx,
+}
+impl TernaryOp {
+    pub const ALL: [TernaryOp; 6] = [
+        TernaryOp::Bextr,
+        TernaryOp::Shld,
+        TernaryOp::Shrd,
+        TernaryOp::Shlx,
+        TernaryOp::Shrx,
+        TernaryOp::Sarx,
+    ];
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Bextr => "bextr",
+            Self::Shld => "shld",
+            Self::Shrd => "shrd",
+            Self::Shlx => "shlx",
+            Self::Shrx => "shrx",
+            Self::Sarx => "sarx",
         }
     }
 }
@@ -332,11 +1587,143 @@ impl UnaryOpSingle {
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum NonaryOp {
     Ret,
+    Cpuid,
+    Xgetbv,
+    Rdmsr,
+    Wrmsr,
+    Swapgs,
+    Iretq,
+    Cld,
+    Sysretq,
+    Cltq,
+    Cbtw,
+    Cwtl,
+    Cwtd,
+    Cltd,
+    Cqto,
+    Mfence,
+    Lfence,
+    Sfence,
 }
 impl NonaryOp {
+    pub const ALL: [NonaryOp; 18] = [
+        NonaryOp::Ret,
+        NonaryOp::Cpuid,
+        NonaryOp::Xgetbv,
+        NonaryOp::Rdmsr,
+        NonaryOp::Wrmsr,
+        NonaryOp::Swapgs,
+        NonaryOp::Iretq,
+        NonaryOp::Cld,
+        NonaryOp::Sysretq,
+        NonaryOp::Cltq,
+        NonaryOp::Cbtw,
+        NonaryOp::Cwtl,
+        NonaryOp::Cwtd,
+        NonaryOp::Cltd,
+        NonaryOp::Cqto,
+        NonaryOp::Mfence,
+        NonaryOp::Lfence,
+        NonaryOp::Sfence,
+    ];
+
     pub fn mnemonic(self) -> &'static str {
         match self {
             Self::Ret => "ret",
+            Self::Cpuid => "cpuid",
+            Self::Xgetbv => "xgetbv",
+            Self::Rdmsr => "rdmsr",
+            Self::Wrmsr => "wrmsr",
+            Self::Swapgs => "swapgs",
+            Self::Iretq => "iretq",
+            Self::Cld => "cld",
+            Self::Sysretq => "sysretq",
+            Self::Cltq => "cltq",
+            Self::Cbtw => "cbtw",
+            Self::Cwtl => "cwtl",
+            Self::Cwtd => "cwtd",
+            Self::Cltd => "cltd",
+            Self::Cqto => "cqto",
+            Self::Mfence => "mfence",
+            Self::Lfence => "lfence",
+            Self::Sfence => "sfence",
+        }
+    }
+}
+
+/// The string instructions, which work on the memory at `%rsi` and `%rdi` and advance
+/// both by the operand size, backwards if the direction flag is set.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StringOp {
+    /// Copies from `(%rsi)` to `(%rdi)`.
+    Movs,
+    /// Stores the accumulator to `(%rdi)`.
+    Stos,
+    /// Loads `(%rsi)` into the accumulator.
+    Lods,
+    /// Compares the accumulator with `(%rdi)`.
+    Scas,
+    /// Compares `(%rsi)` with `(%rdi)`.
+    Cmps,
+}
+impl StringOp {
+    pub const ALL: [StringOp; 5] = [
+        StringOp::Movs,
+        StringOp::Stos,
+        StringOp::Lods,
+        StringOp::Scas,
+        StringOp::Cmps,
+    ];
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Movs => "movs",
+            Self::Stos => "stos",
+            Self::Lods => "lods",
+            Self::Scas => "scas",
+            Self::Cmps => "cmps",
+        }
+    }
+    /// Whether the operation sets the flags, and so repeats with `repe` or `repne`
+    /// instead of `rep`.
+    pub fn compares(self) -> bool {
+        matches!(self, Self::Scas | Self::Cmps)
+    }
+    /// Whether `rep` can prefix the operation.
+    pub fn takes_prefix(self, rep: RepPrefix) -> bool {
+        self.compares() != (rep == RepPrefix::Rep)
+    }
+    /// Fails unless `rep` can prefix the operation. The assembler takes any prefix, but
+    /// only `rep` repeats operations that do not compare, and only `repe` and `repne`
+    /// say when comparisons stop.
+    pub fn check_prefix(self, rep: RepPrefix) -> Result<(), X64WriterError> {
+        if self.takes_prefix(rep) {
+            return Ok(());
+        }
+        Err(X64WriterError::InvalidOperand(format!(
+            "{} cannot take a {} prefix",
+            self.mnemonic(),
+            rep.prefix()
+        )))
+    }
+}
+
+/// Repeats a string instruction `%rcx` times, counting `%rcx` down to zero. The
+/// conditional forms stop early once a comparison is unequal or equal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RepPrefix {
+    Rep,
+    Repe,
+    Repne,
+}
+impl RepPrefix {
+    pub const ALL: [RepPrefix; 3] = [RepPrefix::Rep, RepPrefix::Repe, RepPrefix::Repne];
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Rep => "rep",
+            Self::Repe => "repe",
+            Self::Repne => "repne",
         }
     }
 }
@@ -357,8 +1744,54 @@ pub enum Condition {
     Below,
     AboveEqual,
     BelowEqual,
+    Overflow,
+    NoOverflow,
 }
 impl Condition {
+    pub const ALL: [Condition; 16] = [
+        Condition::Zero,
+        Condition::NotZero,
+        Condition::Equal,
+        Condition::NotEqual,
+        Condition::Negative,
+        Condition::NonNegative,
+        Condition::GreaterThan,
+        Condition::LessThan,
+        Condition::GreaterEqual,
+        Condition::LessEqual,
+        Condition::Above,
+        Condition::Below,
+        Condition::AboveEqual,
+        Condition::BelowEqual,
+        Condition::Overflow,
+        Condition::NoOverflow,
+    ];
+
+    /// The condition that holds exactly when this one does not.
+    pub fn negate(self) -> Self {
+        use Condition::*;
+        match self {
+            Zero => NotZero,
+            NotZero => Zero,
+            Equal => NotEqual,
+            NotEqual => Equal,
+            Negative => NonNegative,
+            NonNegative => Negative,
+            GreaterThan => LessEqual,
+            LessThan => GreaterEqual,
+            GreaterEqual => LessThan,
+            LessEqual => GreaterThan,
+            Above => BelowEqual,
+            Below => AboveEqual,
+            AboveEqual => Below,
+            BelowEqual => Above,
+            Overflow => NoOverflow,
+            NoOverflow => Overflow,
+        }
+    }
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.suffix() == suffix)
+    }
     pub fn suffix(self) -> &'static str {
         use Condition::*;
         match self {
@@ -376,17 +1809,143 @@ impl Condition {
             Below => "b",
             AboveEqual => "ae",
             BelowEqual => "be",
+            Overflow => "o",
+            NoOverflow => "no",
+        }
+    }
+    pub fn flags(self) -> Flags {
+        use Condition::*;
+        match self {
+            Zero | NotZero | Equal | NotEqual => Flags::ZERO,
+            Negative | NonNegative => Flags::SIGN,
+            GreaterThan | LessEqual => Flags::ZERO.union(Flags::SIGN).union(Flags::OVERFLOW),
+            LessThan | GreaterEqual => Flags::SIGN.union(Flags::OVERFLOW),
+            Above | BelowEqual => Flags::CARRY.union(Flags::ZERO),
+            Below | AboveEqual => Flags::CARRY,
+            Overflow | NoOverflow => Flags::OVERFLOW,
+        }
+    }
+}
+
+fn format_instruction(instruction: &Instruction, minify: bool) -> String {
+    match minify {
+        true => format!("{instruction:#}"),
+        false => format!("\t{instruction}"),
+    }
+}
+/// The columns `line` takes up, with tab stops every 8 columns.
+fn columns(line: &str) -> usize {
+    line.chars().fold(0, |column, c| match c {
+        '\t' => column / 8 * 8 + 8,
+        _ => column + 1,
+    })
+}
+/// A comment listing the implicit register operands of `instruction`, if it has any.
+fn implicit_operands(instruction: &Instruction) -> Option<String> {
+    let uses = instruction.implicit_uses();
+    let defs = instruction.implicit_defs();
+    if uses.is_empty() && defs.is_empty() {
+        return None;
+    }
+
+    let mut comment = String::from("implicit");
+    for (kind, registers) in [("uses", uses), ("defs", defs)] {
+        if registers.is_empty() {
+            continue;
+        }
+        comment += &format!(" {kind}:");
+        for register in registers {
+            comment += &format!(" {register}");
         }
     }
+    Some(comment)
+}
+
+/// Writes a vector instruction, which has no size suffix but may broadcast its memory
+/// operand and mask its destination.
+fn write_vector(f: &mut core::fmt::Formatter<'_>, instruction: &Instruction) -> core::fmt::Result {
+    let Instruction::Vector {
+        op,
+        dst,
+        src1,
+        src2,
+        masking,
+        ..
+    } = *instruction
+    else {
+        unreachable!()
+    };
+    let compact = f.alternate();
+    let separator = if compact { "," } else { ", " };
+    let operand = |f: &mut core::fmt::Formatter<'_>, arg: Arg| {
+        if compact {
+            write!(f, "{arg:#}")
+        } else {
+            write!(f, "{arg}")
+        }
+    };
+
+    write!(f, "{} ", op.mnemonic())?;
+    operand(f, src2)?;
+    if let Ok(Some(lanes)) = broadcast_lanes(instruction) {
+        write!(f, "{{1to{lanes}}}")?;
+    }
+    if let Some(src1) = src1 {
+        write!(f, "{separator}")?;
+        operand(f, src1)?;
+    }
+    write!(f, "{separator}")?;
+    operand(f, dst)?;
+    if let Some(masking) = masking {
+        write!(f, "{masking}")?;
+    }
+    Ok(())
 }
 
-fn get_size(a: &Arg, b: &Arg) -> ArgSize {
+/// The number of elements a broadcast memory operand is repeated into, if it is broadcast.
+fn broadcast_lanes(instruction: &Instruction) -> Result<Option<u8>, X64WriterError> {
+    let Instruction::Vector {
+        op, dst, broadcast, ..
+    } = *instruction
+    else {
+        unreachable!()
+    };
+    if !broadcast {
+        return Ok(None);
+    }
+    match (dst, op.element_size()) {
+        (Arg::Vector(register), Some(element)) => Ok(Some(register.1.in_bytes() / element)),
+        _ => Err(X64WriterError::InvalidOperand(format!(
+            "{} cannot broadcast into {dst}",
+            op.mnemonic()
+        ))),
+    }
+}
+
+fn needs_star(target: &Arg) -> bool {
+    target.is_memory() || target.is_register()
+}
+
+/// The size of a shift, which is that of `dst` as the count is always a byte. Only
+/// immediate counts stand in for the size of a memory operand without one.
+fn shift_size(mnemonic: &'static str, dst: &Arg, count: &Arg) -> Result<ArgSize, X64WriterError> {
+    match (dst.size(), count) {
+        (Some(size), _) => Ok(size),
+        (None, Arg::Int(_)) => sized(mnemonic, count),
+        (None, _) => Err(X64WriterError::MissingSize { mnemonic }),
+    }
+}
+/// The size two operands share, of which at least one has to be sized.
+fn operand_size(mnemonic: &'static str, a: &Arg, b: &Arg) -> Result<ArgSize, X64WriterError> {
     match (a.size(), b.size()) {
-        (None, None) => panic!(),
-        (Some(a), None) | (None, Some(a)) => a,
-        (Some(a), Some(b)) => {
-            assert_eq!(a, b);
-            a
+        (None, None) => Err(X64WriterError::MissingSize { mnemonic }),
+        (Some(a), None) | (None, Some(a)) => Ok(a),
+        (Some(expected), Some(found)) if expected != found => {
+            Err(X64WriterError::SizeMismatch { expected, found })
         }
+        (Some(a), Some(_)) => Ok(a),
     }
 }
+fn sized(mnemonic: &'static str, arg: &Arg) -> Result<ArgSize, X64WriterError> {
+    arg.size().ok_or(X64WriterError::MissingSize { mnemonic })
+}
@@ -0,0 +1,124 @@
+use super::{
+    error::X64WriterError,
+    io::{self, Write},
+    section::{Section, SectionType},
+    writer::AsmWriter,
+};
+use alloc::{format, string::String, vec::Vec};
+
+/// The section of the sleds, which the XRay runtime finds through `__start_xray_instr_map`
+/// and `__stop_xray_instr_map`.
+pub const XRAY_INSTR_MAP_SECTION: &str = "xray_instr_map";
+/// The section of the index from each function to its range of sleds.
+pub const XRAY_FN_INDEX_SECTION: &str = "xray_fn_idx";
+const XRAY_SLED_VERSION: u8 = 2;
+
+/// What the XRay runtime patches a sled into a call of its handler for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum XRaySledKind {
+    Entry = 0,
+    Exit = 1,
+    TailCall = 2,
+}
+
+/// Code the XRay runtime can patch at run time, falling through while tracing is off.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct XRaySled {
+    /// The label at the start of the sled.
+    pub label: String,
+    /// The function the sled is in.
+    pub function: String,
+    pub kind: XRaySledKind,
+}
+
+/// The sleds of an [`AsmWriter`] not yet written to the instrumentation map.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct XRayState {
+    pending: Vec<XRaySled>,
+    /// The sleds emitted so far, which number their labels.
+    emitted: usize,
+}
+
+impl<O: Write> AsmWriter<O> {
+    /// Emits the sled at the entry of the open function: a jump over 9 bytes of NOPs, which
+    /// the runtime overwrites with a `mov` of the function id and a call of its handler.
+    pub fn emit_xray_entry_sled(&mut self) -> io::Result<String> {
+        self.emit_xray_jump_sled(XRaySledKind::Entry)
+    }
+    /// Emits the sled right before a tail call of the open function, laid out like the
+    /// entry sled. The jump to the callee follows it.
+    pub fn emit_xray_tail_call_sled(&mut self) -> io::Result<String> {
+        self.emit_xray_jump_sled(XRaySledKind::TailCall)
+    }
+    /// Emits a return from the open function followed by 10 bytes of NOPs, which the runtime
+    /// overwrites with a jump to its handler.
+    pub fn emit_xray_exit_sled(&mut self) -> Result<String, X64WriterError> {
+        let label = self.begin_xray_sled(XRaySledKind::Exit)?;
+        self.build_ret()?;
+        self.emit_nops(10)?;
+        Ok(label)
+    }
+    fn emit_xray_jump_sled(&mut self, kind: XRaySledKind) -> io::Result<String> {
+        let label = self.begin_xray_sled(kind)?;
+        self.comment("jmp .+11")?;
+        self.emit_bytes(&[0xeb, 0x09])?;
+        self.emit_nops(9)?;
+        Ok(label)
+    }
+    /// Aligns the sled so its first two bytes can be patched with a single store, and
+    /// labels it.
+    fn begin_xray_sled(&mut self, kind: XRaySledKind) -> io::Result<String> {
+        let function = self.open_function().clone();
+        let function = function.expect("a sled has to be in a function begun on the writer");
+        let state = self.xray();
+        let label = format!(".Lxray_sled_{}", state.emitted);
+        state.emitted += 1;
+        state.pending.push(XRaySled {
+            label: label.clone(),
+            function,
+            kind,
+        });
+        self.directive(".p2align 1")?;
+        self.emit_label(label.as_str())?;
+        Ok(label)
+    }
+
+    /// Emits the sleds emitted since the last map to [`XRAY_INSTR_MAP_SECTION`], as LLVM's
+    /// version 2 entries of 32 bytes, and an entry per function to [`XRAY_FN_INDEX_SECTION`].
+    /// Both entries hold addresses relative to themselves, so they need no dynamic
+    /// relocations.
+    pub fn emit_xray_instr_map(&mut self, always_instrument: bool) -> io::Result<()> {
+        let sleds = core::mem::take(&mut self.xray().pending);
+        for sleds in sleds.chunk_by(|a, b| a.function == b.function) {
+            let function = &sleds[0].function;
+            let start = format!("{}_start", sleds[0].label);
+            self.push_section(Section::named(
+                XRAY_INSTR_MAP_SECTION,
+                "a",
+                SectionType::Progbits,
+            )?)?;
+            self.emit_balign(8)?;
+            self.emit_label(start.as_str())?;
+            for sled in sleds {
+                self.directive(format_args!(".quad {}-.", sled.label))?;
+                self.directive(format_args!(".quad {function}-."))?;
+                self.emit_byte(sled.kind as u8)?;
+                self.emit_byte(u8::from(always_instrument))?;
+                self.emit_byte(XRAY_SLED_VERSION)?;
+                self.directive(".zero 13")?;
+            }
+            self.pop_section()?;
+
+            self.push_section(Section::named(
+                XRAY_FN_INDEX_SECTION,
+                "a",
+                SectionType::Progbits,
+            )?)?;
+            self.emit_balign(16)?;
+            self.directive(format_args!(".quad {start}-."))?;
+            self.emit_quad(sleds.len() as u64)?;
+            self.pop_section()?;
+        }
+        Ok(())
+    }
+}